@@ -0,0 +1,60 @@
+//! 对比不同分片数下，多个并发客户端读写 `Db` 的吞吐量
+//!
+//! 运行：`cargo bench --bench sharded_db`。分片减少的是锁竞争，
+//! 只有在多核机器上差异才明显。
+
+use bytes::Bytes;
+use my_redis::cmd;
+use my_redis::config::Config;
+use my_redis::state::State;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 并发客户端数
+const CLIENTS: usize = 64;
+/// 每个客户端发出的命令数
+const OPS_PER_CLIENT: usize = 20_000;
+
+fn main() {
+    println!("{CLIENTS} clients x {OPS_PER_CLIENT} ops (50% SET / 50% GET)");
+    for num_shards in [1, 4, 16, 64] {
+        let state: Arc<State> = State::new(Config {
+            shards: num_shards,
+            save: Vec::new(),
+            ..Config::default()
+        });
+        let elapsed: Duration = run(state);
+        let total: usize = CLIENTS * OPS_PER_CLIENT;
+        println!(
+            "shards={num_shards:<3} {:>10.0} ops/sec  ({:?})",
+            total as f64 / elapsed.as_secs_f64(),
+            elapsed
+        );
+    }
+}
+
+fn run(state: Arc<State>) -> Duration {
+    let start: Instant = Instant::now();
+    thread::scope(|scope| {
+        for client in 0..CLIENTS {
+            let state: Arc<State> = state.clone();
+            scope.spawn(move || {
+                for i in 0..OPS_PER_CLIENT {
+                    let key: Bytes = Bytes::from(format!("key:{client}:{}", i % 1024));
+                    let args: Vec<Bytes> = if i % 2 == 0 {
+                        vec![
+                            Bytes::from_static(b"SET"),
+                            key,
+                            Bytes::from_static(b"value"),
+                        ]
+                    } else {
+                        vec![Bytes::from_static(b"GET"), key]
+                    };
+                    cmd::execute(&state, &args);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
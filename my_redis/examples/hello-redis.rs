@@ -1,12 +1,12 @@
-use mini_redis::{client, Result};
+use my_redis::client::{Client, Result};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // 建立与mini-redis服务器的连接
-    let mut client: client::Client = client::connect("127.0.0.1:6379").await?;
+    // 建立与服务器的连接
+    let client: Client = Client::connect("127.0.0.1:6379").await?;
 
     // 设置 key: "hello" 和 值: "world"
-    client.set("hello", "world".into()).await?;
+    client.set("hello", "world").await?;
 
     // 获取"key=hello"的值
     let result = client.get("hello").await?;
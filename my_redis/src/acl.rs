@@ -0,0 +1,589 @@
+//! 访问控制：用户、密码以及允许执行的命令和访问的 key
+//!
+//! 和 Redis 的 ACL 一样，每个用户有启用开关、若干密码的 SHA-256 摘要、允许的命令集合和允许访问的
+//! key 模式。规则按 `ACL SETUSER` 的写法从左到右依次生效，例如 `on >secret ~cache:* +@read -keys`。
+//! `default` 用户总是存在，新连接自动以它的身份登录，除非它需要密码（`requirepass`），
+//! 这时连接要先用 AUTH 或 HELLO ... AUTH 认证。权限在命令分发时、执行之前检查。
+
+use crate::cmd::{self, CommandSpec};
+use crate::glob;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::RwLock;
+
+/// 默认用户
+pub const DEFAULT_USER: &str = "default";
+
+/// 除了 `@read`、`@write` 由命令标志决定、`@all` 包含所有命令之外的命令分类
+const CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "keyspace",
+        &[
+            "del",
+            "exists",
+            "expire",
+            "pexpire",
+            "pexpireat",
+            "expireat",
+            "persist",
+            "dump",
+            "restore",
+            "type",
+            "rename",
+            "move",
+            "keys",
+            "scan",
+            "dbsize",
+            "randomkey",
+            "swapdb",
+            "flushdb",
+            "flushall",
+        ],
+    ),
+    ("string", &["get", "set", "mget", "mset"]),
+    (
+        "list",
+        &[
+            "lpush", "rpush", "lpop", "rpop", "lrange", "llen", "blpop", "brpop",
+        ],
+    ),
+    (
+        "hash",
+        &["hset", "hget", "hgetall", "hdel", "hlen", "hscan"],
+    ),
+    (
+        "set",
+        &[
+            "sadd",
+            "srem",
+            "smembers",
+            "sismember",
+            "scard",
+            "sinter",
+            "sscan",
+        ],
+    ),
+    (
+        "sortedset",
+        &["zadd", "zrem", "zscore", "zcard", "zrange", "zrangebyscore"],
+    ),
+    (
+        "stream",
+        &[
+            "xadd",
+            "xlen",
+            "xrange",
+            "xrevrange",
+            "xdel",
+            "xtrim",
+            "xread",
+            "xgroup",
+            "xreadgroup",
+            "xack",
+            "xpending",
+            "xclaim",
+        ],
+    ),
+    ("blocking", &["blpop", "brpop", "xread", "xreadgroup"]),
+    (
+        "transaction",
+        &["multi", "exec", "discard", "watch", "unwatch"],
+    ),
+    (
+        "connection",
+        &["ping", "hello", "auth", "select", "client", "asking"],
+    ),
+    ("scripting", &["eval", "evalsha", "script"]),
+    (
+        "admin",
+        &[
+            "save",
+            "bgsave",
+            "lastsave",
+            "bgrewriteaof",
+            "replicaof",
+            "slaveof",
+            "psync",
+            "replconf",
+            "role",
+            "config",
+            "monitor",
+            "slowlog",
+            "acl",
+            "cluster",
+        ],
+    ),
+    (
+        "dangerous",
+        &[
+            "keys",
+            "restore",
+            "swapdb",
+            "flushdb",
+            "flushall",
+            "save",
+            "bgsave",
+            "lastsave",
+            "bgrewriteaof",
+            "replicaof",
+            "slaveof",
+            "psync",
+            "replconf",
+            "role",
+            "config",
+            "info",
+            "monitor",
+            "slowlog",
+            "client",
+            "acl",
+        ],
+    ),
+];
+
+/// 所有分类的名字
+pub fn categories() -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = vec!["all", "read", "write"];
+    names.extend(CATEGORIES.iter().map(|(name, _)| *name));
+    names
+}
+
+/// 分类中的命令，未知的分类返回 `None`
+pub fn category(name: &str) -> Option<Vec<&'static str>> {
+    let all = cmd::commands();
+    let by_flag = |flag: u8| -> Vec<&'static str> {
+        all.iter()
+            .filter(|spec| spec.flags & flag != 0)
+            .map(|spec| spec.name)
+            .collect()
+    };
+    match name.to_ascii_lowercase().as_str() {
+        "all" => Some(all.iter().map(|spec| spec.name).collect()),
+        "read" => Some(by_flag(cmd::READONLY)),
+        "write" => Some(by_flag(cmd::WRITE)),
+        name => CATEGORIES
+            .iter()
+            .find(|(category, _)| *category == name)
+            .map(|(_, commands)| commands.to_vec()),
+    }
+}
+
+/// 一个 ACL 用户
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    /// 任何密码都可以通过认证
+    pub nopass: bool,
+    /// 密码的 SHA-256 摘要（十六进制）
+    pub passwords: Vec<String>,
+    /// 允许执行的命令
+    commands: BTreeSet<&'static str>,
+    /// 生成 `commands` 的规则，用于展示
+    command_rules: Vec<String>,
+    /// 允许访问的 key 模式
+    pub keys: Vec<String>,
+}
+
+impl User {
+    /// 新用户：停用、没有密码、不能执行任何命令
+    fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            commands: BTreeSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            keys: Vec::new(),
+        }
+    }
+
+    /// 应用一条规则，出错时用户保持不变
+    pub fn apply(&mut self, rule: &str) -> Result<(), String> {
+        let lower: String = rule.to_ascii_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => {
+                *self = User::new(&self.name);
+            }
+            _ => return self.apply_prefixed(rule),
+        }
+        Ok(())
+    }
+
+    fn apply_prefixed(&mut self, rule: &str) -> Result<(), String> {
+        let mut chars = rule.chars();
+        let prefix: Option<char> = chars.next();
+        let value: &str = chars.as_str();
+        match prefix {
+            Some('>') => {
+                self.add_password(sha256_hex(value.as_bytes()));
+            }
+            Some('<') => {
+                let hash: String = sha256_hex(value.as_bytes());
+                self.remove_password(&hash)?;
+            }
+            Some('#') => {
+                self.add_password(parse_hash(value)?);
+            }
+            Some('!') => {
+                self.remove_password(&parse_hash(value)?)?;
+            }
+            Some('~') => {
+                if !self.keys.iter().any(|pattern| pattern == value) {
+                    self.keys.push(value.to_string());
+                }
+            }
+            Some(sign @ ('+' | '-')) => {
+                let allow: bool = sign == '+';
+                let commands: Vec<&'static str> = match value.strip_prefix('@') {
+                    Some(name) => category(name).ok_or("Unknown command category")?,
+                    None => vec![cmd::lookup(value.as_bytes()).ok_or("Unknown command")?.name],
+                };
+                for command in commands {
+                    if allow {
+                        self.commands.insert(command);
+                    } else {
+                        self.commands.remove(command);
+                    }
+                }
+                let rule: String = rule.to_ascii_lowercase();
+                // +@all 和 -@all 之前的规则都被覆盖了
+                if rule == "+@all" || rule == "-@all" {
+                    self.command_rules.clear();
+                }
+                self.command_rules.push(rule);
+            }
+            _ => return Err("Syntax error".to_string()),
+        }
+        Ok(())
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<(), String> {
+        let before: usize = self.passwords.len();
+        self.passwords.retain(|password| password != hash);
+        if self.passwords.len() == before {
+            return Err(
+                "The password you are trying to remove from the user does not exist".into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// 密码是否正确，停用的用户总是失败
+    pub fn authenticate(&self, password: &[u8]) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&sha256_hex(password)))
+    }
+
+    /// 检查能否执行命令以及访问其中的 key，不能时返回错误回复的内容
+    pub fn check(&self, spec: &CommandSpec, keys: &[&str]) -> Result<(), String> {
+        if !self.commands.contains(spec.name) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                self.name, spec.name
+            ));
+        }
+        let allowed = |key: &str| {
+            self.keys
+                .iter()
+                .any(|pattern| glob::matches(pattern.as_bytes(), key.as_bytes(), false))
+        };
+        if !keys.iter().all(|key| allowed(key)) {
+            return Err("NOPERM No permissions to access a key".to_string());
+        }
+        Ok(())
+    }
+
+    /// 命令权限的规则，例如 `+@all` 或 `-@all +get`
+    pub fn command_rules(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    /// key 权限的规则，例如 `~*`
+    pub fn key_rules(&self) -> String {
+        let patterns: Vec<String> = self.keys.iter().map(|k| format!("~{k}")).collect();
+        patterns.join(" ")
+    }
+
+    /// `ACL LIST` 中的一行，按规则的写法描述用户
+    pub fn describe(&self) -> String {
+        let mut words: Vec<String> = vec![
+            "user".to_string(),
+            self.name.clone(),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+        if self.nopass {
+            words.push("nopass".to_string());
+        }
+        words.extend(self.passwords.iter().map(|hash| format!("#{hash}")));
+        if !self.keys.is_empty() {
+            words.push(self.key_rules());
+        }
+        words.push(self.command_rules());
+        words.join(" ")
+    }
+}
+
+fn parse_hash(value: &str) -> Result<String, String> {
+    if value.len() != 64 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+    }
+    Ok(value.to_ascii_lowercase())
+}
+
+/// 所有用户
+#[derive(Debug)]
+pub struct Acl {
+    users: RwLock<BTreeMap<String, User>>,
+}
+
+impl Acl {
+    /// 按 `requirepass` 和配置文件中的 `user` 行创建用户
+    pub fn new(requirepass: &str, users: &[String]) -> Result<Acl, String> {
+        let mut default: User = User::new(DEFAULT_USER);
+        for rule in ["on", "nopass", "allkeys", "+@all"] {
+            default.apply(rule)?;
+        }
+        let acl: Acl = Acl {
+            users: RwLock::new(BTreeMap::from([(DEFAULT_USER.to_string(), default)])),
+        };
+        acl.set_requirepass(requirepass);
+        for line in users {
+            let words: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+            let (name, rules) = words.split_first().ok_or("missing user name")?;
+            acl.set_user(name, rules)?;
+        }
+        Ok(acl)
+    }
+
+    /// `requirepass`：设置 `default` 用户唯一的密码，空字符串表示不需要密码
+    pub fn set_requirepass(&self, password: &str) {
+        let mut users = self.users.write().unwrap();
+        let default: &mut User = users.get_mut(DEFAULT_USER).expect("default user exists");
+        let rules: [String; 2] = if password.is_empty() {
+            ["resetpass".to_string(), "nopass".to_string()]
+        } else {
+            ["resetpass".to_string(), format!(">{password}")]
+        };
+        for rule in &rules {
+            default.apply(rule).expect("valid rule");
+        }
+    }
+
+    /// 新连接自动登录的用户：`default` 启用且不需要密码时就是它
+    pub fn auto_login(&self) -> Option<String> {
+        let users = self.users.read().unwrap();
+        let default: &User = &users[DEFAULT_USER];
+        (default.enabled && default.nopass).then(|| DEFAULT_USER.to_string())
+    }
+
+    /// 用户名和密码是否匹配
+    pub fn authenticate(&self, name: &str, password: &[u8]) -> bool {
+        let users = self.users.read().unwrap();
+        users
+            .get(name)
+            .is_some_and(|user| user.authenticate(password))
+    }
+
+    /// 检查 `user` 能否执行命令，用户已经被删除时同样拒绝
+    pub fn check(&self, user: &str, spec: &CommandSpec, keys: &[&str]) -> Result<(), String> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(user) => user.check(spec, keys),
+            None => Err(format!("NOPERM User {user} no longer exists")),
+        }
+    }
+
+    /// `ACL SETUSER`：创建或修改用户，所有规则都有效时才生效
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err("Usernames can't contain spaces or be empty".to_string());
+        }
+        let mut users = self.users.write().unwrap();
+        let mut user: User = users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|err| format!("Error in ACL SETUSER modifier '{rule}': {err}"))?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    pub fn get_user(&self, name: &str) -> Option<User> {
+        self.users.read().unwrap().get(name).cloned()
+    }
+
+    /// `ACL DELUSER`：返回删除的个数，`default` 不能删除
+    pub fn del_users(&self, names: &[&str]) -> Result<usize, String> {
+        if names.contains(&DEFAULT_USER) {
+            return Err("The 'default' user cannot be removed".to_string());
+        }
+        let mut users = self.users.write().unwrap();
+        Ok(names
+            .iter()
+            .filter(|name| users.remove(**name).is_some())
+            .count())
+    }
+
+    /// 按名字排序的全部用户
+    pub fn users(&self) -> Vec<User> {
+        self.users.read().unwrap().values().cloned().collect()
+    }
+}
+
+/// SHA-256 摘要的十六进制形式，ACL 只保存密码的摘要
+pub fn sha256_hex(data: &[u8]) -> String {
+    sha256(data).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    // 填充方式与 SHA-1 相同
+    let mut message: Vec<u8> = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w: [u32; 64] = [0; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0: u32 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1: u32 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1: u32 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch: u32 = (e & f) ^ (!e & g);
+            let temp1: u32 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0: u32 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj: u32 = (a & b) ^ (a & c) ^ (b & c);
+            let temp2: u32 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest: [u8; 32] = [0; 32];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::{category, sha256_hex, Acl, CATEGORIES};
+    use crate::cmd;
+
+    #[test]
+    fn sha256_matches_known_digests() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn rules_apply_in_order() {
+        let acl: Acl =
+            Acl::new("", &["alice on >secret ~cache:* +@read -keys".to_string()]).unwrap();
+        assert_eq!(acl.auto_login().as_deref(), Some("default"));
+        assert!(acl.authenticate("alice", b"secret"));
+        assert!(!acl.authenticate("alice", b"wrong"));
+        let get = cmd::lookup(b"get").unwrap();
+        let set = cmd::lookup(b"set").unwrap();
+        let keys = cmd::lookup(b"keys").unwrap();
+        assert!(acl.check("alice", get, &["cache:1"]).is_ok());
+        assert!(acl.check("alice", get, &["other"]).is_err());
+        assert!(acl.check("alice", set, &["cache:1"]).is_err());
+        assert!(acl.check("alice", keys, &[]).is_err());
+        let alice = acl.get_user("alice").unwrap();
+        assert_eq!(alice.command_rules(), "-@all +@read -keys");
+
+        // 出错的规则不会留下一半的修改
+        let err = acl
+            .set_user("alice", &["off".to_string(), "+nosuch".to_string()])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Error in ACL SETUSER modifier '+nosuch': Unknown command"
+        );
+        assert!(acl.get_user("alice").unwrap().enabled);
+
+        acl.set_requirepass("pw");
+        assert_eq!(acl.auto_login(), None);
+        assert!(acl.authenticate("default", b"pw"));
+        assert!(acl.del_users(&["default"]).is_err());
+        assert_eq!(acl.del_users(&["alice", "bob"]), Ok(1));
+    }
+
+    #[test]
+    fn categories_name_real_commands() {
+        for (_, commands) in CATEGORIES {
+            for name in *commands {
+                assert!(cmd::lookup(name.as_bytes()).is_some(), "{name}");
+            }
+        }
+        assert!(category("READ").unwrap().contains(&"get"));
+        assert!(category("nosuch").is_none());
+    }
+}
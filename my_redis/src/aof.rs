@@ -0,0 +1,586 @@
+//! 追加写日志（AOF）
+//!
+//! 每条写命令以 RESP 数组的形式追加到文件末尾，启动时按顺序重放即可恢复数据。
+//! 相对时间的过期命令在写入前已经换算为 `PEXPIREAT`，重放时不会延长过期时间。
+//! 命令所在的数据库与上一条不同时先写一条 `SELECT`，重放时从 0 号数据库开始。
+
+use crate::cmd;
+use crate::db::{Entry, Guard, Value};
+use crate::frame::Frame;
+use crate::log;
+use crate::log::Level;
+use crate::rdb;
+use crate::session::Session;
+use crate::state::State;
+use bytes::{Bytes, BytesMut};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// 何时把日志刷到磁盘
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// 每条命令都 fsync
+    Always,
+    /// 每秒 fsync 一次，最多丢失一秒的数据
+    EverySec,
+    /// 交给操作系统决定
+    No,
+}
+
+impl FsyncPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        }
+    }
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FsyncPolicy, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format!("invalid appendfsync policy: {s}")),
+        }
+    }
+}
+
+/// 重写时每条命令最多带的元素个数
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// 打开的日志文件
+#[derive(Debug)]
+pub struct Aof {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+    /// 是否有 BGREWRITEAOF 正在进行
+    pub rewrite_in_progress: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    /// 刷盘策略，可以用 `CONFIG SET appendfsync` 修改
+    policy: FsyncPolicy,
+    /// 重写期间新到的命令，重写结束时追加到新文件末尾
+    rewrite_buf: Option<Vec<u8>>,
+    /// 文件中最后一条 `SELECT` 选中的数据库，`None` 表示不确定，下一条命令前要先写 `SELECT`
+    selected: Option<usize>,
+}
+
+impl Aof {
+    /// 以追加模式打开日志，文件不存在时创建
+    pub fn open(path: PathBuf, policy: FsyncPolicy) -> io::Result<Aof> {
+        let file: File = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Aof {
+            path,
+            inner: Mutex::new(Inner {
+                file,
+                policy,
+                rewrite_buf: None,
+                selected: None,
+            }),
+            rewrite_in_progress: AtomicBool::new(false),
+        })
+    }
+
+    /// 追加在数据库 `db` 上执行的若干条命令，调用方持有命令涉及分片的锁，
+    /// 保证同一个 key 的日志顺序与执行顺序一致
+    pub fn append(&self, db: usize, commands: &[Vec<Bytes>]) {
+        let mut buf: Vec<u8> = Vec::new();
+        let mut inner = self.inner.lock().unwrap();
+        encode_in(&mut inner.selected, db, commands, &mut buf);
+        if let Some(rewrite_buf) = inner.rewrite_buf.as_mut() {
+            rewrite_buf.extend_from_slice(&buf);
+        }
+        let mut result: io::Result<()> = inner.file.write_all(&buf);
+        if inner.policy == FsyncPolicy::Always {
+            result = result.and_then(|()| inner.file.sync_data());
+        }
+        if let Err(err) = result {
+            log!(Level::Warning, "Error writing to the AOF file: {err}");
+        }
+    }
+
+    /// 当前的刷盘策略
+    pub fn policy(&self) -> FsyncPolicy {
+        self.inner.lock().unwrap().policy
+    }
+
+    /// 修改刷盘策略
+    pub fn set_policy(&self, policy: FsyncPolicy) {
+        self.inner.lock().unwrap().policy = policy;
+    }
+
+    /// 把已写入的日志刷到磁盘
+    pub fn sync(&self) -> io::Result<()> {
+        self.inner.lock().unwrap().file.sync_data()
+    }
+
+    /// 后台重写：在锁内复制一份数据，由单独的线程写出最精简的命令序列。
+    /// 已有重写在进行时返回 `false`
+    pub fn rewrite(self: &Arc<Self>, guard: &Guard<'_>) -> bool {
+        if self.rewrite_in_progress.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let snapshot: Vec<(usize, String, Entry)> = guard
+            .iter_all()
+            .map(|(db, key, entry)| (db, key.clone(), entry.clone()))
+            .collect();
+        // 与复制快照处在同一把分片锁内，之后的写命令都会进入重写缓冲。
+        // 重写缓冲接在新文件末尾，第一条命令前要重新写 SELECT
+        let mut inner = self.inner.lock().unwrap();
+        inner.rewrite_buf = Some(Vec::new());
+        inner.selected = None;
+        drop(inner);
+
+        let aof: Arc<Aof> = self.clone();
+        thread::spawn(move || {
+            match aof.finish_rewrite(&snapshot) {
+                Ok(()) => log!(
+                    Level::Notice,
+                    "Background AOF rewrite finished successfully"
+                ),
+                Err(err) => {
+                    aof.inner.lock().unwrap().rewrite_buf = None;
+                    log!(Level::Warning, "Background AOF rewrite error: {err}");
+                }
+            }
+            aof.rewrite_in_progress.store(false, Ordering::SeqCst);
+        });
+        true
+    }
+
+    fn finish_rewrite(&self, snapshot: &[(usize, String, Entry)]) -> io::Result<()> {
+        let mut buf: Vec<u8> = Vec::new();
+        // 重放从 0 号数据库开始
+        let mut selected: Option<usize> = Some(0);
+        for (db, key, entry) in snapshot {
+            encode_in(&mut selected, *db, &entry_commands(key, entry), &mut buf);
+        }
+        let tmp: PathBuf = temp_path(&self.path);
+        let mut file: File = File::create(&tmp)?;
+        file.write_all(&buf)?;
+
+        // 追加重写期间的增量后替换旧文件，这段时间内写命令会短暂等待
+        let mut inner = self.inner.lock().unwrap();
+        let rewrite_buf: Vec<u8> = inner.rewrite_buf.take().unwrap_or_default();
+        file.write_all(&rewrite_buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        inner.file = file;
+        Ok(())
+    }
+}
+
+/// 重建一条数据所需的命令，集合类型每条命令最多带 `ITEMS_PER_COMMAND` 个元素
+pub fn entry_commands(key: &str, entry: &Entry) -> Vec<Vec<Bytes>> {
+    let key: Bytes = Bytes::copy_from_slice(key.as_bytes());
+    let (name, items): (&'static [u8], Vec<Bytes>) = match &entry.value {
+        // 流带着消费组和待确认条目，用 DUMP 的格式整体恢复，过期时间由后面的 PEXPIREAT 设置
+        Value::Stream(_) => {
+            let name: String = String::from_utf8_lossy(&key).into_owned();
+            let payload: Vec<u8> = rdb::encode(std::iter::once((0, &name, entry)));
+            (
+                b"RESTORE",
+                vec![
+                    Bytes::from_static(b"0"),
+                    Bytes::from(payload),
+                    Bytes::from_static(b"REPLACE"),
+                ],
+            )
+        }
+        Value::String(data) => (b"SET", vec![data.clone()]),
+        Value::List(list) => (b"RPUSH", list.iter().cloned().collect()),
+        Value::Hash(hash) => (
+            b"HSET",
+            hash.iter()
+                .flat_map(|(field, value)| [field.clone(), value.clone()])
+                .collect(),
+        ),
+        Value::Set(set) => (b"SADD", set.iter().cloned().collect()),
+        Value::ZSet(zset) => (
+            b"ZADD",
+            zset.iter()
+                .flat_map(|(member, score)| [Bytes::from(score.to_string()), member.clone()])
+                .collect(),
+        ),
+    };
+    // 哈希和有序集合的元素成对出现，分批时不能拆开
+    let chunk: usize = match entry.value {
+        Value::Hash(_) | Value::ZSet(_) => ITEMS_PER_COMMAND * 2,
+        Value::Stream(_) => usize::MAX,
+        _ => ITEMS_PER_COMMAND,
+    };
+    let mut commands: Vec<Vec<Bytes>> = items
+        .chunks(chunk)
+        .map(|items| {
+            let mut args: Vec<Bytes> = vec![Bytes::from_static(name), key.clone()];
+            args.extend_from_slice(items);
+            args
+        })
+        .collect();
+    if let Some(at) = entry.expires_at {
+        commands.push(vec![
+            Bytes::from_static(b"PEXPIREAT"),
+            key,
+            Bytes::from(at.to_string()),
+        ]);
+    }
+    commands
+}
+
+/// 把在数据库 `db` 上执行的命令编码到 `buf`。`selected` 是命令流中最后选中的数据库，
+/// 与 `db` 不同时先补一条 `SELECT`
+///
+/// 命令中的 `SELECT`（事务里切换了数据库）只标记之后的命令换了数据库，
+/// 等到真有命令要写时才补上，单纯的切换不会写进命令流
+pub fn encode_in(
+    selected: &mut Option<usize>,
+    mut db: usize,
+    commands: &[Vec<Bytes>],
+    buf: &mut Vec<u8>,
+) {
+    for args in commands {
+        if args[0].eq_ignore_ascii_case(b"SELECT") {
+            if let Some(index) = std::str::from_utf8(&args[1])
+                .ok()
+                .and_then(|s| s.parse().ok())
+            {
+                db = index;
+            }
+            continue;
+        }
+        if *selected != Some(db) {
+            encode(
+                &[Bytes::from_static(b"SELECT"), Bytes::from(db.to_string())],
+                buf,
+            );
+            *selected = Some(db);
+        }
+        encode(args, buf);
+    }
+}
+
+/// 把一条命令编码为 RESP 数组
+pub fn encode(args: &[Bytes], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+/// 启动时重放日志，返回执行的命令数。
+///
+/// 末尾不完整的命令（例如写到一半时宕机）会被截掉，没写完的事务从 MULTI 处整个截掉，
+/// 其它格式错误视为文件损坏。
+pub fn load(state: &Arc<State>, path: &Path) -> io::Result<usize> {
+    let mut buf: BytesMut = match fs::read(path) {
+        Ok(buf) => BytesMut::from(&buf[..]),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let len: u64 = buf.len() as u64;
+    let mut session: Session = Session::new();
+    // 当前事务的 MULTI 在文件中的位置
+    let mut multi_start: u64 = 0;
+    let mut replayed: usize = 0;
+    let mut truncate_at: Option<u64> = None;
+    while !buf.is_empty() {
+        let start: u64 = len - buf.len() as u64;
+        let frame: Frame = match Frame::parse(&mut buf) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                truncate_at = Some(start);
+                break;
+            }
+            Err(err) => return Err(corrupt(start, err)),
+        };
+        let args: Vec<Bytes> = cmd::parse_args(frame)
+            .map_err(|_| corrupt(start, "expected an array of bulk strings"))?;
+        if args
+            .first()
+            .is_some_and(|name| name.eq_ignore_ascii_case(b"multi"))
+        {
+            multi_start = start;
+        }
+        cmd::execute_in(state, &mut session, &args);
+        replayed += 1;
+    }
+    if session.in_multi() {
+        truncate_at = Some(multi_start);
+    }
+    if let Some(at) = truncate_at {
+        log!(
+            Level::Warning,
+            "AOF {} is truncated, discarding the last {} bytes",
+            path.display(),
+            len - at
+        );
+        OpenOptions::new().write(true).open(path)?.set_len(at)?;
+    }
+    state.rdb.dirty.store(0, Ordering::SeqCst);
+    Ok(replayed)
+}
+
+/// `everysec` 策略下每秒刷一次盘
+pub async fn everysec(state: Arc<State>) {
+    let aof: Arc<Aof> = match state.aof.get() {
+        Some(aof) => aof.clone(),
+        None => return,
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        // 策略可能在运行时被修改
+        if aof.policy() != FsyncPolicy::EverySec {
+            continue;
+        }
+        let aof: Arc<Aof> = aof.clone();
+        let result = tokio::task::spawn_blocking(move || aof.sync()).await;
+        if let Ok(Err(err)) = result {
+            log!(Level::Warning, "Error syncing the AOF file: {err}");
+        }
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name: String = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("temp-rewriteaof-{}-{name}", std::process::id()))
+}
+
+fn corrupt(offset: u64, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt AOF file at byte {offset}: {err}"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load, Aof, FsyncPolicy};
+    use crate::cmd::test::{render, reply, run, run_in, state};
+    use crate::session::Session;
+    use crate::state::State;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("my_redis-aof-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path: PathBuf = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn replay_with_truncated_tail() {
+        let path: PathBuf = temp_path("replay.aof");
+        let db = state(4);
+        let aof = Arc::new(Aof::open(path.clone(), FsyncPolicy::Always).unwrap());
+        db.aof.set(aof).unwrap();
+        run(&db, "MSET a 1 b 2");
+        run(&db, "SET c 3 EX 100");
+        run(&db, "DEL b");
+        run(&db, "GET a");
+
+        // 模拟写到一半时宕机
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nd").unwrap();
+        drop(file);
+        let len: u64 = std::fs::metadata(&path).unwrap().len();
+
+        let restored = state(2);
+        // 第一条写命令前补了 SELECT 0
+        assert_eq!(load(&restored, &path).unwrap(), 5);
+        assert_eq!(reply(&restored, "MGET a b c d"), "[1 nil 3 nil]");
+        assert!(restored
+            .db
+            .lock_all()
+            .get("c")
+            .unwrap()
+            .expires_at
+            .is_some());
+        assert!(std::fs::metadata(&path).unwrap().len() < len);
+    }
+
+    #[test]
+    fn transactions_replay_whole_or_not_at_all() {
+        let path: PathBuf = temp_path("multi.aof");
+        let db = state(4);
+        let aof = Arc::new(Aof::open(path.clone(), FsyncPolicy::Always).unwrap());
+        db.aof.set(aof).unwrap();
+        let mut session: Session = Session::new();
+        for cmd in ["MULTI", "SET a 1", "GET a", "RPUSH l x", "EXEC"] {
+            run_in(&db, &mut session, cmd);
+        }
+        let committed: u64 = std::fs::metadata(&path).unwrap().len();
+
+        // 只写了一半的事务
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n")
+            .unwrap();
+        drop(file);
+
+        let restored = state(2);
+        load(&restored, &path).unwrap();
+        assert_eq!(reply(&restored, "MGET a b"), "[1 nil]");
+        assert_eq!(reply(&restored, "LRANGE l 0 -1"), "[x]");
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), committed);
+    }
+
+    #[test]
+    fn scripts_replay_as_their_writes() {
+        let path: PathBuf = temp_path("script.aof");
+        let db = state(4);
+        let aof = Arc::new(Aof::open(path.clone(), FsyncPolicy::Always).unwrap());
+        db.aof.set(aof).unwrap();
+        let script: &str = "\"call('SET', KEYS[1], ARGV[1]) call('RPUSH', 'l', ARGV[1])\"";
+        run(&db, &format!("EVAL {script} 1 a 1"));
+        run(&db, "EVAL \"return call('GET', 'a')\" 0");
+        let mut session: Session = Session::new();
+        for cmd in ["MULTI", &format!("EVAL {script} 1 b 2"), "SET c 3", "EXEC"] {
+            run_in(&db, &mut session, cmd);
+        }
+
+        // 每个脚本和事务各包一层，事务里的脚本不再嵌套
+        let log: Vec<u8> = std::fs::read(&path).unwrap();
+        assert_eq!(log.windows(5).filter(|w| w == b"MULTI").count(), 2);
+        assert!(!log.windows(4).any(|w| w == b"EVAL"));
+        let restored = state(2);
+        load(&restored, &path).unwrap();
+        assert_eq!(reply(&restored, "MGET a b c"), "[1 2 3]");
+        assert_eq!(reply(&restored, "LRANGE l 0 -1"), "[1 2]");
+    }
+
+    #[test]
+    fn databases_replay_and_rewrite() {
+        let path: PathBuf = temp_path("databases.aof");
+        let db = state(4);
+        let aof = Arc::new(Aof::open(path.clone(), FsyncPolicy::No).unwrap());
+        db.aof.set(aof.clone()).unwrap();
+        let mut session: Session = Session::new();
+        for cmd in [
+            "SET a zero",
+            "SELECT 3",
+            "SET a three",
+            "RPUSH l x",
+            "MULTI",
+            "SET b three",
+            "SELECT 4",
+            "SET b four",
+            "EXEC",
+            "MOVE b 0",
+        ] {
+            run_in(&db, &mut session, cmd);
+        }
+        run(&db, "SWAPDB 0 3");
+        run(&db, "SET c zero");
+
+        fn check(restored: &Arc<State>) {
+            let mut session: Session = Session::new();
+            let mut send = |cmd: &str| render(&run_in(restored, &mut session, cmd));
+            assert_eq!(send("MGET a b c"), "[three three zero]");
+            assert_eq!(send("LRANGE l 0 -1"), "[x]");
+            send("SELECT 3");
+            assert_eq!(send("MGET a b c"), "[zero four nil]");
+            send("SELECT 4");
+            assert_eq!(send("DBSIZE"), "0");
+        }
+        let restored = state(2);
+        load(&restored, &path).unwrap();
+        check(&restored);
+
+        assert!(aof.rewrite(&db.db.lock_all()));
+        while aof.rewrite_in_progress.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        // 重写后的文件停在 3 号数据库，之后的命令要先写 SELECT
+        run(&db, "SET d zero");
+        let rewritten = state(1);
+        load(&rewritten, &path).unwrap();
+        check(&rewritten);
+        assert_eq!(reply(&rewritten, "GET d"), "zero");
+    }
+
+    #[test]
+    fn corrupt_file_is_rejected() {
+        let path: PathBuf = temp_path("corrupt.aof");
+        std::fs::write(&path, b"*1\r\n$4\r\nPING\r\n!garbage\r\n").unwrap();
+        assert!(load(&state(1), &path).is_err());
+    }
+
+    #[test]
+    fn rewrite_compacts_log() {
+        let path: PathBuf = temp_path("rewrite.aof");
+        let db = state(4);
+        let aof = Arc::new(Aof::open(path.clone(), FsyncPolicy::No).unwrap());
+        db.aof.set(aof.clone()).unwrap();
+        for i in 0..100 {
+            run(&db, &format!("SET counter {i}"));
+        }
+        let before: u64 = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(
+            reply(&db, "BGREWRITEAOF"),
+            "Background append only file rewriting started"
+        );
+        while aof.rewrite_in_progress.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        run(&db, "SET after rewrite");
+        assert!(std::fs::metadata(&path).unwrap().len() < before);
+
+        let restored = state(1);
+        load(&restored, &path).unwrap();
+        assert_eq!(reply(&restored, "MGET counter after"), "[99 rewrite]");
+    }
+
+    #[test]
+    fn rewrite_collections_in_batches() {
+        let path: PathBuf = temp_path("rewrite_types.aof");
+        let db = state(2);
+        let aof = Arc::new(Aof::open(path.clone(), FsyncPolicy::No).unwrap());
+        db.aof.set(aof.clone()).unwrap();
+        let members: Vec<String> = (0..150).map(|i| format!("{i} m{i}")).collect();
+        run(&db, &format!("ZADD z {}", members.join(" ")));
+        run(&db, "HSET h a 1 b 2");
+        run(&db, "RPUSH l x y z");
+        assert!(aof.rewrite(&db.db.lock_all()));
+        while aof.rewrite_in_progress.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let restored = state(1);
+        load(&restored, &path).unwrap();
+        assert_eq!(reply(&restored, "ZCARD z"), "150");
+        assert_eq!(reply(&restored, "ZRANGE z -1 -1 WITHSCORES"), "[m149 149]");
+        assert_eq!(reply(&restored, "HGET h b"), "2");
+        assert_eq!(reply(&restored, "LRANGE l 0 -1"), "[x y z]");
+    }
+}
@@ -1,96 +1,18 @@
-use bytes::Bytes;
-use mini_redis::client;
-use tokio::sync::{mpsc, oneshot};
-
-/// Multiple different commands are multiplexed over a single channel.
-#[derive(Debug)]
-enum Command {
-    Get {
-        key: String,
-        resp: Responder<Option<Bytes>>,
-    },
-    Set {
-        key: String,
-        val: Bytes,
-        resp: Responder<()>,
-    },
-}
-
-/// Provided by the requester and used by the manager task to send the command
-/// response back to the requester.
-type Responder<T> = oneshot::Sender<mini_redis::Result<T>>;
+use my_redis::client::Client;
+use std::error::Error;
 
 #[tokio::main]
-async fn main() {
-    let (tx, mut rx) = mpsc::channel(32);
-    // Clone a `tx` handle for the second f
-    let tx2: mpsc::Sender<Command> = tx.clone();
-
-    let manager: tokio::task::JoinHandle<()> = tokio::spawn(async move {
-        // Open a connection to the mini-redis address.
-        let mut client: client::Client = client::connect("127.0.0.1:6379").await.unwrap();
-
-        while let Some(cmd) = rx.recv().await {
-            match cmd {
-                Command::Get { key, resp } => {
-                    let res: Result<Option<Bytes>, Box<dyn Error + Send + Sync>> =
-                        client.get(&key).await;
-                    // Ignore errors
-                    let _ = resp.send(res);
-                }
-                Command::Set { key, val, resp } => {
-                    let res: Result<(), Box<dyn Error + Send + Sync>> = client.set(&key, val).await;
-                    // Ignore errors
-                    let _ = resp.send(res);
-                }
-            }
-        }
-    });
-
-    // Spawn two tasks, one setting a value and other querying for key that was
-    // set.
-    let t1: tokio::task::JoinHandle<()> = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let cmd = Command::Get {
-            key: "foo".to_string(),
-            resp: resp_tx,
-        };
-
-        // Send the GET request
-        if tx.send(cmd).await.is_err() {
-            eprintln!("connection task shutdown");
-            return;
-        }
-
-        // Await the response
-        let res: Result<
-            Result<Option<Bytes>, Box<dyn Error + Send + Sync>>,
-            oneshot::error::RecvError,
-        > = resp_rx.await;
-        println!("GOT (Get) = {:?}", res);
-    });
-
-    let t2 = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let cmd = Command::Set {
-            key: "foo".to_string(),
-            val: "bar".into(),
-            resp: resp_tx,
-        };
-
-        // Send the SET request
-        if tx2.send(cmd).await.is_err() {
-            eprintln!("connection task shutdown");
-            return;
-        }
-
-        // Await the response
-        let res: Result<Result<(), Box<dyn Error + Send + Sync>>, oneshot::error::RecvError> =
-            resp_rx.await;
-        println!("GOT (Set) = {:?}", res);
-    });
-
-    t1.await.unwrap();
-    t2.await.unwrap();
-    manager.await.unwrap();
+async fn main() -> Result<(), Box<dyn Error>> {
+    // 客户端内部维护连接池，克隆后可以在多个任务间共享
+    let client: Client = Client::connect("127.0.0.1:6379").await?;
+
+    // 两个任务并发地发送请求，同一个连接上的请求会被流水线化
+    let setter: Client = client.clone();
+    let t1 = tokio::spawn(async move { setter.set("foo", "bar").await });
+    let getter: Client = client.clone();
+    let t2 = tokio::spawn(async move { getter.get("foo").await });
+
+    println!("GOT (Set) = {:?}", t1.await?);
+    println!("GOT (Get) = {:?}", t2.await?);
+    Ok(())
 }
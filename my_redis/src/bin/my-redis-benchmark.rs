@@ -0,0 +1,710 @@
+//! 压测工具，用法与 redis-benchmark 相近
+//!
+//! 开 N 个连接并发地发送命令，每个连接一次写出 P 条（流水线深度），等齐这一批的回复再写下一批。
+//! 每条命令的延迟是从这一批写出到收到它的回复的时间，记在按对数分段、段内线性分桶的直方图里
+//! （HDR 直方图的做法），最后报告每秒请求数和 p50/p99/p999 延迟。
+
+use bytes::BytesMut;
+use my_redis::client::{self, ClientConfig};
+use my_redis::evict::random;
+use my_redis::frame::{Decoder, Frame, Protocol};
+use my_redis::net::Stream;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+
+const USAGE: &str = "\
+Usage: my-redis-benchmark [OPTIONS] [cmd [arg [arg ...]]]
+  -h <hostname>      Server hostname (default: 127.0.0.1).
+  -p <port>          Server port (default: 6379).
+  -s <socket>        Server socket (overrides hostname and port).
+  -a <password>      Password to use when connecting to the server.
+  --user <username>  Used to send ACL style 'AUTH username pass'. Needs -a.
+  -c <clients>       Number of parallel connections (default: 50).
+  -n <requests>      Total number of requests per test (default: 100000).
+  -d <size>          Data size of SET/GET value in bytes (default: 3).
+  -r <keyspacelen>   Replace __rand_int__ in the arguments with a random
+                     number from 0 to keyspacelen-1. Without it the keys
+                     are used as they are.
+  -P <numreq>        Pipeline <numreq> requests (default: 1, no pipeline).
+  -t <tests>         Only run the comma separated list of tests.
+  --mix <mix>        Run a single test that picks each request from the
+                     given tests by weight, e.g. get:9,set:1.
+  -q                 Quiet. Just show requests/sec and latency percentiles.
+  --help             Output this help and exit.
+
+Tests: ping, set, get, incr, lpush, rpush, lpop, rpop, sadd, hset, spop,
+zadd, lrange_100, mset.
+
+Examples:
+  my-redis-benchmark -c 100 -n 200000 -P 16 -t set,get
+  my-redis-benchmark --mix get:9,set:1 -r 100000
+  my-redis-benchmark -n 10000 LPUSH mylist __rand_int__";
+
+/// 不指定 `-t` 时依次运行的测试
+const DEFAULT_TESTS: &[&str] = &[
+    "ping",
+    "set",
+    "get",
+    "incr",
+    "lpush",
+    "rpush",
+    "lpop",
+    "rpop",
+    "sadd",
+    "hset",
+    "spop",
+    "zadd",
+    "lrange_100",
+    "mset",
+];
+
+/// 参数中被替换成随机数的占位符，随机数补零到同样的长度，编码好的命令长度不变
+const RAND_INT: &[u8] = b"__rand_int__";
+
+/// 参数中被替换成 `-d` 字节数据的占位符
+const DATA: &str = "__data__";
+
+/// 命令行参数
+struct Options {
+    host: String,
+    port: u16,
+    socket: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    clients: usize,
+    requests: usize,
+    data_size: usize,
+    keyspace: u64,
+    pipeline: usize,
+    tests: Vec<String>,
+    mix: Option<Vec<(String, u32)>>,
+    quiet: bool,
+    command: Vec<String>,
+}
+
+/// 一次测试要发送的命令，每条请求按权重从中挑一条
+#[derive(Debug)]
+struct Workload {
+    name: String,
+    templates: Vec<Template>,
+    total_weight: u64,
+}
+
+/// 编码好的命令，发送前只需要改写占位符处的数字
+#[derive(Debug)]
+struct Template {
+    data: Vec<u8>,
+    /// `__rand_int__` 在 `data` 中的位置
+    rand_offsets: Vec<usize>,
+    weight: u32,
+}
+
+/// 一次测试的结果
+#[derive(Debug)]
+struct Report {
+    elapsed: Duration,
+    latency: Histogram,
+    errors: usize,
+    /// 第一条错误回复
+    first_error: Option<String>,
+}
+
+/// 延迟直方图（微秒）。小于 `SUB_BUCKETS` 的值各占一个桶；更大的值按二进制位数分段，每段分成
+/// `SUB_BUCKETS / 2` 个等宽的桶，相对误差不超过 1/64，而桶的总数只有几千个
+#[derive(Debug, Clone)]
+struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+/// 每段的桶数取 2 的这个次幂
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const HALF_BUCKETS: u64 = SUB_BUCKETS / 2;
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            counts: vec![0; bucket(u64::MAX) + 1],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    fn record(&mut self, value: u64) {
+        self.counts[bucket(value)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    fn min(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    /// 至少 `percentile`% 的值不超过的数，取所在桶的上界，但不超过记录到的最大值
+    fn percentile(&self, percentile: f64) -> u64 {
+        let target: u64 = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen: u64 = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return upper_bound(idx).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// 值所在的桶
+fn bucket(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let shift: u32 = u64::BITS - value.leading_zeros() - SUB_BUCKET_BITS;
+    (u64::from(shift) * HALF_BUCKETS + (value >> shift)) as usize
+}
+
+/// 桶里最小的值
+fn lower_bound(idx: usize) -> u64 {
+    let idx: u64 = idx as u64;
+    if idx < SUB_BUCKETS {
+        return idx;
+    }
+    let shift: u64 = idx / HALF_BUCKETS - 1;
+    (idx % HALF_BUCKETS + HALF_BUCKETS) << shift
+}
+
+/// 桶里最大的值
+fn upper_bound(idx: usize) -> u64 {
+    if idx >= bucket(u64::MAX) {
+        return u64::MAX;
+    }
+    lower_bound(idx + 1) - 1
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options: Options = match parse_options(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let workloads: Vec<Workload> = match workloads(&options) {
+        Ok(workloads) => workloads,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let config: ClientConfig = ClientConfig {
+        addr: match &options.socket {
+            Some(path) => format!("unix://{path}"),
+            None => format!("{}:{}", options.host, options.port),
+        },
+        username: options.user.clone(),
+        password: options.password.clone(),
+        ..ClientConfig::default()
+    };
+
+    for workload in workloads {
+        let workload: Arc<Workload> = Arc::new(workload);
+        let report: Report = match run(&config, &options, workload.clone()).await {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("{}: {err}", workload.name);
+                return ExitCode::FAILURE;
+            }
+        };
+        print_report(&workload.name, &options, &report);
+    }
+    ExitCode::SUCCESS
+}
+
+/// 解析命令行参数，`--help` 时返回 `None`。第一个不是选项的参数开始是要测试的命令
+fn parse_options(args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options: Options = Options {
+        host: "127.0.0.1".to_string(),
+        port: my_redis::DEFAULT_PORT,
+        socket: None,
+        user: None,
+        password: None,
+        clients: 50,
+        requests: 100_000,
+        data_size: 3,
+        keyspace: 0,
+        pipeline: 1,
+        tests: Vec::new(),
+        mix: None,
+        quiet: false,
+        command: Vec::new(),
+    };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with('-')) {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "-h" => options.host = value()?,
+            "-p" => options.port = number(&arg, &value()?)?,
+            "-s" => options.socket = Some(value()?),
+            "-a" => options.password = Some(value()?),
+            "--user" => options.user = Some(value()?),
+            "-c" => options.clients = number(&arg, &value()?)?,
+            "-n" => options.requests = number(&arg, &value()?)?,
+            "-d" => options.data_size = number(&arg, &value()?)?,
+            "-r" => options.keyspace = number(&arg, &value()?)?,
+            "-P" => options.pipeline = number(&arg, &value()?)?,
+            "-t" => {
+                options.tests = value()?
+                    .split(',')
+                    .map(|test| test.trim().to_ascii_lowercase())
+                    .filter(|test| !test.is_empty())
+                    .collect()
+            }
+            "--mix" => options.mix = Some(parse_mix(&value()?)?),
+            "-q" => options.quiet = true,
+            "--help" => return Ok(None),
+            _ => return Err(format!("unrecognized option: {arg}")),
+        }
+    }
+    if options.user.is_some() && options.password.is_none() {
+        return Err("--user requires a password given with -a".to_string());
+    }
+    if options.clients == 0 || options.pipeline == 0 {
+        return Err("-c and -P must be at least 1".to_string());
+    }
+    options.command = args.collect();
+    if !options.command.is_empty() && (options.mix.is_some() || !options.tests.is_empty()) {
+        return Err("a command can't be combined with -t or --mix".to_string());
+    }
+    Ok(Some(options))
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {option}: {value}"))
+}
+
+/// 解析 `--mix`：逗号分隔的 `测试名:权重`，省略权重时为 1
+fn parse_mix(value: &str) -> Result<Vec<(String, u32)>, String> {
+    let mix: Vec<(String, u32)> = value
+        .split(',')
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| {
+            let (name, weight) = part.split_once(':').unwrap_or((part, "1"));
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .ok()
+                .filter(|weight| *weight > 0)
+                .ok_or_else(|| format!("invalid weight in --mix: {part}"))?;
+            Ok((name.trim().to_ascii_lowercase(), weight))
+        })
+        .collect::<Result<_, String>>()?;
+    if mix.is_empty() {
+        return Err("--mix needs at least one test".to_string());
+    }
+    Ok(mix)
+}
+
+/// 按参数决定要运行的测试：命令行上的命令、`--mix` 组合成的一个测试，或者 `-t` 列出的测试
+fn workloads(options: &Options) -> Result<Vec<Workload>, String> {
+    let data: String = "x".repeat(options.data_size);
+    if !options.command.is_empty() {
+        let args: Vec<String> = options
+            .command
+            .iter()
+            .map(|arg| arg.replace(DATA, &data))
+            .collect();
+        return Ok(vec![Workload::new(
+            options.command.join(" "),
+            vec![(args, 1)],
+        )]);
+    }
+    if let Some(mix) = &options.mix {
+        let commands: Vec<(Vec<String>, u32)> = mix
+            .iter()
+            .map(|(name, weight)| Ok((test_args(name, &data)?, *weight)))
+            .collect::<Result<_, String>>()?;
+        let name: String = mix
+            .iter()
+            .map(|(name, weight)| format!("{}:{weight}", name.to_ascii_uppercase()))
+            .collect::<Vec<String>>()
+            .join(",");
+        return Ok(vec![Workload::new(format!("MIX {name}"), commands)]);
+    }
+    let tests: Vec<String> = if options.tests.is_empty() {
+        DEFAULT_TESTS.iter().map(|test| test.to_string()).collect()
+    } else {
+        options.tests.clone()
+    };
+    tests
+        .iter()
+        .map(|name| {
+            Ok(Workload::new(
+                name.to_ascii_uppercase(),
+                vec![(test_args(name, &data)?, 1)],
+            ))
+        })
+        .collect()
+}
+
+/// 内置测试的命令
+fn test_args(name: &str, data: &str) -> Result<Vec<String>, String> {
+    let template: &str = match name {
+        "ping" => "PING",
+        "set" => "SET key:__rand_int__ __data__",
+        "get" => "GET key:__rand_int__",
+        "incr" => "INCR counter:__rand_int__",
+        "lpush" => "LPUSH mylist __data__",
+        "rpush" => "RPUSH mylist __data__",
+        "lpop" => "LPOP mylist",
+        "rpop" => "RPOP mylist",
+        "sadd" => "SADD myset element:__rand_int__",
+        "hset" => "HSET myhash element:__rand_int__ __data__",
+        "spop" => "SPOP myset",
+        "zadd" => "ZADD myzset 0 element:__rand_int__",
+        "lrange_100" => "LRANGE mylist 0 99",
+        "mset" => &format!("MSET{}", " key:__rand_int__ __data__".repeat(10)),
+        _ => return Err(format!("unknown test: {name}")),
+    };
+    Ok(template
+        .split(' ')
+        .map(|arg| arg.replace(DATA, data))
+        .collect())
+}
+
+impl Workload {
+    fn new(name: String, commands: Vec<(Vec<String>, u32)>) -> Workload {
+        let templates: Vec<Template> = commands
+            .into_iter()
+            .map(|(args, weight)| Template::new(&args, weight))
+            .collect();
+        let total_weight: u64 = templates.iter().map(|t| u64::from(t.weight)).sum();
+        Workload {
+            name,
+            templates,
+            total_weight,
+        }
+    }
+
+    /// 挑一条命令编码到 `buf`，`keyspace` 不为 0 时替换其中的随机数占位符
+    fn encode(&self, buf: &mut BytesMut, keyspace: u64) {
+        let template: &Template = if self.templates.len() == 1 {
+            &self.templates[0]
+        } else {
+            let mut pick: u64 = random() % self.total_weight;
+            self.templates
+                .iter()
+                .find(|t| {
+                    let hit: bool = pick < u64::from(t.weight);
+                    pick = pick.saturating_sub(u64::from(t.weight));
+                    hit
+                })
+                .expect("weights add up to total_weight")
+        };
+        let start: usize = buf.len();
+        buf.extend_from_slice(&template.data);
+        if keyspace == 0 {
+            return;
+        }
+        for offset in &template.rand_offsets {
+            let digits: String = format!("{:012}", random() % keyspace);
+            let at: usize = start + offset;
+            buf[at..at + RAND_INT.len()].copy_from_slice(&digits.as_bytes()[..RAND_INT.len()]);
+        }
+    }
+}
+
+impl Template {
+    fn new(args: &[String], weight: u32) -> Template {
+        let mut data: BytesMut = BytesMut::new();
+        let frame: Frame = Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(arg.clone().into()))
+                .collect(),
+        );
+        frame.encode(Protocol::Resp2, &mut data);
+        let rand_offsets: Vec<usize> = data
+            .windows(RAND_INT.len())
+            .enumerate()
+            .filter(|(_, window)| *window == RAND_INT)
+            .map(|(offset, _)| offset)
+            .collect();
+        Template {
+            data: data.to_vec(),
+            rand_offsets,
+            weight,
+        }
+    }
+}
+
+/// 运行一次测试：先建立全部连接，再同时开始发送
+async fn run(
+    config: &ClientConfig,
+    options: &Options,
+    workload: Arc<Workload>,
+) -> Result<Report, String> {
+    let mut connecting: JoinSet<client::Result<Stream>> = JoinSet::new();
+    for _ in 0..options.clients {
+        let config: ClientConfig = config.clone();
+        connecting.spawn(async move { client::open(&config).await });
+    }
+    let mut streams: Vec<Stream> = Vec::with_capacity(options.clients);
+    while let Some(stream) = connecting.join_next().await {
+        let stream: Stream = stream
+            .map_err(|err| err.to_string())?
+            .map_err(|err| format!("could not connect to my_redis at {}: {err}", config.addr))?;
+        streams.push(stream);
+    }
+
+    let remaining: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(options.requests));
+    let start: Instant = Instant::now();
+    let mut workers: JoinSet<Result<Report, String>> = JoinSet::new();
+    for stream in streams {
+        workers.spawn(worker(
+            stream,
+            workload.clone(),
+            remaining.clone(),
+            options.pipeline,
+            options.keyspace,
+        ));
+    }
+    let mut total: Report = Report {
+        elapsed: Duration::ZERO,
+        latency: Histogram::new(),
+        errors: 0,
+        first_error: None,
+    };
+    while let Some(report) = workers.join_next().await {
+        let report: Report = report.map_err(|err| err.to_string())??;
+        total.latency.merge(&report.latency);
+        total.errors += report.errors;
+        total.first_error = total.first_error.or(report.first_error);
+    }
+    total.elapsed = start.elapsed();
+    Ok(total)
+}
+
+/// 从共享的计数里领取最多 `pipeline` 条请求，领完时返回 0
+fn take(remaining: &AtomicUsize, pipeline: usize) -> usize {
+    remaining
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+            (left > 0).then(|| left - left.min(pipeline))
+        })
+        .map_or(0, |left| left.min(pipeline))
+}
+
+/// 一个连接上的发送循环，直到所有请求都被领完
+async fn worker(
+    mut stream: Stream,
+    workload: Arc<Workload>,
+    remaining: Arc<AtomicUsize>,
+    pipeline: usize,
+    keyspace: u64,
+) -> Result<Report, String> {
+    let mut report: Report = Report {
+        elapsed: Duration::ZERO,
+        latency: Histogram::new(),
+        errors: 0,
+        first_error: None,
+    };
+    let mut out: BytesMut = BytesMut::new();
+    let mut buf: BytesMut = BytesMut::with_capacity(16 * 1024);
+    let mut decoder: Decoder = Decoder::default();
+    loop {
+        let batch: usize = take(&remaining, pipeline);
+        if batch == 0 {
+            return Ok(report);
+        }
+        out.clear();
+        for _ in 0..batch {
+            workload.encode(&mut out, keyspace);
+        }
+        let sent: Instant = Instant::now();
+        stream
+            .write_all(&out)
+            .await
+            .map_err(|err| format!("error writing to the server: {err}"))?;
+        let mut replies: usize = 0;
+        while replies < batch {
+            match decoder.decode(&mut buf).map_err(|err| err.to_string())? {
+                Some(frame) => {
+                    replies += 1;
+                    report.latency.record(sent.elapsed().as_micros() as u64);
+                    if let Frame::Error(err) = frame {
+                        report.errors += 1;
+                        report.first_error.get_or_insert(err);
+                    }
+                }
+                None => match stream.read_buf(&mut buf).await {
+                    Ok(0) => return Err("connection closed by the server".to_string()),
+                    Ok(_) => {}
+                    Err(err) => return Err(format!("error reading from the server: {err}")),
+                },
+            }
+        }
+    }
+}
+
+fn print_report(name: &str, options: &Options, report: &Report) {
+    let rps: f64 = report.latency.count as f64 / report.elapsed.as_secs_f64().max(1e-9);
+    let ms = |us: u64| us as f64 / 1000.0;
+    let latency: &Histogram = &report.latency;
+    if options.quiet {
+        println!(
+            "{name}: {rps:.2} requests per second, p50={:.3} p99={:.3} p999={:.3} msec",
+            ms(latency.percentile(50.0)),
+            ms(latency.percentile(99.0)),
+            ms(latency.percentile(99.9)),
+        );
+    } else {
+        println!("====== {name} ======");
+        println!(
+            "  {} requests completed in {:.2} seconds",
+            latency.count,
+            report.elapsed.as_secs_f64()
+        );
+        println!("  {} parallel clients", options.clients);
+        println!("  {} bytes payload", options.data_size);
+        println!("  pipeline depth {}", options.pipeline);
+        println!();
+        println!("Summary:");
+        println!("  throughput summary: {rps:.2} requests per second");
+        println!("  latency summary (msec):");
+        println!(
+            "  {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "avg", "min", "p50", "p99", "p999", "max"
+        );
+        println!(
+            "  {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+            latency.mean() / 1000.0,
+            ms(latency.min()),
+            ms(latency.percentile(50.0)),
+            ms(latency.percentile(99.0)),
+            ms(latency.percentile(99.9)),
+            ms(latency.max),
+        );
+    }
+    if report.errors > 0 {
+        println!(
+            "  {} error replies, first: {}",
+            report.errors,
+            report.first_error.as_deref().unwrap_or("")
+        );
+    }
+    if !options.quiet {
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bucket, lower_bound, parse_mix, take, test_args, Histogram, Workload};
+    use bytes::BytesMut;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn histogram_buckets_and_percentiles() {
+        let mut last: usize = 0;
+        for value in [0, 1, 127, 128, 129, 255, 256, 1000, 1 << 40, u64::MAX] {
+            let idx: usize = bucket(value);
+            assert!(idx >= last);
+            assert!(lower_bound(idx) <= value);
+            // 相对误差不超过 1/64
+            assert!(value - lower_bound(idx) <= value / 64);
+            last = idx;
+        }
+
+        let mut latency: Histogram = Histogram::new();
+        for value in 1..=10_000 {
+            latency.record(value);
+        }
+        let close = |actual: u64, expected: u64| actual.abs_diff(expected) <= expected / 64;
+        assert!(close(latency.percentile(50.0), 5000));
+        assert!(close(latency.percentile(99.0), 9900));
+        assert!(close(latency.percentile(99.9), 9990));
+        assert_eq!(latency.percentile(100.0), 10_000);
+        assert_eq!(latency.min(), 1);
+        assert_eq!(latency.mean(), 5000.5);
+
+        let mut other: Histogram = Histogram::new();
+        other.record(50);
+        latency.merge(&other);
+        assert_eq!(latency.count, 10_001);
+        assert_eq!(Histogram::new().percentile(99.0), 0);
+    }
+
+    #[test]
+    fn workloads_and_options() {
+        assert_eq!(
+            parse_mix("get:9, set").unwrap(),
+            [("get".to_string(), 9), ("set".to_string(), 1)]
+        );
+        assert!(parse_mix("get:0").is_err());
+        assert!(parse_mix("").is_err());
+        assert_eq!(test_args("mset", "x").unwrap().len(), 21);
+        assert!(test_args("nope", "x").is_err());
+
+        let workload: Workload = Workload::new(
+            "SET".to_string(),
+            vec![(test_args("set", "ab").unwrap(), 1)],
+        );
+        let mut buf: BytesMut = BytesMut::new();
+        workload.encode(&mut buf, 0);
+        assert_eq!(
+            &buf[..],
+            b"*3\r\n$3\r\nSET\r\n$16\r\nkey:__rand_int__\r\n$2\r\nab\r\n"
+        );
+        buf.clear();
+        workload.encode(&mut buf, 10);
+        assert!(buf.starts_with(b"*3\r\n$3\r\nSET\r\n$16\r\nkey:00000000000"));
+        assert!(!buf.windows(12).any(|w| w == b"__rand_int__"));
+
+        let remaining: AtomicUsize = AtomicUsize::new(5);
+        assert_eq!(take(&remaining, 2), 2);
+        assert_eq!(take(&remaining, 2), 2);
+        assert_eq!(take(&remaining, 2), 1);
+        assert_eq!(take(&remaining, 2), 0);
+    }
+}
@@ -0,0 +1,511 @@
+//! 命令行客户端
+//!
+//! 不带命令时进入交互模式，带命令时执行一次后退出，`--pipe` 从标准输入批量导入命令。
+
+use bytes::{Bytes, BytesMut};
+use my_redis::client::{self, Client, ClientConfig, Cmd, ToArg};
+use my_redis::config::split_args;
+use my_redis::frame::{format_double, Frame, Protocol};
+use my_redis::net::Stream;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Runtime;
+
+const USAGE: &str = "\
+Usage: my-redis-cli [OPTIONS] [cmd [arg [arg ...]]]
+  -h <hostname>      Server hostname (default: 127.0.0.1).
+  -p <port>          Server port (default: 6379).
+  -s <socket>        Server socket (overrides hostname and port).
+  -a <password>      Password to use when connecting to the server.
+  --user <username>  Used to send ACL style 'AUTH username pass'. Needs -a.
+  --tls              Establish a secure TLS connection (needs the tls feature).
+  --cacert <file>    CA Certificate file to verify with.
+  --cert <file>      Client certificate to authenticate with.
+  --key <file>       Private key file to authenticate with.
+  --raw              Use raw formatting for replies (default when STDOUT is
+                     not a tty).
+  --no-raw           Force formatted output even when STDOUT is not a tty.
+  --pipe             Transfer raw protocol or inline commands from stdin to
+                     the server.
+  --help             Output this help and exit.
+
+Examples:
+  my-redis-cli
+  my-redis-cli -p 6380 GET foo
+  cat commands.txt | my-redis-cli --pipe";
+
+/// 命令行参数
+struct Options {
+    host: String,
+    port: u16,
+    socket: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    tls: bool,
+    cacert: Option<PathBuf>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    raw: bool,
+    pipe: bool,
+    command: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let options: Options = match parse_options(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let runtime: Runtime = Runtime::new().expect("failed to start the runtime");
+    let addr: String = match &options.socket {
+        Some(path) => format!("unix://{path}"),
+        None => format!("{}:{}", options.host, options.port),
+    };
+    let config: ClientConfig = match client_config(&options, &addr) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if options.pipe {
+        return runtime.block_on(pipe(&config));
+    }
+    let client: Client = match runtime.block_on(Client::with_config(config)) {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("Could not connect to my_redis at {addr}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if options.command.is_empty() {
+        repl(&runtime, &client, &addr, options.raw);
+        return ExitCode::SUCCESS;
+    }
+    match runtime.block_on(send(&client, &options.command)) {
+        Ok(frame) => {
+            let failed: bool = matches!(frame, Frame::Error(_));
+            println!("{}", format_reply(&frame, options.raw));
+            if failed {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// 解析命令行参数，`--help` 时返回 `None`。第一个不是选项的参数开始是要执行的命令
+fn parse_options(args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options: Options = Options {
+        host: "127.0.0.1".to_string(),
+        port: my_redis::DEFAULT_PORT,
+        socket: None,
+        user: None,
+        password: None,
+        tls: false,
+        cacert: None,
+        cert: None,
+        key: None,
+        raw: !io::stdout().is_terminal(),
+        pipe: false,
+        command: Vec::new(),
+    };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with('-')) {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "-h" => options.host = value()?,
+            "-p" => {
+                let port: String = value()?;
+                options.port = port.parse().map_err(|_| format!("invalid port: {port}"))?;
+            }
+            "-s" => options.socket = Some(value()?),
+            "-a" => options.password = Some(value()?),
+            "--user" => options.user = Some(value()?),
+            "--tls" => options.tls = true,
+            "--cacert" => options.cacert = Some(PathBuf::from(value()?)),
+            "--cert" => options.cert = Some(PathBuf::from(value()?)),
+            "--key" => options.key = Some(PathBuf::from(value()?)),
+            "--raw" => options.raw = true,
+            "--no-raw" => options.raw = false,
+            "--pipe" => options.pipe = true,
+            "--help" => return Ok(None),
+            _ => return Err(format!("unrecognized option: {arg}")),
+        }
+    }
+    if options.user.is_some() && options.password.is_none() {
+        return Err("--user requires a password given with -a".to_string());
+    }
+    if options.cert.is_some() != options.key.is_some() {
+        return Err("--cert and --key must be given together".to_string());
+    }
+    options.command = args.collect();
+    Ok(Some(options))
+}
+
+/// 按命令行参数构建客户端配置
+fn client_config(options: &Options, addr: &str) -> Result<ClientConfig, String> {
+    let config: ClientConfig = ClientConfig {
+        addr: addr.to_string(),
+        pool_size: 1,
+        // 阻塞命令可能等很久，交给用户用 Ctrl-C 打断
+        timeout: None,
+        username: options.user.clone(),
+        password: options.password.clone(),
+        ..ClientConfig::default()
+    };
+    if !options.tls {
+        return Ok(config);
+    }
+    #[cfg(feature = "tls")]
+    {
+        let cacert: &std::path::Path =
+            options.cacert.as_deref().ok_or("--tls requires --cacert")?;
+        let identity = options.cert.as_deref().zip(options.key.as_deref());
+        Ok(ClientConfig {
+            tls: Some(my_redis::tls::client_config(cacert, identity)?),
+            ..config
+        })
+    }
+    #[cfg(not(feature = "tls"))]
+    Err("TLS support was not compiled in, rebuild with --features tls".to_string())
+}
+
+/// 交互模式：逐行读取命令并打印回复，`quit` 或 Ctrl-D 退出
+fn repl(runtime: &Runtime, client: &Client, addr: &str, raw: bool) {
+    let mut editor: DefaultEditor =
+        DefaultEditor::new().expect("failed to initialize the terminal");
+    let history: Option<PathBuf> = history_file();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+    let prompt: String = format!("{addr}> ");
+    loop {
+        let line: String = match editor.readline(&prompt) {
+            Ok(line) => line,
+            // Ctrl-C 丢弃当前输入，Ctrl-D 退出
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{err}");
+                break;
+            }
+        };
+        let args: Vec<Vec<u8>> = match split_args(&line) {
+            Ok(args) => args,
+            Err(_) => {
+                println!("Invalid argument(s)");
+                continue;
+            }
+        };
+        if args.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+        if args[0].eq_ignore_ascii_case(b"quit") || args[0].eq_ignore_ascii_case(b"exit") {
+            break;
+        }
+        match runtime.block_on(send(client, &args)) {
+            Ok(frame) => println!("{}", format_reply(&frame, raw)),
+            Err(err) => println!("(error) {err}"),
+        }
+    }
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+}
+
+/// 历史记录文件，可以用 `MY_REDIS_CLI_HISTFILE` 指定
+fn history_file() -> Option<PathBuf> {
+    match std::env::var_os("MY_REDIS_CLI_HISTFILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".my_redis_cli_history"))
+        }
+    }
+}
+
+/// 发送一条命令，服务端的错误回复作为 `Frame::Error` 返回，方便统一打印
+async fn send<T: ToArg>(client: &Client, args: &[T]) -> client::Result<Frame> {
+    let cmd: Cmd = client::cmd(&args[0]).args(&args[1..]);
+    match client.request(cmd).await {
+        Err(client::Error::Server(err)) => Ok(Frame::Error(err)),
+        result => result,
+    }
+}
+
+/// `--pipe` 模式：标准输入是 RESP 格式时原样发送，否则按行拆成命令，最后统计回复和错误数
+async fn pipe(config: &ClientConfig) -> ExitCode {
+    let mut input: Vec<u8> = Vec::new();
+    if let Err(err) = io::stdin().read_to_end(&mut input) {
+        eprintln!("Error reading from stdin: {err}");
+        return ExitCode::FAILURE;
+    }
+    let (data, count): (Vec<u8>, usize) = match encode_input(&input) {
+        Ok(encoded) => encoded,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let stream: Stream = match client::open(config).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("Could not connect to my_redis at {}: {err}", config.addr);
+            return ExitCode::FAILURE;
+        }
+    };
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    // 边写边读，避免回复塞满缓冲区后双方互相等待
+    let write = tokio::spawn(async move { writer.write_all(&data).await.map(|()| writer) });
+
+    let mut buf: BytesMut = BytesMut::new();
+    let (mut replies, mut errors): (usize, usize) = (0, 0);
+    while replies < count {
+        match Frame::parse(&mut buf) {
+            Ok(Some(frame)) => {
+                replies += 1;
+                if let Frame::Error(err) = frame {
+                    errors += 1;
+                    eprintln!("{err}");
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                eprintln!("{err}");
+                return ExitCode::FAILURE;
+            }
+        }
+        match reader.read_buf(&mut buf).await {
+            Ok(0) => {
+                eprintln!("Connection closed by the server after {replies} replies");
+                return ExitCode::FAILURE;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("Error reading from the server: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Ok(Err(err)) = write.await {
+        eprintln!("Error writing to the server: {err}");
+        return ExitCode::FAILURE;
+    }
+    println!("All data transferred. errors: {errors}, replies: {replies}");
+    if errors == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// 把 `--pipe` 的输入转成要发送的字节和命令数
+fn encode_input(input: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    if input.first() == Some(&b'*') {
+        let mut pos: usize = 0;
+        let mut count: usize = 0;
+        while pos < input.len() {
+            match Frame::peek(&input[pos..]) {
+                Ok(Some((_, len))) => {
+                    pos += len;
+                    count += 1;
+                }
+                Ok(None) => return Err("incomplete command at the end of input".to_string()),
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        return Ok((input.to_vec(), count));
+    }
+    let text: &str = std::str::from_utf8(input).map_err(|_| "input is not valid UTF-8")?;
+    let mut data: BytesMut = BytesMut::new();
+    let mut count: usize = 0;
+    for (number, line) in text.lines().enumerate() {
+        let args: Vec<Vec<u8>> =
+            split_args(line).map_err(|err| format!("line {}: {err}", number + 1))?;
+        if args.is_empty() {
+            continue;
+        }
+        let frame: Frame = Frame::Array(
+            args.into_iter()
+                .map(|arg| Frame::Bulk(Bytes::from(arg)))
+                .collect(),
+        );
+        frame.encode(Protocol::Resp2, &mut data);
+        count += 1;
+    }
+    Ok((data.to_vec(), count))
+}
+
+/// 按输出模式格式化回复
+fn format_reply(frame: &Frame, raw: bool) -> String {
+    if raw {
+        format_raw(frame)
+    } else {
+        format_pretty(frame)
+    }
+}
+
+/// 终端上的格式：标注类型，字符串加引号，嵌套的集合逐层编号缩进
+fn format_pretty(frame: &Frame) -> String {
+    match frame {
+        Frame::Simple(s) => s.clone(),
+        Frame::Error(err) => format!("(error) {err}"),
+        Frame::Integer(n) => format!("(integer) {n}"),
+        Frame::Double(d) => format!("(double) {}", format_double(*d)),
+        Frame::Boolean(b) => format!("({b})"),
+        Frame::Bulk(b) => quote(b),
+        Frame::Null => "(nil)".to_string(),
+        Frame::Array(items) | Frame::Push(items) => numbered(
+            items.iter().map(format_pretty).collect(),
+            ')',
+            "(empty array)",
+        ),
+        Frame::Set(items) => numbered(
+            items.iter().map(format_pretty).collect(),
+            '~',
+            "(empty set)",
+        ),
+        Frame::Map(pairs) => numbered(
+            pairs
+                .iter()
+                .map(|(k, v)| format!("{} => {}", format_pretty(k), format_pretty(v)))
+                .collect(),
+            '#',
+            "(empty hash)",
+        ),
+    }
+}
+
+/// 给每一项加上对齐的序号，多行的项后续行缩进到序号之后
+fn numbered(entries: Vec<String>, mark: char, empty: &str) -> String {
+    if entries.is_empty() {
+        return empty.to_string();
+    }
+    let width: usize = entries.len().to_string().len();
+    let mut out: String = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let prefix: String = format!("{:>width$}{mark} ", i + 1);
+        for (j, line) in entry.lines().enumerate() {
+            if i > 0 || j > 0 {
+                out.push('\n');
+            }
+            if j == 0 {
+                out.push_str(&prefix);
+            } else {
+                out.push_str(&" ".repeat(prefix.len()));
+            }
+            out.push_str(line);
+        }
+    }
+    out
+}
+
+/// 用双引号括起字符串，不可打印的字节转义
+fn quote(bytes: &[u8]) -> String {
+    let mut out: String = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => out.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// 不是终端时的格式：只输出内容本身，集合每项一行，方便脚本处理
+fn format_raw(frame: &Frame) -> String {
+    match frame {
+        Frame::Simple(s) | Frame::Error(s) => s.clone(),
+        Frame::Integer(n) => n.to_string(),
+        Frame::Double(d) => format_double(*d),
+        Frame::Boolean(b) => b.to_string(),
+        Frame::Bulk(b) => String::from_utf8_lossy(b).into_owned(),
+        Frame::Null => String::new(),
+        Frame::Array(items) | Frame::Set(items) | Frame::Push(items) => items
+            .iter()
+            .map(format_raw)
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Frame::Map(pairs) => pairs
+            .iter()
+            .map(|(k, v)| format!("{}\n{}", format_raw(k), format_raw(v)))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_input, format_pretty, format_raw};
+    use bytes::Bytes;
+    use my_redis::frame::Frame;
+
+    fn bulk(s: &str) -> Frame {
+        Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    #[test]
+    fn pretty_prints_nested_replies() {
+        assert_eq!(format_pretty(&bulk("a\"b\n\x01")), r#""a\"b\n\x01""#);
+        assert_eq!(format_pretty(&Frame::Array(vec![])), "(empty array)");
+        let nested: Frame = Frame::Array(vec![
+            bulk("master"),
+            Frame::Integer(42),
+            Frame::Array(vec![Frame::Array(vec![bulk("127.0.0.1"), bulk("6380")])]),
+        ]);
+        assert_eq!(
+            format_pretty(&nested),
+            "1) \"master\"\n2) (integer) 42\n3) 1) 1) \"127.0.0.1\"\n      2) \"6380\""
+        );
+        let long: Frame = Frame::Array((0..10).map(Frame::Integer).collect());
+        assert!(format_pretty(&long).starts_with(" 1) (integer) 0\n 2)"));
+        let map: Frame = Frame::Map(vec![(bulk("k"), bulk("v"))]);
+        assert_eq!(format_pretty(&map), "1# \"k\" => \"v\"");
+        assert_eq!(
+            format_raw(&Frame::Array(vec![bulk("a"), Frame::Integer(1)])),
+            "a\n1"
+        );
+    }
+
+    #[test]
+    fn pipe_input_accepts_inline_and_resp() {
+        let (data, count) = encode_input(b"SET k \"a b\"\n\nINCR n\n").unwrap();
+        assert_eq!(count, 2);
+        assert!(data.starts_with(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$3\r\na b\r\n"));
+        let raw: &[u8] = b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n";
+        assert_eq!(encode_input(raw).unwrap(), (raw.to_vec(), 2));
+        assert!(encode_input(b"*1\r\n$4\r\nPI").is_err());
+        let (data, _) = encode_input(b"SET k \"\\xff\\x80\"\n").unwrap();
+        assert!(data.ends_with(b"$2\r\n\xff\x80\r\n"));
+    }
+}
@@ -1,66 +1,172 @@
-use mini_redis::Command::{self, Get, Set};
-use mini_redis::{Connection, Frame};
-use std::collections::HashMap;
-use tokio::net::{TcpListener, TcpStream};
+use my_redis::aof::{self, Aof};
+use my_redis::config::Config;
+use my_redis::log;
+use my_redis::log::Level;
+use my_redis::metrics;
+use my_redis::net::Incoming;
+use my_redis::rdb;
+use my_redis::server;
+use my_redis::state::State;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
 
-use bytes::Bytes;
-use std::sync::{Arc, Mutex};
+const USAGE: &str = "\
+Usage: server [/path/to/redis.conf] [--option value ...]
+  Options are the same as in the config file, e.g. --port 6380 --loglevel verbose.
+  Command line options override the ones read from the config file.
+  -h, --help         Output this help and exit.
 
-type Db = Arc<Mutex<HashMap<String, Bytes>>>;
+Examples:
+  server
+  server redis.conf
+  server --port 7777 --appendonly yes";
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{USAGE}");
+        return;
+    }
+    let config: Config = Config::build(args.into_iter()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
 
-    println!("Listening");
+    log::set_level(config.loglevel);
+    let state: Arc<State> = State::new(config);
+    // 开启 AOF 时以日志为准，否则加载快照；文件损坏时拒绝启动，避免覆盖掉原有数据
+    let loaded = if state.config().appendonly {
+        aof::load(&state, &state.config().aof_path())
+            .map(|commands| format!("DB loaded from append only file: {commands} commands"))
+    } else {
+        rdb::load(&state.db, &state.config().rdb_path())
+            .map(|keys| format!("DB loaded from disk: {keys} keys"))
+    };
+    match loaded {
+        Ok(msg) => log!(Level::Notice, "{msg}"),
+        Err(err) => {
+            log!(Level::Warning, "{err}");
+            std::process::exit(1);
+        }
+    }
+    if state.config().appendonly {
+        let aof: Aof = Aof::open(state.config().aof_path(), state.config().appendfsync)
+            .unwrap_or_else(|err| {
+                log!(Level::Warning, "Can't open the append-only file: {err}");
+                std::process::exit(1);
+            });
+        state.aof.set(Arc::new(aof)).unwrap();
+    }
 
-    let db: Arc<Mutex<HashMap<_, _>>> = Arc::new(Mutex::new(HashMap::new()));
+    let replicaof: Option<(String, u16)> = state.config().replicaof.clone();
+    if let Some((host, port)) = replicaof {
+        state.repl.replicaof(&state, host, port);
+    }
 
-    loop {
-        let (socket, _) = listener.accept().await.unwrap();
-        // 将 handle 克隆一份
-        let db: Arc<Mutex<HashMap<_, _>>> = db.clone();
+    let mut listeners: Vec<Incoming> = Vec::new();
+    let addr: Option<String> = state.config().tcp_addr();
+    if let Some(addr) = addr {
+        listeners.push(Incoming::Tcp(bind(&addr).await));
+    }
+    let tls_addr: Option<String> = state.config().tls_addr();
+    if let Some(tls_addr) = tls_addr {
+        listeners.push(tls_listener(&state, &tls_addr).await);
+    }
+    let unixsocket: Option<PathBuf> = state.config().unixsocket.clone();
+    if let Some(path) = unixsocket {
+        listeners.push(unix_listener(&path, state.config().unixsocketperm));
+    }
+    if listeners.is_empty() {
+        log!(
+            Level::Warning,
+            "Configured to not listen anywhere, set port, tls-port or unixsocket"
+        );
+        std::process::exit(1);
+    }
 
-        println!("Accepted");
-        tokio::spawn(async move {
-            process(socket, db).await;
-        });
+    let metrics_addr: Option<String> = state.config().metrics_addr();
+    if let Some(metrics_addr) = metrics_addr {
+        let listener: TcpListener = bind(&metrics_addr).await;
+        log!(
+            Level::Notice,
+            "Serving metrics on http://{metrics_addr}/metrics"
+        );
+        tokio::spawn(metrics::serve(listener, state.clone()));
     }
+
+    for listener in &listeners {
+        if let Ok(local) = listener.local_addr() {
+            log!(Level::Notice, "Ready to accept connections on {local}");
+        }
+    }
+
+    server::run_all(listeners, state, shutdown_signal()).await;
 }
 
-async fn process(socket: TcpStream, db: Db) {
-    use mini_redis::Command::{self, Get, Set};
+/// 绑定地址，失败时退出
+async fn bind(addr: &str) -> TcpListener {
+    TcpListener::bind(addr).await.unwrap_or_else(|err| {
+        log!(Level::Warning, "Could not bind {addr}: {err}");
+        std::process::exit(1);
+    })
+}
 
-    let mut connection: Connection = Connection::new(socket);
+/// 在 Unix 套接字上监听，失败时退出
+#[cfg(unix)]
+fn unix_listener(path: &Path, perm: u32) -> Incoming {
+    Incoming::unix(path, perm).unwrap_or_else(|err| {
+        log!(
+            Level::Warning,
+            "Failed opening Unix socket {}: {err}",
+            path.display()
+        );
+        std::process::exit(1);
+    })
+}
 
-    while let Some(frame) = connection.read_frame().await.unwrap() {
-        let response: Frame = match Command::from_frame(frame).unwrap() {
-            Set(cmd) => {
-                let mut db = db.lock().unwrap();
-                db.insert(cmd.key().to_string(), cmd.value().clone());
-                Frame::Simple("OK".to_string())
-            }
-            Get(cmd) => {
-                let db: std::sync::MutexGuard<'_, HashMap<String, Bytes>> = db.lock().unwrap();
-                if let Some(value) = db.get(cmd.key()) {
-                    Frame::Bulk(value.clone())
-                } else {
-                    Frame::Null
-                }
-            }
-            cmd => panic!("unimplemented {:?}", cmd),
-        };
+#[cfg(not(unix))]
+fn unix_listener(_path: &Path, _perm: u32) -> Incoming {
+    log!(
+        Level::Warning,
+        "unixsocket is set but Unix sockets are not supported on this platform"
+    );
+    std::process::exit(1);
+}
 
-        connection.write_frame(&response).await.unwrap();
-    }
+/// 按配置的证书在 `tls-port` 上监听
+#[cfg(feature = "tls")]
+async fn tls_listener(state: &State, addr: &str) -> Incoming {
+    let config = my_redis::tls::server_config(&state.config()).unwrap_or_else(|err| {
+        log!(Level::Warning, "Failed to configure TLS: {err}");
+        std::process::exit(1);
+    });
+    Incoming::Tls(bind(addr).await, config)
 }
 
-type ShardedDb = Arc<Vec<Mutex<HashMap<String, Vec<u8>>>>>;
+#[cfg(not(feature = "tls"))]
+async fn tls_listener(_state: &State, _addr: &str) -> Incoming {
+    log!(
+        Level::Warning,
+        "tls-port is set but TLS support was not compiled in, rebuild with --features tls"
+    );
+    std::process::exit(1);
+}
 
-fn new_sharded_db(num_shards: usize) -> ShardedDb {
-    let mut db: Vec<Mutex<HashMap<String, Vec<u8>>>> = Vec::with_capacity(num_shards);
-    for _ in 0..num_shards {
-        db.push(Mutex::new(HashMap::new()));
+/// 等待 Ctrl-C 或 SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
     }
-    Arc::new(db)
 }
@@ -0,0 +1,283 @@
+//! 阻塞命令（BLPOP/BRPOP、带 BLOCK 的 XREAD/XREADGROUP）的等待队列
+//!
+//! 每个数据库中的每个 key 有一个先进先出的等待队列。客户端阻塞时把自己挂到所有 key 的队列上，
+//! 之后有数据写入时，写入命令在持有分片锁的情况下直接把回复交给排在最前面的客户端，
+//! 所以先阻塞的客户端一定先拿到数据，也不会出现被唤醒后数据已被别人取走的情况。
+
+use crate::frame::Frame;
+use crate::stream::Read;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// 所有 key 的等待队列，按数据库编号和 key 区分
+#[derive(Debug, Default)]
+pub struct Blocking {
+    waiters: Mutex<HashMap<(usize, String), VecDeque<Waiter>>>,
+    next_id: AtomicU64,
+}
+
+/// 被阻塞的客户端在一个 key 上等待什么
+#[derive(Debug, Clone)]
+pub enum Wait {
+    /// 从列表头部（BLPOP）或尾部（BRPOP）弹出
+    Pop { front: bool },
+    /// 从流中读取新条目，最多 `count` 个
+    Stream { read: Read, count: Option<usize> },
+}
+
+/// 挂在某个 key 上的等待者，同一个客户端挂在多个 key 上时共享同一个发送端
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    wait: Wait,
+    tx: Arc<Mutex<Option<oneshot::Sender<Frame>>>>,
+}
+
+/// 一次阻塞，连接拿着它等待回复
+#[derive(Debug)]
+pub struct Blocked {
+    pub id: u64,
+    /// 等待的 key 所在的数据库
+    pub db: usize,
+    pub keys: Vec<String>,
+    /// `None` 表示一直等待
+    pub timeout: Option<Duration>,
+    pub rx: oneshot::Receiver<Frame>,
+}
+
+/// 为等待者生成回复的数据来源
+pub trait Source {
+    /// 为等待者取出数据并生成回复，没有它要的数据时返回 `None`
+    fn reply(&mut self, wait: &Wait) -> Option<Frame>;
+
+    /// 回复没能送达，撤销 `reply` 对数据的修改
+    fn undo(&mut self, wait: &Wait, frame: Frame);
+}
+
+/// 从列表弹出元素交给 BLPOP/BRPOP
+struct Pop<'a> {
+    key: &'a str,
+    list: &'a mut VecDeque<Bytes>,
+    popped: Vec<Vec<Bytes>>,
+}
+
+impl Source for Pop<'_> {
+    fn reply(&mut self, wait: &Wait) -> Option<Frame> {
+        let Wait::Pop { front } = wait else {
+            return None;
+        };
+        let element: Bytes = if *front {
+            self.list.pop_front()?
+        } else {
+            self.list.pop_back()?
+        };
+        self.popped.push(vec![
+            Bytes::from_static(if *front { b"LPOP" } else { b"RPOP" }),
+            Bytes::copy_from_slice(self.key.as_bytes()),
+        ]);
+        Some(Frame::Array(vec![
+            Frame::Bulk(Bytes::copy_from_slice(self.key.as_bytes())),
+            Frame::Bulk(element),
+        ]))
+    }
+
+    fn undo(&mut self, wait: &Wait, frame: Frame) {
+        let (Wait::Pop { front }, Frame::Array(mut parts)) = (wait, frame) else {
+            return;
+        };
+        self.popped.pop();
+        if let Some(Frame::Bulk(element)) = parts.pop() {
+            if *front {
+                self.list.push_front(element);
+            } else {
+                self.list.push_back(element);
+            }
+        }
+    }
+}
+
+impl Blocking {
+    /// 为弹出列表元素在数据库 `db` 的所有 key 上排队，调用方持有这些 key 所在分片的锁
+    pub fn block(
+        &self,
+        db: usize,
+        keys: &[&str],
+        front: bool,
+        timeout: Option<Duration>,
+    ) -> Blocked {
+        let waits: Vec<(String, Wait)> = keys
+            .iter()
+            .map(|key| (key.to_string(), Wait::Pop { front }))
+            .collect();
+        self.block_on(db, waits, timeout)
+    }
+
+    /// 在数据库 `db` 的每个 key 上按各自的方式排队，调用方持有这些 key 所在分片的锁
+    pub fn block_on(
+        &self,
+        db: usize,
+        waits: Vec<(String, Wait)>,
+        timeout: Option<Duration>,
+    ) -> Blocked {
+        let (tx, rx) = oneshot::channel();
+        let tx = Arc::new(Mutex::new(Some(tx)));
+        let id: u64 = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut waiters = self.waiters.lock().unwrap();
+        let mut keys: Vec<String> = Vec::with_capacity(waits.len());
+        for (key, wait) in waits {
+            waiters
+                .entry((db, key.clone()))
+                .or_default()
+                .push_back(Waiter {
+                    id,
+                    wait,
+                    tx: tx.clone(),
+                });
+            keys.push(key);
+        }
+        Blocked {
+            id,
+            db,
+            keys,
+            timeout,
+            rx,
+        }
+    }
+
+    /// 超时或连接断开后把等待者从所有队列中移除
+    pub fn unblock(&self, blocked: &Blocked) {
+        let mut waiters = self.waiters.lock().unwrap();
+        for key in &blocked.keys {
+            let key: (usize, String) = (blocked.db, key.clone());
+            if let Some(queue) = waiters.get_mut(&key) {
+                queue.retain(|waiter| waiter.id != blocked.id);
+                if queue.is_empty() {
+                    waiters.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// 把列表中的元素按顺序交给等待弹出的客户端，调用方持有 key 所在分片的锁。
+    /// 返回需要传播出去的弹出命令，日志和副本据此重现同样的结果
+    pub fn serve(&self, db: usize, key: &str, list: &mut VecDeque<Bytes>) -> Vec<Vec<Bytes>> {
+        let mut pop: Pop<'_> = Pop {
+            key,
+            list,
+            popped: Vec::new(),
+        };
+        self.serve_with(db, key, &mut pop);
+        pop.popped
+    }
+
+    /// 按排队顺序让 `source` 为每个等待者生成回复，没有回复的等待者继续等待。
+    /// 调用方持有 key 所在分片的锁
+    ///
+    /// 已经在别的 key 上拿到回复或已经放弃的等待者直接移除，不会询问 `source`
+    pub fn serve_with(&self, db: usize, key: &str, source: &mut impl Source) {
+        let key: (usize, String) = (db, key.to_string());
+        let mut waiters = self.waiters.lock().unwrap();
+        let queue: &mut VecDeque<Waiter> = match waiters.get_mut(&key) {
+            Some(queue) => queue,
+            None => return,
+        };
+        let mut waiting: VecDeque<Waiter> = VecDeque::with_capacity(queue.len());
+        while let Some(waiter) = queue.pop_front() {
+            // 同一个客户端可能已经在别的 key 上拿到了数据
+            let tx = match waiter.tx.lock().unwrap().take() {
+                Some(tx) if !tx.is_closed() => tx,
+                _ => continue,
+            };
+            match source.reply(&waiter.wait) {
+                Some(frame) => {
+                    // 接收端刚好超时放弃了，把数据放回原处
+                    if let Err(frame) = tx.send(frame) {
+                        source.undo(&waiter.wait, frame);
+                    }
+                }
+                None => {
+                    *waiter.tx.lock().unwrap() = Some(tx);
+                    waiting.push_back(waiter);
+                }
+            }
+        }
+        *queue = waiting;
+        if queue.is_empty() {
+            waiters.remove(&key);
+        }
+    }
+
+    /// 数据库 `db` 中有客户端在等的 key
+    pub fn keys(&self, db: usize) -> Vec<String> {
+        let waiters = self.waiters.lock().unwrap();
+        waiters
+            .keys()
+            .filter(|(index, _)| *index == db)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    /// 正在等待的客户端数
+    pub fn blocked_clients(&self) -> usize {
+        let waiters = self.waiters.lock().unwrap();
+        let mut ids: Vec<u64> = waiters.values().flatten().map(|w| w.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Blocking;
+    use crate::frame::Frame;
+    use bytes::Bytes;
+    use std::collections::VecDeque;
+
+    fn popped(key: &str, element: &str) -> Frame {
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from(key.to_string())),
+            Frame::Bulk(Bytes::from(element.to_string())),
+        ])
+    }
+
+    #[test]
+    fn first_blocked_is_served_first() {
+        let blocking: Blocking = Blocking::default();
+        let mut first = blocking.block(0, &["q"], true, None);
+        let mut second = blocking.block(0, &["q", "other"], true, None);
+
+        let mut list: VecDeque<Bytes> = VecDeque::from([Bytes::from("a")]);
+        assert_eq!(blocking.serve(0, "q", &mut list).len(), 1);
+        assert_eq!(first.rx.try_recv().unwrap(), popped("q", "a"));
+        assert!(second.rx.try_recv().is_err());
+
+        list.extend([Bytes::from("b"), Bytes::from("c")]);
+        blocking.serve(0, "q", &mut list);
+        assert_eq!(second.rx.try_recv().unwrap(), popped("q", "b"));
+        assert_eq!(list, [Bytes::from("c")]);
+        // second 已经拿到数据，挂在 other 上的等待者不会再被服务
+        let mut other: VecDeque<Bytes> = VecDeque::from([Bytes::from("x")]);
+        assert!(blocking.serve(0, "other", &mut other).is_empty());
+        assert_eq!(other.len(), 1);
+    }
+
+    #[test]
+    fn abandoned_waiter_keeps_element() {
+        let blocking: Blocking = Blocking::default();
+        let gone = blocking.block(0, &["q"], false, None);
+        drop(gone.rx);
+        let live = blocking.block(0, &["q"], false, None);
+        assert_eq!(blocking.blocked_clients(), 2);
+
+        let mut list: VecDeque<Bytes> = VecDeque::from([Bytes::from("a"), Bytes::from("b")]);
+        blocking.serve(0, "q", &mut list);
+        assert_eq!(list, [Bytes::from("a")]);
+        blocking.unblock(&live);
+        assert_eq!(blocking.blocked_clients(), 0);
+    }
+}
@@ -0,0 +1,262 @@
+//! 集群客户端
+//!
+//! `ClusterClient` 为每个节点维护一个 `Client`，并用 `CLUSTER SLOTS` 缓存每个槽在哪个节点上，
+//! 命令直接发往 key 所在的节点，不带 key 的命令发给任意一个节点。收到 `MOVED` 时更新缓存并重新
+//! 拉取槽的分布；收到 `ASK` 时只把这一条命令连同 `ASKING` 发往目标节点，不改缓存；
+//! 收到 `TRYAGAIN` 时稍等再试。
+
+use super::{cmd, Client, ClientConfig, Cmd, Error, Result};
+use crate::cluster::{key_slot, SLOTS};
+use crate::frame::Frame;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 一条命令最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 16;
+
+/// 收到 `TRYAGAIN` 后等待多久再试
+const TRYAGAIN_DELAY: Duration = Duration::from_millis(20);
+
+/// 可以廉价克隆的集群客户端，所有克隆共享连接和槽的缓存
+#[derive(Debug, Clone)]
+pub struct ClusterClient {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// 连接各个节点时使用的配置，`addr` 是最初给出的节点
+    config: ClientConfig,
+    /// 各节点的客户端，按地址索引
+    nodes: Mutex<HashMap<String, Client>>,
+    /// 每个槽所在节点的地址
+    slots: Mutex<Vec<Option<String>>>,
+}
+
+/// 服务端回复的重定向
+#[derive(Debug, PartialEq, Eq)]
+enum Redirect {
+    Moved(u16, String),
+    Ask(String),
+    TryAgain,
+}
+
+impl ClusterClient {
+    /// 用默认配置连接到集群中的任意一个节点
+    pub async fn connect(addr: &str) -> Result<ClusterClient> {
+        ClusterClient::with_config(ClientConfig {
+            addr: addr.to_string(),
+            ..ClientConfig::default()
+        })
+        .await
+    }
+
+    /// 按配置连接 `config.addr` 上的节点并取得槽的分布，其它节点用同样的配置按需连接
+    pub async fn with_config(config: ClientConfig) -> Result<ClusterClient> {
+        let client: ClusterClient = ClusterClient {
+            inner: Arc::new(Inner {
+                config,
+                nodes: Mutex::new(HashMap::new()),
+                slots: Mutex::new(vec![None; SLOTS]),
+            }),
+        };
+        client.refresh_slots().await?;
+        Ok(client)
+    }
+
+    /// 重新向已知的节点询问槽的分布，第一个回复成功的节点为准
+    pub async fn refresh_slots(&self) -> Result<()> {
+        let mut addrs: Vec<String> = self.inner.nodes.lock().unwrap().keys().cloned().collect();
+        if !addrs.contains(&self.inner.config.addr) {
+            addrs.push(self.inner.config.addr.clone());
+        }
+        let mut last: Option<Error> = None;
+        for addr in addrs {
+            let reply: Result<Frame> = match self.node(&addr).await {
+                Ok(client) => client.request(cmd("CLUSTER").arg("SLOTS")).await,
+                Err(err) => Err(err),
+            };
+            match reply.and_then(parse_slots) {
+                Ok(ranges) => {
+                    let mut slots = self.inner.slots.lock().unwrap();
+                    slots.fill(None);
+                    for (start, end, addr) in ranges {
+                        for slot in start..=end {
+                            slots[usize::from(slot)] = Some(addr.clone());
+                        }
+                    }
+                    return Ok(());
+                }
+                Err(err) => last = Some(err),
+            }
+        }
+        Err(last.expect("at least one address was tried"))
+    }
+
+    /// 缓存中 `slot` 所在节点的地址
+    pub fn slot_owner(&self, slot: u16) -> Option<String> {
+        self.inner.slots.lock().unwrap()[usize::from(slot)].clone()
+    }
+
+    /// 把命令发往它的 key 所在的节点，跟随重定向，错误回复转换成 `Error::Server`
+    pub async fn request(&self, cmd: Cmd) -> Result<Frame> {
+        let slot: Option<u16> = command_slot(&cmd);
+        let mut addr: String = slot
+            .and_then(|slot| self.slot_owner(slot))
+            .unwrap_or_else(|| self.inner.config.addr.clone());
+        let mut asking: bool = false;
+        let mut last: String = String::new();
+        for _ in 0..MAX_REDIRECTS {
+            let client: Client = self.node(&addr).await?;
+            let mut cmds: Vec<Cmd> = Vec::with_capacity(2);
+            if asking {
+                cmds.push(super::cmd("ASKING"));
+            }
+            cmds.push(cmd.clone());
+            let reply: Frame = client
+                .pipeline(cmds)
+                .await?
+                .pop()
+                .expect("one reply per command");
+            asking = false;
+            let err: String = match reply {
+                Frame::Error(err) => err,
+                frame => return Ok(frame),
+            };
+            match Redirect::parse(&err) {
+                Some(Redirect::Moved(slot, target)) => {
+                    self.inner.slots.lock().unwrap()[usize::from(slot)] = Some(target.clone());
+                    // 一个槽搬走了，通常意味着还有别的槽也变了
+                    let _ = self.refresh_slots().await;
+                    addr = target;
+                }
+                Some(Redirect::Ask(target)) => {
+                    addr = target;
+                    asking = true;
+                }
+                Some(Redirect::TryAgain) => tokio::time::sleep(TRYAGAIN_DELAY).await,
+                None => return Err(Error::Server(err)),
+            }
+            last = err;
+        }
+        Err(Error::Server(last))
+    }
+
+    /// `addr` 上节点的客户端，还没有连接过时建立连接
+    async fn node(&self, addr: &str) -> Result<Client> {
+        if let Some(client) = self.inner.nodes.lock().unwrap().get(addr) {
+            return Ok(client.clone());
+        }
+        let client: Client = Client::with_config(ClientConfig {
+            addr: addr.to_string(),
+            ..self.inner.config.clone()
+        })
+        .await?;
+        // 并发的请求可能同时建立了连接，留下先放进去的那个
+        Ok(self
+            .inner
+            .nodes
+            .lock()
+            .unwrap()
+            .entry(addr.to_string())
+            .or_insert(client)
+            .clone())
+    }
+}
+
+impl Redirect {
+    /// 解析 `MOVED slot host:port`、`ASK slot host:port` 和 `TRYAGAIN ...`
+    fn parse(err: &str) -> Option<Redirect> {
+        let mut words = err.split_whitespace();
+        match words.next()? {
+            "MOVED" => {
+                let slot: u16 = words.next()?.parse().ok()?;
+                Some(Redirect::Moved(slot, words.next()?.to_string()))
+                    .filter(|_| usize::from(slot) < SLOTS)
+            }
+            "ASK" => {
+                words.next()?;
+                Some(Redirect::Ask(words.next()?.to_string()))
+            }
+            "TRYAGAIN" => Some(Redirect::TryAgain),
+            _ => None,
+        }
+    }
+}
+
+/// 命令的 key 所在的槽。按服务端的命令表找出 key，不认识的命令和不带 key 的命令返回 `None`
+fn command_slot(cmd: &Cmd) -> Option<u16> {
+    let args = cmd.as_args();
+    let spec = crate::cmd::lookup(args.first()?)?;
+    if !spec.check_arity(args.len()) {
+        return None;
+    }
+    let keys: Vec<&str> = spec.routing_keys(args).ok()?;
+    keys.first().map(|key| key_slot(key.as_bytes()))
+}
+
+/// 解析 `CLUSTER SLOTS` 的回复，每一项是起止槽号和主人的地址
+fn parse_slots(frame: Frame) -> Result<Vec<(u16, u16, String)>> {
+    let Frame::Array(ranges) = frame else {
+        return Err(Error::Unexpected(frame));
+    };
+    ranges
+        .into_iter()
+        .map(|range| parse_range(&range).ok_or(Error::Unexpected(range)))
+        .collect()
+}
+
+/// `[start, end, [host, port, id]]`
+fn parse_range(range: &Frame) -> Option<(u16, u16, String)> {
+    let slot = |n: &i64| u16::try_from(*n).ok().filter(|s| usize::from(*s) < SLOTS);
+    let Frame::Array(parts) = range else {
+        return None;
+    };
+    let [Frame::Integer(start), Frame::Integer(end), Frame::Array(node), ..] = parts.as_slice()
+    else {
+        return None;
+    };
+    let [Frame::Bulk(host), Frame::Integer(port), ..] = node.as_slice() else {
+        return None;
+    };
+    let port: u16 = u16::try_from(*port).ok()?;
+    Some((
+        slot(start)?,
+        slot(end)?,
+        format!("{}:{port}", String::from_utf8_lossy(host)),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command_slot, Redirect};
+    use crate::client::cmd;
+    use crate::cluster::key_slot;
+
+    #[test]
+    fn redirects_and_command_slots() {
+        assert_eq!(
+            Redirect::parse("MOVED 3999 127.0.0.1:6381"),
+            Some(Redirect::Moved(3999, "127.0.0.1:6381".to_string()))
+        );
+        assert_eq!(
+            Redirect::parse("ASK 3999 127.0.0.1:6381"),
+            Some(Redirect::Ask("127.0.0.1:6381".to_string()))
+        );
+        assert_eq!(
+            Redirect::parse("TRYAGAIN Multiple keys request during rehashing of slot"),
+            Some(Redirect::TryAgain)
+        );
+        assert_eq!(Redirect::parse("MOVED 99999 127.0.0.1:6381"), None);
+        assert_eq!(Redirect::parse("ERR unknown command"), None);
+
+        assert_eq!(command_slot(&cmd("GET").arg("foo")), Some(key_slot(b"foo")));
+        assert_eq!(
+            command_slot(&cmd("MSET").arg("{a}1").arg("x").arg("{a}2").arg("y")),
+            Some(key_slot(b"a"))
+        );
+        assert_eq!(command_slot(&cmd("PING")), None);
+        assert_eq!(command_slot(&cmd("GET")), None);
+    }
+}
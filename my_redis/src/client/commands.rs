@@ -0,0 +1,637 @@
+//! 各命令的类型化接口，把回复转换成对应的 Rust 类型
+//!
+//! 复制协议内部使用的 PSYNC、REPLCONF 以及切换协议的 HELLO 没有对应的方法，需要时可以用 `Client::request`。
+
+use super::{cmd, single, Client, Cmd, Error, Result, ToArg};
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::time::Duration;
+
+/// SET 的可选参数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SetOptions {
+    /// 只在 key 不存在时设置
+    pub nx: bool,
+    /// 只在 key 已存在时设置
+    pub xx: bool,
+    /// 过期时间，按毫秒发送
+    pub expire: Option<Duration>,
+    /// 保留原有的过期时间
+    pub keep_ttl: bool,
+}
+
+impl Client {
+    /// PING
+    pub async fn ping(&self) -> Result<()> {
+        match self.request(cmd("PING")).await? {
+            Frame::Simple(s) if s == "PONG" => Ok(()),
+            frame => Err(Error::Unexpected(frame)),
+        }
+    }
+
+    /// GET key
+    pub async fn get(&self, key: impl ToArg) -> Result<Option<Bytes>> {
+        optional_bulk(self.request(cmd("GET").arg(key)).await?)
+    }
+
+    /// SET key value
+    pub async fn set(&self, key: impl ToArg, value: impl ToArg) -> Result<()> {
+        ok(self.request(cmd("SET").arg(key).arg(value)).await?)
+    }
+
+    /// SET key value 带可选参数，因为 NX/XX 条件不满足而没有设置时返回 `false`
+    pub async fn set_with(
+        &self,
+        key: impl ToArg,
+        value: impl ToArg,
+        options: SetOptions,
+    ) -> Result<bool> {
+        let mut cmd: Cmd = cmd("SET").arg(key).arg(value);
+        if options.nx {
+            cmd = cmd.arg("NX");
+        }
+        if options.xx {
+            cmd = cmd.arg("XX");
+        }
+        if let Some(expire) = options.expire {
+            cmd = cmd.arg("PX").arg(expire.as_millis() as u64);
+        }
+        if options.keep_ttl {
+            cmd = cmd.arg("KEEPTTL");
+        }
+        match self.request(cmd).await? {
+            Frame::Null => Ok(false),
+            frame => ok(frame).map(|()| true),
+        }
+    }
+
+    /// MGET key [key ...]
+    pub async fn mget<K: ToArg>(&self, keys: &[K]) -> Result<Vec<Option<Bytes>>> {
+        array(self.request(cmd("MGET").args(keys)).await?)?
+            .into_iter()
+            .map(optional_bulk)
+            .collect()
+    }
+
+    /// MSET key value [key value ...]
+    pub async fn mset<K: ToArg, V: ToArg>(&self, pairs: &[(K, V)]) -> Result<()> {
+        ok(self.request(pairs_cmd(cmd("MSET"), pairs)).await?)
+    }
+
+    /// DEL key [key ...]，返回删除的个数
+    pub async fn del<K: ToArg>(&self, keys: &[K]) -> Result<i64> {
+        integer(self.request(cmd("DEL").args(keys)).await?)
+    }
+
+    /// EXISTS key [key ...]，返回存在的个数
+    pub async fn exists<K: ToArg>(&self, keys: &[K]) -> Result<i64> {
+        integer(self.request(cmd("EXISTS").args(keys)).await?)
+    }
+
+    /// EXPIRE key seconds，key 不存在时返回 `false`
+    pub async fn expire(&self, key: impl ToArg, seconds: i64) -> Result<bool> {
+        boolean(self.request(cmd("EXPIRE").arg(key).arg(seconds)).await?)
+    }
+
+    /// PEXPIRE key milliseconds
+    pub async fn pexpire(&self, key: impl ToArg, milliseconds: i64) -> Result<bool> {
+        boolean(
+            self.request(cmd("PEXPIRE").arg(key).arg(milliseconds))
+                .await?,
+        )
+    }
+
+    /// EXPIREAT key unix-time-seconds
+    pub async fn expireat(&self, key: impl ToArg, timestamp: i64) -> Result<bool> {
+        boolean(
+            self.request(cmd("EXPIREAT").arg(key).arg(timestamp))
+                .await?,
+        )
+    }
+
+    /// PEXPIREAT key unix-time-milliseconds
+    pub async fn pexpireat(&self, key: impl ToArg, timestamp: i64) -> Result<bool> {
+        boolean(
+            self.request(cmd("PEXPIREAT").arg(key).arg(timestamp))
+                .await?,
+        )
+    }
+
+    /// PERSIST key，key 不存在或没有过期时间时返回 `false`
+    pub async fn persist(&self, key: impl ToArg) -> Result<bool> {
+        boolean(self.request(cmd("PERSIST").arg(key)).await?)
+    }
+
+    /// TYPE key，key 不存在时返回 `"none"`
+    pub async fn key_type(&self, key: impl ToArg) -> Result<String> {
+        simple(self.request(cmd("TYPE").arg(key)).await?)
+    }
+
+    /// RENAME key newkey
+    pub async fn rename(&self, key: impl ToArg, new_key: impl ToArg) -> Result<()> {
+        ok(self.request(cmd("RENAME").arg(key).arg(new_key)).await?)
+    }
+
+    /// KEYS pattern
+    pub async fn keys(&self, pattern: impl ToArg) -> Result<Vec<Bytes>> {
+        bulks(self.request(cmd("KEYS").arg(pattern)).await?)
+    }
+
+    /// DBSIZE
+    pub async fn dbsize(&self) -> Result<i64> {
+        integer(self.request(cmd("DBSIZE")).await?)
+    }
+
+    /// RANDOMKEY，数据库为空时返回 `None`
+    pub async fn randomkey(&self) -> Result<Option<Bytes>> {
+        optional_bulk(self.request(cmd("RANDOMKEY")).await?)
+    }
+
+    /// SCAN cursor [MATCH pattern] [COUNT count]，返回下一个游标和这一批 key，游标为 0 表示遍历结束
+    pub async fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> Result<(u64, Vec<Bytes>)> {
+        scan_page(
+            self.request(scan_cmd(cmd("SCAN"), cursor, pattern, count))
+                .await?,
+        )
+    }
+
+    /// HSCAN key cursor [MATCH pattern] [COUNT count]，字段和值交替排列
+    pub async fn hscan(
+        &self,
+        key: impl ToArg,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> Result<(u64, Vec<Bytes>)> {
+        let cmd: Cmd = scan_cmd(cmd("HSCAN").arg(key), cursor, pattern, count);
+        scan_page(self.request(cmd).await?)
+    }
+
+    /// SSCAN key cursor [MATCH pattern] [COUNT count]
+    pub async fn sscan(
+        &self,
+        key: impl ToArg,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> Result<(u64, Vec<Bytes>)> {
+        let cmd: Cmd = scan_cmd(cmd("SSCAN").arg(key), cursor, pattern, count);
+        scan_page(self.request(cmd).await?)
+    }
+
+    /// LPUSH key element [element ...]，返回列表长度
+    pub async fn lpush<V: ToArg>(&self, key: impl ToArg, elements: &[V]) -> Result<i64> {
+        integer(self.request(cmd("LPUSH").arg(key).args(elements)).await?)
+    }
+
+    /// RPUSH key element [element ...]，返回列表长度
+    pub async fn rpush<V: ToArg>(&self, key: impl ToArg, elements: &[V]) -> Result<i64> {
+        integer(self.request(cmd("RPUSH").arg(key).args(elements)).await?)
+    }
+
+    /// LPOP key
+    pub async fn lpop(&self, key: impl ToArg) -> Result<Option<Bytes>> {
+        optional_bulk(self.request(cmd("LPOP").arg(key)).await?)
+    }
+
+    /// RPOP key
+    pub async fn rpop(&self, key: impl ToArg) -> Result<Option<Bytes>> {
+        optional_bulk(self.request(cmd("RPOP").arg(key)).await?)
+    }
+
+    /// LRANGE key start stop
+    pub async fn lrange(&self, key: impl ToArg, start: i64, stop: i64) -> Result<Vec<Bytes>> {
+        bulks(
+            self.request(cmd("LRANGE").arg(key).arg(start).arg(stop))
+                .await?,
+        )
+    }
+
+    /// LLEN key
+    pub async fn llen(&self, key: impl ToArg) -> Result<i64> {
+        integer(self.request(cmd("LLEN").arg(key)).await?)
+    }
+
+    /// BLPOP key [key ...] timeout，超时返回 `None`，`timeout` 为零时一直等待
+    pub async fn blpop<K: ToArg>(
+        &self,
+        keys: &[K],
+        timeout: Duration,
+    ) -> Result<Option<(Bytes, Bytes)>> {
+        self.bpop("BLPOP", keys, timeout).await
+    }
+
+    /// BRPOP key [key ...] timeout
+    pub async fn brpop<K: ToArg>(
+        &self,
+        keys: &[K],
+        timeout: Duration,
+    ) -> Result<Option<(Bytes, Bytes)>> {
+        self.bpop("BRPOP", keys, timeout).await
+    }
+
+    async fn bpop<K: ToArg>(
+        &self,
+        name: &str,
+        keys: &[K],
+        timeout: Duration,
+    ) -> Result<Option<(Bytes, Bytes)>> {
+        let cmd: Cmd = cmd(name).args(keys).arg(timeout.as_secs_f64());
+        // 阻塞期间不能占用共享的连接，等待多久由服务端按 `timeout` 决定
+        let client: Client = self.blocking().await?;
+        match client.unbounded(cmd).await? {
+            Frame::Null => Ok(None),
+            frame => {
+                let mut pair = bulks(frame)?.into_iter();
+                match (pair.next(), pair.next()) {
+                    (Some(key), Some(value)) => Ok(Some((key, value))),
+                    _ => Err(Error::Unexpected(Frame::Null)),
+                }
+            }
+        }
+    }
+
+    /// HSET key field value [field value ...]，返回新增的字段数
+    pub async fn hset<F: ToArg, V: ToArg>(&self, key: impl ToArg, pairs: &[(F, V)]) -> Result<i64> {
+        integer(self.request(pairs_cmd(cmd("HSET").arg(key), pairs)).await?)
+    }
+
+    /// HGET key field
+    pub async fn hget(&self, key: impl ToArg, field: impl ToArg) -> Result<Option<Bytes>> {
+        optional_bulk(self.request(cmd("HGET").arg(key).arg(field)).await?)
+    }
+
+    /// HGETALL key
+    pub async fn hgetall(&self, key: impl ToArg) -> Result<Vec<(Bytes, Bytes)>> {
+        let flat: Vec<Bytes> = bulks(self.request(cmd("HGETALL").arg(key)).await?)?;
+        Ok(flat
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect())
+    }
+
+    /// HDEL key field [field ...]，返回删除的字段数
+    pub async fn hdel<F: ToArg>(&self, key: impl ToArg, fields: &[F]) -> Result<i64> {
+        integer(self.request(cmd("HDEL").arg(key).args(fields)).await?)
+    }
+
+    /// HLEN key
+    pub async fn hlen(&self, key: impl ToArg) -> Result<i64> {
+        integer(self.request(cmd("HLEN").arg(key)).await?)
+    }
+
+    /// SADD key member [member ...]，返回新增的成员数
+    pub async fn sadd<M: ToArg>(&self, key: impl ToArg, members: &[M]) -> Result<i64> {
+        integer(self.request(cmd("SADD").arg(key).args(members)).await?)
+    }
+
+    /// SREM key member [member ...]，返回删除的成员数
+    pub async fn srem<M: ToArg>(&self, key: impl ToArg, members: &[M]) -> Result<i64> {
+        integer(self.request(cmd("SREM").arg(key).args(members)).await?)
+    }
+
+    /// SMEMBERS key
+    pub async fn smembers(&self, key: impl ToArg) -> Result<Vec<Bytes>> {
+        bulks(self.request(cmd("SMEMBERS").arg(key)).await?)
+    }
+
+    /// SISMEMBER key member
+    pub async fn sismember(&self, key: impl ToArg, member: impl ToArg) -> Result<bool> {
+        boolean(self.request(cmd("SISMEMBER").arg(key).arg(member)).await?)
+    }
+
+    /// SCARD key
+    pub async fn scard(&self, key: impl ToArg) -> Result<i64> {
+        integer(self.request(cmd("SCARD").arg(key)).await?)
+    }
+
+    /// SINTER key [key ...]
+    pub async fn sinter<K: ToArg>(&self, keys: &[K]) -> Result<Vec<Bytes>> {
+        bulks(self.request(cmd("SINTER").args(keys)).await?)
+    }
+
+    /// ZADD key score member [score member ...]，返回新增的成员数
+    pub async fn zadd<M: ToArg>(&self, key: impl ToArg, members: &[(f64, M)]) -> Result<i64> {
+        integer(
+            self.request(pairs_cmd(cmd("ZADD").arg(key), members))
+                .await?,
+        )
+    }
+
+    /// ZREM key member [member ...]，返回删除的成员数
+    pub async fn zrem<M: ToArg>(&self, key: impl ToArg, members: &[M]) -> Result<i64> {
+        integer(self.request(cmd("ZREM").arg(key).args(members)).await?)
+    }
+
+    /// ZSCORE key member
+    pub async fn zscore(&self, key: impl ToArg, member: impl ToArg) -> Result<Option<f64>> {
+        match self.request(cmd("ZSCORE").arg(key).arg(member)).await? {
+            Frame::Null => Ok(None),
+            frame => double(frame).map(Some),
+        }
+    }
+
+    /// ZCARD key
+    pub async fn zcard(&self, key: impl ToArg) -> Result<i64> {
+        integer(self.request(cmd("ZCARD").arg(key)).await?)
+    }
+
+    /// ZRANGE key start stop
+    pub async fn zrange(&self, key: impl ToArg, start: i64, stop: i64) -> Result<Vec<Bytes>> {
+        bulks(
+            self.request(cmd("ZRANGE").arg(key).arg(start).arg(stop))
+                .await?,
+        )
+    }
+
+    /// ZRANGE key start stop WITHSCORES
+    pub async fn zrange_withscores(
+        &self,
+        key: impl ToArg,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(Bytes, f64)>> {
+        let cmd: Cmd = cmd("ZRANGE")
+            .arg(key)
+            .arg(start)
+            .arg(stop)
+            .arg("WITHSCORES");
+        with_scores(self.request(cmd).await?)
+    }
+
+    /// ZRANGEBYSCORE key min max，`min` 和 `max` 按服务端的写法，可以是 `(1`、`-inf` 等
+    pub async fn zrangebyscore(
+        &self,
+        key: impl ToArg,
+        min: impl ToArg,
+        max: impl ToArg,
+    ) -> Result<Vec<Bytes>> {
+        bulks(
+            self.request(cmd("ZRANGEBYSCORE").arg(key).arg(min).arg(max))
+                .await?,
+        )
+    }
+
+    /// ZRANGEBYSCORE key min max WITHSCORES
+    pub async fn zrangebyscore_withscores(
+        &self,
+        key: impl ToArg,
+        min: impl ToArg,
+        max: impl ToArg,
+    ) -> Result<Vec<(Bytes, f64)>> {
+        let cmd: Cmd = cmd("ZRANGEBYSCORE")
+            .arg(key)
+            .arg(min)
+            .arg(max)
+            .arg("WITHSCORES");
+        with_scores(self.request(cmd).await?)
+    }
+
+    /// WATCH key [key ...]，只在专用客户端上有意义
+    pub async fn watch<K: ToArg>(&self, keys: &[K]) -> Result<()> {
+        ok(self.request(cmd("WATCH").args(keys)).await?)
+    }
+
+    /// UNWATCH
+    pub async fn unwatch(&self) -> Result<()> {
+        ok(self.request(cmd("UNWATCH")).await?)
+    }
+
+    /// SAVE
+    pub async fn save(&self) -> Result<()> {
+        ok(self.unbounded(cmd("SAVE")).await?)
+    }
+
+    /// BGSAVE，返回服务端的状态描述
+    pub async fn bgsave(&self) -> Result<String> {
+        simple(self.request(cmd("BGSAVE")).await?)
+    }
+
+    /// LASTSAVE，返回最近一次成功保存的 Unix 时间戳
+    pub async fn lastsave(&self) -> Result<i64> {
+        integer(self.request(cmd("LASTSAVE")).await?)
+    }
+
+    /// BGREWRITEAOF，返回服务端的状态描述
+    pub async fn bgrewriteaof(&self) -> Result<String> {
+        simple(self.request(cmd("BGREWRITEAOF")).await?)
+    }
+
+    /// INFO [section ...]，返回原始文本
+    pub async fn info<S: ToArg>(&self, sections: &[S]) -> Result<String> {
+        string(self.request(cmd("INFO").args(sections)).await?)
+    }
+
+    /// CLIENT LIST，每个连接一行
+    pub async fn client_list(&self) -> Result<String> {
+        string(self.request(cmd("CLIENT").arg("LIST")).await?)
+    }
+
+    /// CONFIG GET pattern [pattern ...]，返回匹配的参数名和值
+    pub async fn config_get<P: ToArg>(&self, patterns: &[P]) -> Result<Vec<(String, String)>> {
+        let pairs: Vec<(Frame, Frame)> = match self
+            .request(cmd("CONFIG").arg("GET").args(patterns))
+            .await?
+        {
+            Frame::Map(pairs) => pairs,
+            Frame::Array(flat) => {
+                let mut flat = flat.into_iter();
+                std::iter::from_fn(|| Some((flat.next()?, flat.next()?))).collect()
+            }
+            frame => return Err(Error::Unexpected(frame)),
+        };
+        pairs
+            .into_iter()
+            .map(|(name, value)| Ok((string(name)?, string(value)?)))
+            .collect()
+    }
+
+    /// CONFIG SET parameter value [parameter value ...]
+    pub async fn config_set<N: ToArg, V: ToArg>(&self, pairs: &[(N, V)]) -> Result<()> {
+        ok(self
+            .request(pairs_cmd(cmd("CONFIG").arg("SET"), pairs))
+            .await?)
+    }
+
+    /// REPLICAOF host port
+    pub async fn replicaof(&self, host: &str, port: u16) -> Result<()> {
+        match self.request(cmd("REPLICAOF").arg(host).arg(port)).await? {
+            Frame::Simple(s) if s.starts_with("OK") => Ok(()),
+            frame => Err(Error::Unexpected(frame)),
+        }
+    }
+
+    /// REPLICAOF NO ONE
+    pub async fn replicaof_no_one(&self) -> Result<()> {
+        ok(self.request(cmd("REPLICAOF").arg("NO").arg("ONE")).await?)
+    }
+
+    /// ROLE，回复的结构随角色不同，原样返回
+    pub async fn role(&self) -> Result<Frame> {
+        self.request(cmd("ROLE")).await
+    }
+
+    /// EVAL，脚本的返回值原样返回
+    pub async fn eval<K: ToArg, A: ToArg>(
+        &self,
+        script: &str,
+        keys: &[K],
+        args: &[A],
+    ) -> Result<Frame> {
+        let cmd: Cmd = cmd("EVAL")
+            .arg(script)
+            .arg(keys.len())
+            .args(keys)
+            .args(args);
+        self.request(cmd).await
+    }
+
+    /// EVALSHA，脚本不在缓存中时返回 `NOSCRIPT` 错误
+    pub async fn evalsha<K: ToArg, A: ToArg>(
+        &self,
+        sha: &str,
+        keys: &[K],
+        args: &[A],
+    ) -> Result<Frame> {
+        let cmd: Cmd = cmd("EVALSHA")
+            .arg(sha)
+            .arg(keys.len())
+            .args(keys)
+            .args(args);
+        self.request(cmd).await
+    }
+
+    /// SCRIPT LOAD，返回脚本的 SHA-1 摘要
+    pub async fn script_load(&self, script: &str) -> Result<String> {
+        string(self.request(cmd("SCRIPT").arg("LOAD").arg(script)).await?)
+    }
+
+    /// 不受请求超时限制地执行一条命令
+    async fn unbounded(&self, cmd: Cmd) -> Result<Frame> {
+        single(self.send_with(vec![cmd], None).await?)
+    }
+}
+
+fn pairs_cmd<A: ToArg, B: ToArg>(mut cmd: Cmd, pairs: &[(A, B)]) -> Cmd {
+    for (a, b) in pairs {
+        cmd = cmd.arg(a).arg(b);
+    }
+    cmd
+}
+
+fn scan_cmd(mut cmd: Cmd, cursor: u64, pattern: Option<&str>, count: Option<usize>) -> Cmd {
+    cmd = cmd.arg(cursor);
+    if let Some(pattern) = pattern {
+        cmd = cmd.arg("MATCH").arg(pattern);
+    }
+    if let Some(count) = count {
+        cmd = cmd.arg("COUNT").arg(count);
+    }
+    cmd
+}
+
+/// SCAN 系列命令的回复：游标和一批元素
+fn scan_page(frame: Frame) -> Result<(u64, Vec<Bytes>)> {
+    let mut parts = array(frame)?.into_iter();
+    match (parts.next(), parts.next()) {
+        (Some(cursor), Some(batch)) => {
+            let cursor: String = string(cursor)?;
+            let cursor: u64 = cursor
+                .parse()
+                .map_err(|_| Error::Unexpected(Frame::Bulk(Bytes::from(cursor))))?;
+            Ok((cursor, bulks(batch)?))
+        }
+        _ => Err(Error::Unexpected(Frame::Null)),
+    }
+}
+
+fn ok(frame: Frame) -> Result<()> {
+    match frame {
+        Frame::Simple(s) if s == "OK" => Ok(()),
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn simple(frame: Frame) -> Result<String> {
+    match frame {
+        Frame::Simple(s) => Ok(s),
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn integer(frame: Frame) -> Result<i64> {
+    match frame {
+        Frame::Integer(n) => Ok(n),
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn boolean(frame: Frame) -> Result<bool> {
+    match frame {
+        Frame::Integer(n) => Ok(n != 0),
+        Frame::Boolean(b) => Ok(b),
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn double(frame: Frame) -> Result<f64> {
+    match frame {
+        Frame::Double(d) => Ok(d),
+        Frame::Bulk(ref b) => match std::str::from_utf8(b).ok().and_then(|s| s.parse().ok()) {
+            Some(d) => Ok(d),
+            None => Err(Error::Unexpected(frame)),
+        },
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn string(frame: Frame) -> Result<String> {
+    match frame {
+        Frame::Bulk(b) => Ok(String::from_utf8_lossy(&b).into_owned()),
+        Frame::Simple(s) => Ok(s),
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn optional_bulk(frame: Frame) -> Result<Option<Bytes>> {
+    match frame {
+        Frame::Bulk(b) => Ok(Some(b)),
+        Frame::Null => Ok(None),
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn array(frame: Frame) -> Result<Vec<Frame>> {
+    match frame {
+        Frame::Array(items) | Frame::Set(items) => Ok(items),
+        frame => Err(Error::Unexpected(frame)),
+    }
+}
+
+fn bulks(frame: Frame) -> Result<Vec<Bytes>> {
+    array(frame)?
+        .into_iter()
+        .map(|frame| match frame {
+            Frame::Bulk(b) => Ok(b),
+            frame => Err(Error::Unexpected(frame)),
+        })
+        .collect()
+}
+
+fn with_scores(frame: Frame) -> Result<Vec<(Bytes, f64)>> {
+    let mut items = array(frame)?.into_iter();
+    let mut pairs: Vec<(Bytes, f64)> = Vec::new();
+    while let Some(member) = items.next() {
+        let score: Frame = items.next().ok_or(Error::Unexpected(Frame::Null))?;
+        match member {
+            Frame::Bulk(member) => pairs.push((member, double(score)?)),
+            frame => return Err(Error::Unexpected(frame)),
+        }
+    }
+    Ok(pairs)
+}
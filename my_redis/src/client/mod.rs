@@ -0,0 +1,683 @@
+//! 异步客户端
+//!
+//! `Client` 持有一个连接池，每个连接由一个后台任务维护。并发的请求被轮流分配到各个连接上，
+//! 同一个连接上的请求不等前一个回复就接着写出去（自动流水线），回复按顺序交还给请求方。
+//! 连接断开后后台任务按指数退避重连，等待重连期间到达的请求直接返回错误。
+//!
+//! 阻塞命令（BLPOP 等）会占住连接，共享的客户端为它们临时建立专用连接；
+//! WATCH 需要在同一个连接上跨多次请求，要先用 `Client::dedicated` 取得专用的客户端。
+//! 连接集群时用 `ClusterClient`，它按 key 所在的槽把命令发往对应的节点。
+//!
+//! ```ignore
+//! let client = Client::connect("127.0.0.1:6379").await?;
+//! // 或者在同一台机器上：Client::connect("unix:///tmp/my_redis.sock")
+//! client.set("hello", "world").await?;
+//! assert_eq!(client.get("hello").await?.as_deref(), Some(&b"world"[..]));
+//! ```
+
+mod cluster;
+mod commands;
+mod pipe;
+
+pub use cluster::ClusterClient;
+pub use commands::SetOptions;
+
+use crate::frame::{Frame, ProtocolError};
+use crate::net::Stream;
+use bytes::Bytes;
+use pipe::Request;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
+use tokio::sync::{mpsc, oneshot};
+
+/// 客户端配置
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// 服务端地址，形如 `127.0.0.1:6379`；`unix:///tmp/my_redis.sock` 表示通过 Unix 套接字连接
+    pub addr: String,
+    /// 连接池中的连接数
+    pub pool_size: usize,
+    /// 建立连接的超时时间
+    pub connect_timeout: Duration,
+    /// 等待回复的超时时间，`None` 表示一直等待
+    pub timeout: Option<Duration>,
+    /// 第一次重连前的等待时间，之后每次翻倍
+    pub reconnect_min: Duration,
+    /// 重连等待时间的上限
+    pub reconnect_max: Duration,
+    /// 认证用的 ACL 用户名，`None` 表示 `default` 用户
+    pub username: Option<String>,
+    /// 设置后每个连接建立时先用它 AUTH
+    pub password: Option<String>,
+    /// 设置后用 TLS 连接，服务端证书要和 `addr` 中的主机名或 IP 相符，Unix 套接字上不使用
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<rustls::ClientConfig>>,
+}
+
+/// 客户端错误
+#[derive(Debug)]
+pub enum Error {
+    /// 连接失败或连接中断
+    Io(io::Error),
+    /// 服务端发来的数据不符合协议
+    Protocol(ProtocolError),
+    /// 服务端回复了错误
+    Server(String),
+    /// 回复的类型与命令不符
+    Unexpected(Frame),
+    /// 没有在规定的时间内收到回复
+    Timeout,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// 可以廉价克隆的客户端，所有克隆共享同一个连接池
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: ClientConfig,
+    /// 每个连接的请求队列
+    pipes: Vec<mpsc::Sender<Request>>,
+    /// 下一个请求使用的连接
+    next: AtomicUsize,
+    /// 是否是 `dedicated` 创建的专用客户端
+    dedicated: bool,
+}
+
+/// 一条命令及其参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cmd {
+    args: Vec<Bytes>,
+}
+
+/// 可以作为命令参数的类型
+pub trait ToArg {
+    fn to_arg(&self) -> Bytes;
+}
+
+/// 开始构造一条命令
+pub fn cmd(name: impl ToArg) -> Cmd {
+    Cmd {
+        args: vec![name.to_arg()],
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            addr: format!("127.0.0.1:{}", crate::DEFAULT_PORT),
+            pool_size: 4,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Some(Duration::from_secs(30)),
+            reconnect_min: Duration::from_millis(100),
+            reconnect_max: Duration::from_secs(5),
+            username: None,
+            password: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// 按配置单独建立一个连接，包括 TLS 握手和 AUTH，不经过连接池。
+/// 给需要直接读写协议的场景用，例如命令行客户端的 `--pipe`
+pub async fn open(config: &ClientConfig) -> Result<Stream> {
+    pipe::connect(config).await
+}
+
+impl ClientConfig {
+    /// `addr` 是 `unix://` 地址时返回套接字文件的路径
+    pub fn socket_path(&self) -> Option<&str> {
+        self.addr.strip_prefix("unix://")
+    }
+}
+
+impl Client {
+    /// 用默认配置连接到 `addr`
+    pub async fn connect(addr: &str) -> Result<Client> {
+        Client::with_config(ClientConfig {
+            addr: addr.to_string(),
+            ..ClientConfig::default()
+        })
+        .await
+    }
+
+    /// 按配置创建连接池，先用 PING 确认服务端可以连上
+    pub async fn with_config(config: ClientConfig) -> Result<Client> {
+        let client: Client = Client::spawn(config, false);
+        client.ping().await?;
+        Ok(client)
+    }
+
+    fn spawn(config: ClientConfig, dedicated: bool) -> Client {
+        let size: usize = if dedicated {
+            1
+        } else {
+            config.pool_size.max(1)
+        };
+        let pipes: Vec<mpsc::Sender<Request>> =
+            (0..size).map(|_| pipe::spawn(config.clone())).collect();
+        Client {
+            inner: Arc::new(Inner {
+                config,
+                pipes,
+                next: AtomicUsize::new(0),
+                dedicated,
+            }),
+        }
+    }
+
+    /// 创建一个只有一个连接、不与其它客户端共享的客户端。
+    /// 阻塞命令、WATCH 和事务在它上面按调用顺序执行，不会和别的请求交错
+    pub async fn dedicated(&self) -> Result<Client> {
+        let client: Client = Client::spawn(self.inner.config.clone(), true);
+        client.ping().await?;
+        Ok(client)
+    }
+
+    /// 客户端配置
+    pub fn config(&self) -> &ClientConfig {
+        &self.inner.config
+    }
+
+    /// 发送一条命令，错误回复转换成 `Error::Server`
+    pub async fn request(&self, cmd: Cmd) -> Result<Frame> {
+        single(self.send(vec![cmd]).await?)
+    }
+
+    /// 在同一个连接上连续发送多条命令，一次取回所有回复。
+    /// 某条命令出错时对应的位置是 `Frame::Error`，不影响其它命令
+    pub async fn pipeline(&self, cmds: Vec<Cmd>) -> Result<Vec<Frame>> {
+        if cmds.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.send(cmds).await
+    }
+
+    /// 用 MULTI/EXEC 包住若干命令原子地执行，返回每条命令的回复。
+    /// 专用客户端上之前的 WATCH 检测到修改时返回 `None`
+    pub async fn transaction(&self, cmds: Vec<Cmd>) -> Result<Option<Vec<Frame>>> {
+        let count: usize = cmds.len();
+        let mut batch: Vec<Cmd> = Vec::with_capacity(count + 2);
+        batch.push(cmd("MULTI"));
+        batch.extend(cmds);
+        batch.push(cmd("EXEC"));
+        let mut replies: Vec<Frame> = self.send(batch).await?;
+        // 排队阶段的错误让 EXEC 返回 EXECABORT，把最先出现的错误报告出来
+        if let Some(Frame::Error(err)) = replies[..=count]
+            .iter()
+            .find(|frame| matches!(frame, Frame::Error(_)))
+        {
+            return Err(Error::Server(err.clone()));
+        }
+        match replies.pop() {
+            Some(Frame::Array(results)) => Ok(Some(results)),
+            Some(Frame::Null) => Ok(None),
+            Some(Frame::Error(err)) => Err(Error::Server(err)),
+            Some(frame) => Err(Error::Unexpected(frame)),
+            None => unreachable!("one reply per command"),
+        }
+    }
+
+    /// 把命令交给一个连接并等待回复
+    async fn send(&self, cmds: Vec<Cmd>) -> Result<Vec<Frame>> {
+        self.send_with(cmds, self.inner.config.timeout).await
+    }
+
+    /// 同 `send`，但使用指定的超时时间
+    async fn send_with(&self, cmds: Vec<Cmd>, timeout: Option<Duration>) -> Result<Vec<Frame>> {
+        let idx: usize = self.inner.next.fetch_add(1, Ordering::Relaxed) % self.inner.pipes.len();
+        let (tx, rx) = oneshot::channel();
+        let request: Request = Request::new(&cmds, tx);
+        self.inner.pipes[idx]
+            .send(request)
+            .await
+            .map_err(|_| closed())?;
+        let reply = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, rx)
+                .await
+                .map_err(|_| Error::Timeout)?,
+            None => rx.await,
+        };
+        reply.map_err(|_| closed())?
+    }
+
+    /// 阻塞命令使用的客户端：专用客户端用自己，共享的客户端临时建一个专用连接
+    async fn blocking(&self) -> Result<Client> {
+        if self.inner.dedicated {
+            Ok(self.clone())
+        } else {
+            self.dedicated().await
+        }
+    }
+}
+
+impl Cmd {
+    /// 追加一个参数
+    pub fn arg(mut self, arg: impl ToArg) -> Cmd {
+        self.args.push(arg.to_arg());
+        self
+    }
+
+    /// 追加若干参数
+    pub fn args<T: ToArg>(mut self, args: impl IntoIterator<Item = T>) -> Cmd {
+        self.args.extend(args.into_iter().map(|arg| arg.to_arg()));
+        self
+    }
+
+    /// 全部参数，包括命令名
+    pub fn as_args(&self) -> &[Bytes] {
+        &self.args
+    }
+}
+
+impl<T: ToArg + ?Sized> ToArg for &T {
+    fn to_arg(&self) -> Bytes {
+        (**self).to_arg()
+    }
+}
+
+impl ToArg for str {
+    fn to_arg(&self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl ToArg for String {
+    fn to_arg(&self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl ToArg for [u8] {
+    fn to_arg(&self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl ToArg for Vec<u8> {
+    fn to_arg(&self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl ToArg for Bytes {
+    fn to_arg(&self) -> Bytes {
+        self.clone()
+    }
+}
+
+impl ToArg for f64 {
+    fn to_arg(&self) -> Bytes {
+        Bytes::from(crate::frame::format_double(*self))
+    }
+}
+
+macro_rules! integer_args {
+    ($($t:ty),*) => {
+        $(impl ToArg for $t {
+            fn to_arg(&self) -> Bytes {
+                Bytes::from(self.to_string())
+            }
+        })*
+    };
+}
+
+integer_args!(i64, u64, i32, u32, u16, usize);
+
+/// 取出单条命令的回复，错误回复转换成 `Error::Server`
+fn single(mut frames: Vec<Frame>) -> Result<Frame> {
+    match frames.pop() {
+        Some(Frame::Error(err)) => Err(Error::Server(err)),
+        Some(frame) => Ok(frame),
+        None => unreachable!("one reply per command"),
+    }
+}
+
+fn closed() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "connection task has stopped",
+    ))
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => err.fmt(f),
+            Error::Protocol(err) => err.fmt(f),
+            Error::Server(err) => f.write_str(err),
+            Error::Unexpected(frame) => write!(f, "unexpected reply: {frame:?}"),
+            Error::Timeout => f.write_str("timed out waiting for the reply"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Protocol(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(err: ProtocolError) -> Error {
+        Error::Protocol(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cmd, Client, ClientConfig, Error, SetOptions};
+    use crate::cmd::test::state;
+    use crate::frame::Frame;
+    use crate::server;
+    use crate::state::State;
+    use bytes::Bytes;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    /// 在 `addr` 上启动服务端，发送到返回的通道时关闭
+    async fn start(addr: &str, state: Arc<State>) -> (SocketAddr, oneshot::Sender<()>) {
+        let listener: TcpListener = TcpListener::bind(addr).await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(server::run(listener, state, rx));
+        (addr, tx)
+    }
+
+    fn config(addr: SocketAddr) -> ClientConfig {
+        ClientConfig {
+            addr: addr.to_string(),
+            timeout: Some(Duration::from_secs(5)),
+            reconnect_min: Duration::from_millis(10),
+            reconnect_max: Duration::from_millis(50),
+            ..ClientConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_commands() {
+        let (addr, _stop) = start("127.0.0.1:0", state(4)).await;
+        let client: Client = Client::with_config(config(addr)).await.unwrap();
+
+        client.set("k", "v").await.unwrap();
+        assert_eq!(client.get("k").await.unwrap(), Some(Bytes::from("v")));
+        let nx: SetOptions = SetOptions {
+            nx: true,
+            ..SetOptions::default()
+        };
+        assert!(!client.set_with("k", "w", nx).await.unwrap());
+        client.mset(&[("a", 1), ("b", 2)]).await.unwrap();
+        assert_eq!(
+            client.mget(&["a", "b", "none"]).await.unwrap(),
+            vec![Some(Bytes::from("1")), Some(Bytes::from("2")), None]
+        );
+        assert_eq!(client.del(&["a", "none"]).await.unwrap(), 1);
+
+        assert_eq!(client.rpush("l", &["x", "y", "z"]).await.unwrap(), 3);
+        assert_eq!(client.lpop("l").await.unwrap(), Some(Bytes::from("x")));
+        assert_eq!(client.lrange("l", 0, -1).await.unwrap(), vec!["y", "z"]);
+
+        assert_eq!(
+            client.hset("h", &[("f", "1"), ("g", "2")]).await.unwrap(),
+            2
+        );
+        assert_eq!(client.hgetall("h").await.unwrap().len(), 2);
+        assert!(client.sadd("s", &["m"]).await.unwrap() == 1);
+        assert!(client.sismember("s", "m").await.unwrap());
+
+        assert_eq!(
+            client.zadd("z", &[(1.5, "a"), (0.5, "b")]).await.unwrap(),
+            2
+        );
+        assert_eq!(client.zscore("z", "a").await.unwrap(), Some(1.5));
+        assert_eq!(
+            client.zrange_withscores("z", 0, -1).await.unwrap(),
+            vec![(Bytes::from("b"), 0.5), (Bytes::from("a"), 1.5)]
+        );
+        assert_eq!(
+            client.zrangebyscore("z", "(1", "+inf").await.unwrap(),
+            vec!["a"]
+        );
+
+        let sha: String = client
+            .script_load("return ARGV[1] .. KEYS[1]")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.evalsha(&sha, &["k"], &["v"]).await.unwrap(),
+            Frame::Bulk(Bytes::from("vk"))
+        );
+        assert_eq!(
+            client
+                .eval("return #KEYS", &["a", "b"], &[] as &[&str])
+                .await
+                .unwrap(),
+            Frame::Integer(2)
+        );
+
+        let mut cursor: u64 = 0;
+        let mut scanned: Vec<Bytes> = Vec::new();
+        loop {
+            let (next, batch) = client.scan(cursor, Some("[hz]"), Some(2)).await.unwrap();
+            scanned.extend(batch);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        scanned.sort();
+        assert_eq!(scanned, vec!["h", "z"]);
+        assert_eq!(client.key_type("z").await.unwrap(), "zset");
+
+        // 类型不符时是服务端错误
+        match client.llen("k").await {
+            Err(Error::Server(err)) => assert!(err.starts_with("WRONGTYPE")),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_connection() {
+        let (addr, _stop) = start("127.0.0.1:0", state(4)).await;
+        let client: Client = Client::with_config(ClientConfig {
+            pool_size: 1,
+            ..config(addr)
+        })
+        .await
+        .unwrap();
+
+        let mut tasks = Vec::new();
+        for i in 0..200 {
+            let client: Client = client.clone();
+            tasks.push(tokio::spawn(async move {
+                let key: String = format!("key:{i}");
+                client.set(&key, i).await.unwrap();
+                assert_eq!(
+                    client.get(&key).await.unwrap(),
+                    Some(Bytes::from(i.to_string()))
+                );
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let replies: Vec<Frame> = client
+            .pipeline(vec![cmd("INCR"), cmd("GET").arg("key:7")])
+            .await
+            .unwrap();
+        assert!(matches!(&replies[0], Frame::Error(_)));
+        assert_eq!(replies[1], Frame::Bulk(Bytes::from("7")));
+    }
+
+    #[tokio::test]
+    async fn transaction_and_blocking_pop() {
+        let (addr, _stop) = start("127.0.0.1:0", state(4)).await;
+        let client: Client = Client::with_config(config(addr)).await.unwrap();
+
+        let results: Vec<Frame> = client
+            .transaction(vec![cmd("SET").arg("n").arg(1), cmd("GET").arg("n")])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(results[1], Frame::Bulk(Bytes::from("1")));
+        assert!(client.transaction(vec![cmd("NOPE")]).await.is_err());
+
+        let waiter: Client = client.clone();
+        let pop = tokio::spawn(async move { waiter.blpop(&["jobs"], Duration::ZERO).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // 阻塞的 BLPOP 不占用共享连接
+        client.rpush("jobs", &["j1"]).await.unwrap();
+        assert_eq!(
+            pop.await.unwrap().unwrap(),
+            Some((Bytes::from("jobs"), Bytes::from("j1")))
+        );
+        assert_eq!(
+            client
+                .blpop(&["empty"], Duration::from_millis(50))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_server_restart() {
+        let state: Arc<State> = state(4);
+        let (addr, stop) = start("127.0.0.1:0", state.clone()).await;
+        let client: Client = Client::with_config(config(addr)).await.unwrap();
+        client.set("k", "v").await.unwrap();
+
+        stop.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // 服务端不在时请求很快失败，而不是等到超时
+        assert!(matches!(client.get("k").await, Err(Error::Io(_))));
+
+        let (_, _stop) = start(&addr.to_string(), state).await;
+        let mut value = None;
+        for _ in 0..50 {
+            if let Ok(v) = client.get("k").await {
+                value = v;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(value, Some(Bytes::from("v")));
+    }
+
+    #[tokio::test]
+    async fn backs_off_when_connections_are_dropped_at_once() {
+        // 接受连接后立刻关闭，记下连接的次数
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let accepted: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let counter: Arc<AtomicUsize> = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                drop(socket);
+            }
+        });
+        let client: Client = Client::spawn(
+            ClientConfig {
+                pool_size: 1,
+                ..config(addr)
+            },
+            false,
+        );
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        // 退避从 10ms 翻倍到 50ms，300ms 内只会重连几次
+        let attempts: usize = accepted.load(Ordering::Relaxed);
+        assert!((1..=15).contains(&attempts), "{attempts} connections");
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn request_times_out() {
+        // 只回复第一个 PING，之后不再回复
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf: Vec<u8> = vec![0; 64];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket.write_all(b"+PONG\r\n").await.unwrap();
+            std::future::pending::<()>().await;
+        });
+        let client: Client = Client::with_config(ClientConfig {
+            pool_size: 1,
+            timeout: Some(Duration::from_millis(100)),
+            ..config(addr)
+        })
+        .await
+        .unwrap();
+        assert!(matches!(client.get("k").await, Err(Error::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn authenticates_new_connections() {
+        let state: Arc<State> = state(4);
+        state.set_config("requirepass", "secret").unwrap();
+        state
+            .acl
+            .set_user(
+                "app",
+                &[
+                    "on".to_string(),
+                    ">pw".to_string(),
+                    "+@all".to_string(),
+                    "~*".to_string(),
+                ],
+            )
+            .unwrap();
+        let (addr, _stop) = start("127.0.0.1:0", state).await;
+
+        let wrong: Result<Client, Error> = Client::with_config(ClientConfig {
+            password: Some("nope".to_string()),
+            ..config(addr)
+        })
+        .await;
+        assert!(matches!(wrong, Err(Error::Server(err)) if err.starts_with("WRONGPASS")));
+
+        let client: Client = Client::with_config(ClientConfig {
+            password: Some("secret".to_string()),
+            ..config(addr)
+        })
+        .await
+        .unwrap();
+        client.set("k", "v").await.unwrap();
+        let client: Client = Client::with_config(ClientConfig {
+            username: Some("app".to_string()),
+            password: Some("pw".to_string()),
+            ..config(addr)
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            client.request(cmd("ACL").arg("WHOAMI")).await.unwrap(),
+            Frame::Bulk(Bytes::from("app"))
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! 维护单个连接的后台任务
+//!
+//! 写半边把队列里攒下的请求合并成一次写入，读半边按发送顺序把回复交还给请求方，
+//! 两者通过一个先进先出的队列对应起来，所以同一个连接上可以同时有任意多个请求在途。
+
+use super::{ClientConfig, Cmd, Error, Result};
+use crate::frame::{Decoder, Frame, Protocol, ProtocolError};
+use crate::log;
+use crate::log::Level;
+use crate::net::Stream;
+use bytes::BytesMut;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+/// 一次写入最多合并的请求数
+const MAX_BATCH: usize = 128;
+
+/// 请求队列的长度，队列满时请求方等待
+const QUEUE_SIZE: usize = 1024;
+
+/// 连接至少用了这么久、也收到过回复，断开后才马上重连，否则和连不上一样退避
+const STABLE_AFTER: Duration = Duration::from_secs(1);
+
+/// 一组需要连续发送的命令
+#[derive(Debug)]
+pub(super) struct Request {
+    /// 编码好的命令
+    data: BytesMut,
+    pending: Pending,
+}
+
+/// 已经写出、等待回复的请求
+#[derive(Debug)]
+struct Pending {
+    /// 还要收到的回复数
+    count: usize,
+    replies: Vec<Frame>,
+    tx: oneshot::Sender<Result<Vec<Frame>>>,
+}
+
+impl Request {
+    pub(super) fn new(cmds: &[Cmd], tx: oneshot::Sender<Result<Vec<Frame>>>) -> Request {
+        let mut data: BytesMut = BytesMut::new();
+        for cmd in cmds {
+            let frame: Frame = Frame::Array(cmd.args.iter().cloned().map(Frame::Bulk).collect());
+            frame.encode(Protocol::Resp2, &mut data);
+        }
+        Request {
+            data,
+            pending: Pending {
+                count: cmds.len(),
+                replies: Vec::with_capacity(cmds.len()),
+                tx,
+            },
+        }
+    }
+}
+
+impl Pending {
+    fn fail(self, err: &Error) {
+        let _ = self.tx.send(Err(duplicate(err)));
+    }
+}
+
+/// 启动连接任务，返回它的请求队列。所有发送端都被丢弃后任务退出
+pub(super) fn spawn(config: ClientConfig) -> mpsc::Sender<Request> {
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    tokio::spawn(run(config, rx));
+    tx
+}
+
+async fn run(config: ClientConfig, mut rx: mpsc::Receiver<Request>) {
+    let mut backoff: Duration = config.reconnect_min;
+    loop {
+        let err: Error = match connect(&config).await {
+            Ok(stream) => {
+                let connected: Instant = Instant::now();
+                let mut answered: bool = false;
+                match serve(stream, &mut rx, &mut answered).await {
+                    Ok(()) => return,
+                    // 连接正常用过一段时间后断开，马上重连一次
+                    Err(err) if answered && connected.elapsed() >= STABLE_AFTER => {
+                        log!(Level::Verbose, "Lost connection to {}: {err}", config.addr);
+                        backoff = config.reconnect_min;
+                        continue;
+                    }
+                    // 刚连上就被断开，例如服务端连接数满了，不能不停地重连
+                    Err(err) => err,
+                }
+            }
+            Err(err) => err,
+        };
+        log!(
+            Level::Verbose,
+            "Connection to {} failed: {err}, retrying in {backoff:?}",
+            config.addr
+        );
+        // 等待重连期间到达的请求直接失败，免得请求方一直等到超时
+        let sleep = tokio::time::sleep(backoff);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                request = rx.recv() => match request {
+                    Some(request) => request.pending.fail(&err),
+                    None => return,
+                },
+            }
+        }
+        backoff = (backoff * 2).min(config.reconnect_max);
+    }
+}
+
+/// 建立连接：TCP 或 Unix 套接字连接、TLS 握手（配置了的话）和 AUTH 合在一起算连接超时
+pub(super) async fn connect(config: &ClientConfig) -> Result<Stream> {
+    tokio::time::timeout(config.connect_timeout, establish(config))
+        .await
+        .map_err(|_| Error::Io(io::Error::new(io::ErrorKind::TimedOut, "connect timed out")))?
+}
+
+async fn establish(config: &ClientConfig) -> Result<Stream> {
+    let mut stream: Stream = match config.socket_path() {
+        Some(path) => unix(path).await?,
+        None => tcp(config).await?,
+    };
+    if let Some(password) = &config.password {
+        auth(&mut stream, config.username.as_deref(), password).await?;
+    }
+    Ok(stream)
+}
+
+/// 同一台机器上的服务端走 Unix 套接字，不加 TLS
+#[cfg(unix)]
+async fn unix(path: &str) -> Result<Stream> {
+    Ok(Stream::Unix(tokio::net::UnixStream::connect(path).await?))
+}
+
+#[cfg(not(unix))]
+async fn unix(_path: &str) -> Result<Stream> {
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets are not supported on this platform",
+    )))
+}
+
+/// TCP 连接，配置了 TLS 时再完成握手
+async fn tcp(config: &ClientConfig) -> Result<Stream> {
+    let stream: TcpStream = TcpStream::connect(&config.addr).await?;
+    stream.set_nodelay(true)?;
+    #[cfg(feature = "tls")]
+    let stream: Stream = match &config.tls {
+        Some(tls) => {
+            let host: &str = match config.addr.rsplit_once(':') {
+                Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+                None => &config.addr,
+            };
+            let name = rustls::pki_types::ServerName::try_from(host.to_string())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let stream = tokio_rustls::TlsConnector::from(tls.clone())
+                .connect(name, stream)
+                .await?;
+            Stream::Tls(Box::new(stream.into()))
+        }
+        None => Stream::Tcp(stream),
+    };
+    #[cfg(not(feature = "tls"))]
+    let stream: Stream = Stream::Tcp(stream);
+    Ok(stream)
+}
+
+/// 新连接先认证，认证失败和连不上一样处理
+async fn auth(stream: &mut Stream, user: Option<&str>, password: &str) -> Result<()> {
+    let mut cmd: Cmd = super::cmd("AUTH");
+    if let Some(user) = user {
+        cmd = cmd.arg(user);
+    }
+    let cmd: Cmd = cmd.arg(password);
+    let mut buf: BytesMut = BytesMut::new();
+    Frame::Array(cmd.args.into_iter().map(Frame::Bulk).collect()).encode(Protocol::Resp2, &mut buf);
+    stream.write_all(&buf).await?;
+    buf.clear();
+    loop {
+        if let Some(frame) = Frame::parse(&mut buf)? {
+            return match frame {
+                Frame::Error(err) => Err(Error::Server(err)),
+                _ => Ok(()),
+            };
+        }
+        if stream.read_buf(&mut buf).await? == 0 {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+    }
+}
+
+/// 在一个连接上收发，直到连接出错（返回错误）或请求队列关闭（返回 `Ok`）。收到过完整的回复时
+/// 把 `answered` 置为 `true`
+async fn serve(
+    stream: Stream,
+    rx: &mut mpsc::Receiver<Request>,
+    answered: &mut bool,
+) -> Result<()> {
+    let (reader, writer) = tokio::io::split(stream);
+    let (pending_tx, mut pending_rx) = mpsc::unbounded_channel();
+    // 正在接收回复的请求，连接出错时要通知它
+    let mut current: Option<Pending> = None;
+    let result: Result<()> = tokio::select! {
+        result = write(writer, rx, pending_tx) => result,
+        result = read(reader, &mut pending_rx, &mut current, answered) => result,
+    };
+    if let Err(err) = &result {
+        current.into_iter().for_each(|pending| pending.fail(err));
+        while let Ok(pending) = pending_rx.try_recv() {
+            pending.fail(err);
+        }
+    }
+    result
+}
+
+async fn write(
+    mut writer: WriteHalf<Stream>,
+    rx: &mut mpsc::Receiver<Request>,
+    pending_tx: mpsc::UnboundedSender<Pending>,
+) -> Result<()> {
+    let mut buf: BytesMut = BytesMut::new();
+    while let Some(request) = rx.recv().await {
+        let mut next: Option<Request> = Some(request);
+        let mut batched: usize = 0;
+        while let Some(request) = next {
+            buf.extend_from_slice(&request.data);
+            // 先登记再写出，读半边收到回复时一定能找到对应的请求
+            let _ = pending_tx.send(request.pending);
+            batched += 1;
+            next = if batched < MAX_BATCH {
+                rx.try_recv().ok()
+            } else {
+                None
+            };
+        }
+        writer.write_all(&buf).await?;
+        buf.clear();
+    }
+    Ok(())
+}
+
+async fn read(
+    mut reader: ReadHalf<Stream>,
+    pending_rx: &mut mpsc::UnboundedReceiver<Pending>,
+    current: &mut Option<Pending>,
+    answered: &mut bool,
+) -> Result<()> {
+    let mut buf: BytesMut = BytesMut::with_capacity(4 * 1024);
+    let mut decoder: Decoder = Decoder::default();
+    loop {
+        while let Some(frame) = decoder.decode(&mut buf)? {
+            // 客户端不订阅任何东西，推送消息直接丢弃
+            if matches!(frame, Frame::Push(_)) {
+                continue;
+            }
+            let mut pending: Pending = match current.take() {
+                Some(pending) => pending,
+                None => pending_rx.try_recv().map_err(|_| {
+                    Error::Protocol(ProtocolError("reply without a request".to_string()))
+                })?,
+            };
+            pending.replies.push(frame);
+            if pending.replies.len() == pending.count {
+                *answered = true;
+                let _ = pending.tx.send(Ok(pending.replies));
+            } else {
+                *current = Some(pending);
+            }
+        }
+        if reader.read_buf(&mut buf).await? == 0 {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+    }
+}
+
+/// 同一个连接错误要交给多个请求方，`io::Error` 不能克隆，只能按类型和描述重建
+fn duplicate(err: &Error) -> Error {
+    match err {
+        Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+        Error::Protocol(err) => Error::Protocol(err.clone()),
+        Error::Server(err) => Error::Server(err.clone()),
+        Error::Unexpected(frame) => Error::Unexpected(frame.clone()),
+        Error::Timeout => Error::Timeout,
+    }
+}
@@ -0,0 +1,317 @@
+//! 已连接客户端的登记表
+//!
+//! 每个连接在登记表里有一份 `ClientInfo`，连接任务在每条命令前后更新它，`CLIENT LIST` 和 `INFO`
+//! 从这里读取其它连接的状态，`CLIENT KILL` 通过它通知连接任务退出。MONITOR 的订阅也记在这里。
+
+use crate::session::Session;
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Notify};
+
+/// MONITOR 客户端最多积压的行数，跟不上时断开
+const MONITOR_BUFFER: usize = 4096;
+
+/// 所有已连接的客户端以及连接相关的统计
+#[derive(Debug)]
+pub struct Clients {
+    next_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, Arc<ClientInfo>>>,
+    /// MONITOR 客户端，发送失败的在下一次发送时移除
+    monitors: Mutex<Vec<mpsc::Sender<String>>>,
+    /// `monitors` 的长度，没有 MONITOR 客户端时不必加锁
+    monitor_count: AtomicUsize,
+    /// 累计接受的连接数
+    pub total_connections: AtomicU64,
+    /// 因为超过 maxclients 被拒绝的连接数
+    pub rejected_connections: AtomicU64,
+    /// 累计执行的命令数
+    pub total_commands: AtomicU64,
+}
+
+/// 一个已连接的客户端
+#[derive(Debug)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: Option<SocketAddr>,
+    created: Instant,
+    killed: AtomicBool,
+    kill: Notify,
+    status: Mutex<Status>,
+}
+
+/// 连接任务随命令更新的状态
+#[derive(Debug)]
+struct Status {
+    name: Option<String>,
+    /// 认证的 ACL 用户
+    user: Option<String>,
+    /// 最近一条命令的名字
+    cmd: String,
+    last_active: Instant,
+    /// 选中的数据库
+    db: usize,
+    /// 在事务中时是排队的命令数
+    multi: Option<usize>,
+    mode: Mode,
+}
+
+/// 连接当前在做什么，对应 `CLIENT LIST` 的 flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    /// 在阻塞命令上等待
+    Blocked,
+    /// 执行了 MONITOR
+    Monitor,
+    /// 副本的复制连接
+    Replica,
+}
+
+impl Default for Clients {
+    fn default() -> Clients {
+        Clients {
+            next_id: AtomicU64::new(1),
+            clients: Mutex::new(BTreeMap::new()),
+            monitors: Mutex::new(Vec::new()),
+            monitor_count: AtomicUsize::new(0),
+            total_connections: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            total_commands: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Clients {
+    /// 登记一个新连接
+    pub fn register(&self, addr: Option<SocketAddr>) -> Arc<ClientInfo> {
+        let now: Instant = Instant::now();
+        let client: Arc<ClientInfo> = Arc::new(ClientInfo {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            addr,
+            created: now,
+            killed: AtomicBool::new(false),
+            kill: Notify::new(),
+            status: Mutex::new(Status {
+                name: None,
+                user: None,
+                cmd: "NULL".to_string(),
+                last_active: now,
+                db: 0,
+                multi: None,
+                mode: Mode::Normal,
+            }),
+        });
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        self.clients
+            .lock()
+            .unwrap()
+            .insert(client.id, client.clone());
+        client
+    }
+
+    /// 连接关闭
+    pub fn unregister(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    /// 当前连接数
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// 是否没有任何连接
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 按 id 排序的全部连接
+    pub fn list(&self) -> Vec<Arc<ClientInfo>> {
+        self.clients.lock().unwrap().values().cloned().collect()
+    }
+
+    /// 订阅之后执行的所有命令
+    pub fn monitor(&self) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel(MONITOR_BUFFER);
+        let mut monitors = self.monitors.lock().unwrap();
+        monitors.push(tx);
+        self.monitor_count.store(monitors.len(), Ordering::Relaxed);
+        rx
+    }
+
+    /// 把客户端发来的命令发给 MONITOR 客户端，格式与 Redis 相同：
+    /// `1700000000.123456 [0 127.0.0.1:50000] "set" "key" "value"`
+    pub fn feed_monitors(&self, session: &Session, args: &[Bytes]) {
+        if self.monitor_count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let addr: String = session
+            .addr
+            .map_or_else(|| "?".to_string(), |addr| addr.to_string());
+        let mut line: String = format!(
+            "{}.{:06} [{} {addr}]",
+            now.as_secs(),
+            now.subsec_micros(),
+            session.db
+        );
+        for arg in args {
+            line.push(' ');
+            quote(&mut line, arg);
+        }
+        let mut monitors = self.monitors.lock().unwrap();
+        monitors.retain(|tx| tx.try_send(line.clone()).is_ok());
+        self.monitor_count.store(monitors.len(), Ordering::Relaxed);
+    }
+}
+
+impl ClientInfo {
+    /// 开始执行一条命令
+    pub fn begin(&self, args: &[Bytes]) {
+        let mut status = self.status.lock().unwrap();
+        status.cmd = args.first().map_or_else(String::new, |name| {
+            String::from_utf8_lossy(name).to_ascii_lowercase()
+        });
+        status.last_active = Instant::now();
+    }
+
+    /// 命令执行完，同步会话中的连接名、用户、数据库和事务状态
+    pub fn end(&self, session: &Session) {
+        let mut status = self.status.lock().unwrap();
+        status.name.clone_from(&session.name);
+        status.user.clone_from(&session.user);
+        status.db = session.db;
+        status.multi = session.multi.as_ref().map(Vec::len);
+        status.last_active = Instant::now();
+    }
+
+    pub fn set_mode(&self, mode: Mode) {
+        self.status.lock().unwrap().mode = mode;
+    }
+
+    /// `CLIENT KILL`：让连接在处理完当前命令后关闭
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+        self.kill.notify_one();
+    }
+
+    /// 是否已经被 `kill`
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+    }
+
+    /// 等待 `kill`
+    pub async fn killed(&self) {
+        if self.killed.load(Ordering::SeqCst) {
+            return;
+        }
+        self.kill.notified().await;
+    }
+
+    /// 连接名
+    pub fn name(&self) -> Option<String> {
+        self.status.lock().unwrap().name.clone()
+    }
+
+    /// 认证的 ACL 用户
+    pub fn user(&self) -> Option<String> {
+        self.status.lock().unwrap().user.clone()
+    }
+
+    /// `CLIENT LIST` 中的一行，不含换行
+    pub fn describe(&self) -> String {
+        let status = self.status.lock().unwrap();
+        let mut flags: String = match status.mode {
+            Mode::Normal => String::new(),
+            Mode::Blocked => "b".to_string(),
+            Mode::Monitor => "O".to_string(),
+            Mode::Replica => "S".to_string(),
+        };
+        if status.multi.is_some() {
+            flags.push('x');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        let mut line: String = String::new();
+        let _ = write!(
+            line,
+            "id={} addr={} name={} age={} idle={} flags={flags} db={} multi={} cmd={} user={}",
+            self.id,
+            self.addr.map_or_else(String::new, |addr| addr.to_string()),
+            status.name.as_deref().unwrap_or(""),
+            self.created.elapsed().as_secs(),
+            status.last_active.elapsed().as_secs(),
+            status.db,
+            status.multi.map_or(-1, |n| n as i64),
+            status.cmd,
+            status.user.as_deref().unwrap_or(""),
+        );
+        line
+    }
+}
+
+/// 按 Redis 的规则给参数加引号，不可打印的字节写成 `\xHH`
+fn quote(out: &mut String, arg: &[u8]) {
+    out.push('"');
+    for &byte in arg {
+        match byte {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            0x20..=0x7e => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "\\x{byte:02x}");
+            }
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Clients, Mode};
+    use crate::session::Session;
+    use bytes::Bytes;
+
+    #[test]
+    fn monitor_lines_and_client_list() {
+        let clients: Clients = Clients::default();
+        let client = clients.register(Some("127.0.0.1:5000".parse().unwrap()));
+        let mut session: Session = Session::new();
+        session.addr = client.addr;
+        session.name = Some("worker".to_string());
+        session.multi = Some(vec![Vec::new()]);
+        session.db = 2;
+        client.begin(&[Bytes::from("GET")]);
+        client.end(&session);
+        client.set_mode(Mode::Blocked);
+        let line: String = client.describe();
+        assert!(line.starts_with("id=1 addr=127.0.0.1:5000 name=worker age=0 idle=0"));
+        assert!(line.ends_with("flags=bx db=2 multi=1 cmd=get user="));
+
+        let mut monitor = clients.monitor();
+        let args: Vec<Bytes> = vec![
+            Bytes::from("set"),
+            Bytes::from("k"),
+            Bytes::from("a \"b\"\n\x01"),
+        ];
+        clients.feed_monitors(&session, &args);
+        let line: String = monitor.try_recv().unwrap();
+        assert!(line.ends_with(r#" [2 127.0.0.1:5000] "set" "k" "a \"b\"\n\x01""#));
+        // 接收端关闭后下一次发送时移除
+        drop(monitor);
+        clients.feed_monitors(&session, &args);
+        assert!(clients.monitors.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,713 @@
+//! 集群模式
+//!
+//! 16384 个哈希槽分给各个节点，key 属于哪个槽由它的 CRC16 决定，key 中非空的 `{...}` 是哈希标签，
+//! 只有括号里的部分参与计算，用来让相关的 key 落在同一个槽。节点收到不属于自己的槽的命令时回复
+//! `MOVED slot host:port`；槽迁移期间，源节点上找不到的 key 回复 `ASK slot host:port`，客户端先发
+//! `ASKING` 再到目标节点重试这一条命令。没有 `MIGRATE`，迁移时用 `DUMP` 取出 key，带着 `ASKING`
+//! 在目标节点上 `RESTORE`，再从源节点删除。
+//!
+//! 没有单独的集群总线，节点每隔 `GOSSIP_INTERVAL` 用普通连接向其它节点发送 `CLUSTER GOSSIP`，
+//! 带上自己的 `CLUSTER NODES`，对方合并后回复它自己的，新节点和槽的分配就这样传开。每个节点有一个
+//! 配置纪元，对同一个槽的归属有分歧时纪元大的一方胜出；`CLUSTER SETSLOT <slot> NODE <自己>` 会把
+//! 自己的纪元提升为最大，迁移完成后新的归属因此能覆盖旧的。没有故障检测和自动故障转移，
+//! 集群配置也不落盘，节点重启后以新的 id 重新加入。
+
+use crate::client::{self, Client, ClientConfig};
+use crate::db::now_ms;
+use crate::frame::Frame;
+use crate::log;
+use crate::log::Level;
+use crate::replication::random_id;
+use crate::state::State;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 哈希槽的个数
+pub const SLOTS: usize = 16384;
+
+/// 交换集群状态的间隔
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 连接其它节点和等待回复的超时
+const GOSSIP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// key 所在的槽
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tagged: Option<&[u8]> = key.iter().position(|&b| b == b'{').and_then(|open| {
+        let rest: &[u8] = &key[open + 1..];
+        let len: usize = rest.iter().position(|&b| b == b'}')?;
+        (len > 0).then(|| &rest[..len])
+    });
+    crc16(tagged.unwrap_or(key)) & (SLOTS as u16 - 1)
+}
+
+/// CRC16-CCITT（XMODEM），和 Redis 使用的一样
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// 集群中的一个节点
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    /// 配置纪元
+    pub config_epoch: u64,
+    /// 最近一次和它交换信息的时间（毫秒时间戳），0 表示还没有直接联系过
+    pub last_seen: u64,
+}
+
+/// 命令应当在哪里执行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// 在本节点执行
+    Local,
+    /// 槽属于本节点但正在迁往这个地址，本节点没有的 key 要去那里找
+    Migrating(String),
+    /// 槽属于这个地址上的节点
+    Moved(String),
+    /// 槽还没有分配
+    Unassigned,
+}
+
+/// `CLUSTER SETSLOT` 的操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetSlot {
+    /// 从这个节点迁入
+    Importing(String),
+    /// 迁往这个节点
+    Migrating(String),
+    /// 取消迁入或迁出
+    Stable,
+    /// 直接交给这个节点
+    Node(String),
+}
+
+/// 本节点所知的集群状态
+#[derive(Debug)]
+pub struct Cluster {
+    /// 本节点的 id
+    myself: String,
+    inner: Mutex<Topology>,
+}
+
+#[derive(Debug)]
+struct Topology {
+    /// 见过的最大的配置纪元
+    current_epoch: u64,
+    /// 所有已知的节点，包括自己
+    nodes: BTreeMap<String, Node>,
+    /// 每个槽的主人
+    slots: Vec<Option<String>>,
+    /// 正在迁出的槽和目标节点
+    migrating: BTreeMap<u16, String>,
+    /// 正在迁入的槽和源节点
+    importing: BTreeMap<u16, String>,
+    /// `CLUSTER MEET` 了、还没有交换过信息的地址
+    meet: Vec<String>,
+    /// 超过这么久没有联系上的节点显示为断开
+    node_timeout: Duration,
+}
+
+/// 从 `CLUSTER NODES` 的一行中读出的信息
+struct Gossip {
+    id: String,
+    host: String,
+    port: u16,
+    /// 是不是发送方自己
+    sender: bool,
+    config_epoch: u64,
+    slots: Vec<u16>,
+}
+
+impl Cluster {
+    /// 创建只有自己一个节点、没有分配任何槽的集群，`host` 和 `port` 是告诉其它节点的地址
+    pub fn new(host: &str, port: u16, node_timeout: Duration) -> Cluster {
+        let myself: String = random_id();
+        let node: Node = Node {
+            id: myself.clone(),
+            host: host.to_string(),
+            port,
+            config_epoch: 0,
+            last_seen: 0,
+        };
+        Cluster {
+            inner: Mutex::new(Topology {
+                current_epoch: 0,
+                nodes: BTreeMap::from([(myself.clone(), node)]),
+                slots: vec![None; SLOTS],
+                migrating: BTreeMap::new(),
+                importing: BTreeMap::new(),
+                meet: Vec::new(),
+                node_timeout,
+            }),
+            myself,
+        }
+    }
+
+    /// 本节点的 id
+    pub fn myself(&self) -> &str {
+        &self.myself
+    }
+
+    pub fn set_node_timeout(&self, timeout: Duration) {
+        self.inner.lock().unwrap().node_timeout = timeout;
+    }
+
+    /// 访问 `slot` 的命令应当在哪里执行，`asking` 表示命令前面有 `ASKING`
+    pub fn route(&self, slot: u16, asking: bool) -> Route {
+        let inner = self.inner.lock().unwrap();
+        let slot_idx: usize = usize::from(slot);
+        match &inner.slots[slot_idx] {
+            Some(owner) if *owner == self.myself => match inner.migrating.get(&slot) {
+                Some(target) => inner.addr_of(target).map_or(Route::Local, Route::Migrating),
+                None => Route::Local,
+            },
+            _ if asking && inner.importing.contains_key(&slot) => Route::Local,
+            Some(owner) => inner.addr_of(owner).map_or(Route::Unassigned, Route::Moved),
+            None => Route::Unassigned,
+        }
+    }
+
+    /// 把没有主人的槽分给自己
+    pub fn add_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(slot) = slots
+            .iter()
+            .find(|&&slot| inner.slots[usize::from(slot)].is_some())
+        {
+            return Err(format!("ERR Slot {slot} is already busy"));
+        }
+        for &slot in slots {
+            inner.slots[usize::from(slot)] = Some(self.myself.clone());
+        }
+        Ok(())
+    }
+
+    /// 忘掉槽的主人。只改本节点的记录，不会传给其它节点
+    pub fn del_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(slot) = slots
+            .iter()
+            .find(|&&slot| inner.slots[usize::from(slot)].is_none())
+        {
+            return Err(format!("ERR Slot {slot} is already unassigned"));
+        }
+        for &slot in slots {
+            inner.slots[usize::from(slot)] = None;
+            inner.migrating.remove(&slot);
+            inner.importing.remove(&slot);
+        }
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT`，`has_keys` 表示本节点还有这个槽的 key
+    pub fn set_slot(&self, slot: u16, action: SetSlot, has_keys: bool) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        let slot_idx: usize = usize::from(slot);
+        let owned: bool = inner.slots[slot_idx].as_deref() == Some(self.myself.as_str());
+        let known = |inner: &Topology, id: &str| -> Result<(), String> {
+            if inner.nodes.contains_key(id) {
+                Ok(())
+            } else {
+                Err(format!("ERR I don't know about node {id}"))
+            }
+        };
+        match action {
+            SetSlot::Migrating(target) => {
+                if !owned {
+                    return Err(format!("ERR I'm not the owner of hash slot {slot}"));
+                }
+                known(&inner, &target)?;
+                inner.migrating.insert(slot, target);
+            }
+            SetSlot::Importing(source) => {
+                if owned {
+                    return Err(format!("ERR I'm already the owner of hash slot {slot}"));
+                }
+                known(&inner, &source)?;
+                inner.importing.insert(slot, source);
+            }
+            SetSlot::Stable => {
+                inner.migrating.remove(&slot);
+                inner.importing.remove(&slot);
+            }
+            SetSlot::Node(id) => {
+                known(&inner, &id)?;
+                if owned && id != self.myself && has_keys {
+                    return Err(format!(
+                        "ERR Can't assign hashslot {slot} to a different node while I still hold keys for this hash slot."
+                    ));
+                }
+                inner.migrating.remove(&slot);
+                // 迁入完成，提升自己的纪元，让新的归属在其它节点上胜过源节点
+                if id == self.myself && inner.importing.remove(&slot).is_some() {
+                    inner.bump_epoch(&self.myself);
+                }
+                inner.slots[slot_idx] = Some(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// 记下要联系的地址，下一轮交换信息时认识对方
+    pub fn meet(&self, host: &str, port: u16) {
+        let addr: String = format!("{host}:{port}");
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.meet.contains(&addr) {
+            inner.meet.push(addr);
+        }
+    }
+
+    /// 已知的节点
+    pub fn nodes(&self) -> Vec<Node> {
+        self.inner.lock().unwrap().nodes.values().cloned().collect()
+    }
+
+    /// 分配了的槽，按连续的区间列出主人
+    pub fn slot_ranges(&self) -> Vec<(u16, u16, Node)> {
+        let inner = self.inner.lock().unwrap();
+        let mut ranges: Vec<(u16, u16, Node)> = Vec::new();
+        for (slot, owner) in inner.slots.iter().enumerate() {
+            let Some(node) = owner.as_ref().and_then(|owner| inner.nodes.get(owner)) else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some((_, end, last)) if last.id == node.id && usize::from(*end) + 1 == slot => {
+                    *end = slot as u16;
+                }
+                _ => ranges.push((slot as u16, slot as u16, node.clone())),
+            }
+        }
+        ranges
+    }
+
+    /// `CLUSTER NODES` 的内容
+    pub fn describe(&self) -> String {
+        self.inner.lock().unwrap().describe(&self.myself)
+    }
+
+    /// `CLUSTER INFO` 的内容
+    pub fn info(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let assigned: usize = inner.slots.iter().filter(|owner| owner.is_some()).count();
+        let size: usize = inner
+            .nodes
+            .keys()
+            .filter(|id| inner.slots.iter().any(|owner| owner.as_ref() == Some(id)))
+            .count();
+        let mut out: String = String::new();
+        let state: &str = if assigned == SLOTS { "ok" } else { "fail" };
+        let _ = write!(
+            out,
+            "cluster_enabled:1\r\ncluster_state:{state}\r\ncluster_slots_assigned:{assigned}\r\n\
+             cluster_known_nodes:{}\r\ncluster_size:{size}\r\ncluster_current_epoch:{}\r\n\
+             cluster_my_epoch:{}\r\n",
+            inner.nodes.len(),
+            inner.current_epoch,
+            inner.nodes[&self.myself].config_epoch,
+        );
+        out
+    }
+
+    /// 合并另一个节点发来的 `CLUSTER NODES`。其它节点的行只用来认识新节点，纪元和槽的归属
+    /// 只听节点自己说，转述的信息可能已经过时
+    pub fn merge(&self, text: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let now: u64 = now_ms();
+        for gossip in text.lines().filter_map(Gossip::parse) {
+            if gossip.id == self.myself {
+                continue;
+            }
+            inner.current_epoch = inner.current_epoch.max(gossip.config_epoch);
+            let node: &mut Node = inner
+                .nodes
+                .entry(gossip.id.clone())
+                .or_insert_with(|| Node {
+                    id: gossip.id.clone(),
+                    host: gossip.host.clone(),
+                    port: gossip.port,
+                    config_epoch: 0,
+                    last_seen: 0,
+                });
+            if !gossip.sender {
+                continue;
+            }
+            node.host = gossip.host.clone();
+            node.port = gossip.port;
+            node.last_seen = now;
+            node.config_epoch = node.config_epoch.max(gossip.config_epoch);
+            let epoch: u64 = node.config_epoch;
+            let mine: u64 = inner.nodes[&self.myself].config_epoch;
+            let mut collision: bool = false;
+            for slot in gossip.slots {
+                let slot_idx: usize = usize::from(slot);
+                let wins: bool = match &inner.slots[slot_idx] {
+                    None => true,
+                    Some(owner) if *owner == gossip.id => false,
+                    Some(owner) => inner
+                        .nodes
+                        .get(owner)
+                        .is_none_or(|n| n.config_epoch < epoch),
+                };
+                if inner.slots[slot_idx].as_deref() == Some(self.myself.as_str()) {
+                    collision |= epoch == mine;
+                    if wins {
+                        inner.migrating.remove(&slot);
+                    }
+                }
+                if wins {
+                    inner.slots[slot_idx] = Some(gossip.id.clone());
+                }
+            }
+            // 对方用相同的纪元声明了自己的槽，谁也胜不过谁，id 较大的一方提升纪元分出胜负
+            if collision && gossip.id < self.myself {
+                inner.bump_epoch(&self.myself);
+            }
+        }
+    }
+
+    /// 本轮要发送的内容和要联系的地址
+    fn gossip_targets(&self) -> (String, Vec<String>) {
+        let inner = self.inner.lock().unwrap();
+        let mut targets: Vec<String> = inner
+            .nodes
+            .values()
+            .filter(|node| node.id != self.myself)
+            .map(|node| format!("{}:{}", node.host, node.port))
+            .collect();
+        targets.extend(inner.meet.iter().cloned());
+        targets.sort_unstable();
+        targets.dedup();
+        (inner.describe(&self.myself), targets)
+    }
+
+    /// 和 `addr` 交换过信息了
+    fn met(&self, addr: &str) {
+        self.inner.lock().unwrap().meet.retain(|meet| meet != addr);
+    }
+}
+
+impl Topology {
+    fn node_mut(&mut self, id: &str) -> &mut Node {
+        self.nodes.get_mut(id).expect("node is known")
+    }
+
+    /// 把自己的纪元提升到比见过的任何纪元都大
+    fn bump_epoch(&mut self, myself: &str) {
+        let seen: u64 = self
+            .nodes
+            .values()
+            .map(|node| node.config_epoch)
+            .fold(self.current_epoch, u64::max);
+        self.current_epoch = seen + 1;
+        self.node_mut(myself).config_epoch = seen + 1;
+    }
+
+    fn addr_of(&self, id: &str) -> Option<String> {
+        self.nodes
+            .get(id)
+            .map(|node| format!("{}:{}", node.host, node.port))
+    }
+
+    /// 每个节点一行：`id host:port@0 flags - 0 last-seen epoch link-state slot ...`，自己那一行
+    /// 还带上迁移中的槽
+    fn describe(&self, myself: &str) -> String {
+        let now: u64 = now_ms();
+        let timeout: u64 = self.node_timeout.as_millis() as u64;
+        let mut out: String = String::new();
+        for node in self.nodes.values() {
+            let is_myself: bool = node.id == myself;
+            let flags: &str = if is_myself { "myself,master" } else { "master" };
+            let connected: bool = is_myself || now.saturating_sub(node.last_seen) <= timeout;
+            let _ = write!(
+                out,
+                "{} {}:{}@0 {flags} - 0 {} {} {}",
+                node.id,
+                node.host,
+                node.port,
+                node.last_seen,
+                node.config_epoch,
+                if connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                },
+            );
+            let mut slot: usize = 0;
+            while slot < SLOTS {
+                if self.slots[slot].as_deref() != Some(node.id.as_str()) {
+                    slot += 1;
+                    continue;
+                }
+                let start: usize = slot;
+                while slot + 1 < SLOTS && self.slots[slot + 1].as_deref() == Some(node.id.as_str())
+                {
+                    slot += 1;
+                }
+                if start == slot {
+                    let _ = write!(out, " {start}");
+                } else {
+                    let _ = write!(out, " {start}-{slot}");
+                }
+                slot += 1;
+            }
+            if is_myself {
+                for (slot, target) in &self.migrating {
+                    let _ = write!(out, " [{slot}->-{target}]");
+                }
+                for (slot, source) in &self.importing {
+                    let _ = write!(out, " [{slot}-<-{source}]");
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Gossip {
+    fn parse(line: &str) -> Option<Gossip> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 {
+            return None;
+        }
+        let addr: &str = fields[1].split('@').next()?;
+        let (host, port) = addr.rsplit_once(':')?;
+        let mut slots: Vec<u16> = Vec::new();
+        for range in &fields[8..] {
+            // 迁移中的槽只对发送方自己有意义
+            if range.starts_with('[') {
+                continue;
+            }
+            let (start, end): (u16, u16) = match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None => (range.parse().ok()?, range.parse().ok()?),
+            };
+            if start > end || usize::from(end) >= SLOTS {
+                return None;
+            }
+            slots.extend(start..=end);
+        }
+        Some(Gossip {
+            id: fields[0].to_string(),
+            host: host.to_string(),
+            port: port.parse().ok()?,
+            sender: fields[2].split(',').any(|flag| flag == "myself"),
+            config_epoch: fields[6].parse().ok()?,
+            slots,
+        })
+    }
+}
+
+/// 定期和其它节点交换集群状态，直到任务被取消
+pub async fn gossip(state: Arc<State>) {
+    let Some(cluster) = &state.cluster else {
+        return;
+    };
+    let mut clients: HashMap<String, Client> = HashMap::new();
+    let mut interval = tokio::time::interval(GOSSIP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let (text, targets) = cluster.gossip_targets();
+        for addr in targets {
+            match exchange(&state, &mut clients, &addr, &text).await {
+                Ok(reply) => {
+                    cluster.merge(&reply);
+                    cluster.met(&addr);
+                }
+                Err(err) => {
+                    log!(Level::Verbose, "Cluster gossip with {addr} failed: {err}");
+                    clients.remove(&addr);
+                }
+            }
+        }
+    }
+}
+
+/// 把自己的状态发给 `addr`，返回对方的状态。节点之间用 masteruser/masterauth 认证
+async fn exchange(
+    state: &State,
+    clients: &mut HashMap<String, Client>,
+    addr: &str,
+    text: &str,
+) -> client::Result<String> {
+    let client: Client = match clients.get(addr) {
+        Some(client) => client.clone(),
+        None => {
+            let (username, password) = {
+                let config = state.config();
+                (config.masteruser.clone(), config.masterauth.clone())
+            };
+            let client: Client = Client::with_config(ClientConfig {
+                addr: addr.to_string(),
+                pool_size: 1,
+                connect_timeout: GOSSIP_TIMEOUT,
+                timeout: Some(GOSSIP_TIMEOUT),
+                username,
+                password,
+                ..ClientConfig::default()
+            })
+            .await?;
+            clients.insert(addr.to_string(), client.clone());
+            client
+        }
+    };
+    match client
+        .request(client::cmd("CLUSTER").arg("GOSSIP").arg(text))
+        .await?
+    {
+        Frame::Bulk(reply) => Ok(String::from_utf8_lossy(&reply).into_owned()),
+        frame => Err(client::Error::Unexpected(frame)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc16, key_slot, Cluster, Route, SetSlot, SLOTS};
+    use std::time::Duration;
+
+    #[test]
+    fn slots_and_hash_tags() {
+        assert_eq!(key_slot(b"123456789"), 0x31C3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(
+            key_slot(b"{user1000}.following"),
+            key_slot(b"{user1000}.followers")
+        );
+        // 空的标签不算，只认第一个 `{`
+        assert_eq!(
+            key_slot(b"foo{}{bar}"),
+            crc16(b"foo{}{bar}") & (SLOTS as u16 - 1)
+        );
+        assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+
+    #[test]
+    fn gossip_resolves_ownership_by_epoch() {
+        let timeout: Duration = Duration::from_secs(15);
+        let a: Cluster = Cluster::new("127.0.0.1", 7000, timeout);
+        let b: Cluster = Cluster::new("127.0.0.1", 7001, timeout);
+        a.add_slots(&[1, 2, 3]).unwrap();
+        assert_eq!(a.add_slots(&[3]).unwrap_err(), "ERR Slot 3 is already busy");
+        b.merge(&a.describe());
+        a.merge(&b.describe());
+        assert_eq!(
+            b.route(2, false),
+            Route::Moved("127.0.0.1:7000".to_string())
+        );
+        assert_eq!(b.route(9, false), Route::Unassigned);
+        // 已知节点的地址又在等待 MEET 的列表里时只联系一次
+        a.meet("127.0.0.1", 7009);
+        a.meet("127.0.0.1", 7001);
+        assert_eq!(a.gossip_targets().1, ["127.0.0.1:7001", "127.0.0.1:7009"]);
+
+        // 把槽 2 从 a 迁到 b
+        b.set_slot(2, SetSlot::Importing(a.myself().to_string()), false)
+            .unwrap();
+        a.set_slot(2, SetSlot::Migrating(b.myself().to_string()), true)
+            .unwrap();
+        assert_eq!(
+            a.route(2, false),
+            Route::Migrating("127.0.0.1:7001".to_string())
+        );
+        assert_eq!(b.route(2, true), Route::Local);
+        assert!(a
+            .set_slot(2, SetSlot::Node(b.myself().to_string()), true)
+            .is_err());
+        b.set_slot(2, SetSlot::Node(b.myself().to_string()), false)
+            .unwrap();
+        // 源节点还没改过来，从 b 的纪元得知槽已经易主
+        a.merge(&b.describe());
+        assert_eq!(
+            a.route(2, false),
+            Route::Moved("127.0.0.1:7001".to_string())
+        );
+        assert_eq!(a.route(1, false), Route::Local);
+        assert!(a.describe().contains("myself,master"));
+        assert!(a.info().contains("cluster_known_nodes:2\r\n"));
+    }
+
+    #[test]
+    fn only_the_sender_speaks_for_itself() {
+        let timeout: Duration = Duration::from_secs(15);
+        let a: Cluster = Cluster::new("127.0.0.1", 7000, timeout);
+        let b: Cluster = Cluster::new("127.0.0.1", 7001, timeout);
+        let c: Cluster = Cluster::new("127.0.0.1", 7002, timeout);
+        a.add_slots(&[1, 2]).unwrap();
+        b.merge(&a.describe());
+        // c 从 b 那里认识 a，但 a 的槽要等 a 自己说
+        c.merge(&b.describe());
+        assert_eq!(c.nodes().len(), 3);
+        assert_eq!(c.route(1, false), Route::Unassigned);
+        let stale: String = b.describe();
+
+        // 把槽 2 从 a 迁到 c，c 的纪元超过见过的所有纪元
+        c.merge(&a.describe());
+        c.set_slot(2, SetSlot::Importing(a.myself().to_string()), false)
+            .unwrap();
+        c.set_slot(2, SetSlot::Node(c.myself().to_string()), false)
+            .unwrap();
+        assert!(c.info().contains("cluster_my_epoch:1\r\n"));
+        a.merge(&c.describe());
+        assert_eq!(
+            a.route(2, false),
+            Route::Moved("127.0.0.1:7002".to_string())
+        );
+        // b 转述的旧归属不会把槽还给 a
+        a.merge(&stale);
+        c.merge(&stale);
+        assert_eq!(c.route(2, false), Route::Local);
+        assert!(a.info().contains("cluster_my_epoch:0\r\n"));
+    }
+
+    #[test]
+    fn equal_epochs_are_broken_by_id() {
+        let timeout: Duration = Duration::from_secs(15);
+        let a: Cluster = Cluster::new("127.0.0.1", 7000, timeout);
+        let b: Cluster = Cluster::new("127.0.0.1", 7001, timeout);
+        let c: Cluster = Cluster::new("127.0.0.1", 7002, timeout);
+        a.add_slots(&[5]).unwrap();
+        b.add_slots(&[5]).unwrap();
+        c.add_slots(&[9]).unwrap();
+        // 没有冲突的节点纪元相同也不提升
+        a.merge(&c.describe());
+        c.merge(&a.describe());
+        assert!(c.info().contains("cluster_current_epoch:0\r\n"));
+
+        // 双方用相同的纪元声明槽 5，id 较大的一方提升纪元后胜出
+        a.merge(&b.describe());
+        b.merge(&a.describe());
+        a.merge(&b.describe());
+        b.merge(&a.describe());
+        let (winner, port): (&str, &str) = if a.myself() > b.myself() {
+            (a.myself(), "7000")
+        } else {
+            (b.myself(), "7001")
+        };
+        for node in [&a, &b] {
+            let expected: Route = if node.myself() == winner {
+                Route::Local
+            } else {
+                Route::Moved(format!("127.0.0.1:{port}"))
+            };
+            assert_eq!(node.route(5, false), expected);
+            assert!(node.info().contains("cluster_current_epoch:1\r\n"));
+        }
+    }
+}
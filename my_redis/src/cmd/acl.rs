@@ -0,0 +1,203 @@
+//! ACL 命令
+
+use super::connection::kill;
+use super::{error, ok, wrong_arity, Ctx};
+use crate::acl::{self, User};
+use crate::frame::Frame;
+use bytes::Bytes;
+
+/// ACL SETUSER name [rule ...] | GETUSER name | DELUSER name [name ...] | LIST | USERS | WHOAMI |
+/// CAT [category]
+pub fn acl(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let sub: Vec<u8> = args[1].to_ascii_lowercase();
+    let bulk = |s: String| Frame::Bulk(Bytes::from(s));
+    match (sub.as_slice(), args.len()) {
+        (b"setuser", 3..) => {
+            let name: String = String::from_utf8_lossy(&args[2]).into_owned();
+            let rules: Vec<String> = args[3..]
+                .iter()
+                .map(|rule| String::from_utf8_lossy(rule).into_owned())
+                .collect();
+            match ctx.state.acl.set_user(&name, &rules) {
+                Ok(()) => ok(),
+                Err(err) => error(format!("ERR {err}")),
+            }
+        }
+        (b"getuser", 3) => match ctx.state.acl.get_user(&String::from_utf8_lossy(&args[2])) {
+            Some(user) => describe(&user),
+            None => Frame::Null,
+        },
+        (b"deluser", 3..) => {
+            let names: Vec<String> = args[2..]
+                .iter()
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .collect();
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            match ctx.state.acl.del_users(&names) {
+                Ok(deleted) => {
+                    // 和 Redis 一样断开以被删除的用户登录的连接
+                    kill(ctx, |client| {
+                        client
+                            .user()
+                            .is_some_and(|user| names.contains(&user.as_str()))
+                    });
+                    Frame::Integer(deleted as i64)
+                }
+                Err(err) => error(format!("ERR {err}")),
+            }
+        }
+        (b"list", 2) => Frame::Array(
+            ctx.state
+                .acl
+                .users()
+                .iter()
+                .map(|user| bulk(user.describe()))
+                .collect(),
+        ),
+        (b"users", 2) => Frame::Array(
+            ctx.state
+                .acl
+                .users()
+                .into_iter()
+                .map(|user| bulk(user.name))
+                .collect(),
+        ),
+        (b"whoami", 2) => match &ctx.session.user {
+            Some(user) => bulk(user.clone()),
+            None => Frame::Null,
+        },
+        (b"cat", 2) => Frame::Array(
+            acl::categories()
+                .into_iter()
+                .map(|name| bulk(name.to_string()))
+                .collect(),
+        ),
+        (b"cat", 3) => {
+            let name: String = String::from_utf8_lossy(&args[2]).into_owned();
+            match acl::category(&name) {
+                Some(commands) => Frame::Array(
+                    commands
+                        .into_iter()
+                        .map(|name| bulk(name.to_string()))
+                        .collect(),
+                ),
+                None => error(format!("ERR Unknown category '{name}'")),
+            }
+        }
+        (b"setuser" | b"getuser" | b"deluser" | b"list" | b"users" | b"whoami" | b"cat", _) => {
+            wrong_arity(&format!("acl|{}", String::from_utf8_lossy(&sub)))
+        }
+        _ => error(format!(
+            "ERR unknown subcommand '{}'. Try ACL SETUSER, ACL GETUSER, ACL LIST or ACL WHOAMI.",
+            String::from_utf8_lossy(&args[1])
+        )),
+    }
+}
+
+/// `ACL GETUSER` 的回复
+fn describe(user: &User) -> Frame {
+    let bulk = |s: &str| Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()));
+    let mut flags: Vec<Frame> = vec![bulk(if user.enabled { "on" } else { "off" })];
+    if user.nopass {
+        flags.push(bulk("nopass"));
+    }
+    Frame::Map(vec![
+        (bulk("flags"), Frame::Array(flags)),
+        (
+            bulk("passwords"),
+            Frame::Array(user.passwords.iter().map(|hash| bulk(hash)).collect()),
+        ),
+        (bulk("commands"), bulk(&user.command_rules())),
+        (bulk("keys"), bulk(&user.key_rules())),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{render, run_in, send, state};
+    use crate::cmd::{dispatch, Outcome};
+    use crate::session::Session;
+    use bytes::Bytes;
+
+    #[test]
+    fn acl_users_and_permissions() {
+        let db = state(2);
+        assert_eq!(
+            send(&db, "ACL SETUSER alice on >secret ~cache:* +get +set"),
+            "OK"
+        );
+        assert_eq!(
+            send(&db, "ACL GETUSER alice"),
+            "{flags [on] passwords [2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b] \
+             commands -@all +get +set keys ~cache:*}"
+        );
+        assert!(send(&db, "ACL LIST").contains("user default on nopass ~* +@all"));
+        assert_eq!(send(&db, "ACL USERS"), "[alice default]");
+        assert_eq!(send(&db, "ACL WHOAMI"), "default");
+        assert!(send(&db, "ACL SETUSER bob +nosuch").starts_with("!ERR Error in ACL SETUSER"));
+        assert!(send(&db, "ACL CAT").contains("sortedset"));
+        assert_eq!(
+            send(&db, "ACL CAT nosuch"),
+            "!ERR Unknown category 'nosuch'"
+        );
+
+        let mut session: Session = Session::new();
+        let mut send_as = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        assert_eq!(
+            send_as("AUTH alice wrong"),
+            "!WRONGPASS invalid username-password pair or user is disabled."
+        );
+        assert_eq!(send_as("AUTH alice secret"), "OK");
+        assert_eq!(send_as("ACL WHOAMI"), "alice");
+
+        assert_eq!(send(&db, "ACL DELUSER alice nobody"), "1");
+        assert_eq!(
+            send(&db, "ACL DELUSER default"),
+            "!ERR The 'default' user cannot be removed"
+        );
+        assert_eq!(send(&db, "ACL GETUSER alice"), "nil");
+    }
+
+    #[test]
+    fn dispatch_checks_permissions() {
+        let db = state(2);
+        db.set_config("requirepass", "pw").unwrap();
+        send(
+            &db,
+            "ACL SETUSER reader on >r ~public:* +@read +multi +exec +auth",
+        );
+        let mut session: Session = Session::new();
+        session.user = db.acl.auto_login();
+        let mut send_as = |cmd: &str| {
+            let args: Vec<Bytes> = cmd
+                .split(' ')
+                .map(|arg| Bytes::from(arg.to_string()))
+                .collect();
+            match dispatch(&db, &mut session, &args) {
+                Outcome::Reply(frame) => render(&frame),
+                other => panic!("unexpected outcome {other:?}"),
+            }
+        };
+        assert_eq!(send_as("GET public:a"), "!NOAUTH Authentication required.");
+        assert_eq!(
+            send_as("AUTH wrong"),
+            "!WRONGPASS invalid username-password pair or user is disabled."
+        );
+        assert_eq!(send_as("AUTH reader r"), "OK");
+        assert_eq!(send_as("GET public:a"), "nil");
+        assert_eq!(
+            send_as("GET secret"),
+            "!NOPERM No permissions to access a key"
+        );
+        assert_eq!(
+            send_as("SET public:a 1"),
+            "!NOPERM User reader has no permissions to run the 'set' command"
+        );
+        // 排队时被拒绝的命令让整个事务失败
+        send_as("MULTI");
+        send_as("SET public:a 1");
+        assert!(send_as("EXEC").starts_with("!EXECABORT"));
+        assert_eq!(send_as("AUTH pw"), "OK");
+        assert_eq!(send_as("SET secret 1"), "OK");
+    }
+}
@@ -0,0 +1,244 @@
+//! 服务端管理命令
+
+use super::{error, ok, parse_int, wrong_arity, Ctx};
+use crate::config::PARAMETERS;
+use crate::frame::Frame;
+use crate::glob;
+use crate::rdb;
+use bytes::Bytes;
+use std::sync::atomic::Ordering;
+
+/// SAVE，在持有全部分片锁的情况下同步写快照
+pub fn save(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    if ctx.state.rdb.bgsave_in_progress.load(Ordering::SeqCst) {
+        return error("ERR Background save already in progress");
+    }
+    match rdb::save(&ctx.db, &ctx.state.config().rdb_path(), &ctx.state.rdb) {
+        Ok(()) => ok(),
+        Err(err) => error(format!("ERR {err}")),
+    }
+}
+
+/// BGSAVE
+pub fn bgsave(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    let path = ctx.state.config().rdb_path();
+    if rdb::bgsave(&ctx.db, path, ctx.state.rdb.clone()) {
+        Frame::Simple("Background saving started".to_string())
+    } else {
+        error("ERR Background save already in progress")
+    }
+}
+
+/// BGREWRITEAOF
+pub fn bgrewriteaof(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    let aof = match ctx.state.aof.get() {
+        Some(aof) => aof,
+        None => return error("ERR AOF is not enabled"),
+    };
+    if aof.rewrite(&ctx.db) {
+        Frame::Simple("Background append only file rewriting started".to_string())
+    } else {
+        error("ERR Background append only file rewriting already in progress")
+    }
+}
+
+/// LASTSAVE，上次成功保存的 Unix 时间戳
+pub fn lastsave(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    Frame::Integer(ctx.state.rdb.last_save.load(Ordering::SeqCst) as i64)
+}
+
+/// CONFIG GET pattern [pattern ...] | CONFIG SET name value [name value ...]
+pub fn config(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let sub: Vec<u8> = args[1].to_ascii_lowercase();
+    match sub.as_slice() {
+        b"get" if args.len() >= 3 => {
+            let config = ctx.state.config();
+            let pairs: Vec<(Frame, Frame)> = PARAMETERS
+                .iter()
+                .filter(|name| {
+                    args[2..]
+                        .iter()
+                        .any(|pattern| glob::matches(pattern, name.as_bytes(), true))
+                })
+                .map(|name| {
+                    let value: String = config.get(name).unwrap_or_default();
+                    (
+                        Frame::Bulk(Bytes::from_static(name.as_bytes())),
+                        Frame::Bulk(Bytes::from(value)),
+                    )
+                })
+                .collect();
+            Frame::Map(pairs)
+        }
+        b"set" if args.len() >= 4 && args.len().is_multiple_of(2) => {
+            for pair in args[2..].chunks(2) {
+                let name: String = String::from_utf8_lossy(&pair[0]).to_ascii_lowercase();
+                let value: String = String::from_utf8_lossy(&pair[1]).into_owned();
+                if let Err(err) = ctx.state.set_config(&name, &value) {
+                    return error(format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{name}') - {err}"
+                    ));
+                }
+            }
+            ok()
+        }
+        b"get" | b"set" => wrong_arity(&format!("config|{}", String::from_utf8_lossy(&sub))),
+        _ => error(format!(
+            "ERR unknown subcommand '{}'. Try CONFIG GET or CONFIG SET.",
+            String::from_utf8_lossy(&args[1])
+        )),
+    }
+}
+
+/// MONITOR，之后连接只接收其他客户端执行的命令
+pub fn monitor(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    // 事务中的命令要求每条都有回复
+    if !ctx.may_block {
+        return error("ERR MONITOR isn't allowed for DENY BLOCKING client");
+    }
+    ctx.monitor = Some(ctx.state.clients.monitor());
+    ok()
+}
+
+/// SLOWLOG GET [count] | SLOWLOG LEN | SLOWLOG RESET
+pub fn slowlog(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let sub: Vec<u8> = args[1].to_ascii_lowercase();
+    match (sub.as_slice(), args.len()) {
+        (b"get", 2 | 3) => {
+            let count: i64 = match args.get(2).map(parse_int::<i64>) {
+                None => 10,
+                Some(Ok(count)) if count >= -1 => count,
+                Some(Ok(_)) => return error("ERR count should be greater than or equal to -1"),
+                Some(Err(err)) => return err,
+            };
+            let count: usize = usize::try_from(count).unwrap_or(usize::MAX);
+            let entries: Vec<Frame> = ctx
+                .state
+                .slowlog
+                .get(count)
+                .into_iter()
+                .map(|entry| {
+                    Frame::Array(vec![
+                        Frame::Integer(entry.id as i64),
+                        Frame::Integer(entry.time as i64),
+                        Frame::Integer(entry.duration as i64),
+                        Frame::Array(entry.args.into_iter().map(Frame::Bulk).collect()),
+                        Frame::Bulk(Bytes::from(entry.addr)),
+                        Frame::Bulk(Bytes::from(entry.name)),
+                    ])
+                })
+                .collect();
+            Frame::Array(entries)
+        }
+        (b"len", 2) => Frame::Integer(ctx.state.slowlog.len() as i64),
+        (b"reset", 2) => {
+            ctx.state.slowlog.reset();
+            ok()
+        }
+        (b"get" | b"len" | b"reset", _) => {
+            wrong_arity(&format!("slowlog|{}", String::from_utf8_lossy(&sub)))
+        }
+        _ => error(format!(
+            "ERR unknown subcommand '{}'. Try SLOWLOG GET, SLOWLOG LEN or SLOWLOG RESET.",
+            String::from_utf8_lossy(&args[1])
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{reply, run, send};
+    use crate::config::Config;
+    use crate::db::Db;
+    use crate::log::{self, Level};
+    use crate::rdb;
+    use crate::state::State;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[test]
+    fn bgsave_writes_snapshot() {
+        let dir = std::env::temp_dir().join(format!("my_redis-bgsave-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = State::new(Config {
+            dir: dir.clone(),
+            save: Vec::new(),
+            ..Config::default()
+        });
+        run(&state, "MSET a 1 b 2");
+        assert_eq!(reply(&state, "BGSAVE"), "Background saving started");
+        while state.rdb.bgsave_in_progress.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(state.rdb.dirty.load(Ordering::SeqCst), 0);
+
+        let restored: Db = Db::new(2, 16);
+        assert_eq!(rdb::load(&restored, &state.config().rdb_path()).unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_get_and_set() {
+        let state = State::new(Config {
+            save: Vec::new(),
+            ..Config::default()
+        });
+        assert_eq!(reply(&state, "CONFIG GET port"), "{port 6379}");
+        assert_eq!(
+            reply(&state, "CONFIG GET append*"),
+            "{appendonly no appendfilename appendonly.aof appendfsync everysec}"
+        );
+        assert_eq!(reply(&state, "CONFIG GET nosuch"), "{}");
+
+        assert_eq!(
+            reply(&state, "CONFIG SET maxmemory 1mb appendfsync always"),
+            "OK"
+        );
+        assert_eq!(
+            reply(&state, "CONFIG GET maxmemory appendfsync"),
+            "{appendfsync always maxmemory 1048576}"
+        );
+        assert_eq!(state.config().maxmemory, 1 << 20);
+
+        assert_eq!(
+            reply(&state, "CONFIG SET port 7000"),
+            "!ERR CONFIG SET failed (possibly related to argument 'port') - can't set immutable config"
+        );
+        assert_eq!(
+            reply(&state, "CONFIG SET maxmemory lots"),
+            "!ERR CONFIG SET failed (possibly related to argument 'maxmemory') - invalid memory size: lots"
+        );
+        assert_eq!(
+            reply(&state, "CONFIG SET maxmemory"),
+            "!ERR wrong number of arguments for 'config|set' command"
+        );
+        assert!(reply(&state, "CONFIG RESET").starts_with("!ERR unknown subcommand 'RESET'"));
+
+        assert_eq!(reply(&state, "CONFIG SET loglevel warning"), "OK");
+        assert_eq!(log::level(), Level::Warning);
+        assert_eq!(reply(&state, "CONFIG SET loglevel notice"), "OK");
+    }
+
+    #[test]
+    fn slowlog_records_slow_commands() {
+        let state = crate::cmd::test::state(2);
+        send(&state, "CONFIG SET slowlog-log-slower-than 0");
+        send(&state, "SET key value");
+        assert_eq!(send(&state, "SLOWLOG LEN"), "2");
+        // SLOWLOG 自己也会被记录
+        let entries: String = send(&state, "SLOWLOG GET 2");
+        assert!(entries.starts_with("[[2 "), "{entries}");
+        assert!(entries.contains(" [SET key value] "), "{entries}");
+        assert_eq!(
+            send(&state, "SLOWLOG GET -2"),
+            "!ERR count should be greater than or equal to -1"
+        );
+
+        send(&state, "CONFIG SET slowlog-log-slower-than -1");
+        assert_eq!(send(&state, "SLOWLOG RESET"), "OK");
+        send(&state, "SET key value");
+        assert_eq!(send(&state, "SLOWLOG LEN"), "0");
+        assert_eq!(send(&state, "SLOWLOG GET"), "[]");
+        assert!(send(&state, "SLOWLOG FOO").starts_with("!ERR unknown subcommand 'FOO'"));
+    }
+}
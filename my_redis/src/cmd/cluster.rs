@@ -0,0 +1,163 @@
+//! 集群相关的命令
+
+use super::{error, ok, parse_int, syntax_error, Ctx};
+use crate::cluster::{key_slot, Cluster, SetSlot, SLOTS};
+use crate::frame::Frame;
+use bytes::Bytes;
+
+/// CLUSTER INFO | NODES | MYID | SLOTS | KEYSLOT | ADDSLOTS | ADDSLOTSRANGE | DELSLOTS | SETSLOT |
+/// MEET | COUNTKEYSINSLOT | GETKEYSINSLOT | GOSSIP，调用时已经锁住全部分片
+pub fn cluster(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let cluster: &Cluster = match &ctx.state.cluster {
+        Some(cluster) => cluster,
+        None => return error("ERR This instance has cluster support disabled"),
+    };
+    let bulk = |s: String| Frame::Bulk(Bytes::from(s));
+    let sub: Vec<u8> = args[1].to_ascii_lowercase();
+    match (sub.as_slice(), args.len()) {
+        (b"info", 2) => bulk(cluster.info()),
+        (b"nodes", 2) => bulk(cluster.describe()),
+        (b"myid", 2) => bulk(cluster.myself().to_string()),
+        (b"slots", 2) => slots(cluster),
+        (b"keyslot", 3) => Frame::Integer(i64::from(key_slot(&args[2]))),
+        (b"addslots", 3..) => match parse_slots(&args[2..]) {
+            Ok(slots) => reply(cluster.add_slots(&slots)),
+            Err(err) => err,
+        },
+        (b"addslotsrange", n) if n > 2 && n.is_multiple_of(2) => {
+            let mut slots: Vec<u16> = Vec::new();
+            for range in args[2..].chunks(2) {
+                match (parse_slot(&range[0]), parse_slot(&range[1])) {
+                    (Ok(start), Ok(end)) if start <= end => slots.extend(start..=end),
+                    (Ok(start), Ok(end)) => {
+                        return error(format!(
+                            "ERR start slot number {start} is greater than end slot number {end}"
+                        ))
+                    }
+                    (Err(err), _) | (_, Err(err)) => return err,
+                }
+            }
+            reply(cluster.add_slots(&slots))
+        }
+        (b"delslots", 3..) => match parse_slots(&args[2..]) {
+            Ok(slots) => reply(cluster.del_slots(&slots)),
+            Err(err) => err,
+        },
+        (b"setslot", 4 | 5) => {
+            let slot: u16 = match parse_slot(&args[2]) {
+                Ok(slot) => slot,
+                Err(err) => return err,
+            };
+            let node = || String::from_utf8_lossy(&args[4]).into_owned();
+            let action: SetSlot = match (args[3].to_ascii_lowercase().as_slice(), args.len()) {
+                (b"importing", 5) => SetSlot::Importing(node()),
+                (b"migrating", 5) => SetSlot::Migrating(node()),
+                (b"node", 5) => SetSlot::Node(node()),
+                (b"stable", 4) => SetSlot::Stable,
+                _ => return syntax_error(),
+            };
+            let has_keys: bool = ctx
+                .db
+                .iter()
+                .any(|(key, _)| key_slot(key.as_bytes()) == slot);
+            reply(cluster.set_slot(slot, action, has_keys))
+        }
+        (b"meet", 4) => {
+            let host: String = String::from_utf8_lossy(&args[2]).into_owned();
+            match parse_int::<u16>(&args[3]) {
+                Ok(port) => {
+                    cluster.meet(&host, port);
+                    ok()
+                }
+                Err(_) => error(format!(
+                    "ERR Invalid node address specified: {host}:{}",
+                    String::from_utf8_lossy(&args[3])
+                )),
+            }
+        }
+        (b"countkeysinslot", 3) => match parse_slot(&args[2]) {
+            Ok(slot) => Frame::Integer(
+                ctx.db
+                    .iter()
+                    .filter(|(key, _)| key_slot(key.as_bytes()) == slot)
+                    .count() as i64,
+            ),
+            Err(err) => err,
+        },
+        (b"getkeysinslot", 4) => {
+            let slot: u16 = match parse_slot(&args[2]) {
+                Ok(slot) => slot,
+                Err(err) => return err,
+            };
+            let count: usize = match parse_int(&args[3]) {
+                Ok(count) => count,
+                Err(_) => return error("ERR Invalid number of keys"),
+            };
+            Frame::Array(
+                ctx.db
+                    .iter()
+                    .filter(|(key, _)| key_slot(key.as_bytes()) == slot)
+                    .take(count)
+                    .map(|(key, _)| Frame::Bulk(Bytes::copy_from_slice(key.as_bytes())))
+                    .collect(),
+            )
+        }
+        // 其它节点发来它的 `CLUSTER NODES`，回复自己的
+        (b"gossip", 3) => {
+            cluster.merge(&String::from_utf8_lossy(&args[2]));
+            bulk(cluster.describe())
+        }
+        _ => error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try CLUSTER HELP.",
+            String::from_utf8_lossy(&args[1])
+        )),
+    }
+}
+
+/// ASKING，下一条命令可以访问本节点正在迁入的槽
+pub fn asking(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    if ctx.state.cluster.is_none() {
+        return error("ERR This instance has cluster support disabled");
+    }
+    ctx.session.asking = true;
+    ok()
+}
+
+/// 每个连续区间一项：起止槽号和主人的地址与 id
+fn slots(cluster: &Cluster) -> Frame {
+    Frame::Array(
+        cluster
+            .slot_ranges()
+            .into_iter()
+            .map(|(start, end, node)| {
+                Frame::Array(vec![
+                    Frame::Integer(i64::from(start)),
+                    Frame::Integer(i64::from(end)),
+                    Frame::Array(vec![
+                        Frame::Bulk(Bytes::from(node.host)),
+                        Frame::Integer(i64::from(node.port)),
+                        Frame::Bulk(Bytes::from(node.id)),
+                    ]),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn reply(result: Result<(), String>) -> Frame {
+    match result {
+        Ok(()) => ok(),
+        Err(err) => error(err),
+    }
+}
+
+fn parse_slot(arg: &Bytes) -> Result<u16, Frame> {
+    parse_int::<u16>(arg)
+        .ok()
+        .filter(|slot| usize::from(*slot) < SLOTS)
+        .ok_or_else(|| error("ERR Invalid or out of range slot"))
+}
+
+fn parse_slots(args: &[Bytes]) -> Result<Vec<u16>, Frame> {
+    args.iter().map(parse_slot).collect()
+}
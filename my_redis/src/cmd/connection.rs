@@ -0,0 +1,297 @@
+//! 连接相关的命令
+
+use super::{error, ok, parse_db, syntax_error, wrong_arity, Ctx};
+use crate::acl::DEFAULT_USER;
+use crate::clients::ClientInfo;
+use crate::frame::{Frame, Protocol};
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// SELECT index，之后的命令都作用在这个数据库上
+pub fn select(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let index: usize = match parse_db(&ctx.db, &args[1]) {
+        Ok(index) => index,
+        Err(err) => return err,
+    };
+    // 集群按 key 分槽，只有 0 号数据库
+    if ctx.state.cluster.is_some() && index != 0 {
+        return error("ERR SELECT is not allowed in cluster mode");
+    }
+    ctx.db.select(index);
+    ctx.session.db = index;
+    ok()
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]
+///
+/// 切换连接的协议版本并返回服务端信息，回复本身已经按新版本编码
+pub fn hello(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let mut protocol: Protocol = ctx.session.protocol;
+    if let Some(version) = args.get(1) {
+        protocol = match &version[..] {
+            b"2" => Protocol::Resp2,
+            b"3" => Protocol::Resp3,
+            version if std::str::from_utf8(version).is_ok_and(|v| v.parse::<i64>().is_ok()) => {
+                return error("NOPROTO unsupported protocol version")
+            }
+            _ => return error("ERR Protocol version is not an integer or out of range"),
+        };
+    }
+    let mut name: Option<String> = None;
+    let mut auth: Option<(&Bytes, &Bytes)> = None;
+    let mut options = args.iter().skip(2);
+    while let Some(option) = options.next() {
+        match (option.to_ascii_lowercase().as_slice(), options.next()) {
+            (b"setname", Some(value)) => match client_name(value) {
+                Ok(value) => name = Some(value),
+                Err(err) => return err,
+            },
+            (b"auth", Some(user)) => match options.next() {
+                Some(password) => auth = Some((user, password)),
+                None => return syntax_error(),
+            },
+            _ => return syntax_error(),
+        }
+    }
+
+    match auth {
+        Some((user, password)) => {
+            if let Err(err) = login(ctx, user, password) {
+                return err;
+            }
+        }
+        None if ctx.session.user.is_none() => {
+            return error(
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise \
+                 the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the \
+                 client and select the RESP protocol version at the same time",
+            )
+        }
+        None => {}
+    }
+    ctx.session.protocol = protocol;
+    if name.is_some() {
+        ctx.session.name = name;
+    }
+    let version: u8 = match protocol {
+        Protocol::Resp2 => 2,
+        Protocol::Resp3 => 3,
+    };
+    let mode: &'static str = if ctx.state.cluster.is_some() {
+        "cluster"
+    } else {
+        "standalone"
+    };
+    let role: &'static str = if ctx.state.repl.is_replica() {
+        "replica"
+    } else {
+        "master"
+    };
+    let field = |name: &'static str, value: Frame| {
+        (Frame::Bulk(Bytes::from_static(name.as_bytes())), value)
+    };
+    Frame::Map(vec![
+        field("server", Frame::Bulk(Bytes::from_static(b"my_redis"))),
+        field(
+            "version",
+            Frame::Bulk(Bytes::from_static(env!("CARGO_PKG_VERSION").as_bytes())),
+        ),
+        field("proto", Frame::Integer(version as i64)),
+        field("mode", Frame::Bulk(Bytes::from_static(mode.as_bytes()))),
+        field("role", Frame::Bulk(Bytes::from_static(role.as_bytes()))),
+        field("modules", Frame::Array(Vec::new())),
+    ])
+}
+
+/// AUTH [username] password
+pub fn auth(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (user, password): (&[u8], &Bytes) = match args {
+        [_, password] => {
+            let default = ctx.state.acl.get_user(DEFAULT_USER);
+            if default.is_some_and(|user| user.nopass) {
+                return error(
+                    "ERR AUTH <password> called without any password configured for the default \
+                     user. Are you sure your configuration is correct?",
+                );
+            }
+            (DEFAULT_USER.as_bytes(), password)
+        }
+        [_, user, password] => (user, password),
+        _ => return syntax_error(),
+    };
+    match login(ctx, user, password) {
+        Ok(()) => ok(),
+        Err(err) => err,
+    }
+}
+
+/// 验证用户名和密码，成功后连接以这个用户的身份执行之后的命令
+fn login(ctx: &mut Ctx<'_>, user: &[u8], password: &[u8]) -> Result<(), Frame> {
+    let user: String = String::from_utf8_lossy(user).into_owned();
+    if !ctx.state.acl.authenticate(&user, password) {
+        return Err(error(
+            "WRONGPASS invalid username-password pair or user is disabled.",
+        ));
+    }
+    ctx.session.user = Some(user);
+    Ok(())
+}
+
+/// CLIENT ID | GETNAME | SETNAME name | LIST | INFO | KILL addr | KILL filter value ...
+pub fn client(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let sub: Vec<u8> = args[1].to_ascii_lowercase();
+    match (sub.as_slice(), args.len()) {
+        (b"id", 2) => Frame::Integer(ctx.session.id as i64),
+        (b"getname", 2) => match &ctx.session.name {
+            Some(name) => Frame::Bulk(Bytes::from(name.clone())),
+            None => Frame::Null,
+        },
+        // 空的名字表示清除连接名
+        (b"setname", 3) => match client_name(&args[2]) {
+            Ok(name) => {
+                ctx.session.name = Some(name).filter(|name| !name.is_empty());
+                ok()
+            }
+            Err(err) => err,
+        },
+        (b"list", 2) => {
+            let mut list: String = String::new();
+            for client in ctx.state.clients.list() {
+                list.push_str(&client.describe());
+                list.push('\n');
+            }
+            Frame::Bulk(Bytes::from(list))
+        }
+        (b"info", 2) => {
+            let id: u64 = ctx.session.id;
+            match ctx.state.clients.list().into_iter().find(|c| c.id == id) {
+                Some(client) => Frame::Bulk(Bytes::from(client.describe() + "\n")),
+                None => Frame::Null,
+            }
+        }
+        // 旧的写法：CLIENT KILL ip:port
+        (b"kill", 3) => match kill(ctx, |client| addr_is(client, &args[2])) {
+            0 => error("ERR No such client"),
+            _ => ok(),
+        },
+        (b"kill", n) if n >= 4 && n.is_multiple_of(2) => {
+            let mut id: Option<u64> = None;
+            let mut addr: Option<&Bytes> = None;
+            let mut user: Option<&Bytes> = None;
+            let mut skip_me: bool = true;
+            for pair in args[2..].chunks(2) {
+                match (pair[0].to_ascii_lowercase().as_slice(), &pair[1]) {
+                    (b"id", value) => {
+                        match std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                            Some(value) => id = Some(value),
+                            None => return error("ERR client-id should be greater than 0"),
+                        }
+                    }
+                    (b"addr", value) => addr = Some(value),
+                    (b"user", value) => user = Some(value),
+                    (b"skipme", value) => match value.to_ascii_lowercase().as_slice() {
+                        b"yes" => skip_me = true,
+                        b"no" => skip_me = false,
+                        _ => return syntax_error(),
+                    },
+                    _ => return syntax_error(),
+                }
+            }
+            let me: u64 = ctx.session.id;
+            let killed: usize = kill(ctx, |client| {
+                id.is_none_or(|id| client.id == id)
+                    && addr.is_none_or(|addr| addr_is(client, addr))
+                    && user.is_none_or(|user| client.user().is_some_and(|u| u.as_bytes() == user))
+                    && !(skip_me && client.id == me)
+            });
+            Frame::Integer(killed as i64)
+        }
+        (b"id" | b"getname" | b"setname" | b"list" | b"info" | b"kill", _) => {
+            wrong_arity(&format!("client|{}", String::from_utf8_lossy(&sub)))
+        }
+        _ => error(format!(
+            "ERR unknown subcommand '{}'. Try CLIENT LIST, CLIENT KILL or CLIENT SETNAME.",
+            String::from_utf8_lossy(&args[1])
+        )),
+    }
+}
+
+/// 关闭满足条件的连接，返回个数。连接在处理完当前命令后才关闭，所以也可以关闭自己
+pub(super) fn kill(ctx: &Ctx<'_>, filter: impl Fn(&ClientInfo) -> bool) -> usize {
+    let clients: Vec<Arc<ClientInfo>> = ctx.state.clients.list();
+    let matched: Vec<&Arc<ClientInfo>> = clients.iter().filter(|c| filter(c)).collect();
+    for client in &matched {
+        client.kill();
+    }
+    matched.len()
+}
+
+fn addr_is(client: &ClientInfo, addr: &[u8]) -> bool {
+    client
+        .addr
+        .is_some_and(|peer| peer.to_string().as_bytes() == addr)
+}
+
+/// 校验连接名，不能包含空格和换行
+fn client_name(value: &[u8]) -> Result<String, Frame> {
+    match std::str::from_utf8(value) {
+        Ok(value) if !value.contains([' ', '\n']) => Ok(value.to_string()),
+        _ => Err(error(
+            "ERR Client names cannot contain spaces, newlines or special characters.",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{client_session, render, run_in, state};
+    use crate::frame::Protocol;
+    use crate::session::Session;
+
+    #[test]
+    fn hello_switches_protocol() {
+        let db = state(2);
+        let mut session: Session = client_session();
+        let reply: String = render(&run_in(&db, &mut session, "HELLO 3 SETNAME worker"));
+        assert!(reply.contains("proto 3"), "{reply}");
+        assert_eq!(session.protocol, Protocol::Resp3);
+        assert_eq!(session.name.as_deref(), Some("worker"));
+
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        assert!(send("HELLO").contains("proto 3"));
+        assert_eq!(send("HELLO 4"), "!NOPROTO unsupported protocol version");
+        assert!(send("HELLO x").starts_with("!ERR Protocol version"));
+        assert_eq!(send("HELLO 2 SETNAME"), "!ERR syntax error");
+        assert!(send("HELLO 2").contains("proto 2"));
+        assert_eq!(session.protocol, Protocol::Resp2);
+    }
+
+    #[test]
+    fn client_names_and_kill() {
+        let db = state(2);
+        let me = db.clients.register(Some("127.0.0.1:6000".parse().unwrap()));
+        let other = db.clients.register(Some("127.0.0.1:6001".parse().unwrap()));
+        let mut session: Session = Session::new();
+        session.id = me.id;
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        assert_eq!(send("CLIENT GETNAME"), "nil");
+        assert_eq!(send("CLIENT SETNAME worker"), "OK");
+        assert_eq!(send("CLIENT GETNAME"), "worker");
+        assert!(send("CLIENT SETNAME \"a b\"").starts_with("!ERR Client names"));
+        assert_eq!(send("CLIENT ID"), me.id.to_string());
+        assert!(send("CLIENT LIST").contains("addr=127.0.0.1:6001 "));
+
+        assert_eq!(send("CLIENT KILL 127.0.0.1:7000"), "!ERR No such client");
+        // 默认跳过自己
+        assert_eq!(send(&format!("CLIENT KILL ID {}", me.id)), "0");
+        assert!(!me.is_killed());
+        assert_eq!(send("CLIENT KILL ADDR 127.0.0.1:6001 SKIPME no"), "1");
+        assert!(other.is_killed());
+        assert_eq!(send("CLIENT KILL 127.0.0.1:6000"), "OK");
+        assert!(me.is_killed());
+        assert_eq!(
+            send("CLIENT KILL ID x"),
+            "!ERR client-id should be greater than 0"
+        );
+    }
+}
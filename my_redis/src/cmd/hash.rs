@@ -0,0 +1,140 @@
+//! 哈希相关命令
+
+use super::keys::{parse_scan, scan_by_hash, scan_reply};
+use super::{key, wrong_arity, wrong_type, Ctx};
+use crate::db::{Entry, Value};
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// HSET key field value [field value ...]，返回新增字段数
+pub fn hset(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if !args.len().is_multiple_of(2) {
+        return wrong_arity("hset");
+    }
+    let key: &str = key(&args[1]);
+    if ctx.db.get(key).is_none() {
+        ctx.db
+            .insert(key.to_string(), Entry::new(Value::Hash(HashMap::new())));
+    }
+    let hash: &mut HashMap<Bytes, Bytes> = match hash_mut(ctx, key) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => unreachable!("hash was just created"),
+        Err(err) => return err,
+    };
+    let added: usize = args[2..]
+        .chunks(2)
+        .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+        .count();
+    Frame::Integer(added as i64)
+}
+
+/// HGET key field
+pub fn hget(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match hash(ctx, &args[1]) {
+        Ok(Some(hash)) => match hash.get(&args[2]) {
+            Some(value) => Frame::Bulk(value.clone()),
+            None => Frame::Null,
+        },
+        Ok(None) => Frame::Null,
+        Err(err) => err,
+    }
+}
+
+/// HGETALL key
+pub fn hgetall(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match hash(ctx, &args[1]) {
+        Ok(Some(hash)) => Frame::Map(
+            hash.iter()
+                .map(|(field, value)| (Frame::Bulk(field.clone()), Frame::Bulk(value.clone())))
+                .collect(),
+        ),
+        Ok(None) => Frame::Map(Vec::new()),
+        Err(err) => err,
+    }
+}
+
+/// HDEL key field [field ...]
+pub fn hdel(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    let hash: &mut HashMap<Bytes, Bytes> = match hash_mut(ctx, key) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return Frame::Integer(0),
+        Err(err) => return err,
+    };
+    let removed: usize = args[2..]
+        .iter()
+        .filter(|field| hash.remove(*field).is_some())
+        .count();
+    if hash.is_empty() {
+        ctx.db.remove(key);
+    }
+    Frame::Integer(removed as i64)
+}
+
+/// HLEN key
+pub fn hlen(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match hash(ctx, &args[1]) {
+        Ok(hash) => Frame::Integer(hash.map_or(0, |h| h.len()) as i64),
+        Err(err) => err,
+    }
+}
+
+/// HSCAN key cursor [MATCH pattern] [COUNT count]
+pub fn hscan(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (cursor, options) = match parse_scan(&args[2..], false) {
+        Ok(parsed) => parsed,
+        Err(err) => return err,
+    };
+    let hash: &HashMap<Bytes, Bytes> = match hash(ctx, &args[1]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return scan_reply(0, Vec::new()),
+        Err(err) => return err,
+    };
+    let (batch, next) = scan_by_hash(hash.iter(), cursor, options.count);
+    let pairs: Vec<Frame> = batch
+        .into_iter()
+        .filter(|(field, _)| options.matches(field))
+        .flat_map(|(field, value)| [Frame::Bulk(field.clone()), Frame::Bulk(value.clone())])
+        .collect();
+    scan_reply(next, pairs)
+}
+
+fn hash<'a>(ctx: &'a Ctx<'_>, arg: &Bytes) -> Result<Option<&'a HashMap<Bytes, Bytes>>, Frame> {
+    match ctx.db.get(key(arg)).map(|e| &e.value) {
+        Some(Value::Hash(hash)) => Ok(Some(hash)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+fn hash_mut<'a>(
+    ctx: &'a mut Ctx<'_>,
+    key: &str,
+) -> Result<Option<&'a mut HashMap<Bytes, Bytes>>, Frame> {
+    match ctx.db.get_mut(key).map(|e| &mut e.value) {
+        Some(Value::Hash(hash)) => Ok(Some(hash)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{reply, state};
+
+    #[test]
+    fn set_get_delete() {
+        let db = state(4);
+        assert_eq!(reply(&db, "HSET h a 1 b 2"), "2");
+        assert_eq!(reply(&db, "HSET h a 3 c 4"), "1");
+        assert_eq!(reply(&db, "HGET h a"), "3");
+        assert_eq!(reply(&db, "HGET h x"), "nil");
+        assert_eq!(reply(&db, "HLEN h"), "3");
+        let all: String = reply(&db, "HGETALL h");
+        assert!(all.contains("a 3") && all.contains("b 2") && all.contains("c 4"));
+        assert_eq!(reply(&db, "HDEL h a b c x"), "3");
+        assert_eq!(reply(&db, "EXISTS h"), "0");
+        assert!(reply(&db, "HSET h a").starts_with("!ERR wrong number"));
+    }
+}
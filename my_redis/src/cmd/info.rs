@@ -0,0 +1,292 @@
+//! INFO：按节输出服务端状态
+
+use super::Ctx;
+use crate::frame::Frame;
+use crate::replication::{LinkState, RoleInfo};
+use bytes::Bytes;
+use std::fmt::{Display, Write};
+use std::sync::atomic::Ordering;
+
+/// 不带参数时输出的节，按这个顺序
+const SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "cluster",
+    "keyspace",
+];
+
+/// INFO [section ...]
+pub fn info(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let wanted: Vec<String> = args[1..]
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg).to_ascii_lowercase())
+        .collect();
+    let all: bool = wanted.is_empty()
+        || wanted
+            .iter()
+            .any(|s| matches!(s.as_str(), "default" | "all" | "everything"));
+    let mut out: String = String::new();
+    for &section in SECTIONS {
+        if !all && !wanted.iter().any(|s| s == section) {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str("\r\n");
+        }
+        let mut title: Vec<char> = section.chars().collect();
+        title[0] = title[0].to_ascii_uppercase();
+        out.push_str("# ");
+        out.extend(title);
+        out.push_str("\r\n");
+        match section {
+            "server" => server(ctx, &mut out),
+            "clients" => clients(ctx, &mut out),
+            "memory" => memory(ctx, &mut out),
+            "persistence" => persistence(ctx, &mut out),
+            "stats" => stats(ctx, &mut out),
+            "replication" => replication(ctx, &mut out),
+            "cluster" => field(
+                &mut out,
+                "cluster_enabled",
+                u8::from(ctx.state.cluster.is_some()),
+            ),
+            _ => keyspace(ctx, &mut out),
+        }
+    }
+    Frame::Bulk(Bytes::from(out))
+}
+
+fn field(out: &mut String, name: &str, value: impl Display) {
+    let _ = write!(out, "{name}:{value}\r\n");
+}
+
+fn server(ctx: &Ctx<'_>, out: &mut String) {
+    let uptime: u64 = ctx.state.started.elapsed().as_secs();
+    field(out, "redis_version", env!("CARGO_PKG_VERSION"));
+    let mode: &str = if ctx.state.cluster.is_some() {
+        "cluster"
+    } else {
+        "standalone"
+    };
+    field(out, "redis_mode", mode);
+    field(
+        out,
+        "os",
+        format_args!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+    );
+    field(out, "arch_bits", usize::BITS);
+    field(out, "process_id", std::process::id());
+    field(out, "tcp_port", ctx.state.config().port);
+    field(out, "uptime_in_seconds", uptime);
+    field(out, "uptime_in_days", uptime / 86400);
+}
+
+fn clients(ctx: &Ctx<'_>, out: &mut String) {
+    field(out, "connected_clients", ctx.state.clients.len());
+    field(out, "blocked_clients", ctx.state.blocking.blocked_clients());
+    field(out, "maxclients", ctx.state.config().max_clients);
+}
+
+fn memory(ctx: &Ctx<'_>, out: &mut String) {
+    let used: u64 = ctx.state.db.used_memory() as u64;
+    let config = ctx.state.config();
+    field(out, "used_memory", used);
+    field(out, "used_memory_human", human(used));
+    field(out, "maxmemory", config.maxmemory);
+    field(out, "maxmemory_human", human(config.maxmemory));
+    field(out, "maxmemory_policy", config.maxmemory_policy.as_str());
+}
+
+fn persistence(ctx: &Ctx<'_>, out: &mut String) {
+    let rdb = &ctx.state.rdb;
+    let flag = |b: bool| u8::from(b);
+    field(
+        out,
+        "rdb_changes_since_last_save",
+        rdb.dirty.load(Ordering::Relaxed),
+    );
+    field(
+        out,
+        "rdb_bgsave_in_progress",
+        flag(rdb.bgsave_in_progress.load(Ordering::Relaxed)),
+    );
+    field(
+        out,
+        "rdb_last_save_time",
+        rdb.last_save.load(Ordering::Relaxed),
+    );
+    field(
+        out,
+        "rdb_last_bgsave_status",
+        if rdb.last_bgsave_ok.load(Ordering::Relaxed) {
+            "ok"
+        } else {
+            "err"
+        },
+    );
+    let aof = ctx.state.aof.get();
+    field(out, "aof_enabled", flag(aof.is_some()));
+    field(
+        out,
+        "aof_rewrite_in_progress",
+        flag(aof.is_some_and(|aof| aof.rewrite_in_progress.load(Ordering::Relaxed))),
+    );
+}
+
+fn stats(ctx: &Ctx<'_>, out: &mut String) {
+    let clients = &ctx.state.clients;
+    let (full, partial_ok, partial_err) = ctx.state.repl.stats();
+    field(
+        out,
+        "total_connections_received",
+        clients.total_connections.load(Ordering::Relaxed),
+    );
+    field(
+        out,
+        "total_commands_processed",
+        clients.total_commands.load(Ordering::Relaxed),
+    );
+    field(
+        out,
+        "rejected_connections",
+        clients.rejected_connections.load(Ordering::Relaxed),
+    );
+    field(out, "expired_keys", ctx.state.db.expired_keys());
+    field(
+        out,
+        "evicted_keys",
+        ctx.state.evicted_keys.load(Ordering::Relaxed),
+    );
+    field(out, "sync_full", full);
+    field(out, "sync_partial_ok", partial_ok);
+    field(out, "sync_partial_err", partial_err);
+}
+
+fn replication(ctx: &Ctx<'_>, out: &mut String) {
+    let (replid, _) = ctx.state.repl.position();
+    match ctx.state.repl.role() {
+        RoleInfo::Master { offset, replicas } => {
+            field(out, "role", "master");
+            field(out, "connected_slaves", replicas.len());
+            for (i, (ip, port, ack)) in replicas.into_iter().enumerate() {
+                field(
+                    out,
+                    &format!("slave{i}"),
+                    format_args!("ip={ip},port={port},state=online,offset={ack}"),
+                );
+            }
+            field(out, "master_replid", replid);
+            field(out, "master_repl_offset", offset);
+        }
+        RoleInfo::Replica {
+            host,
+            port,
+            link,
+            offset,
+        } => {
+            field(out, "role", "slave");
+            field(out, "master_host", host);
+            field(out, "master_port", port);
+            field(
+                out,
+                "master_link_status",
+                if link == LinkState::Connected {
+                    "up"
+                } else {
+                    "down"
+                },
+            );
+            field(out, "slave_repl_offset", offset);
+            field(out, "master_replid", replid);
+            field(out, "master_repl_offset", offset);
+        }
+    }
+}
+
+/// INFO 是 `lock_all` 的命令，`ctx.db` 持有全部分片。每个非空的数据库一行，
+/// 和 DBSIZE 一样包括尚未清理的过期 key，分片里记着计数，不用遍历数据
+fn keyspace(ctx: &mut Ctx<'_>, out: &mut String) {
+    let selected: usize = ctx.db.selected();
+    for db in 0..ctx.db.databases() {
+        ctx.db.select(db);
+        let keys: usize = ctx.db.len();
+        let (expires, avg_ttl) = ctx.db.expiry_stats();
+        if keys > 0 {
+            field(
+                out,
+                &format!("db{db}"),
+                format_args!("keys={keys},expires={expires},avg_ttl={avg_ttl}"),
+            );
+        }
+    }
+    ctx.db.select(selected);
+}
+
+/// 和 Redis 一样用 1024 进位，保留两位小数
+fn human(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+    let mut value: f64 = bytes as f64 / 1024.0;
+    let mut unit: usize = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.2}{}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::human;
+    use crate::cmd::test::{render, run, state};
+
+    #[test]
+    fn sections_and_keyspace() {
+        let state = state(4);
+        run(&state, "SET a 1");
+        run(&state, "SET b 2 PX 100000");
+        run(&state, "MOVE a 3");
+        let all: String = render(&run(&state, "INFO"));
+        for title in ["# Server", "# Clients", "# Memory", "# Stats", "# Keyspace"] {
+            assert!(all.contains(title), "{all}");
+        }
+        assert!(all.contains("role:master\r\n"));
+        assert!(all.contains("db0:keys=1,expires=1,avg_ttl="));
+        assert!(all.contains("db3:keys=1,expires=0,avg_ttl=0\r\n"));
+        assert!(all.contains("redis_mode:standalone\r\n"));
+
+        // 过期时间的计数跟着修改、删除和交换数据库变化
+        run(&state, "SET c 3");
+        run(&state, "EXPIRE c 1000");
+        run(&state, "PERSIST b");
+        run(&state, "SWAPDB 0 3");
+        let keyspace: String = render(&run(&state, "INFO keyspace"));
+        assert!(keyspace.contains("db0:keys=1,expires=0,avg_ttl=0\r\n"));
+        assert!(keyspace.contains("db3:keys=2,expires=1,avg_ttl="));
+        let avg_ttl: u64 = keyspace
+            .split("avg_ttl=")
+            .last()
+            .and_then(|rest| rest.trim_end().parse().ok())
+            .unwrap();
+        assert!(avg_ttl > 990_000 && avg_ttl <= 1_000_000, "{keyspace}");
+        run(&state, "SWAPDB 0 3");
+        run(&state, "SET c 4");
+        let keyspace: String = render(&run(&state, "INFO keyspace"));
+        assert!(keyspace.contains("db0:keys=2,expires=0,avg_ttl=0\r\n"));
+        run(&state, "FLUSHALL");
+        assert_eq!(render(&run(&state, "INFO keyspace")), "# Keyspace\r\n");
+
+        let only: String = render(&run(&state, "INFO keyspace stats"));
+        assert!(only.contains("# Stats") && only.contains("# Keyspace"));
+        assert!(!only.contains("# Server"));
+        assert!(only.contains("total_commands_processed:"));
+        assert_eq!(human(1536), "1.50K");
+        assert_eq!(human(100), "100B");
+    }
+}
@@ -0,0 +1,670 @@
+//! 与值类型无关的 key 操作
+
+use super::{error, in_db, key, ok, parse_db, parse_int, syntax_error, Ctx};
+use crate::db::{now_ms, Entry, Value};
+use crate::dict::Dict;
+use crate::frame::Frame;
+use crate::glob;
+use crate::rdb;
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// DEL key [key ...]
+pub fn del(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let removed: usize = args[1..]
+        .iter()
+        .filter(|arg| ctx.db.remove(key(arg)).is_some())
+        .count();
+    Frame::Integer(removed as i64)
+}
+
+/// EXISTS key [key ...]，重复的 key 会被重复计数
+pub fn exists(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let found: usize = args[1..]
+        .iter()
+        .filter(|arg| ctx.db.get(key(arg)).is_some())
+        .count();
+    Frame::Integer(found as i64)
+}
+
+/// EXPIRE key seconds
+pub fn expire(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match parse_int::<u64>(&args[2]) {
+        Ok(secs) => set_expire(ctx, &args[1], secs.saturating_mul(1000)),
+        Err(err) => err,
+    }
+}
+
+/// PEXPIRE key milliseconds
+pub fn pexpire(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match parse_int::<u64>(&args[2]) {
+        Ok(ms) => set_expire(ctx, &args[1], ms),
+        Err(err) => err,
+    }
+}
+
+/// PEXPIREAT key unix-time-milliseconds
+pub fn pexpireat(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match parse_int::<u64>(&args[2]) {
+        Ok(at) => set_expire_at(ctx, &args[1], at),
+        Err(err) => err,
+    }
+}
+
+/// EXPIREAT key unix-time-seconds
+pub fn expireat(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match parse_int::<u64>(&args[2]) {
+        Ok(at) => set_expire_at(ctx, &args[1], at.saturating_mul(1000)),
+        Err(err) => err,
+    }
+}
+
+fn set_expire(ctx: &mut Ctx<'_>, arg: &Bytes, ms: u64) -> Frame {
+    match now_ms().checked_add(ms) {
+        Some(at) => set_expire_at(ctx, arg, at),
+        None => error("ERR invalid expire time in 'expire' command"),
+    }
+}
+
+fn set_expire_at(ctx: &mut Ctx<'_>, arg: &Bytes, at: u64) -> Frame {
+    let key: &str = key(arg);
+    match ctx.db.get_mut(key) {
+        Some(entry) => {
+            entry.expires_at = Some(at);
+            Frame::Integer(1)
+        }
+        None => Frame::Integer(0),
+    }
+}
+
+/// PERSIST key
+pub fn persist(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match ctx.db.get_mut(key(&args[1])) {
+        Some(entry) if entry.expires_at.is_some() => {
+            entry.expires_at = None;
+            Frame::Integer(1)
+        }
+        _ => Frame::Integer(0),
+    }
+}
+
+/// TYPE key
+pub fn key_type(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let name: &str = ctx
+        .db
+        .get(key(&args[1]))
+        .map_or("none", |entry| entry.value.type_name());
+    Frame::Simple(name.to_string())
+}
+
+/// RENAME key newkey，保留过期时间，覆盖 newkey 原有的值
+pub fn rename(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (from, to): (&str, &str) = (key(&args[1]), key(&args[2]));
+    let entry: Entry = match ctx.db.remove(from) {
+        Some(entry) => entry,
+        None => return error("ERR no such key"),
+    };
+    ctx.db.insert(to.to_string(), entry);
+    // 改名得到的列表或流上可能有客户端在等
+    serve_blocked(ctx, to);
+    ok()
+}
+
+/// MOVE key db，把 key 移到另一个数据库，目标数据库已有同名 key 时什么都不做
+pub fn move_key(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    if ctx.state.cluster.is_some() {
+        return error("ERR MOVE is not allowed in cluster mode");
+    }
+    let target: usize = match parse_db(&ctx.db, &args[2]) {
+        Ok(target) => target,
+        Err(err) => return err,
+    };
+    if target == ctx.db.selected() {
+        return error("ERR source and destination objects are the same");
+    }
+    // 同一个 key 在所有数据库中都落在同一个分片上，已经锁住了
+    if in_db(ctx, target, |ctx| ctx.db.get(key).is_some()) {
+        return Frame::Integer(0);
+    }
+    let entry: Entry = match ctx.db.remove(key) {
+        Some(entry) => entry,
+        None => return Frame::Integer(0),
+    };
+    in_db(ctx, target, |ctx| {
+        ctx.db.insert(key.to_string(), entry);
+        serve_blocked(ctx, key);
+    });
+    Frame::Integer(1)
+}
+
+/// DUMP key，序列化的格式与快照相同。其中的过期时间会被忽略，由 RESTORE 的参数决定
+pub fn dump(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: String = key(&args[1]).to_string();
+    match ctx.db.get(&key) {
+        Some(entry) => Frame::Bulk(Bytes::from(rdb::encode(std::iter::once((0, &key, entry))))),
+        None => Frame::Null,
+    }
+}
+
+/// RESTORE key ttl serialized-value [REPLACE] [ABSTTL]，ttl 为 0 表示不过期
+pub fn restore(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    let ttl: u64 = match parse_int(&args[2]) {
+        Ok(ttl) => ttl,
+        Err(_) => return error("ERR Invalid TTL value, must be >= 0"),
+    };
+    let (mut replace, mut absttl): (bool, bool) = (false, false);
+    for option in &args[4..] {
+        match option.to_ascii_lowercase().as_slice() {
+            b"replace" => replace = true,
+            b"absttl" => absttl = true,
+            _ => return syntax_error(),
+        }
+    }
+    let mut entry: Entry = match rdb::decode(&args[3]) {
+        Ok(mut entries) if entries.len() == 1 => entries.pop().expect("one entry").2,
+        _ => return error("ERR DUMP payload version or checksum are wrong"),
+    };
+    if !replace && ctx.db.get(key).is_some() {
+        return error("BUSYKEY Target key name already exists.");
+    }
+    entry.expires_at = match (ttl, absttl) {
+        (0, _) => None,
+        (at, true) => Some(at),
+        (ms, false) => Some(now_ms().saturating_add(ms)),
+    };
+    ctx.db.insert(key.to_string(), entry);
+    serve_blocked(ctx, key);
+    ok()
+}
+
+/// 新放进来的列表或流上可能有客户端在等，把数据交给它们
+fn serve_blocked(ctx: &mut Ctx<'_>, key: &str) {
+    let state = ctx.state;
+    let db: usize = ctx.db.selected();
+    if let Some(Value::List(list)) = ctx.db.get_mut(key).map(|entry| &mut entry.value) {
+        let mut served: Vec<Vec<Bytes>> = state.blocking.serve(db, key, list);
+        if list.is_empty() {
+            ctx.db.remove(key);
+        }
+        ctx.propagate.append(&mut served);
+    }
+    super::stream::serve_blocked(ctx, key);
+}
+
+/// SWAPDB index1 index2，调用时已经锁住全部分片
+pub fn swapdb(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if ctx.state.cluster.is_some() {
+        return error("ERR SWAPDB is not allowed in cluster mode");
+    }
+    let (a, b): (usize, usize) = match (parse_db(&ctx.db, &args[1]), parse_db(&ctx.db, &args[2])) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    ctx.db.swap(a, b);
+    // 换过来的列表或流上可能有客户端在等
+    for db in [a, b] {
+        in_db(ctx, db, |ctx| {
+            for key in ctx.state.blocking.keys(db) {
+                serve_blocked(ctx, &key);
+            }
+        });
+    }
+    ok()
+}
+
+/// FLUSHDB [ASYNC|SYNC]，调用时已经锁住全部分片
+pub fn flushdb(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let lazy: bool = match flush_mode(args) {
+        Ok(lazy) => lazy,
+        Err(err) => return err,
+    };
+    let mut detached: Vec<Dict<String, Entry>> = Vec::new();
+    flush(ctx, lazy, &mut detached);
+    free_in_background(detached);
+    ok()
+}
+
+/// FLUSHALL [ASYNC|SYNC]，调用时已经锁住全部分片
+pub fn flushall(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let lazy: bool = match flush_mode(args) {
+        Ok(lazy) => lazy,
+        Err(err) => return err,
+    };
+    let mut detached: Vec<Dict<String, Entry>> = Vec::new();
+    for db in 0..ctx.db.databases() {
+        in_db(ctx, db, |ctx| flush(ctx, lazy, &mut detached));
+    }
+    free_in_background(detached);
+    ok()
+}
+
+/// 解析 FLUSHDB/FLUSHALL 的参数，`ASYNC` 返回 `true`
+fn flush_mode(args: &[Bytes]) -> Result<bool, Frame> {
+    match args {
+        [_] => Ok(false),
+        [_, mode] if mode.eq_ignore_ascii_case(b"sync") => Ok(false),
+        [_, mode] if mode.eq_ignore_ascii_case(b"async") => Ok(true),
+        _ => Err(syntax_error()),
+    }
+}
+
+/// 清空选中的数据库。`lazy` 时整张表摘下来放进 `detached`，由 `free_in_background` 释放
+fn flush(ctx: &mut Ctx<'_>, lazy: bool, detached: &mut Vec<Dict<String, Entry>>) {
+    if lazy {
+        detached.extend(ctx.db.detach());
+    } else {
+        ctx.db.clear();
+    }
+}
+
+/// 摘下来的表都交给同一个后台线程释放，大集合不会卡住其他命令
+fn free_in_background(detached: Vec<Dict<String, Entry>>) {
+    if !detached.is_empty() {
+        std::thread::spawn(move || drop(detached));
+    }
+}
+
+/// KEYS pattern，调用时已经锁住全部分片
+pub fn keys(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    Frame::Array(
+        ctx.db
+            .iter()
+            .filter(|(key, _)| glob::matches(&args[1], key.as_bytes(), false))
+            .map(|(key, _)| Frame::Bulk(Bytes::copy_from_slice(key.as_bytes())))
+            .collect(),
+    )
+}
+
+/// DBSIZE，包括尚未清理的过期 key
+pub fn dbsize(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    Frame::Integer(ctx.db.len() as i64)
+}
+
+/// RANDOMKEY
+pub fn randomkey(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    match ctx.db.random_key() {
+        Some(key) => Frame::Bulk(Bytes::copy_from_slice(key.as_bytes())),
+        None => Frame::Null,
+    }
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+pub fn scan(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (cursor, options) = match parse_scan(&args[1..], true) {
+        Ok(parsed) => parsed,
+        Err(err) => return err,
+    };
+    let mut keys: Vec<Frame> = Vec::new();
+    let next: u64 = ctx.db.scan(cursor, options.count, |key, entry| {
+        let kind_matches: bool = options
+            .kind
+            .as_deref()
+            .is_none_or(|kind| kind.eq_ignore_ascii_case(entry.value.type_name()));
+        if kind_matches && options.matches(key.as_bytes()) {
+            keys.push(Frame::Bulk(Bytes::copy_from_slice(key.as_bytes())));
+        }
+    });
+    scan_reply(next, keys)
+}
+
+/// SCAN 系列命令的可选参数
+pub(super) struct ScanOptions {
+    pattern: Option<Bytes>,
+    /// 每次大约返回多少个元素，只是一个提示
+    pub count: usize,
+    /// 只返回这种类型的 key，只有 SCAN 支持
+    kind: Option<String>,
+}
+
+impl ScanOptions {
+    /// 是否匹配 MATCH 给出的模式
+    pub fn matches(&self, element: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob::matches(pattern, element, false))
+    }
+}
+
+/// 解析 `cursor [MATCH pattern] [COUNT count] [TYPE type]`
+pub(super) fn parse_scan(args: &[Bytes], allow_type: bool) -> Result<(u64, ScanOptions), Frame> {
+    let cursor: u64 = std::str::from_utf8(&args[0])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| error("ERR invalid cursor"))?;
+    let mut options: ScanOptions = ScanOptions {
+        pattern: None,
+        count: 10,
+        kind: None,
+    };
+    let mut rest = args[1..].iter();
+    while let Some(name) = rest.next() {
+        let value: &Bytes = rest.next().ok_or_else(syntax_error)?;
+        match name.to_ascii_lowercase().as_slice() {
+            b"match" => options.pattern = Some(value.clone()),
+            b"count" => {
+                options.count = parse_int(value)?;
+                if options.count == 0 {
+                    return Err(syntax_error());
+                }
+            }
+            b"type" if allow_type => {
+                options.kind = Some(String::from_utf8_lossy(value).into_owned())
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok((cursor, options))
+}
+
+/// 按元素的哈希值从小到大分批遍历集合，返回这一批和下一个游标，游标是下一批的最小哈希值
+///
+/// 顺序只取决于元素本身，与集合内部的哈希表怎样扩容无关，所以遍历期间一直存在的元素都会返回，
+/// 也不会重复。代价是每次调用都要看一遍整个集合。
+pub(super) fn scan_by_hash<'a, T>(
+    items: impl Iterator<Item = (&'a Bytes, T)>,
+    cursor: u64,
+    count: usize,
+) -> (Vec<(&'a Bytes, T)>, u64) {
+    let mut found: Vec<(u64, (&'a Bytes, T))> = items
+        .map(|item| (element_hash(item.0), item))
+        .filter(|(hash, _)| *hash >= cursor)
+        .collect();
+    if found.len() <= count {
+        return (found.into_iter().map(|(_, item)| item).collect(), 0);
+    }
+    found.select_nth_unstable_by_key(count - 1, |(hash, _)| *hash);
+    let last: u64 = found[count - 1].0;
+    // 哈希值相同的元素必须在同一批里返回
+    let batch: Vec<(&'a Bytes, T)> = found
+        .into_iter()
+        .filter(|(hash, _)| *hash <= last)
+        .map(|(_, item)| item)
+        .collect();
+    (batch, last.wrapping_add(1))
+}
+
+/// 固定种子的哈希，同一个元素在不同的调用之间结果相同
+fn element_hash(element: &Bytes) -> u64 {
+    let mut hasher: DefaultHasher = DefaultHasher::new();
+    element.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// SCAN 系列命令的回复：下一个游标和这一批元素
+pub(super) fn scan_reply(cursor: u64, elements: Vec<Frame>) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::from(cursor.to_string())),
+        Frame::Array(elements),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{client_session, render, reply, run, run_in, state};
+    use crate::cmd::{dispatch, execute, Outcome};
+    use crate::frame::Frame;
+    use crate::session::Session;
+    use bytes::Bytes;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    /// 用给定的命令模板反复调用 SCAN 系列命令直到游标回到 0，返回收到的全部元素
+    fn scan_all(
+        db: &Arc<crate::state::State>,
+        cmd: impl Fn(&str) -> String,
+        mut between: impl FnMut(usize),
+    ) -> Vec<String> {
+        let mut cursor: String = "0".to_string();
+        let mut seen: Vec<String> = Vec::new();
+        for call in 0.. {
+            let (next, batch) = match run(db, &cmd(&cursor)) {
+                Frame::Array(mut parts) => match (parts.remove(0), parts.remove(0)) {
+                    (Frame::Bulk(next), Frame::Array(batch)) => (next, batch),
+                    other => panic!("unexpected {other:?}"),
+                },
+                other => panic!("unexpected {other:?}"),
+            };
+            seen.extend(batch.iter().map(|frame| match frame {
+                Frame::Bulk(data) => String::from_utf8_lossy(data).into_owned(),
+                other => panic!("unexpected {other:?}"),
+            }));
+            cursor = String::from_utf8_lossy(&next).into_owned();
+            if cursor == "0" {
+                break;
+            }
+            between(call);
+        }
+        seen
+    }
+
+    #[test]
+    fn scan_returns_every_key_while_the_table_grows() {
+        let db = state(4);
+        for i in 0..500 {
+            run(&db, &format!("SET key:{i} {i}"));
+        }
+        run(&db, "RPUSH list:0 a");
+        let seen: HashSet<String> = scan_all(
+            &db,
+            |cursor| format!("SCAN {cursor} COUNT 7"),
+            |call| {
+                // 遍历途中插入大量 key，每个分片的哈希表都会扩容几次
+                if call == 10 {
+                    for i in 0..4000 {
+                        run(&db, &format!("SET other:{i} {i}"));
+                    }
+                }
+            },
+        )
+        .into_iter()
+        .collect();
+        assert!((0..500).all(|i| seen.contains(&format!("key:{i}"))));
+        assert!(seen.contains("list:0"));
+
+        let lists: Vec<String> = scan_all(&db, |c| format!("SCAN {c} COUNT 100 TYPE list"), |_| {});
+        assert_eq!(lists, vec!["list:0"]);
+        let matched: Vec<String> = scan_all(&db, |c| format!("SCAN {c} MATCH key:1?"), |_| {});
+        assert_eq!(matched.len(), 10);
+        assert_eq!(reply(&db, "SCAN x"), "!ERR invalid cursor");
+        assert_eq!(reply(&db, "SCAN 0 COUNT 0"), "!ERR syntax error");
+    }
+
+    #[test]
+    fn keys_type_rename_dbsize_randomkey() {
+        let db = state(4);
+        assert_eq!(reply(&db, "RANDOMKEY"), "nil");
+        run(&db, "MSET user:1 a user:2 b other c");
+        run(&db, "SADD tags x");
+        let mut keys: Vec<String> = match run(&db, "KEYS user:*") {
+            Frame::Array(keys) => keys.iter().map(crate::cmd::test::render).collect(),
+            other => panic!("unexpected {other:?}"),
+        };
+        keys.sort();
+        assert_eq!(keys, vec!["user:1", "user:2"]);
+        assert_eq!(reply(&db, "DBSIZE"), "4");
+        assert_eq!(reply(&db, "TYPE tags"), "set");
+        assert_eq!(reply(&db, "TYPE missing"), "none");
+
+        run(&db, "EXPIRE user:1 100");
+        assert_eq!(reply(&db, "RENAME user:1 other"), "OK");
+        assert_eq!(reply(&db, "GET other"), "a");
+        assert_eq!(reply(&db, "EXISTS user:1"), "0");
+        assert!(db.db.lock_all().get("other").unwrap().expires_at.is_some());
+        assert_eq!(reply(&db, "RENAME user:1 x"), "!ERR no such key");
+        assert_eq!(reply(&db, "RENAME tags tags"), "OK");
+
+        let names: [&str; 3] = ["user:2", "other", "tags"];
+        for _ in 0..20 {
+            assert!(names.contains(&reply(&db, "RANDOMKEY").as_str()));
+        }
+    }
+
+    #[test]
+    fn hscan_and_sscan_cover_the_whole_collection() {
+        let db = state(2);
+        for i in 0..300 {
+            run(&db, &format!("HSET h f{i} v{i}"));
+            run(&db, &format!("SADD s m{i}"));
+        }
+        let pairs: Vec<String> = scan_all(&db, |c| format!("HSCAN h {c} COUNT 20"), |_| {});
+        assert_eq!(pairs.len(), 600);
+        let fields: HashSet<&String> = pairs.iter().step_by(2).collect();
+        assert_eq!(fields.len(), 300);
+        assert!(pairs
+            .chunks(2)
+            .all(|pair| pair[1] == pair[0].replace('f', "v")));
+
+        let members: Vec<String> = scan_all(
+            &db,
+            |c| format!("SSCAN s {c} COUNT 15"),
+            |call| {
+                // 遍历途中集合扩容，已有成员的顺序不受影响，也不会重复返回
+                if call == 3 {
+                    for i in 300..2000 {
+                        run(&db, &format!("SADD s m{i}"));
+                    }
+                }
+            },
+        );
+        let unique: HashSet<&String> = members.iter().collect();
+        assert_eq!(unique.len(), members.len());
+        assert!((0..300).all(|i| unique.contains(&format!("m{i}"))));
+
+        assert_eq!(reply(&db, "SSCAN s 0 MATCH m1 COUNT 5000"), "[0 [m1]]");
+        assert_eq!(reply(&db, "SSCAN none 0"), "[0 []]");
+        assert_eq!(reply(&db, "HSCAN h 0 TYPE hash"), "!ERR syntax error");
+        assert!(reply(&db, "SSCAN h 0").starts_with("!WRONGTYPE"));
+    }
+
+    #[test]
+    fn dump_and_restore() {
+        let db = state(2);
+        run(&db, "RPUSH l a b c");
+        run(&db, "PEXPIRE l 100000");
+        let payload: Bytes = match run(&db, "DUMP l") {
+            Frame::Bulk(payload) => payload,
+            other => panic!("unexpected {other:?}"),
+        };
+        assert_eq!(reply(&db, "DUMP none"), "nil");
+        let restore = |key: &str, ttl: &str, options: &[&str]| -> String {
+            let mut args: Vec<Bytes> = vec![
+                Bytes::from("RESTORE"),
+                Bytes::from(key.to_string()),
+                Bytes::from(ttl.to_string()),
+                payload.clone(),
+            ];
+            args.extend(options.iter().map(|option| Bytes::from(option.to_string())));
+            render(&execute(&db, &args))
+        };
+        assert_eq!(restore("copy", "0", &[]), "OK");
+        assert_eq!(reply(&db, "LRANGE copy 0 -1"), "[a b c]");
+        // DUMP 中的过期时间不会带过去
+        assert_eq!(reply(&db, "PERSIST copy"), "0");
+        assert!(restore("copy", "0", &[]).starts_with("!BUSYKEY"));
+        assert_eq!(restore("copy", "5000", &["REPLACE"]), "OK");
+        assert_eq!(reply(&db, "PERSIST copy"), "1");
+        assert_eq!(
+            render(&execute(
+                &db,
+                &[
+                    Bytes::from("RESTORE"),
+                    Bytes::from("bad"),
+                    Bytes::from("0"),
+                    Bytes::from("junk"),
+                ]
+            )),
+            "!ERR DUMP payload version or checksum are wrong"
+        );
+    }
+
+    #[test]
+    fn select_move_and_swapdb() {
+        let db = state(4);
+        let mut session: Session = Session::new();
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        send("SET a zero");
+        assert_eq!(send("SELECT 1"), "OK");
+        assert_eq!(send("GET a"), "nil");
+        send("SET a one");
+        send("RPUSH l x");
+        assert_eq!(send("DBSIZE"), "2");
+        assert_eq!(send("SELECT 16"), "!ERR DB index is out of range");
+        assert!(send("SELECT x").starts_with("!ERR value is not an integer"));
+
+        // 目标数据库已有同名 key 时不移动
+        assert_eq!(send("MOVE a 0"), "0");
+        assert_eq!(send("MOVE l 0"), "1");
+        assert_eq!(send("MOVE l 0"), "0");
+        assert_eq!(
+            send("MOVE a 1"),
+            "!ERR source and destination objects are the same"
+        );
+        assert_eq!(reply(&db, "LRANGE l 0 -1"), "[x]");
+
+        assert_eq!(send("SWAPDB 0 1"), "OK");
+        assert_eq!(send("GET a"), "zero");
+        assert_eq!(send("EXISTS l"), "1");
+        assert_eq!(reply(&db, "GET a"), "one");
+        assert_eq!(send("SWAPDB 0 99"), "!ERR DB index is out of range");
+    }
+
+    #[test]
+    fn move_and_swapdb_serve_blocked_clients() {
+        let db = state(4);
+        let mut waiter: Session = client_session();
+        run_in(&db, &mut waiter, "SELECT 2");
+        let args: Vec<Bytes> = ["BLPOP", "q", "0"]
+            .iter()
+            .map(|s| Bytes::from(*s))
+            .collect();
+        let mut blocked = match dispatch(&db, &mut waiter, &args) {
+            Outcome::Block(blocked) => blocked,
+            other => panic!("expected to block, got {other:?}"),
+        };
+        reply(&db, "RPUSH q a b");
+        assert!(blocked.rx.try_recv().is_err());
+        assert_eq!(reply(&db, "MOVE q 2"), "1");
+        assert_eq!(render(&blocked.rx.try_recv().unwrap()), "[q a]");
+
+        assert_eq!(render(&run_in(&db, &mut waiter, "LPOP q")), "b");
+        let mut blocked = match dispatch(&db, &mut waiter, &args) {
+            Outcome::Block(blocked) => blocked,
+            other => panic!("expected to block, got {other:?}"),
+        };
+        reply(&db, "RPUSH q c");
+        assert_eq!(reply(&db, "SWAPDB 0 2"), "OK");
+        assert_eq!(render(&blocked.rx.try_recv().unwrap()), "[q c]");
+        assert_eq!(reply(&db, "EXISTS q"), "0");
+    }
+
+    #[test]
+    fn flushdb_and_flushall() {
+        let db = state(4);
+        let mut session: Session = Session::new();
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        send("MSET a 1 b 2");
+        send("SELECT 5");
+        send("MSET a 1 c 3");
+        send("HSET h f v");
+        assert_eq!(send("FLUSHDB"), "OK");
+        assert_eq!(send("DBSIZE"), "0");
+        assert_eq!(reply(&db, "DBSIZE"), "2");
+        send("RPUSH l x y");
+        assert_eq!(send("FLUSHDB ASYNC"), "OK");
+        assert_eq!(send("EXISTS l"), "0");
+        assert!(send("FLUSHDB NOW").starts_with("!ERR syntax error"));
+
+        send("SET d 4");
+        assert_eq!(send("FLUSHALL ASYNC"), "OK");
+        assert_eq!(send("DBSIZE"), "0");
+        assert_eq!(reply(&db, "DBSIZE"), "0");
+        // 摘下来的数据不再计入内存统计
+        assert_eq!(db.db.used_memory(), 0);
+        send("SET e 5");
+        assert_eq!(send("FLUSHALL"), "OK");
+        assert_eq!(db.db.used_memory(), 0);
+    }
+}
@@ -0,0 +1,267 @@
+//! 列表相关命令
+
+use super::{error, key, parse_int, wrong_type, Ctx};
+use crate::db::{Entry, Value};
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// LPUSH key element [element ...]
+pub fn lpush(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    push(ctx, args, true)
+}
+
+/// RPUSH key element [element ...]
+pub fn rpush(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    push(ctx, args, false)
+}
+
+fn push(ctx: &mut Ctx<'_>, args: &[Bytes], front: bool) -> Frame {
+    let key: &str = key(&args[1]);
+    let state = ctx.state;
+    let db: usize = ctx.db.selected();
+    let list: &mut VecDeque<Bytes> = match list_or_create(ctx, key) {
+        Ok(list) => list,
+        Err(err) => return err,
+    };
+    for element in &args[2..] {
+        if front {
+            list.push_front(element.clone());
+        } else {
+            list.push_back(element.clone());
+        }
+    }
+    // 返回值是推入后、服务阻塞客户端之前的长度，与 Redis 一致
+    let len: usize = list.len();
+    let mut served: Vec<Vec<Bytes>> = state.blocking.serve(db, key, list);
+    if list.is_empty() {
+        ctx.db.remove(key);
+    }
+    ctx.propagate.append(&mut served);
+    Frame::Integer(len as i64)
+}
+
+/// LPOP key [count]
+pub fn lpop(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    pop(ctx, args, true)
+}
+
+/// RPOP key [count]
+pub fn rpop(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    pop(ctx, args, false)
+}
+
+fn pop(ctx: &mut Ctx<'_>, args: &[Bytes], front: bool) -> Frame {
+    let count: Option<usize> = match args.get(2).map(parse_int) {
+        Some(Ok(count)) => Some(count),
+        Some(Err(err)) => return err,
+        None => None,
+    };
+    let key: &str = key(&args[1]);
+    let list: &mut VecDeque<Bytes> = match list_mut(ctx, key) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Frame::Null,
+        Err(err) => return err,
+    };
+    let mut popped: Vec<Frame> = Vec::new();
+    for _ in 0..count.unwrap_or(1) {
+        let element: Option<Bytes> = if front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+        match element {
+            Some(element) => popped.push(Frame::Bulk(element)),
+            None => break,
+        }
+    }
+    if list.is_empty() {
+        ctx.db.remove(key);
+    }
+    match count {
+        Some(_) => Frame::Array(popped),
+        None => popped.pop().unwrap_or(Frame::Null),
+    }
+}
+
+/// BLPOP key [key ...] timeout
+pub fn blpop(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    blocking_pop(ctx, args, true)
+}
+
+/// BRPOP key [key ...] timeout
+pub fn brpop(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    blocking_pop(ctx, args, false)
+}
+
+/// 按顺序检查每个 key，从第一个非空列表弹出；都为空时在所有 key 上排队等待
+fn blocking_pop(ctx: &mut Ctx<'_>, args: &[Bytes], front: bool) -> Frame {
+    let timeout: f64 = match std::str::from_utf8(&args[args.len() - 1])
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|t| t.is_finite())
+    {
+        Some(timeout) if timeout >= 0.0 => timeout,
+        Some(_) => return error("ERR timeout is negative"),
+        None => return error("ERR timeout is not a float or out of range"),
+    };
+    // 0 表示一直等待
+    let timeout: Option<Duration> = match Duration::try_from_secs_f64(timeout) {
+        Ok(duration) => (timeout > 0.0).then_some(duration),
+        Err(_) => return error("ERR timeout is out of range"),
+    };
+    let keys: Vec<&str> = args[1..args.len() - 1].iter().map(key).collect();
+    for key in &keys {
+        let list: &mut VecDeque<Bytes> = match list_mut(ctx, key) {
+            Ok(Some(list)) => list,
+            Ok(None) => continue,
+            Err(err) => return err,
+        };
+        let element: Bytes = if front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        }
+        .expect("empty lists are removed");
+        if list.is_empty() {
+            ctx.db.remove(key);
+        }
+        return Frame::Array(vec![
+            Frame::Bulk(Bytes::copy_from_slice(key.as_bytes())),
+            Frame::Bulk(element),
+        ]);
+    }
+    if ctx.may_block {
+        // 仍然持有这些 key 的分片锁，检查和排队之间不会有推入插进来
+        ctx.blocked = Some(
+            ctx.state
+                .blocking
+                .block(ctx.db.selected(), &keys, front, timeout),
+        );
+    }
+    Frame::Null
+}
+
+/// LRANGE key start stop
+pub fn lrange(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (start, stop): (i64, i64) = match (parse_int(&args[2]), parse_int(&args[3])) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    let list: &VecDeque<Bytes> = match ctx.db.get(key(&args[1])).map(|e| &e.value) {
+        Some(Value::List(list)) => list,
+        Some(_) => return wrong_type(),
+        None => return Frame::Array(Vec::new()),
+    };
+    let items: Vec<Frame> = match normalize_range(start, stop, list.len()) {
+        Some((start, stop)) => list
+            .range(start..=stop)
+            .map(|item| Frame::Bulk(item.clone()))
+            .collect(),
+        None => Vec::new(),
+    };
+    Frame::Array(items)
+}
+
+/// LLEN key
+pub fn llen(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match ctx.db.get(key(&args[1])).map(|e| &e.value) {
+        Some(Value::List(list)) => Frame::Integer(list.len() as i64),
+        Some(_) => wrong_type(),
+        None => Frame::Integer(0),
+    }
+}
+
+/// 把可能为负数的下标区间换算成 `[start, stop]`，区间为空时返回 `None`
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len: i64 = len as i64;
+    let start: i64 = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop: i64 = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+/// 取出 key 上的列表
+pub fn list_mut<'a>(
+    ctx: &'a mut Ctx<'_>,
+    key: &str,
+) -> Result<Option<&'a mut VecDeque<Bytes>>, Frame> {
+    match ctx.db.get_mut(key).map(|e| &mut e.value) {
+        Some(Value::List(list)) => Ok(Some(list)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// 取出 key 上的列表，key 不存在时新建一个空列表
+fn list_or_create<'a>(ctx: &'a mut Ctx<'_>, key: &str) -> Result<&'a mut VecDeque<Bytes>, Frame> {
+    if ctx.db.get(key).is_none() {
+        ctx.db
+            .insert(key.to_string(), Entry::new(Value::List(VecDeque::new())));
+    }
+    list_mut(ctx, key).map(|list| list.expect("list was just created"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{client_session, render, reply, state};
+    use crate::cmd::{dispatch, Outcome};
+    use bytes::Bytes;
+
+    #[test]
+    fn push_pop_range() {
+        let db = state(4);
+        assert_eq!(reply(&db, "RPUSH l a b c"), "3");
+        assert_eq!(reply(&db, "LPUSH l z"), "4");
+        assert_eq!(reply(&db, "LRANGE l 0 -1"), "[z a b c]");
+        assert_eq!(reply(&db, "LRANGE l -2 100"), "[b c]");
+        assert_eq!(reply(&db, "LRANGE l 5 10"), "[]");
+        assert_eq!(reply(&db, "LPOP l"), "z");
+        assert_eq!(reply(&db, "RPOP l 2"), "[c b]");
+        assert_eq!(reply(&db, "LLEN l"), "1");
+        assert_eq!(reply(&db, "LPOP l"), "a");
+        assert_eq!(reply(&db, "EXISTS l"), "0");
+        assert_eq!(reply(&db, "LPOP l"), "nil");
+    }
+
+    #[test]
+    fn blocking_pop_is_served_by_push() {
+        let db = state(4);
+        reply(&db, "RPUSH ready x");
+        assert_eq!(reply(&db, "BLPOP empty ready 0"), "[ready x]");
+        // 事务、日志重放等场景不会阻塞
+        assert_eq!(reply(&db, "BLPOP empty 0"), "nil");
+
+        let args: Vec<Bytes> = ["BRPOP", "q1", "q2", "0"]
+            .iter()
+            .map(|s| Bytes::from(*s))
+            .collect();
+        let mut blocked = match dispatch(&db, &mut client_session(), &args) {
+            Outcome::Block(blocked) => blocked,
+            other => panic!("expected to block, got {other:?}"),
+        };
+        assert_eq!(reply(&db, "RPUSH q2 a b"), "2");
+        assert_eq!(render(&blocked.rx.try_recv().unwrap()), "[q2 b]");
+        assert_eq!(reply(&db, "LRANGE q2 0 -1"), "[a]");
+        assert!(reply(&db, "BLPOP q1 -1").starts_with("!ERR timeout is negative"));
+        assert_eq!(reply(&db, "BLPOP q1 1e30"), "!ERR timeout is out of range");
+        assert_eq!(reply(&db, "LPOP q2"), "a");
+    }
+
+    #[test]
+    fn wrong_type() {
+        let db = state(2);
+        reply(&db, "SET s v");
+        assert!(reply(&db, "LPUSH s a").starts_with("!WRONGTYPE"));
+        reply(&db, "RPUSH l a");
+        assert!(reply(&db, "GET l").starts_with("!WRONGTYPE"));
+    }
+}
@@ -0,0 +1,879 @@
+mod acl;
+mod admin;
+mod cluster;
+mod connection;
+mod hash;
+mod info;
+mod keys;
+mod list;
+mod replication;
+mod scripting;
+mod set;
+mod sorted_set;
+mod stream;
+mod string;
+mod transaction;
+
+use crate::blocking::Blocked;
+use crate::cluster::{key_slot, Route};
+use crate::db::Guard;
+use crate::evict;
+use crate::frame::Frame;
+use crate::replication::Feed;
+use crate::session::Session;
+use crate::state::State;
+use bytes::Bytes;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// 命令的执行函数，参数中包含命令名本身
+pub type Handler = fn(&mut Ctx<'_>, &[Bytes]) -> Frame;
+
+/// 命令执行时可以访问的内容
+pub struct Ctx<'a> {
+    /// 命令涉及的 key 所在的分片，已经加锁
+    pub db: Guard<'a>,
+    /// 服务端共享状态
+    pub state: &'a Arc<State>,
+    /// 发出命令的连接的会话
+    pub session: &'a mut Session,
+    /// 是否允许阻塞，事务、脚本和日志重放中的阻塞命令立即返回
+    pub may_block: bool,
+    /// 阻塞命令在这里留下等待句柄
+    pub blocked: Option<Blocked>,
+    /// `PSYNC` 成功后连接转为向副本发送命令流
+    pub feed: Option<Feed>,
+    /// `MONITOR` 成功后连接转为接收所有客户端的命令
+    pub monitor: Option<mpsc::Receiver<String>>,
+    /// 需要传播的命令。执行函数只追加命令本身之外的部分，例如推入时顺带服务了阻塞客户端，
+    /// 命令本身在执行后插到它们前面
+    pub propagate: Vec<Vec<Bytes>>,
+}
+
+/// 命令的执行结果
+#[derive(Debug)]
+pub enum Outcome {
+    /// 立即回复
+    Reply(Frame),
+    /// 客户端被阻塞，连接需要等待结果
+    Block(Blocked),
+    /// 连接来自副本，之后向它发送命令流
+    Sync(Feed),
+    /// 连接执行了 MONITOR，之后向它发送其他客户端执行的命令
+    Monitor(mpsc::Receiver<String>),
+}
+
+/// 会修改数据的命令
+pub const WRITE: u8 = 1 << 0;
+/// 只读命令
+pub const READONLY: u8 = 1 << 1;
+/// 可能增加内存的写命令，超出内存上限且无法淘汰时拒绝执行
+pub const DENYOOM: u8 = 1 << 2;
+/// 不能在脚本中执行的命令
+pub const NOSCRIPT: u8 = 1 << 3;
+/// 还没有通过认证的连接也可以执行的命令
+pub const NOAUTH: u8 = 1 << 4;
+/// 要锁住全部分片的命令：遍历或整体替换键空间，或者在一把锁下执行别的命令（EXEC、EVAL）。
+/// 其它不带 key 的命令不碰数据，不加锁
+pub const KEYSPACE: u8 = 1 << 5;
+/// 不能在事务中排队的命令，它们会把连接转成别的用途
+pub const NOMULTI: u8 = 1 << 6;
+
+/// 命令表中的一项，字段含义与 Redis 的 `COMMAND INFO` 一致
+pub struct CommandSpec {
+    /// 小写命令名
+    pub name: &'static str,
+    /// 参数个数（含命令名），负数表示至少 `-arity` 个
+    pub arity: i32,
+    /// `WRITE`、`READONLY` 等标志位
+    pub flags: u8,
+    /// 第一个 key 的位置，0 表示命令不带 key
+    pub first_key: usize,
+    /// 最后一个 key 的位置，负数从末尾倒数
+    pub last_key: i32,
+    /// 相邻两个 key 之间的间隔
+    pub step: usize,
+    pub handler: Handler,
+}
+
+/// 所有支持的命令
+static COMMANDS: &[CommandSpec] = &[
+    spec("ping", -1, 0, 0, 0, 0, string::ping),
+    spec("hello", -1, NOSCRIPT | NOAUTH, 0, 0, 0, connection::hello),
+    spec("auth", -2, NOSCRIPT | NOAUTH, 0, 0, 0, connection::auth),
+    spec("get", 2, READONLY, 1, 1, 1, string::get),
+    spec("set", -3, WRITE | DENYOOM, 1, 1, 1, string::set),
+    spec("mget", -2, READONLY, 1, -1, 1, string::mget),
+    spec("mset", -3, WRITE | DENYOOM, 1, -1, 2, string::mset),
+    spec("del", -2, WRITE, 1, -1, 1, keys::del),
+    spec("exists", -2, READONLY, 1, -1, 1, keys::exists),
+    spec("expire", 3, WRITE, 1, 1, 1, keys::expire),
+    spec("pexpire", 3, WRITE, 1, 1, 1, keys::pexpire),
+    spec("pexpireat", 3, WRITE, 1, 1, 1, keys::pexpireat),
+    spec("expireat", 3, WRITE, 1, 1, 1, keys::expireat),
+    spec("persist", 2, WRITE, 1, 1, 1, keys::persist),
+    spec("dump", 2, READONLY, 1, 1, 1, keys::dump),
+    spec("restore", -4, WRITE | DENYOOM, 1, 1, 1, keys::restore),
+    spec("type", 2, READONLY, 1, 1, 1, keys::key_type),
+    spec("rename", 3, WRITE, 1, 2, 1, keys::rename),
+    spec("move", 3, WRITE, 1, 1, 1, keys::move_key),
+    spec("keys", 2, READONLY | KEYSPACE, 0, 0, 0, keys::keys),
+    spec("scan", -2, READONLY | KEYSPACE, 0, 0, 0, keys::scan),
+    spec("dbsize", 1, READONLY | KEYSPACE, 0, 0, 0, keys::dbsize),
+    spec(
+        "randomkey",
+        1,
+        READONLY | KEYSPACE,
+        0,
+        0,
+        0,
+        keys::randomkey,
+    ),
+    spec("select", 2, NOSCRIPT, 0, 0, 0, connection::select),
+    spec("swapdb", 3, WRITE | KEYSPACE, 0, 0, 0, keys::swapdb),
+    spec("flushdb", -1, WRITE | KEYSPACE, 0, 0, 0, keys::flushdb),
+    spec("flushall", -1, WRITE | KEYSPACE, 0, 0, 0, keys::flushall),
+    spec("lpush", -3, WRITE | DENYOOM, 1, 1, 1, list::lpush),
+    spec("rpush", -3, WRITE | DENYOOM, 1, 1, 1, list::rpush),
+    spec("lpop", -2, WRITE, 1, 1, 1, list::lpop),
+    spec("rpop", -2, WRITE, 1, 1, 1, list::rpop),
+    spec("lrange", 4, READONLY, 1, 1, 1, list::lrange),
+    spec("llen", 2, READONLY, 1, 1, 1, list::llen),
+    spec("blpop", -3, WRITE, 1, -2, 1, list::blpop),
+    spec("brpop", -3, WRITE, 1, -2, 1, list::brpop),
+    spec("hset", -4, WRITE | DENYOOM, 1, 1, 1, hash::hset),
+    spec("hget", 3, READONLY, 1, 1, 1, hash::hget),
+    spec("hgetall", 2, READONLY, 1, 1, 1, hash::hgetall),
+    spec("hdel", -3, WRITE, 1, 1, 1, hash::hdel),
+    spec("hlen", 2, READONLY, 1, 1, 1, hash::hlen),
+    spec("hscan", -3, READONLY, 1, 1, 1, hash::hscan),
+    spec("sadd", -3, WRITE | DENYOOM, 1, 1, 1, set::sadd),
+    spec("srem", -3, WRITE, 1, 1, 1, set::srem),
+    spec("smembers", 2, READONLY, 1, 1, 1, set::smembers),
+    spec("sismember", 3, READONLY, 1, 1, 1, set::sismember),
+    spec("scard", 2, READONLY, 1, 1, 1, set::scard),
+    spec("sinter", -2, READONLY, 1, -1, 1, set::sinter),
+    spec("sscan", -3, READONLY, 1, 1, 1, set::sscan),
+    spec("zadd", -4, WRITE | DENYOOM, 1, 1, 1, sorted_set::zadd),
+    spec("zrem", -3, WRITE, 1, 1, 1, sorted_set::zrem),
+    spec("zscore", 3, READONLY, 1, 1, 1, sorted_set::zscore),
+    spec("zcard", 2, READONLY, 1, 1, 1, sorted_set::zcard),
+    spec("zrange", -4, READONLY, 1, 1, 1, sorted_set::zrange),
+    spec(
+        "zrangebyscore",
+        -4,
+        READONLY,
+        1,
+        1,
+        1,
+        sorted_set::zrangebyscore,
+    ),
+    spec("xadd", -5, WRITE | DENYOOM, 1, 1, 1, stream::xadd),
+    spec("xlen", 2, READONLY, 1, 1, 1, stream::xlen),
+    spec("xrange", -4, READONLY, 1, 1, 1, stream::xrange),
+    spec("xrevrange", -4, READONLY, 1, 1, 1, stream::xrevrange),
+    spec("xdel", -3, WRITE, 1, 1, 1, stream::xdel),
+    spec("xtrim", -4, WRITE, 1, 1, 1, stream::xtrim),
+    spec("xread", -4, READONLY, 1, -1, 1, stream::xread),
+    spec("xgroup", -4, WRITE | DENYOOM, 2, 2, 1, stream::xgroup),
+    spec("xreadgroup", -7, WRITE, 1, -1, 1, stream::xreadgroup),
+    spec("xack", -4, WRITE, 1, 1, 1, stream::xack),
+    spec("xpending", -3, READONLY, 1, 1, 1, stream::xpending),
+    spec("xclaim", -6, WRITE, 1, 1, 1, stream::xclaim),
+    spec("multi", 1, NOSCRIPT, 0, 0, 0, transaction::multi),
+    spec("exec", 1, NOSCRIPT | KEYSPACE, 0, 0, 0, transaction::exec),
+    spec(
+        "discard",
+        1,
+        NOSCRIPT | KEYSPACE,
+        0,
+        0,
+        0,
+        transaction::discard,
+    ),
+    spec("watch", -2, NOSCRIPT, 1, -1, 1, transaction::watch),
+    spec(
+        "unwatch",
+        1,
+        NOSCRIPT | KEYSPACE,
+        0,
+        0,
+        0,
+        transaction::unwatch,
+    ),
+    spec("save", 1, NOSCRIPT | KEYSPACE, 0, 0, 0, admin::save),
+    spec("bgsave", 1, NOSCRIPT | KEYSPACE, 0, 0, 0, admin::bgsave),
+    spec("lastsave", 1, 0, 0, 0, 0, admin::lastsave),
+    spec(
+        "bgrewriteaof",
+        1,
+        NOSCRIPT | KEYSPACE,
+        0,
+        0,
+        0,
+        admin::bgrewriteaof,
+    ),
+    spec("replicaof", 3, NOSCRIPT, 0, 0, 0, replication::replicaof),
+    spec("slaveof", 3, NOSCRIPT, 0, 0, 0, replication::replicaof),
+    spec(
+        "psync",
+        3,
+        NOSCRIPT | KEYSPACE | NOMULTI,
+        0,
+        0,
+        0,
+        replication::psync,
+    ),
+    spec("replconf", -1, NOSCRIPT, 0, 0, 0, replication::replconf),
+    spec("role", 1, 0, 0, 0, 0, replication::role),
+    spec("config", -2, NOSCRIPT, 0, 0, 0, admin::config),
+    spec("info", -1, KEYSPACE, 0, 0, 0, info::info),
+    spec("monitor", 1, NOSCRIPT | NOMULTI, 0, 0, 0, admin::monitor),
+    spec("slowlog", -2, 0, 0, 0, 0, admin::slowlog),
+    spec("client", -2, NOSCRIPT, 0, 0, 0, connection::client),
+    spec(
+        "eval",
+        -3,
+        DENYOOM | NOSCRIPT | KEYSPACE,
+        0,
+        0,
+        0,
+        scripting::eval,
+    ),
+    spec(
+        "evalsha",
+        -3,
+        DENYOOM | NOSCRIPT | KEYSPACE,
+        0,
+        0,
+        0,
+        scripting::evalsha,
+    ),
+    spec("script", -2, NOSCRIPT, 0, 0, 0, scripting::script),
+    spec("acl", -2, NOSCRIPT, 0, 0, 0, acl::acl),
+    spec(
+        "cluster",
+        -2,
+        NOSCRIPT | KEYSPACE,
+        0,
+        0,
+        0,
+        cluster::cluster,
+    ),
+    spec("asking", 1, 0, 0, 0, 0, cluster::asking),
+];
+
+const fn spec(
+    name: &'static str,
+    arity: i32,
+    flags: u8,
+    first_key: usize,
+    last_key: i32,
+    step: usize,
+    handler: Handler,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key,
+        last_key,
+        step,
+        handler,
+    }
+}
+
+/// 命令表
+pub fn commands() -> &'static [CommandSpec] {
+    COMMANDS
+}
+
+/// 按名字查找命令，大小写不敏感
+pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+}
+
+impl CommandSpec {
+    /// 检查参数个数
+    pub fn check_arity(&self, argc: usize) -> bool {
+        if self.arity >= 0 {
+            argc == self.arity as usize
+        } else {
+            argc >= (-self.arity) as usize
+        }
+    }
+
+    /// 取出参数中的全部 key
+    pub fn keys<'a>(&self, args: &'a [Bytes]) -> Result<Vec<&'a str>, Frame> {
+        if self.first_key == 0 {
+            return Ok(Vec::new());
+        }
+        // XREAD/XREADGROUP 的 key 跟在 STREAMS 之后，位置不固定
+        if matches!(self.name, "xread" | "xreadgroup") {
+            return stream::read_keys(args);
+        }
+        let last: usize = if self.last_key < 0 {
+            args.len() - (-self.last_key) as usize
+        } else {
+            self.last_key as usize
+        };
+        (self.first_key..=last)
+            .step_by(self.step)
+            .map(|idx| key_str(&args[idx]))
+            .collect()
+    }
+
+    /// 集群模式下决定命令去哪个节点的 key。EVAL/EVALSHA 按 numkeys 声明的 key 路由，
+    /// 执行时仍然锁住全部分片
+    pub fn routing_keys<'a>(&self, args: &'a [Bytes]) -> Result<Vec<&'a str>, Frame> {
+        if matches!(self.name, "eval" | "evalsha") {
+            return scripting::declared_keys(args);
+        }
+        self.keys(args)
+    }
+}
+
+/// 把请求帧拆成参数列表，请求必须是由字符串组成的数组
+pub fn parse_args(frame: Frame) -> Result<Vec<Bytes>, Frame> {
+    let parts: Vec<Frame> = match frame {
+        Frame::Array(parts) => parts,
+        frame => {
+            return Err(error(format!(
+                "ERR Protocol error: expected array, got {}",
+                frame.kind()
+            )))
+        }
+    };
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Frame::Bulk(data) => Ok(data),
+            Frame::Simple(s) => Ok(Bytes::from(s)),
+            part => Err(error(format!(
+                "ERR Protocol error: expected bulk string, got {}",
+                part.kind()
+            ))),
+        })
+        .collect()
+}
+
+/// 执行一条命令，阻塞命令在没有数据时立即返回空值
+pub fn execute(state: &Arc<State>, args: &[Bytes]) -> Frame {
+    execute_in(state, &mut Session::new(), args)
+}
+
+/// 在给定会话中执行一条命令，用于重放日志这样需要跨命令保留事务状态的场景
+pub fn execute_in(state: &Arc<State>, session: &mut Session, args: &[Bytes]) -> Frame {
+    match run(state, session, args, false) {
+        Outcome::Reply(frame) => frame,
+        Outcome::Block(_) => unreachable!("blocking is disabled"),
+        Outcome::Sync(_) => unreachable!("PSYNC requires a client connection"),
+        Outcome::Monitor(_) => unreachable!("MONITOR requires a client connection"),
+    }
+}
+
+/// 执行一条来自客户端连接的命令，阻塞命令可能返回 `Outcome::Block`
+///
+/// 只有客户端的命令受内存上限和副本只读的约束，重放日志和执行主节点的命令流时不受限制，
+/// 也只有它们计入统计、慢查询日志和 MONITOR
+pub fn dispatch(state: &Arc<State>, session: &mut Session, args: &[Bytes]) -> Outcome {
+    let spec: Option<&CommandSpec> = args.first().and_then(|name| lookup(name));
+    let flags: u8 = spec.map_or(0, |spec| spec.flags);
+    state.clients.total_commands.fetch_add(1, Ordering::Relaxed);
+    // 带密码的命令不给 MONITOR 看，也不记入慢查询日志
+    let secret: bool = spec.is_some_and(|spec| matches!(spec.name, "auth" | "hello" | "acl"));
+    if spec.is_some() && !secret {
+        state.clients.feed_monitors(session, args);
+    }
+    if let Err(frame) = authorize(state, session, spec, args) {
+        if session.in_multi() {
+            session.multi_failed = true;
+        }
+        return Outcome::Reply(frame);
+    }
+    if let Err(frame) = route(state, session, args) {
+        if session.in_multi() {
+            session.multi_failed = true;
+        }
+        return Outcome::Reply(frame);
+    }
+    if flags & WRITE != 0 && state.repl.is_replica() {
+        if session.in_multi() {
+            session.multi_failed = true;
+        }
+        return Outcome::Reply(error(
+            "READONLY You can't write against a read only replica.",
+        ));
+    }
+    if flags & DENYOOM != 0 && !evict::free_memory(state) {
+        if session.in_multi() {
+            session.multi_failed = true;
+        }
+        return Outcome::Reply(error(
+            "OOM command not allowed when used memory > 'maxmemory'.",
+        ));
+    }
+    let start: Instant = Instant::now();
+    let outcome: Outcome = run(state, session, args, true);
+    let elapsed: Duration = start.elapsed();
+    if let Some(spec) = spec {
+        state.metrics.record(spec.name, elapsed);
+    }
+    if !secret {
+        let (threshold, max_len): (i64, usize) = {
+            let config = state.config();
+            (config.slowlog_log_slower_than, config.slowlog_max_len)
+        };
+        state
+            .slowlog
+            .record(session, args, elapsed, threshold, max_len);
+    }
+    outcome
+}
+
+/// 检查连接是否已经认证、用户能否执行这条命令。参数个数不对的命令放行，由执行时报告错误
+fn authorize(
+    state: &State,
+    session: &Session,
+    spec: Option<&CommandSpec>,
+    args: &[Bytes],
+) -> Result<(), Frame> {
+    let user: &str = match &session.user {
+        Some(user) => user,
+        None if spec.is_some_and(|spec| spec.flags & NOAUTH != 0) => return Ok(()),
+        None => return Err(error("NOAUTH Authentication required.")),
+    };
+    match check(args) {
+        Ok((spec, keys)) => state.acl.check(user, spec, &keys).map_err(error),
+        Err(_) => Ok(()),
+    }
+}
+
+/// 集群模式下检查命令的 key 是否由本节点负责，不是时回复 `MOVED` 或 `ASK`。
+/// 槽正在迁出时本节点只处理 key 都还在的命令，key 都不在了就让客户端去目标节点问
+fn route(state: &State, session: &mut Session, args: &[Bytes]) -> Result<(), Frame> {
+    // `ASKING` 只对紧接着的一条命令有效
+    let asking: bool = std::mem::take(&mut session.asking);
+    let Some(cluster) = &state.cluster else {
+        return Ok(());
+    };
+    let keys: Vec<&str> = match check(args).and_then(|(spec, _)| spec.routing_keys(args)) {
+        Ok(keys) => keys,
+        Err(_) => return Ok(()),
+    };
+    let Some(first) = keys.first() else {
+        return Ok(());
+    };
+    let slot: u16 = key_slot(first.as_bytes());
+    if keys.iter().any(|key| key_slot(key.as_bytes()) != slot) {
+        return Err(error(
+            "CROSSSLOT Keys in request don't hash to the same slot",
+        ));
+    }
+    match cluster.route(slot, asking) {
+        Route::Local => Ok(()),
+        Route::Moved(addr) => Err(error(format!("MOVED {slot} {addr}"))),
+        Route::Unassigned => Err(error("CLUSTERDOWN Hash slot not served")),
+        Route::Migrating(addr) => {
+            let db: Guard<'_> = state.db.lock_keys(&keys);
+            let missing: usize = keys.iter().filter(|key| db.get(key).is_none()).count();
+            if missing == 0 {
+                Ok(())
+            } else if missing == keys.len() {
+                Err(error(format!("ASK {slot} {addr}")))
+            } else {
+                Err(error(
+                    "TRYAGAIN Multiple keys request during rehashing of slot",
+                ))
+            }
+        }
+    }
+}
+
+fn run(state: &Arc<State>, session: &mut Session, args: &[Bytes], may_block: bool) -> Outcome {
+    let (spec, keys) = match check(args) {
+        Ok(checked) => checked,
+        Err(frame) => {
+            // 事务中排队的命令有错，EXEC 时放弃整个事务
+            if session.in_multi() {
+                session.multi_failed = true;
+            }
+            return Outcome::Reply(frame);
+        }
+    };
+    if let Some(queued) = &mut session.multi {
+        if spec.flags & NOMULTI != 0 {
+            session.multi_failed = true;
+            return Outcome::Reply(error("ERR Command not allowed inside a transaction"));
+        }
+        if !matches!(spec.name, "multi" | "exec" | "discard" | "watch") {
+            queued.push(args.to_vec());
+            return Outcome::Reply(Frame::Simple("QUEUED".to_string()));
+        }
+    }
+    // 只锁住命令涉及的分片，多 key 命令一次性锁住所有相关分片，保证原子性。
+    // 不带 key 也不碰键空间的命令（PING、SELECT 等）不加锁
+    let mut db: Guard<'_> = if spec.flags & KEYSPACE != 0 {
+        state.db.lock_all()
+    } else {
+        state.db.lock_keys(keys)
+    };
+    // 传播的命令从执行前选中的数据库开始，事务中的 SELECT 会在其中留下切换的标记
+    let index: usize = session.db;
+    db.select(index);
+    let mut ctx: Ctx<'_> = Ctx {
+        db,
+        state,
+        session,
+        may_block,
+        blocked: None,
+        feed: None,
+        monitor: None,
+        propagate: Vec::new(),
+    };
+    let response: Frame = invoke(&mut ctx, spec, args);
+    // 仍然持有分片锁，保证日志中同一个 key 的命令顺序与执行顺序一致。
+    // 阻塞的命令也可能已经改了数据，例如 XREADGROUP 新建了消费者
+    state.propagate(index, &ctx.propagate);
+    if let Some(blocked) = ctx.blocked {
+        return Outcome::Block(blocked);
+    }
+    if let Some(feed) = ctx.feed {
+        return Outcome::Sync(feed);
+    }
+    if let Some(monitor) = ctx.monitor {
+        return Outcome::Monitor(monitor);
+    }
+    Outcome::Reply(response)
+}
+
+/// 查找命令并校验参数个数和 key 的编码
+fn check(args: &[Bytes]) -> Result<(&'static CommandSpec, Vec<&str>), Frame> {
+    let name: &Bytes = match args.first() {
+        Some(name) => name,
+        None => return Err(error("ERR empty command")),
+    };
+    let spec: &CommandSpec = match lookup(name) {
+        Some(spec) => spec,
+        None => {
+            return Err(error(format!(
+                "ERR unknown command '{}'",
+                String::from_utf8_lossy(name)
+            )))
+        }
+    };
+    if !spec.check_arity(args.len()) {
+        return Err(wrong_arity(spec.name));
+    }
+    Ok((spec, spec.keys(args)?))
+}
+
+/// 调用执行函数。写命令成功后记录修改次数，并把它在日志中的形式记到 `ctx.propagate`
+fn invoke(ctx: &mut Ctx<'_>, spec: &CommandSpec, args: &[Bytes]) -> Frame {
+    let start: usize = ctx.propagate.len();
+    let response: Frame = (spec.handler)(ctx, args);
+    if ctx.blocked.is_none() && spec.flags & WRITE != 0 && !matches!(response, Frame::Error(_)) {
+        ctx.state.rdb.dirty.fetch_add(1, Ordering::SeqCst);
+        let commands: Vec<Vec<Bytes>> = propagation(&ctx.db, spec, args, &response);
+        ctx.propagate.splice(start..start, commands);
+    }
+    response
+}
+
+/// 写命令在日志中的形式：相对时间的过期设置换算成绝对时间，重放时结果不变
+fn propagation(
+    db: &Guard<'_>,
+    spec: &CommandSpec,
+    args: &[Bytes],
+    response: &Frame,
+) -> Vec<Vec<Bytes>> {
+    let expire_at = |key: &Bytes| -> Vec<Bytes> {
+        let at: u64 = db
+            .get(self::key(key))
+            .and_then(|e| e.expires_at)
+            .unwrap_or(0);
+        vec![
+            Bytes::from_static(b"PEXPIREAT"),
+            key.clone(),
+            Bytes::from(at.to_string()),
+        ]
+    };
+    match spec.name {
+        // NX/XX 条件不满足，什么都没做
+        "set" if matches!(response, Frame::Null) => Vec::new(),
+        "set" => {
+            let mut commands: Vec<Vec<Bytes>> = vec![args[..3].to_vec()];
+            if db
+                .get(key(&args[1]))
+                .is_some_and(|e| e.expires_at.is_some())
+            {
+                commands.push(expire_at(&args[1]));
+            }
+            commands
+        }
+        "expire" | "pexpire" | "expireat" if matches!(response, Frame::Integer(1)) => {
+            vec![expire_at(&args[1])]
+        }
+        "expire" | "pexpire" | "expireat" => Vec::new(),
+        "restore" => {
+            let at: u64 = db
+                .get(key(&args[1]))
+                .and_then(|e| e.expires_at)
+                .unwrap_or(0);
+            vec![vec![
+                Bytes::from_static(b"RESTORE"),
+                args[1].clone(),
+                Bytes::from(at.to_string()),
+                args[3].clone(),
+                Bytes::from_static(b"REPLACE"),
+                Bytes::from_static(b"ABSTTL"),
+            ]]
+        }
+        // 阻塞弹出立即拿到数据时，等价于一次普通的弹出
+        "blpop" | "brpop" => match response {
+            Frame::Array(popped) => {
+                let pop: &'static [u8] = if spec.name == "blpop" {
+                    b"LPOP"
+                } else {
+                    b"RPOP"
+                };
+                match popped.first() {
+                    Some(Frame::Bulk(key)) => vec![vec![Bytes::from_static(pop), key.clone()]],
+                    _ => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        },
+        "xadd" => stream::xadd_propagation(args, response),
+        // 执行函数自己记下了重现分发记录的命令
+        "xreadgroup" | "xclaim" => Vec::new(),
+        _ => vec![args.to_vec()],
+    }
+}
+
+/// 构造错误帧
+pub fn error(msg: impl Into<String>) -> Frame {
+    Frame::Error(msg.into())
+}
+
+pub fn wrong_arity(name: &str) -> Frame {
+    error(format!(
+        "ERR wrong number of arguments for '{name}' command"
+    ))
+}
+
+pub fn wrong_type() -> Frame {
+    error("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+pub fn syntax_error() -> Frame {
+    error("ERR syntax error")
+}
+
+fn ok() -> Frame {
+    Frame::Simple("OK".to_string())
+}
+
+/// 把参数解析为整数
+fn parse_int<T: std::str::FromStr>(arg: &Bytes) -> Result<T, Frame> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| error("ERR value is not an integer or out of range"))
+}
+
+/// 把参数解析为数据库编号，必须小于配置的数据库个数
+fn parse_db(db: &Guard<'_>, arg: &Bytes) -> Result<usize, Frame> {
+    let index: i64 = parse_int(arg)?;
+    if index < 0 || index as usize >= db.databases() {
+        return Err(error("ERR DB index is out of range"));
+    }
+    Ok(index as usize)
+}
+
+/// 切换数据库的标记。放在传播的命令中间，表示之后的命令在这个数据库上执行
+fn select_command(db: usize) -> Vec<Bytes> {
+    vec![Bytes::from_static(b"SELECT"), Bytes::from(db.to_string())]
+}
+
+/// 临时切换到数据库 `db` 执行 `f`，之后切回来。期间追加的传播命令前后加上切换的标记
+fn in_db<T>(ctx: &mut Ctx<'_>, db: usize, f: impl FnOnce(&mut Ctx<'_>) -> T) -> T {
+    let selected: usize = ctx.db.select(db);
+    let start: usize = ctx.propagate.len();
+    let result: T = f(ctx);
+    if ctx.propagate.len() > start && db != selected {
+        ctx.propagate.insert(start, select_command(db));
+        ctx.propagate.push(select_command(selected));
+    }
+    ctx.db.select(selected);
+    result
+}
+
+/// key 必须是合法的 UTF-8
+fn key_str(arg: &Bytes) -> Result<&str, Frame> {
+    std::str::from_utf8(arg).map_err(|_| error("ERR invalid key, keys must be valid UTF-8"))
+}
+
+/// 在执行函数中读取 key，`execute` 加锁前已经校验过编码
+fn key(arg: &Bytes) -> &str {
+    key_str(arg).expect("key was validated before dispatch")
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{
+        dispatch, execute, execute_in, invoke, key, spec, CommandSpec, Ctx, Outcome, WRITE,
+    };
+    use crate::config::Config;
+    use crate::frame::Frame;
+    use crate::session::Session;
+    use crate::state::State;
+    use bytes::Bytes;
+    use std::sync::Arc;
+
+    /// 创建一个有 `shards` 个分片、不做持久化的服务端状态
+    pub(crate) fn state(shards: usize) -> Arc<State> {
+        State::new(Config {
+            shards,
+            save: Vec::new(),
+            ..Config::default()
+        })
+    }
+
+    /// 执行一条以空格分隔的命令
+    pub(crate) fn run(state: &Arc<State>, cmd: &str) -> Frame {
+        execute(state, &split(cmd))
+    }
+
+    /// 在同一个会话中执行一条命令
+    pub(crate) fn run_in(state: &Arc<State>, session: &mut Session, cmd: &str) -> Frame {
+        execute_in(state, session, &split(cmd))
+    }
+
+    /// 按 redis-cli 的规则拆分参数，带空格的参数可以用引号括起来
+    fn split(cmd: &str) -> Vec<Bytes> {
+        crate::config::split_args(cmd)
+            .unwrap()
+            .into_iter()
+            .map(Bytes::from)
+            .collect()
+    }
+
+    /// 把回复渲染成便于断言的字符串
+    pub(crate) fn render(frame: &Frame) -> String {
+        match frame {
+            Frame::Simple(s) => s.clone(),
+            Frame::Error(e) => format!("!{e}"),
+            Frame::Integer(n) => n.to_string(),
+            Frame::Bulk(b) => String::from_utf8_lossy(b).into_owned(),
+            Frame::Null => "nil".to_string(),
+            Frame::Array(parts) => format!("[{}]", render_all(parts)),
+            Frame::Set(parts) => format!("({})", render_all(parts)),
+            Frame::Push(parts) => format!(">[{}]", render_all(parts)),
+            Frame::Map(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(k, v)| format!("{} {}", render(k), render(v)))
+                    .collect();
+                format!("{{{}}}", pairs.join(" "))
+            }
+            Frame::Double(d) => crate::frame::format_double(*d),
+            Frame::Boolean(b) => b.to_string(),
+        }
+    }
+
+    fn render_all(parts: &[Frame]) -> String {
+        let parts: Vec<String> = parts.iter().map(render).collect();
+        parts.join(" ")
+    }
+
+    pub(crate) fn reply(state: &Arc<State>, cmd: &str) -> String {
+        render(&run(state, cmd))
+    }
+
+    /// 以 `default` 用户登录的客户端会话
+    pub(crate) fn client_session() -> Session {
+        let mut session: Session = Session::new();
+        session.user = Some(crate::acl::DEFAULT_USER.to_string());
+        session
+    }
+
+    /// 像客户端连接一样执行命令，受内存上限和副本只读的约束
+    pub(crate) fn send(state: &Arc<State>, cmd: &str) -> String {
+        match dispatch(state, &mut client_session(), &split(cmd)) {
+            Outcome::Reply(frame) => render(&frame),
+            other => panic!("unexpected outcome {other:?}"),
+        }
+    }
+
+    #[test]
+    fn mset_mget_across_shards() {
+        let db = state(8);
+        assert_eq!(reply(&db, "MSET a 1 b 2 c 3 d 4"), "OK");
+        assert_eq!(reply(&db, "MGET a b missing d"), "[1 2 nil 4]");
+        assert_eq!(reply(&db, "DEL a b missing"), "2");
+        assert_eq!(reply(&db, "EXISTS a c d c"), "3");
+    }
+
+    #[test]
+    fn arity_and_unknown() {
+        let db = state(2);
+        assert!(reply(&db, "GET").starts_with("!ERR wrong number"));
+        assert!(reply(&db, "MSET a 1 b").starts_with("!ERR wrong number"));
+        assert!(reply(&db, "NOPE x").starts_with("!ERR unknown command"));
+    }
+
+    #[test]
+    fn keyless_commands_do_not_lock_the_keyspace() {
+        let db = state(2);
+        // 同一个线程再去锁分片会死锁，能回复说明这些命令没有加锁
+        let guard = db.db.lock_all();
+        assert_eq!(reply(&db, "PING"), "PONG");
+        assert_eq!(reply(&db, "SELECT 1"), "OK");
+        assert_eq!(reply(&db, "CONFIG GET port"), "{port 6379}");
+        drop(guard);
+        assert_eq!(reply(&db, "DBSIZE"), "0");
+    }
+
+    #[test]
+    fn panicking_command_does_not_poison_the_keyspace() {
+        let db = state(2);
+        assert_eq!(reply(&db, "SET a 1"), "OK");
+        let panicking: CommandSpec = spec("panic", 2, WRITE, 1, 1, 1, |ctx, args| {
+            ctx.db.remove(key(&args[1]));
+            panic!("bug in a command handler");
+        });
+        let state: Arc<State> = db.clone();
+        let handle = std::thread::spawn(move || {
+            let mut session: Session = Session::new();
+            let mut ctx: Ctx<'_> = Ctx {
+                db: state.db.lock_all(),
+                state: &state,
+                session: &mut session,
+                may_block: false,
+                blocked: None,
+                feed: None,
+                monitor: None,
+                propagate: Vec::new(),
+            };
+            invoke(&mut ctx, &panicking, &split("PANIC a"))
+        });
+        assert!(handle.join().is_err());
+        assert_eq!(reply(&db, "GET a"), "nil");
+        assert_eq!(reply(&db, "SET a 2"), "OK");
+        assert_eq!(reply(&db, "DBSIZE"), "1");
+    }
+
+    #[test]
+    fn write_commands_mark_dirty() {
+        let db = state(2);
+        run(&db, "SET a 1");
+        run(&db, "GET a");
+        run(&db, "MSET b 2 c 3");
+        assert_eq!(db.rdb.dirty.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+}
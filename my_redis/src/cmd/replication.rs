@@ -0,0 +1,124 @@
+//! 主从复制相关的命令
+
+use super::{error, ok, parse_int, syntax_error, Ctx};
+use crate::frame::Frame;
+use crate::replication::{LinkState, RoleInfo};
+use bytes::Bytes;
+
+/// REPLICAOF host port | REPLICAOF NO ONE
+pub fn replicaof(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if args[1].eq_ignore_ascii_case(b"no") && args[2].eq_ignore_ascii_case(b"one") {
+        ctx.state.repl.promote();
+        return ok();
+    }
+    // 复制由后台任务维护，只能由客户端连接发起
+    if !ctx.may_block {
+        return error("ERR REPLICAOF is not allowed here");
+    }
+    let host: String = String::from_utf8_lossy(&args[1]).into_owned();
+    let port: u16 = match parse_int(&args[2]) {
+        Ok(port) => port,
+        Err(_) => return error("ERR Invalid master port"),
+    };
+    if ctx.state.repl.replicaof(ctx.state, host, port) {
+        ok()
+    } else {
+        Frame::Simple("OK Already connected to specified master".to_string())
+    }
+}
+
+/// PSYNC replid offset，副本请求同步。成功后这个连接只用来发送命令流
+pub fn psync(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if !ctx.may_block {
+        return error("ERR PSYNC is not allowed here");
+    }
+    if let RoleInfo::Replica { link, .. } = ctx.state.repl.role() {
+        if link != LinkState::Connected {
+            return error("NOMASTERLINK Can't SYNC while not connected with my master");
+        }
+    }
+    let offset: i64 = match parse_int(&args[2]) {
+        Ok(offset) => offset,
+        Err(frame) => return frame,
+    };
+    let (addr, port): (String, u16) = match ctx.session.addr {
+        Some(addr) => (
+            addr.ip().to_string(),
+            ctx.session.listening_port.unwrap_or(addr.port()),
+        ),
+        None => ("?".to_string(), ctx.session.listening_port.unwrap_or(0)),
+    };
+    let replid: String = String::from_utf8_lossy(&args[1]).into_owned();
+    let partial = match u64::try_from(offset) {
+        Ok(offset) => ctx
+            .state
+            .repl
+            .attach_partial(&replid, offset, addr.clone(), port),
+        Err(_) => None,
+    };
+    ctx.feed = Some(match partial {
+        Some(feed) => feed,
+        None => ctx.state.repl.attach_full(&ctx.db, addr, port),
+    });
+    // 回复已经放在命令流的开头
+    Frame::Null
+}
+
+/// REPLCONF option value [option value ...]，副本在握手时告知自己的信息
+pub fn replconf(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if args.len().is_multiple_of(2) {
+        return syntax_error();
+    }
+    for pair in args[1..].chunks(2) {
+        match pair[0].to_ascii_lowercase().as_slice() {
+            b"listening-port" => match parse_int(&pair[1]) {
+                Ok(port) => ctx.session.listening_port = Some(port),
+                Err(frame) => return frame,
+            },
+            // 主节点总是支持 PSYNC，ACK 由发送命令流的连接处理
+            b"capa" | b"ack" | b"getack" => {}
+            _ => {
+                return error(format!(
+                    "ERR Unrecognized REPLCONF option: {}",
+                    String::from_utf8_lossy(&pair[0])
+                ))
+            }
+        }
+    }
+    ok()
+}
+
+/// ROLE
+pub fn role(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    let bulk = |s: String| Frame::Bulk(Bytes::from(s));
+    match ctx.state.repl.role() {
+        RoleInfo::Master { offset, replicas } => Frame::Array(vec![
+            bulk("master".to_string()),
+            Frame::Integer(offset as i64),
+            Frame::Array(
+                replicas
+                    .into_iter()
+                    .map(|(addr, port, ack)| {
+                        Frame::Array(vec![
+                            bulk(addr),
+                            bulk(port.to_string()),
+                            bulk(ack.to_string()),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ]),
+        RoleInfo::Replica {
+            host,
+            port,
+            link,
+            offset,
+        } => Frame::Array(vec![
+            bulk("slave".to_string()),
+            bulk(host),
+            Frame::Integer(port as i64),
+            bulk(link.as_str().to_string()),
+            Frame::Integer(offset as i64),
+        ]),
+    }
+}
@@ -0,0 +1,275 @@
+//! 脚本命令：EVAL、EVALSHA 和 SCRIPT
+//!
+//! 脚本执行时已经锁住全部分片，`call` 直接在当前的锁下执行命令。集群模式下按 numkeys 声明的 key
+//! 路由，脚本只能访问本节点负责的 key。
+
+use super::{check, error, invoke, key_str, ok, parse_int, wrong_arity, Ctx, NOSCRIPT, WRITE};
+use crate::cluster::{key_slot, Route};
+use crate::frame::Frame;
+use crate::script::{self, Block, Host, SyntaxError};
+use bytes::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// EVAL script numkeys [key ...] [arg ...]
+pub fn eval(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match ctx.state.scripts.load(&args[1]) {
+        Ok((_, program)) => run(ctx, &program, &args[2..]),
+        Err(err) => compile_error(err),
+    }
+}
+
+/// EVALSHA sha1 numkeys [key ...] [arg ...]
+pub fn evalsha(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let program: Option<Arc<Block>> = std::str::from_utf8(&args[1])
+        .ok()
+        .and_then(|sha| ctx.state.scripts.get(sha));
+    match program {
+        Some(program) => run(ctx, &program, &args[2..]),
+        None => error("NOSCRIPT No matching script. Please use EVAL."),
+    }
+}
+
+/// SCRIPT LOAD script | SCRIPT EXISTS sha1 [sha1 ...] | SCRIPT FLUSH
+pub fn script(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let subcommand: Vec<u8> = args[1].to_ascii_lowercase();
+    match (subcommand.as_slice(), args.len()) {
+        (b"load", 3) => match ctx.state.scripts.load(&args[2]) {
+            Ok((sha, _)) => Frame::Bulk(Bytes::from(sha)),
+            Err(err) => compile_error(err),
+        },
+        (b"exists", 3..) => Frame::Array(
+            args[2..]
+                .iter()
+                .map(|sha| {
+                    let cached: bool = std::str::from_utf8(sha)
+                        .ok()
+                        .and_then(|sha| ctx.state.scripts.get(sha))
+                        .is_some();
+                    Frame::Integer(cached as i64)
+                })
+                .collect(),
+        ),
+        // 缓存总是同步清空，接受 ASYNC/SYNC 只是为了兼容
+        (b"flush", 2) => {
+            ctx.state.scripts.flush();
+            ok()
+        }
+        (b"flush", 3)
+            if [&b"async"[..], b"sync"].contains(&args[2].to_ascii_lowercase().as_slice()) =>
+        {
+            ctx.state.scripts.flush();
+            ok()
+        }
+        (b"load" | b"exists" | b"flush", _) => {
+            wrong_arity(&format!("script|{}", String::from_utf8_lossy(&subcommand)))
+        }
+        _ => error(format!(
+            "ERR unknown subcommand '{}'. Try SCRIPT LOAD, SCRIPT EXISTS or SCRIPT FLUSH.",
+            String::from_utf8_lossy(&args[1])
+        )),
+    }
+}
+
+fn compile_error(err: SyntaxError) -> Frame {
+    error(format!(
+        "ERR Error compiling script: line {}: {}",
+        err.line, err.msg
+    ))
+}
+
+/// EVAL/EVALSHA 用 numkeys 声明的 key，集群模式下按它们路由
+pub(super) fn declared_keys(args: &[Bytes]) -> Result<Vec<&str>, Frame> {
+    split_keys(&args[2..])?.0.iter().map(key_str).collect()
+}
+
+/// 按 numkeys 把参数分成 key 和其余参数，`args` 从 numkeys 开始
+fn split_keys(args: &[Bytes]) -> Result<(&[Bytes], &[Bytes]), Frame> {
+    let numkeys: i64 = parse_int(&args[0])?;
+    if numkeys < 0 {
+        return Err(error("ERR Number of keys can't be negative"));
+    }
+    if numkeys as usize > args.len() - 1 {
+        return Err(error(
+            "ERR Number of keys can't be greater than number of args",
+        ));
+    }
+    Ok(args[1..].split_at(numkeys as usize))
+}
+
+/// 解析 numkeys 并执行脚本，`args` 从 numkeys 开始
+fn run(ctx: &mut Ctx<'_>, program: &Block, args: &[Bytes]) -> Frame {
+    let (keys, argv) = match split_keys(args) {
+        Ok(split) => split,
+        Err(frame) => return frame,
+    };
+    // 命令已经按声明的 key 路由到了本节点，这个槽里的 key 都可以访问
+    let slot: Option<u16> = keys.first().map(|key| key_slot(key));
+    let limit: u64 = ctx.state.config().script_time_limit;
+
+    // 脚本中的阻塞命令不等待
+    let may_block: bool = std::mem::replace(&mut ctx.may_block, false);
+    let start: usize = ctx.propagate.len();
+    let reply: Frame = script::run(
+        program,
+        keys,
+        argv,
+        &mut ScriptHost { ctx, slot },
+        (limit > 0).then(|| Duration::from_millis(limit)),
+    );
+    ctx.may_block = may_block;
+    // 和 EXEC 一样用 MULTI/EXEC 包起来，重放时脚本的全部写入同样原子地执行
+    if ctx.propagate.len() > start {
+        ctx.propagate
+            .insert(start, vec![Bytes::from_static(b"MULTI")]);
+        ctx.propagate.push(vec![Bytes::from_static(b"EXEC")]);
+    }
+    reply
+}
+
+/// 让脚本在当前命令的上下文中执行命令
+struct ScriptHost<'c, 'a> {
+    ctx: &'c mut Ctx<'a>,
+    /// 声明的 key 所在的槽
+    slot: Option<u16>,
+}
+
+impl ScriptHost<'_, '_> {
+    /// 集群模式下 key 必须在声明的槽里，或者在本节点负责的槽里
+    fn is_local(&self, key: &str) -> bool {
+        let Some(cluster) = &self.ctx.state.cluster else {
+            return true;
+        };
+        let slot: u16 = key_slot(key.as_bytes());
+        self.slot == Some(slot) || cluster.route(slot, false) == Route::Local
+    }
+}
+
+impl Host for ScriptHost<'_, '_> {
+    fn call(&mut self, args: Vec<Bytes>) -> Frame {
+        let (spec, keys) = match check(&args) {
+            Ok(checked) => checked,
+            Err(frame) => return frame,
+        };
+        if spec.flags & NOSCRIPT != 0 {
+            return error("ERR This command is not allowed from script");
+        }
+        // 脚本里的命令同样受调用者的 ACL 约束，内部会话没有用户，不做检查
+        if let Some(user) = &self.ctx.session.user {
+            if let Err(err) = self.ctx.state.acl.check(user, spec, &keys) {
+                return error(err);
+            }
+        }
+        if !keys.iter().all(|key| self.is_local(key)) {
+            return error("ERR Script attempted to access a non local key in a cluster node");
+        }
+        if spec.flags & WRITE != 0 && self.ctx.state.repl.is_replica() {
+            return error("READONLY You can't write against a read only replica.");
+        }
+        invoke(self.ctx, spec, &args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{reply, state};
+    use crate::config::Config;
+    use crate::script::sha1_hex;
+    use crate::state::State;
+
+    #[test]
+    fn eval_runs_commands_atomically() {
+        let db = state(4);
+        let incr_by: &str = "\"local n = tonumber(call('GET', KEYS[1])) or 0 \
+            n = n + ARGV[1] call('SET', KEYS[1], n) return n\"";
+        assert_eq!(reply(&db, &format!("EVAL {incr_by} 1 counter 5")), "5");
+        assert_eq!(reply(&db, &format!("EVAL {incr_by} 1 counter 2")), "7");
+        assert_eq!(reply(&db, "GET counter"), "7");
+
+        assert_eq!(
+            reply(&db, "EVAL \"return {KEYS[1], ARGV[1], #ARGV}\" 1 k a b"),
+            "[k a 2]"
+        );
+        assert_eq!(
+            reply(&db, "EVAL \"return 1\" 3 a"),
+            "!ERR Number of keys can't be greater than number of args"
+        );
+        assert_eq!(
+            reply(&db, "EVAL \"return (\" 0"),
+            "!ERR Error compiling script: line 1: unexpected <eof>"
+        );
+        // call 的错误原样返回，之前的写入保留
+        assert_eq!(
+            reply(
+                &db,
+                "EVAL \"call('SET', 'x', 1) return call('LPUSH', 'x', 2)\" 0"
+            ),
+            "!WRONGTYPE Operation against a key holding the wrong kind of value"
+        );
+        assert_eq!(reply(&db, "GET x"), "1");
+        assert_eq!(
+            reply(&db, "EVAL \"return call('MULTI')\" 0"),
+            "!ERR This command is not allowed from script"
+        );
+        assert_eq!(
+            reply(&db, "EVAL \"return call('BLPOP', 'none', 0)\" 0"),
+            "nil"
+        );
+    }
+
+    #[test]
+    fn evalsha_uses_the_script_cache() {
+        let db = state(2);
+        let sha: String = sha1_hex(b"return ARGV[1]");
+        assert_eq!(
+            reply(&db, &format!("EVALSHA {sha} 0 hi")),
+            "!NOSCRIPT No matching script. Please use EVAL."
+        );
+        assert_eq!(reply(&db, "SCRIPT LOAD \"return ARGV[1]\""), sha);
+        assert_eq!(
+            reply(&db, &format!("EVALSHA {} 0 hi", sha.to_uppercase())),
+            "hi"
+        );
+        assert_eq!(reply(&db, &format!("SCRIPT EXISTS {sha} 0000")), "[1 0]");
+        assert_eq!(reply(&db, "SCRIPT FLUSH"), "OK");
+        assert_eq!(reply(&db, &format!("SCRIPT EXISTS {sha}")), "[0]");
+    }
+
+    #[test]
+    fn deeply_nested_scripts_are_rejected() {
+        let db = state(2);
+        let deep: String = format!("return {}1{}", "(".repeat(200_000), ")".repeat(200_000));
+        assert_eq!(
+            reply(&db, &format!("EVAL \"{deep}\" 0")),
+            "!ERR Error compiling script: line 1: chunk has too many syntax levels"
+        );
+        // 服务端照常工作
+        assert_eq!(reply(&db, "PING"), "PONG");
+        let nested: String = format!("return {}1{}", "tostring(".repeat(198), ")".repeat(198));
+        assert_eq!(reply(&db, &format!("EVAL \"{nested}\" 0")), "1");
+    }
+
+    #[test]
+    fn long_running_script_is_killed() {
+        let db = State::new(Config {
+            save: Vec::new(),
+            script_time_limit: 20,
+            ..Config::default()
+        });
+        assert_eq!(
+            reply(&db, "EVAL \"call('SET', 'a', 1) while true do end\" 0"),
+            "!ERR Script killed after exceeding the time limit of 20 ms"
+        );
+        assert_eq!(reply(&db, "CONFIG SET script-time-limit 0"), "OK");
+        assert_eq!(
+            reply(&db, "EVAL \"for i = 1, 100000 do end return 1\" 0"),
+            "1"
+        );
+        // 算不出截止时间的限制等同于不限制
+        assert_eq!(
+            reply(&db, "CONFIG SET script-time-limit 18446744073709551615"),
+            "OK"
+        );
+        assert_eq!(reply(&db, "EVAL \"return 1\" 0"), "1");
+    }
+}
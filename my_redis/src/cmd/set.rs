@@ -0,0 +1,161 @@
+//! 集合相关命令
+
+use super::keys::{parse_scan, scan_by_hash, scan_reply};
+use super::{key, wrong_type, Ctx};
+use crate::db::{Entry, Value};
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::collections::HashSet;
+
+/// SADD key member [member ...]，返回新增成员数
+pub fn sadd(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    if ctx.db.get(key).is_none() {
+        ctx.db
+            .insert(key.to_string(), Entry::new(Value::Set(HashSet::new())));
+    }
+    let set: &mut HashSet<Bytes> = match set_mut(ctx, key) {
+        Ok(Some(set)) => set,
+        Ok(None) => unreachable!("set was just created"),
+        Err(err) => return err,
+    };
+    let added: usize = args[2..]
+        .iter()
+        .filter(|member| set.insert((*member).clone()))
+        .count();
+    Frame::Integer(added as i64)
+}
+
+/// SREM key member [member ...]
+pub fn srem(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    let set: &mut HashSet<Bytes> = match set_mut(ctx, key) {
+        Ok(Some(set)) => set,
+        Ok(None) => return Frame::Integer(0),
+        Err(err) => return err,
+    };
+    let removed: usize = args[2..]
+        .iter()
+        .filter(|member| set.remove(*member))
+        .count();
+    if set.is_empty() {
+        ctx.db.remove(key);
+    }
+    Frame::Integer(removed as i64)
+}
+
+/// SMEMBERS key
+pub fn smembers(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match set(ctx, &args[1]) {
+        Ok(set) => Frame::Set(
+            set.into_iter()
+                .flatten()
+                .map(|member| Frame::Bulk(member.clone()))
+                .collect(),
+        ),
+        Err(err) => err,
+    }
+}
+
+/// SISMEMBER key member
+pub fn sismember(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match set(ctx, &args[1]) {
+        Ok(set) => Frame::Integer(set.is_some_and(|s| s.contains(&args[2])) as i64),
+        Err(err) => err,
+    }
+}
+
+/// SCARD key
+pub fn scard(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match set(ctx, &args[1]) {
+        Ok(set) => Frame::Integer(set.map_or(0, |s| s.len()) as i64),
+        Err(err) => err,
+    }
+}
+
+/// SINTER key [key ...]，所有 key 所在的分片已经一起加锁
+pub fn sinter(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let mut sets: Vec<&HashSet<Bytes>> = Vec::with_capacity(args.len() - 1);
+    for arg in &args[1..] {
+        match set(ctx, arg) {
+            Ok(Some(set)) => sets.push(set),
+            // 任何一个 key 不存在，交集就是空集，但仍要检查其余 key 的类型
+            Ok(None) => {}
+            Err(err) => return err,
+        }
+    }
+    if sets.len() < args.len() - 1 {
+        return Frame::Set(Vec::new());
+    }
+    // 从最小的集合开始求交，减少比较次数
+    sets.sort_by_key(|set| set.len());
+    let members: Vec<Frame> = sets[0]
+        .iter()
+        .filter(|member| sets[1..].iter().all(|set| set.contains(*member)))
+        .map(|member| Frame::Bulk(member.clone()))
+        .collect();
+    Frame::Set(members)
+}
+
+/// SSCAN key cursor [MATCH pattern] [COUNT count]
+pub fn sscan(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (cursor, options) = match parse_scan(&args[2..], false) {
+        Ok(parsed) => parsed,
+        Err(err) => return err,
+    };
+    let set: &HashSet<Bytes> = match set(ctx, &args[1]) {
+        Ok(Some(set)) => set,
+        Ok(None) => return scan_reply(0, Vec::new()),
+        Err(err) => return err,
+    };
+    let (batch, next) = scan_by_hash(set.iter().map(|member| (member, ())), cursor, options.count);
+    let members: Vec<Frame> = batch
+        .into_iter()
+        .filter(|(member, _)| options.matches(member))
+        .map(|(member, _)| Frame::Bulk(member.clone()))
+        .collect();
+    scan_reply(next, members)
+}
+
+fn set<'a>(ctx: &'a Ctx<'_>, arg: &Bytes) -> Result<Option<&'a HashSet<Bytes>>, Frame> {
+    match ctx.db.get(key(arg)).map(|e| &e.value) {
+        Some(Value::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+fn set_mut<'a>(ctx: &'a mut Ctx<'_>, key: &str) -> Result<Option<&'a mut HashSet<Bytes>>, Frame> {
+    match ctx.db.get_mut(key).map(|e| &mut e.value) {
+        Some(Value::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{render, reply, run, state};
+    use crate::frame::Frame;
+
+    #[test]
+    fn add_remove_intersect() {
+        let db = state(8);
+        assert_eq!(reply(&db, "SADD s1 a b c d"), "4");
+        assert_eq!(reply(&db, "SADD s1 a"), "0");
+        assert_eq!(reply(&db, "SADD s2 b d e"), "3");
+        assert_eq!(reply(&db, "SADD s3 d b"), "2");
+        let mut inter: Vec<String> = match run(&db, "SINTER s1 s2 s3") {
+            Frame::Set(items) => items.iter().map(render).collect(),
+            other => panic!("unexpected {other:?}"),
+        };
+        inter.sort();
+        assert_eq!(inter, ["b", "d"]);
+        assert_eq!(reply(&db, "SINTER s1 missing"), "()");
+        assert_eq!(reply(&db, "SISMEMBER s1 c"), "1");
+        assert_eq!(reply(&db, "SREM s3 b d x"), "2");
+        assert_eq!(reply(&db, "SCARD s3"), "0");
+        reply(&db, "SET str v");
+        assert!(reply(&db, "SINTER s1 str").starts_with("!WRONGTYPE"));
+    }
+}
@@ -0,0 +1,185 @@
+//! 有序集合相关命令
+
+use super::{error, key, parse_int, syntax_error, wrong_arity, wrong_type, Ctx};
+use crate::db::{Entry, Value};
+use crate::frame::Frame;
+use crate::zset::{format_score, parse_score, ScoreBound, ZSet};
+use bytes::Bytes;
+
+/// ZADD key score member [score member ...]，返回新增成员数
+pub fn zadd(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if !args.len().is_multiple_of(2) {
+        return wrong_arity("zadd");
+    }
+    // 先解析全部分数，避免写入一半时才发现参数错误
+    let mut pairs: Vec<(f64, Bytes)> = Vec::with_capacity(args.len() / 2 - 1);
+    for pair in args[2..].chunks(2) {
+        match parse_score(&pair[0]) {
+            Some(score) => pairs.push((score, pair[1].clone())),
+            None => return error("ERR value is not a valid float"),
+        }
+    }
+    let key: &str = key(&args[1]);
+    if ctx.db.get(key).is_none() {
+        ctx.db
+            .insert(key.to_string(), Entry::new(Value::ZSet(ZSet::new())));
+    }
+    let zset: &mut ZSet = match zset_mut(ctx, key) {
+        Ok(Some(zset)) => zset,
+        Ok(None) => unreachable!("zset was just created"),
+        Err(err) => return err,
+    };
+    let added: usize = pairs
+        .into_iter()
+        .filter(|(score, member)| zset.insert(member.clone(), *score))
+        .count();
+    Frame::Integer(added as i64)
+}
+
+/// ZREM key member [member ...]
+pub fn zrem(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    let zset: &mut ZSet = match zset_mut(ctx, key) {
+        Ok(Some(zset)) => zset,
+        Ok(None) => return Frame::Integer(0),
+        Err(err) => return err,
+    };
+    let removed: usize = args[2..].iter().filter(|m| zset.remove(m)).count();
+    if zset.is_empty() {
+        ctx.db.remove(key);
+    }
+    Frame::Integer(removed as i64)
+}
+
+/// ZSCORE key member
+pub fn zscore(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match zset(ctx, &args[1]) {
+        Ok(zset) => match zset.and_then(|z| z.score(&args[2])) {
+            Some(score) => Frame::Double(score),
+            None => Frame::Null,
+        },
+        Err(err) => err,
+    }
+}
+
+/// ZCARD key
+pub fn zcard(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match zset(ctx, &args[1]) {
+        Ok(zset) => Frame::Integer(zset.map_or(0, |z| z.len()) as i64),
+        Err(err) => err,
+    }
+}
+
+/// ZRANGE key start stop [WITHSCORES]
+pub fn zrange(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (start, stop): (i64, i64) = match (parse_int(&args[2]), parse_int(&args[3])) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    let with_scores: bool = match &args[4..] {
+        [] => false,
+        [opt] if opt.eq_ignore_ascii_case(b"WITHSCORES") => true,
+        _ => return syntax_error(),
+    };
+    match zset(ctx, &args[1]) {
+        Ok(Some(zset)) => reply(zset.range_by_rank(start, stop), with_scores),
+        Ok(None) => Frame::Array(Vec::new()),
+        Err(err) => err,
+    }
+}
+
+/// ZRANGEBYSCORE key min max [WITHSCORES] [LIMIT offset count]
+pub fn zrangebyscore(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let (min, max) = match (ScoreBound::parse(&args[2]), ScoreBound::parse(&args[3])) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return error("ERR min or max is not a float"),
+    };
+    let mut with_scores: bool = false;
+    let (mut offset, mut count): (usize, Option<usize>) = (0, None);
+    let mut options = args[4..].iter();
+    while let Some(option) = options.next() {
+        if option.eq_ignore_ascii_case(b"WITHSCORES") {
+            with_scores = true;
+        } else if option.eq_ignore_ascii_case(b"LIMIT") {
+            let (o, c) = match (options.next(), options.next()) {
+                (Some(o), Some(c)) => (o, c),
+                _ => return syntax_error(),
+            };
+            offset = match parse_int(o) {
+                Ok(offset) => offset,
+                Err(err) => return err,
+            };
+            // 负数的 count 表示不限制
+            count = match parse_int::<i64>(c) {
+                Ok(c) => usize::try_from(c).ok(),
+                Err(err) => return err,
+            };
+        } else {
+            return syntax_error();
+        }
+    }
+    match zset(ctx, &args[1]) {
+        Ok(Some(zset)) => {
+            let items = zset
+                .range_by_score(min, max)
+                .into_iter()
+                .skip(offset)
+                .take(count.unwrap_or(usize::MAX))
+                .collect();
+            reply(items, with_scores)
+        }
+        Ok(None) => Frame::Array(Vec::new()),
+        Err(err) => err,
+    }
+}
+
+fn reply(items: Vec<(&Bytes, f64)>, with_scores: bool) -> Frame {
+    let mut frames: Vec<Frame> = Vec::with_capacity(items.len() * 2);
+    for (member, score) in items {
+        frames.push(Frame::Bulk(member.clone()));
+        if with_scores {
+            frames.push(Frame::Bulk(Bytes::from(format_score(score))));
+        }
+    }
+    Frame::Array(frames)
+}
+
+fn zset<'a>(ctx: &'a Ctx<'_>, arg: &Bytes) -> Result<Option<&'a ZSet>, Frame> {
+    match ctx.db.get(key(arg)).map(|e| &e.value) {
+        Some(Value::ZSet(zset)) => Ok(Some(zset)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+fn zset_mut<'a>(ctx: &'a mut Ctx<'_>, key: &str) -> Result<Option<&'a mut ZSet>, Frame> {
+    match ctx.db.get_mut(key).map(|e| &mut e.value) {
+        Some(Value::ZSet(zset)) => Ok(Some(zset)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{reply, state};
+
+    #[test]
+    fn add_and_ranges() {
+        let db = state(4);
+        assert_eq!(reply(&db, "ZADD z 1 a 2 b 3 c 1.5 d"), "4");
+        assert_eq!(reply(&db, "ZADD z 0 c"), "0");
+        assert_eq!(reply(&db, "ZRANGE z 0 -1"), "[c a d b]");
+        assert_eq!(reply(&db, "ZRANGE z 0 1 WITHSCORES"), "[c 0 a 1]");
+        assert_eq!(reply(&db, "ZRANGEBYSCORE z (1 +inf"), "[d b]");
+        assert_eq!(
+            reply(&db, "ZRANGEBYSCORE z -inf +inf WITHSCORES LIMIT 1 2"),
+            "[a 1 d 1.5]"
+        );
+        assert_eq!(reply(&db, "ZSCORE z d"), "1.5");
+        assert_eq!(reply(&db, "ZREM z a b x"), "2");
+        assert_eq!(reply(&db, "ZCARD z"), "2");
+        assert!(reply(&db, "ZADD z x a").starts_with("!ERR value is not a valid float"));
+        assert!(reply(&db, "ZRANGEBYSCORE z a b").starts_with("!ERR min or max"));
+    }
+}
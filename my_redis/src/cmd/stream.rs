@@ -0,0 +1,1146 @@
+//! 流相关命令
+//!
+//! XREADGROUP 和 XCLAIM 改变的是消费组的分发记录，重放原命令得不到同样的结果（分发时间、
+//! 阻塞时才分发的条目），所以它们在日志中记为等价的 `XCLAIM ... FORCE JUSTID` 和
+//! `XGROUP SETID`，与 Redis 的做法相同。
+
+use super::{error, key, key_str, ok, parse_int, syntax_error, wrong_arity, wrong_type, Ctx};
+use crate::blocking::{Source, Wait};
+use crate::db::{now_ms, Entry, Value};
+use crate::frame::Frame;
+use crate::stream::{Claim, Group, Item, Pending, Read, Stream, StreamId};
+use bytes::Bytes;
+use std::ops::Bound;
+use std::time::Duration;
+
+/// XADD key [NOMKSTREAM] [MAXLEN [=|~] threshold] *|id field value [field value ...]
+///
+/// `~` 也按精确长度裁剪
+pub fn xadd(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let parsed: AddArgs = match parse_add(args) {
+        Ok(parsed) => parsed,
+        Err(err) => return err,
+    };
+    let key: &str = key(&args[1]);
+    let id: StreamId = {
+        let empty: Stream = Stream::new();
+        let stream: &Stream = match stream_ref(ctx, key) {
+            Ok(Some(stream)) => stream,
+            Ok(None) if parsed.nomkstream => return Frame::Null,
+            Ok(None) => &empty,
+            Err(err) => return err,
+        };
+        match new_id(&args[parsed.id], stream) {
+            Ok(id) => id,
+            Err(err) => return err,
+        }
+    };
+    if ctx.db.get(key).is_none() {
+        ctx.db
+            .insert(key.to_string(), Entry::new(Value::Stream(Stream::new())));
+    }
+    let stream: &mut Stream = stream_mut(ctx, key)
+        .ok()
+        .flatten()
+        .expect("stream was just created");
+    stream.add(id, args[parsed.id + 1..].to_vec());
+    if let Some(maxlen) = parsed.maxlen {
+        stream.trim(maxlen);
+    }
+    serve_blocked(ctx, key);
+    id_frame(id)
+}
+
+/// XLEN key
+pub fn xlen(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match stream_ref(ctx, key(&args[1])) {
+        Ok(stream) => Frame::Integer(stream.map_or(0, Stream::len) as i64),
+        Err(err) => err,
+    }
+}
+
+/// XRANGE key start end [COUNT count]
+pub fn xrange(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    range(ctx, args, false)
+}
+
+/// XREVRANGE key end start [COUNT count]
+pub fn xrevrange(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    range(ctx, args, true)
+}
+
+fn range(ctx: &mut Ctx<'_>, args: &[Bytes], rev: bool) -> Frame {
+    let (start, end): (&Bytes, &Bytes) = if rev {
+        (&args[3], &args[2])
+    } else {
+        (&args[2], &args[3])
+    };
+    let (start, end) = match (parse_bound(start, 0), parse_bound(end, u64::MAX)) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    let count: Option<usize> = match &args[4..] {
+        [] => None,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => match parse_int(count) {
+            Ok(count) => Some(count),
+            Err(err) => return err,
+        },
+        _ => return syntax_error(),
+    };
+    if count == Some(0) {
+        return Frame::Array(Vec::new());
+    }
+    match stream_ref(ctx, key(&args[1])) {
+        Ok(Some(stream)) => entries_frame(stream.range(start, end, rev, count)),
+        Ok(None) => Frame::Array(Vec::new()),
+        Err(err) => err,
+    }
+}
+
+/// XDEL key id [id ...]
+pub fn xdel(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let ids: Vec<StreamId> = match parse_ids(&args[2..]) {
+        Ok(ids) => ids,
+        Err(err) => return err,
+    };
+    match stream_mut(ctx, key(&args[1])) {
+        Ok(Some(stream)) => {
+            Frame::Integer(ids.into_iter().filter(|id| stream.remove(*id)).count() as i64)
+        }
+        Ok(None) => Frame::Integer(0),
+        Err(err) => err,
+    }
+}
+
+/// XTRIM key MAXLEN [=|~] threshold
+pub fn xtrim(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if !args[2].eq_ignore_ascii_case(b"maxlen") {
+        return syntax_error();
+    }
+    let maxlen: usize = match parse_maxlen(&args[3..]) {
+        Ok((maxlen, used)) if 3 + used == args.len() => maxlen,
+        Ok(_) => return syntax_error(),
+        Err(err) => return err,
+    };
+    match stream_mut(ctx, key(&args[1])) {
+        Ok(Some(stream)) => Frame::Integer(stream.trim(maxlen) as i64),
+        Ok(None) => Frame::Integer(0),
+        Err(err) => err,
+    }
+}
+
+/// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+///
+/// `$` 表示流当前最大的 ID。所有流都没有新条目时，带 BLOCK 的请求在所有流上排队等待
+pub fn xread(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let read: ReadArgs<'_> = match parse_read(args) {
+        Ok(read) => read,
+        Err(err) => return err,
+    };
+    let mut after: Vec<(&str, StreamId)> = Vec::with_capacity(read.keys.len());
+    for (key, id) in read.keys.iter().map(key).zip(read.ids) {
+        let stream: Option<&Stream> = match stream_ref(ctx, key) {
+            Ok(stream) => stream,
+            Err(err) => return err,
+        };
+        let id: StreamId = match id.as_ref() {
+            b"$" => stream.map_or(StreamId::MIN, Stream::last_id),
+            b">" => {
+                return error(
+                    "ERR The > ID can be specified only when calling XREADGROUP using the \
+                     GROUP <group> <consumer> option.",
+                )
+            }
+            id => match StreamId::parse(id, 0) {
+                Some(id) => id,
+                None => return invalid_id(),
+            },
+        };
+        after.push((key, id));
+    }
+    let mut replies: Vec<Frame> = Vec::new();
+    for (key, id) in &after {
+        if let Ok(Some(stream)) = stream_ref(ctx, key) {
+            let items = stream.range(Bound::Excluded(*id), Bound::Unbounded, false, read.count);
+            if !items.is_empty() {
+                replies.push(stream_reply(key, entries_frame(items)));
+            }
+        }
+    }
+    if !replies.is_empty() {
+        return Frame::Array(replies);
+    }
+    if let Some(ms) = read.block {
+        let reads: Vec<(&str, Read)> = after
+            .into_iter()
+            .map(|(key, id)| (key, Read::After(id)))
+            .collect();
+        block(ctx, reads, read.count, ms);
+    }
+    Frame::Null
+}
+
+/// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+/// STREAMS key [key ...] id [id ...]
+///
+/// `>` 读组里还没有分发过的条目，其它 ID 读消费者自己的待确认条目
+pub fn xreadgroup(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let read: ReadArgs<'_> = match parse_read(args) {
+        Ok(read) => read,
+        Err(err) => return err,
+    };
+    let (group, consumer) = read.group.expect("checked by parse_read");
+    // 先检查所有的流和 ID，出错时什么都不改
+    let mut history: Vec<Option<StreamId>> = Vec::with_capacity(read.keys.len());
+    for (key, id) in read.keys.iter().map(key).zip(read.ids) {
+        match stream_ref(ctx, key) {
+            Ok(Some(stream)) if stream.groups().contains_key(group) => {}
+            Ok(_) => {
+                return error(format!(
+                    "NOGROUP No such key '{key}' or consumer group '{}' in XREADGROUP with \
+                     GROUP option",
+                    String::from_utf8_lossy(group)
+                ))
+            }
+            Err(err) => return err,
+        }
+        history.push(match id.as_ref() {
+            b">" => None,
+            b"$" => return error("ERR The $ ID is meaningful only for XREAD command"),
+            id => match StreamId::parse(id, 0) {
+                Some(id) => Some(id),
+                None => return invalid_id(),
+            },
+        });
+    }
+    let now: u64 = now_ms();
+    let mut propagate: Vec<Vec<Bytes>> = Vec::new();
+    let mut replies: Vec<Frame> = Vec::new();
+    for (key, after) in read.keys.iter().map(key).zip(history) {
+        let stream: &mut Stream = stream_mut(ctx, key).ok().flatten().expect("checked above");
+        let entries: Frame = match after {
+            Some(after) => {
+                if !has_consumer(stream, group, consumer) {
+                    propagate.push(create_consumer(key, group, consumer));
+                }
+                let items: Vec<Item> = stream
+                    .read_pending(group, consumer, after, read.count, now)
+                    .expect("checked above");
+                Frame::Array(
+                    items
+                        .into_iter()
+                        .map(|(id, fields)| entry_frame(id, fields))
+                        .collect(),
+                )
+            }
+            None => {
+                let items: Vec<(StreamId, Vec<Bytes>)> = deliver(
+                    stream,
+                    key,
+                    group,
+                    consumer,
+                    read.count,
+                    read.noack,
+                    &mut propagate,
+                )
+                .expect("checked above");
+                if items.is_empty() {
+                    continue;
+                }
+                entries_frame(items)
+            }
+        };
+        replies.push(stream_reply(key, entries));
+    }
+    ctx.propagate.append(&mut propagate);
+    if !replies.is_empty() {
+        return Frame::Array(replies);
+    }
+    // 指定 ID 的读取总有回复，走到这里说明所有 ID 都是 `>`
+    if let Some(ms) = read.block {
+        let reads: Vec<(&str, Read)> = read
+            .keys
+            .iter()
+            .map(|k| {
+                let wait: Read = Read::Group {
+                    group: group.clone(),
+                    consumer: consumer.clone(),
+                    noack: read.noack,
+                };
+                (key(k), wait)
+            })
+            .collect();
+        block(ctx, reads, read.count, ms);
+    }
+    Frame::Null
+}
+
+/// XGROUP CREATE key group id|$ [MKSTREAM] | SETID key group id|$ | DESTROY key group |
+/// CREATECONSUMER key group consumer | DELCONSUMER key group consumer
+pub fn xgroup(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[2]);
+    let group: &Bytes = &args[3];
+    let sub: Vec<u8> = args[1].to_ascii_lowercase();
+    let valid: bool = matches!(
+        (sub.as_slice(), args.len()),
+        (b"create", 5 | 6)
+            | (b"setid", 5)
+            | (b"destroy", 4)
+            | (b"createconsumer", 5)
+            | (b"delconsumer", 5)
+    );
+    if !valid {
+        return error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try XGROUP HELP.",
+            String::from_utf8_lossy(&args[1])
+        ));
+    }
+    if sub == b"create" {
+        match args.get(5) {
+            Some(option) if !option.eq_ignore_ascii_case(b"mkstream") => return syntax_error(),
+            Some(_) if ctx.db.get(key).is_none() => {
+                ctx.db
+                    .insert(key.to_string(), Entry::new(Value::Stream(Stream::new())));
+            }
+            _ => {}
+        }
+    }
+    let stream: &mut Stream =
+        match stream_mut(ctx, key) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return error(
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you \
+                 may want to use the MKSTREAM option to create an empty stream automatically.",
+            ),
+            Err(err) => return err,
+        };
+    let group_id = |stream: &Stream| -> Result<StreamId, Frame> {
+        match args[4].as_ref() {
+            b"$" => Ok(stream.last_id()),
+            id => StreamId::parse(id, 0).ok_or_else(invalid_id),
+        }
+    };
+    if sub == b"create" {
+        return match group_id(stream) {
+            Ok(id) if stream.create_group(group.clone(), id) => ok(),
+            Ok(_) => error("BUSYGROUP Consumer Group name already exists"),
+            Err(err) => err,
+        };
+    }
+    if sub == b"destroy" {
+        return Frame::Integer(i64::from(stream.groups_mut().remove(group).is_some()));
+    }
+    let id: Result<StreamId, Frame> = group_id(stream);
+    let Some(entry) = stream.group_mut(group) else {
+        return error(format!(
+            "NOGROUP No such consumer group '{}' for key name '{key}'",
+            String::from_utf8_lossy(group)
+        ));
+    };
+    match sub.as_slice() {
+        b"setid" => match id {
+            Ok(id) => {
+                entry.last_delivered = id;
+                ok()
+            }
+            Err(err) => err,
+        },
+        b"createconsumer" => Frame::Integer(i64::from(entry.touch(&args[4], now_ms()))),
+        _ => Frame::Integer(entry.remove_consumer(&args[4]) as i64),
+    }
+}
+
+/// XACK key group id [id ...]
+pub fn xack(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let ids: Vec<StreamId> = match parse_ids(&args[3..]) {
+        Ok(ids) => ids,
+        Err(err) => return err,
+    };
+    match stream_mut(ctx, key(&args[1])) {
+        Ok(Some(stream)) => match stream.group_mut(&args[2]) {
+            Some(group) => {
+                Frame::Integer(ids.into_iter().filter(|id| group.ack(*id)).count() as i64)
+            }
+            None => Frame::Integer(0),
+        },
+        Ok(None) => Frame::Integer(0),
+        Err(err) => err,
+    }
+}
+
+/// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+///
+/// 不带区间时回复概要：条目数、最小和最大 ID、每个消费者的条目数
+pub fn xpending(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    let (mut idx, mut min_idle): (usize, u64) = (3, 0);
+    if args.len() > 3 && args[3].eq_ignore_ascii_case(b"idle") {
+        match args.get(4).map(parse_int) {
+            Some(Ok(idle)) => min_idle = idle,
+            Some(Err(err)) => return err,
+            None => return syntax_error(),
+        }
+        idx = 5;
+    }
+    let summary: bool = args.len() == 3;
+    if !summary && args.len() != idx + 3 && args.len() != idx + 4 {
+        return syntax_error();
+    }
+    let group: &Group = match stream_ref(ctx, key) {
+        Ok(Some(stream)) => match stream.groups().get(&args[2]) {
+            Some(group) => group,
+            None => return no_group(key, &args[2]),
+        },
+        Ok(None) => return no_group(key, &args[2]),
+        Err(err) => return err,
+    };
+    if summary {
+        return pending_summary(group);
+    }
+    let (start, end) = match (
+        parse_bound(&args[idx], 0),
+        parse_bound(&args[idx + 1], u64::MAX),
+    ) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    let count: usize = match parse_int(&args[idx + 2]) {
+        Ok(count) => count,
+        Err(err) => return err,
+    };
+    let consumer: Option<&Bytes> = args.get(idx + 3);
+    let now: u64 = now_ms();
+    Frame::Array(
+        group
+            .pending_range(start, end)
+            .filter(|(_, pending)| consumer.is_none_or(|c| pending.consumer == c))
+            .filter(|(_, pending)| now.saturating_sub(pending.delivered_at) >= min_idle)
+            .take(count)
+            .map(|(id, pending)| {
+                Frame::Array(vec![
+                    id_frame(*id),
+                    Frame::Bulk(pending.consumer.clone()),
+                    Frame::Integer(now.saturating_sub(pending.delivered_at) as i64),
+                    Frame::Integer(pending.deliveries as i64),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+/// [RETRYCOUNT count] [FORCE] [JUSTID]
+pub fn xclaim(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    let (group, consumer): (&Bytes, &Bytes) = (&args[2], &args[3]);
+    let now: u64 = now_ms();
+    let mut claim: Claim = Claim {
+        min_idle: match parse_int(&args[4]) {
+            Ok(idle) => idle,
+            Err(_) => return error("ERR Invalid min-idle-time argument for XCLAIM"),
+        },
+        ..Claim::default()
+    };
+    let mut ids: Vec<StreamId> = Vec::new();
+    let mut idx: usize = 5;
+    while let Some(id) = args.get(idx).and_then(|arg| StreamId::parse(arg, 0)) {
+        ids.push(id);
+        idx += 1;
+    }
+    if ids.is_empty() {
+        return invalid_id();
+    }
+    while idx < args.len() {
+        let value: Option<Result<u64, Frame>> = args.get(idx + 1).map(parse_int);
+        match args[idx].to_ascii_lowercase().as_slice() {
+            b"force" => claim.force = true,
+            b"justid" => claim.justid = true,
+            option @ (b"idle" | b"time" | b"retrycount") => {
+                let value: u64 = match value {
+                    Some(Ok(value)) => value,
+                    Some(Err(err)) => return err,
+                    None => return syntax_error(),
+                };
+                match option {
+                    b"idle" => claim.delivered_at = Some(now.saturating_sub(value)),
+                    b"time" => claim.delivered_at = Some(value),
+                    _ => claim.retry_count = Some(value),
+                }
+                idx += 1;
+            }
+            _ => return syntax_error(),
+        }
+        idx += 1;
+    }
+    let stream: &mut Stream = match stream_mut(ctx, key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return no_group(key, group),
+        Err(err) => return err,
+    };
+    let claimed: Vec<StreamId> = match stream.claim(group, consumer, &ids, &claim, now) {
+        Some(claimed) => claimed,
+        None => return no_group(key, group),
+    };
+    let entry: &Group = &stream.groups()[group];
+    let propagate: Vec<Vec<Bytes>> = claimed
+        .iter()
+        .map(|id| claim_command(key, group, *id, &entry.pending[id]))
+        .collect();
+    let reply: Frame = if claim.justid {
+        Frame::Array(claimed.into_iter().map(id_frame).collect())
+    } else {
+        Frame::Array(
+            claimed
+                .into_iter()
+                .map(|id| entry_frame(id, stream.get(id).cloned()))
+                .collect(),
+        )
+    };
+    ctx.propagate.extend(propagate);
+    reply
+}
+
+/// XREAD 和 XREADGROUP 命令中的 key，它们跟在 STREAMS 之后，位置不固定
+pub(super) fn read_keys(args: &[Bytes]) -> Result<Vec<&str>, Frame> {
+    parse_read(args)?.keys.iter().map(key_str).collect()
+}
+
+/// XADD 在日志中的形式：自动生成的 ID 换成实际的 ID，重放时得到同样的条目
+pub(super) fn xadd_propagation(args: &[Bytes], response: &Frame) -> Vec<Vec<Bytes>> {
+    match (parse_add(args), response) {
+        (Ok(parsed), Frame::Bulk(id)) => {
+            let mut args: Vec<Bytes> = args.to_vec();
+            args[parsed.id] = id.clone();
+            vec![args]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// 流上有了新条目，交给阻塞在它上面的客户端
+pub(super) fn serve_blocked(ctx: &mut Ctx<'_>, key: &str) {
+    let state = ctx.state;
+    let db: usize = ctx.db.selected();
+    if let Some(Value::Stream(stream)) = ctx.db.get_mut(key).map(|entry| &mut entry.value) {
+        let mut readers: Readers<'_> = Readers {
+            key,
+            stream,
+            propagate: Vec::new(),
+        };
+        state.blocking.serve_with(db, key, &mut readers);
+        ctx.propagate.append(&mut readers.propagate);
+    }
+}
+
+/// 为阻塞在流上的 XREAD/XREADGROUP 生成回复
+struct Readers<'a> {
+    key: &'a str,
+    stream: &'a mut Stream,
+    propagate: Vec<Vec<Bytes>>,
+}
+
+impl Source for Readers<'_> {
+    fn reply(&mut self, wait: &Wait) -> Option<Frame> {
+        let Wait::Stream { read, count } = wait else {
+            return None;
+        };
+        let items: Vec<(StreamId, Vec<Bytes>)> = match read {
+            Read::After(id) => {
+                self.stream
+                    .range(Bound::Excluded(*id), Bound::Unbounded, false, *count)
+            }
+            Read::Group {
+                group,
+                consumer,
+                noack,
+            } => match deliver(
+                self.stream,
+                self.key,
+                group,
+                consumer,
+                *count,
+                *noack,
+                &mut self.propagate,
+            ) {
+                Some(items) => items,
+                // 组在等待期间被删除了
+                None => return Some(no_group(self.key, group)),
+            },
+        };
+        (!items.is_empty())
+            .then(|| Frame::Array(vec![stream_reply(self.key, entries_frame(items))]))
+    }
+
+    fn undo(&mut self, _wait: &Wait, _frame: Frame) {
+        // 分发给消费组的条目已经进入 PEL，之后可以用 XCLAIM 取回，不用撤销
+    }
+}
+
+/// 把组里还没分发过的条目交给消费者，并记下在日志中重现这次分发的命令。组不存在时返回 `None`
+fn deliver(
+    stream: &mut Stream,
+    key: &str,
+    group: &Bytes,
+    consumer: &Bytes,
+    count: Option<usize>,
+    noack: bool,
+    propagate: &mut Vec<Vec<Bytes>>,
+) -> Option<Vec<(StreamId, Vec<Bytes>)>> {
+    let created: bool = !stream.groups().get(group)?.consumers.contains_key(consumer);
+    let items: Vec<(StreamId, Vec<Bytes>)> =
+        stream.read_new(group, consumer, count, noack, now_ms())?;
+    if created {
+        propagate.push(create_consumer(key, group, consumer));
+    }
+    let entry: &Group = &stream.groups()[group];
+    if !noack {
+        for (id, _) in &items {
+            propagate.push(claim_command(key, group, *id, &entry.pending[id]));
+        }
+    }
+    if let Some((last, _)) = items.last() {
+        propagate.push(vec![
+            Bytes::from_static(b"XGROUP"),
+            Bytes::from_static(b"SETID"),
+            Bytes::copy_from_slice(key.as_bytes()),
+            group.clone(),
+            Bytes::from(last.to_string()),
+        ]);
+    }
+    Some(items)
+}
+
+fn has_consumer(stream: &Stream, group: &[u8], consumer: &[u8]) -> bool {
+    stream
+        .groups()
+        .get(group)
+        .is_some_and(|group| group.consumers.contains_key(consumer))
+}
+
+fn create_consumer(key: &str, group: &Bytes, consumer: &Bytes) -> Vec<Bytes> {
+    vec![
+        Bytes::from_static(b"XGROUP"),
+        Bytes::from_static(b"CREATECONSUMER"),
+        Bytes::copy_from_slice(key.as_bytes()),
+        group.clone(),
+        consumer.clone(),
+    ]
+}
+
+/// 在日志中重现一个待确认条目的命令
+fn claim_command(key: &str, group: &Bytes, id: StreamId, pending: &Pending) -> Vec<Bytes> {
+    vec![
+        Bytes::from_static(b"XCLAIM"),
+        Bytes::copy_from_slice(key.as_bytes()),
+        group.clone(),
+        pending.consumer.clone(),
+        Bytes::from_static(b"0"),
+        Bytes::from(id.to_string()),
+        Bytes::from_static(b"TIME"),
+        Bytes::from(pending.delivered_at.to_string()),
+        Bytes::from_static(b"RETRYCOUNT"),
+        Bytes::from(pending.deliveries.to_string()),
+        Bytes::from_static(b"FORCE"),
+        Bytes::from_static(b"JUSTID"),
+    ]
+}
+
+/// 在每个流上排队等待新条目，`ms` 为 0 表示一直等待
+fn block(ctx: &mut Ctx<'_>, reads: Vec<(&str, Read)>, count: Option<usize>, ms: u64) {
+    if !ctx.may_block {
+        return;
+    }
+    // 仍然持有这些 key 的分片锁，检查和排队之间不会有新条目插进来
+    let timeout: Option<Duration> = (ms > 0).then(|| Duration::from_millis(ms));
+    let waits: Vec<(String, Wait)> = reads
+        .into_iter()
+        .map(|(key, read)| (key.to_string(), Wait::Stream { read, count }))
+        .collect();
+    ctx.blocked = Some(
+        ctx.state
+            .blocking
+            .block_on(ctx.db.selected(), waits, timeout),
+    );
+}
+
+fn pending_summary(group: &Group) -> Frame {
+    let (first, last) = match (
+        group.pending.first_key_value(),
+        group.pending.last_key_value(),
+    ) {
+        (Some((first, _)), Some((last, _))) => (id_frame(*first), id_frame(*last)),
+        _ => {
+            return Frame::Array(vec![
+                Frame::Integer(0),
+                Frame::Null,
+                Frame::Null,
+                Frame::Null,
+            ])
+        }
+    };
+    let mut counts: Vec<(&Bytes, usize)> = Vec::new();
+    for pending in group.pending.values() {
+        match counts.iter_mut().find(|(c, _)| *c == &pending.consumer) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((&pending.consumer, 1)),
+        }
+    }
+    counts.sort();
+    Frame::Array(vec![
+        Frame::Integer(group.pending.len() as i64),
+        first,
+        last,
+        Frame::Array(
+            counts
+                .into_iter()
+                .map(|(consumer, n)| {
+                    Frame::Array(vec![
+                        Frame::Bulk(consumer.clone()),
+                        Frame::Bulk(Bytes::from(n.to_string())),
+                    ])
+                })
+                .collect(),
+        ),
+    ])
+}
+
+/// XADD 的选项和 ID 参数的位置
+struct AddArgs {
+    nomkstream: bool,
+    maxlen: Option<usize>,
+    id: usize,
+}
+
+fn parse_add(args: &[Bytes]) -> Result<AddArgs, Frame> {
+    let mut parsed: AddArgs = AddArgs {
+        nomkstream: false,
+        maxlen: None,
+        id: 2,
+    };
+    loop {
+        let arg: &Bytes = args.get(parsed.id).ok_or_else(syntax_error)?;
+        match arg.to_ascii_lowercase().as_slice() {
+            b"nomkstream" => {
+                parsed.nomkstream = true;
+                parsed.id += 1;
+            }
+            b"maxlen" => {
+                let (maxlen, used) = parse_maxlen(&args[parsed.id + 1..])?;
+                parsed.maxlen = Some(maxlen);
+                parsed.id += 1 + used;
+            }
+            _ => break,
+        }
+    }
+    let fields: usize = args.len() - parsed.id - 1;
+    if fields == 0 || !fields.is_multiple_of(2) {
+        return Err(wrong_arity("xadd"));
+    }
+    Ok(parsed)
+}
+
+/// `[=|~] threshold`，返回阈值和用掉的参数个数
+fn parse_maxlen(args: &[Bytes]) -> Result<(usize, usize), Frame> {
+    let skip: usize = match args.first().map(|arg| arg.as_ref()) {
+        Some(b"=" | b"~") => 1,
+        Some(_) => 0,
+        None => return Err(syntax_error()),
+    };
+    let threshold: &Bytes = args.get(skip).ok_or_else(syntax_error)?;
+    let maxlen: usize =
+        parse_int(threshold).map_err(|_| error("ERR The MAXLEN argument must be >= 0."))?;
+    Ok((maxlen, skip + 1))
+}
+
+/// XADD 的 ID 参数：`*` 完全自动生成，`ms-*` 自动生成序号，其它是明确的 ID
+fn new_id(arg: &[u8], stream: &Stream) -> Result<StreamId, Frame> {
+    let last: StreamId = stream.last_id();
+    let id: Option<StreamId> = if arg == b"*" {
+        stream.next_id(now_ms())
+    } else if let Some(ms) = arg.strip_suffix(b"-*") {
+        let ms: u64 = std::str::from_utf8(ms)
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .ok_or_else(invalid_id)?;
+        if ms == last.ms {
+            last.seq.checked_add(1).map(|seq| StreamId::new(ms, seq))
+        } else {
+            Some(StreamId::new(ms, 0))
+        }
+    } else {
+        Some(StreamId::parse(arg, 0).ok_or_else(invalid_id)?)
+    };
+    match id {
+        None => Err(error(
+            "ERR The stream has exhausted the last possible ID, unable to add more items",
+        )),
+        Some(StreamId::MIN) => Err(error(
+            "ERR The ID specified in XADD must be greater than 0-0",
+        )),
+        Some(id) if id <= last => Err(error(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+        )),
+        Some(id) => Ok(id),
+    }
+}
+
+/// XREAD 和 XREADGROUP 的参数
+struct ReadArgs<'a> {
+    count: Option<usize>,
+    /// BLOCK 的毫秒数
+    block: Option<u64>,
+    /// XREADGROUP 的组名和消费者名
+    group: Option<(&'a Bytes, &'a Bytes)>,
+    noack: bool,
+    keys: &'a [Bytes],
+    ids: &'a [Bytes],
+}
+
+fn parse_read(args: &[Bytes]) -> Result<ReadArgs<'_>, Frame> {
+    let name: String = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let grouped: bool = name == "xreadgroup";
+    let mut read: ReadArgs<'_> = ReadArgs {
+        count: None,
+        block: None,
+        group: None,
+        noack: false,
+        keys: &[],
+        ids: &[],
+    };
+    let mut idx: usize = 1;
+    while idx < args.len() {
+        let value: Option<&Bytes> = args.get(idx + 1);
+        match args[idx].to_ascii_lowercase().as_slice() {
+            b"count" => {
+                let count: usize = parse_int(value.ok_or_else(syntax_error)?)?;
+                // COUNT 0 表示不限
+                read.count = Some(count).filter(|count| *count > 0);
+                idx += 2;
+            }
+            b"block" => {
+                let ms: i64 = parse_int(value.ok_or_else(syntax_error)?)
+                    .map_err(|_| error("ERR timeout is not an integer or out of range"))?;
+                if ms < 0 {
+                    return Err(error("ERR timeout is negative"));
+                }
+                read.block = Some(ms as u64);
+                idx += 2;
+            }
+            b"group" if grouped => {
+                let consumer: &Bytes = args.get(idx + 2).ok_or_else(syntax_error)?;
+                read.group = Some((value.ok_or_else(syntax_error)?, consumer));
+                idx += 3;
+            }
+            b"noack" if grouped => {
+                read.noack = true;
+                idx += 1;
+            }
+            b"streams" => {
+                let rest: &[Bytes] = &args[idx + 1..];
+                if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                    return Err(error(format!(
+                        "ERR Unbalanced '{name}' list of streams: for each stream key an ID or \
+                         '{}' must be specified.",
+                        if grouped { ">" } else { "$" }
+                    )));
+                }
+                (read.keys, read.ids) = rest.split_at(rest.len() / 2);
+                if grouped && read.group.is_none() {
+                    return Err(error("ERR Missing GROUP option for XREADGROUP"));
+                }
+                return Ok(read);
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Err(syntax_error())
+}
+
+/// XRANGE 的区间端点：`-` 和 `+` 是最小和最大的 ID，`(` 开头表示不含，
+/// 省略序号时取 `default_seq`（起点取 0，终点取最大）
+fn parse_bound(arg: &[u8], default_seq: u64) -> Result<Bound<StreamId>, Frame> {
+    match arg {
+        b"-" => Ok(Bound::Included(StreamId::MIN)),
+        b"+" => Ok(Bound::Included(StreamId::MAX)),
+        _ => match arg.strip_prefix(b"(") {
+            Some(id) => StreamId::parse(id, default_seq)
+                .map(Bound::Excluded)
+                .ok_or_else(invalid_id),
+            None => StreamId::parse(arg, default_seq)
+                .map(Bound::Included)
+                .ok_or_else(invalid_id),
+        },
+    }
+}
+
+fn parse_ids(args: &[Bytes]) -> Result<Vec<StreamId>, Frame> {
+    args.iter()
+        .map(|arg| StreamId::parse(arg, 0).ok_or_else(invalid_id))
+        .collect()
+}
+
+/// 取出 key 上的流
+fn stream_ref<'a>(ctx: &'a Ctx<'_>, key: &str) -> Result<Option<&'a Stream>, Frame> {
+    match ctx.db.get(key).map(|e| &e.value) {
+        Some(Value::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+fn stream_mut<'a>(ctx: &'a mut Ctx<'_>, key: &str) -> Result<Option<&'a mut Stream>, Frame> {
+    match ctx.db.get_mut(key).map(|e| &mut e.value) {
+        Some(Value::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+fn invalid_id() -> Frame {
+    error("ERR Invalid stream ID specified as stream command argument")
+}
+
+fn no_group(key: &str, group: &[u8]) -> Frame {
+    error(format!(
+        "NOGROUP No such key '{key}' or consumer group '{}'",
+        String::from_utf8_lossy(group)
+    ))
+}
+
+fn id_frame(id: StreamId) -> Frame {
+    Frame::Bulk(Bytes::from(id.to_string()))
+}
+
+/// `[id, [field, value, ...]]`，条目已经被删除时字段为空
+fn entry_frame(id: StreamId, fields: Option<Vec<Bytes>>) -> Frame {
+    let fields: Frame = match fields {
+        Some(fields) => Frame::Array(fields.into_iter().map(Frame::Bulk).collect()),
+        None => Frame::Null,
+    };
+    Frame::Array(vec![id_frame(id), fields])
+}
+
+fn entries_frame(items: Vec<(StreamId, Vec<Bytes>)>) -> Frame {
+    Frame::Array(
+        items
+            .into_iter()
+            .map(|(id, fields)| entry_frame(id, Some(fields)))
+            .collect(),
+    )
+}
+
+/// XREAD/XREADGROUP 回复中的一项 `[key, entries]`
+fn stream_reply(key: &str, entries: Frame) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::copy_from_slice(key.as_bytes())),
+        entries,
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aof::{self, Aof, FsyncPolicy};
+    use crate::blocking::Blocked;
+    use crate::cmd::test::{client_session, render, reply, state};
+    use crate::cmd::{dispatch, execute, Outcome};
+    use crate::db::Value;
+    use crate::state::State;
+    use crate::stream::{Pending, StreamId};
+    use bytes::Bytes;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// 流 `s` 上消费组 `g` 的待确认条目
+    fn pending(state: &Arc<State>) -> BTreeMap<StreamId, Pending> {
+        match &state.db.lock_all().get("s").unwrap().value {
+            Value::Stream(stream) => stream.groups()[&Bytes::from("g")].pending.clone(),
+            _ => panic!("s is a stream"),
+        }
+    }
+
+    /// 像客户端连接一样执行一条会阻塞的命令
+    fn block(state: &Arc<State>, cmd: &str) -> Blocked {
+        let args: Vec<Bytes> = cmd.split(' ').map(|s| Bytes::from(s.to_string())).collect();
+        match dispatch(state, &mut client_session(), &args) {
+            Outcome::Block(blocked) => blocked,
+            other => panic!("expected to block, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn add_and_range() {
+        let db = state(4);
+        assert_eq!(reply(&db, "XADD s 1-1 a 1"), "1-1");
+        assert_eq!(reply(&db, "XADD s 1-* b 2"), "1-2");
+        assert_eq!(reply(&db, "XADD s 2 c 3"), "2-0");
+        assert!(reply(&db, "XADD s 1-5 d 4").starts_with("!ERR The ID specified in XADD is equal"));
+        assert!(reply(&db, "XADD t 0-0 a 1").starts_with("!ERR The ID specified in XADD must"));
+        assert!(reply(&db, "XADD s 3 a").starts_with("!ERR wrong number"));
+        let auto: String = reply(&db, "XADD s * e 5");
+        assert!(auto.split_once('-').unwrap().0.parse::<u64>().unwrap() > 2);
+
+        assert_eq!(reply(&db, "XLEN s"), "4");
+        assert_eq!(reply(&db, "TYPE s"), "stream");
+        assert_eq!(
+            reply(&db, "XRANGE s - + COUNT 2"),
+            "[[1-1 [a 1]] [1-2 [b 2]]]"
+        );
+        assert_eq!(reply(&db, "XRANGE s (1-1 2"), "[[1-2 [b 2]] [2-0 [c 3]]]");
+        assert_eq!(
+            reply(&db, "XREVRANGE s 2 -"),
+            "[[2-0 [c 3]] [1-2 [b 2]] [1-1 [a 1]]]"
+        );
+        assert_eq!(reply(&db, "XRANGE s 5 1"), "[]");
+        assert!(reply(&db, "XRANGE s x +").starts_with("!ERR Invalid stream ID"));
+
+        assert_eq!(reply(&db, "XDEL s 1-2 9-9"), "1");
+        assert_eq!(reply(&db, "XTRIM s MAXLEN ~ 1"), "2");
+        assert_eq!(reply(&db, "XRANGE s - +"), format!("[[{auto} [e 5]]]"));
+        // 条目删掉后 ID 也不会回退
+        assert!(reply(&db, "XADD s 2-1 x y").starts_with("!ERR"));
+        reply(&db, "XADD s MAXLEN = 1 * f 6");
+        assert_eq!(reply(&db, "XLEN s"), "1");
+
+        assert_eq!(reply(&db, "XADD missing NOMKSTREAM * a 1"), "nil");
+        assert_eq!(reply(&db, "EXISTS missing"), "0");
+        reply(&db, "SET str v");
+        assert!(reply(&db, "XADD str * a 1").starts_with("!WRONGTYPE"));
+        assert!(reply(&db, "XLEN str").starts_with("!WRONGTYPE"));
+    }
+
+    #[test]
+    fn read_and_block() {
+        let db = state(4);
+        reply(&db, "XADD s 1 a 1");
+        reply(&db, "XADD s 2 b 2");
+        reply(&db, "XADD other 5 c 3");
+        assert_eq!(
+            reply(&db, "XREAD COUNT 1 STREAMS s other 0 0"),
+            "[[s [[1-0 [a 1]]]] [other [[5-0 [c 3]]]]]"
+        );
+        assert_eq!(reply(&db, "XREAD STREAMS s 1"), "[[s [[2-0 [b 2]]]]]");
+        assert_eq!(reply(&db, "XREAD STREAMS s $"), "nil");
+        // 事务、日志重放等场景不会阻塞
+        assert_eq!(reply(&db, "XREAD BLOCK 0 STREAMS s $"), "nil");
+        assert!(reply(&db, "XREAD STREAMS s other 0").starts_with("!ERR Unbalanced 'xread'"));
+        assert!(reply(&db, "XREAD STREAMS s >").starts_with("!ERR The > ID"));
+
+        // `$` 在阻塞时就确定了，之后的条目都算新的
+        let mut first = block(&db, "XREAD BLOCK 0 STREAMS s fresh $ $");
+        let mut second = block(&db, "XREAD BLOCK 1000 STREAMS fresh 0");
+        assert_eq!(db.blocking.blocked_clients(), 2);
+        assert_eq!(reply(&db, "XADD fresh 7 x y"), "7-0");
+        assert_eq!(
+            render(&first.rx.try_recv().unwrap()),
+            "[[fresh [[7-0 [x y]]]]]"
+        );
+        assert_eq!(
+            render(&second.rx.try_recv().unwrap()),
+            "[[fresh [[7-0 [x y]]]]]"
+        );
+        // first 已经拿到回复，s 上的新条目不会再交给它
+        reply(&db, "XADD s 3 c 3");
+        assert_eq!(db.blocking.blocked_clients(), 0);
+    }
+
+    #[test]
+    fn consumer_groups() {
+        let db = state(4);
+        for id in 1..=3 {
+            reply(&db, &format!("XADD s {id} n {id}"));
+        }
+        assert_eq!(reply(&db, "XGROUP CREATE s g 0"), "OK");
+        assert!(reply(&db, "XGROUP CREATE s g 0").starts_with("!BUSYGROUP"));
+        assert!(reply(&db, "XGROUP CREATE missing g $").starts_with("!ERR The XGROUP"));
+        assert_eq!(reply(&db, "XGROUP CREATE fresh g $ MKSTREAM"), "OK");
+        assert_eq!(reply(&db, "XLEN fresh"), "0");
+
+        assert_eq!(
+            reply(&db, "XREADGROUP GROUP g alice COUNT 2 STREAMS s >"),
+            "[[s [[1-0 [n 1]] [2-0 [n 2]]]]]"
+        );
+        assert_eq!(
+            reply(&db, "XREADGROUP GROUP g bob STREAMS s >"),
+            "[[s [[3-0 [n 3]]]]]"
+        );
+        assert_eq!(reply(&db, "XREADGROUP GROUP g bob STREAMS s >"), "nil");
+        assert!(reply(&db, "XREADGROUP GROUP nope bob STREAMS s >").starts_with("!NOGROUP"));
+        assert!(reply(&db, "XREADGROUP GROUP g bob STREAMS s $").starts_with("!ERR The $ ID"));
+        assert_eq!(
+            reply(&db, "XPENDING s g"),
+            "[3 1-0 3-0 [[alice 2] [bob 1]]]"
+        );
+
+        assert_eq!(reply(&db, "XACK s g 1-0 9-0"), "1");
+        assert_eq!(
+            reply(&db, "XREADGROUP GROUP g alice STREAMS s 0"),
+            "[[s [[2-0 [n 2]]]]]"
+        );
+        let detail: String = reply(&db, "XPENDING s g - + 10 alice");
+        assert!(detail.starts_with("[[2-0 alice ") && detail.ends_with(" 1]]"));
+
+        // 空闲时间不够的条目不转移
+        assert_eq!(reply(&db, "XCLAIM s g bob 3600000 2-0"), "[]");
+        assert_eq!(reply(&db, "XCLAIM s g bob 0 2-0 JUSTID"), "[2-0]");
+        assert_eq!(
+            reply(&db, "XCLAIM s g bob 0 2-0 RETRYCOUNT 5"),
+            "[[2-0 [n 2]]]"
+        );
+        assert_eq!(reply(&db, "XPENDING s g"), "[2 2-0 3-0 [[bob 2]]]");
+        let detail: String = reply(&db, "XPENDING s g IDLE 0 2-0 2-0 1");
+        assert!(detail.starts_with("[[2-0 bob ") && detail.ends_with(" 5]]"));
+
+        // 被删除的条目在历史中显示为空
+        reply(&db, "XDEL s 3-0");
+        assert_eq!(
+            reply(&db, "XREADGROUP GROUP g bob STREAMS s 0"),
+            "[[s [[2-0 [n 2]] [3-0 nil]]]]"
+        );
+        assert_eq!(reply(&db, "XGROUP CREATECONSUMER s g carol"), "1");
+        assert_eq!(reply(&db, "XGROUP DELCONSUMER s g bob"), "2");
+        assert_eq!(reply(&db, "XPENDING s g"), "[0 nil nil nil]");
+        assert_eq!(reply(&db, "XGROUP DESTROY s g"), "1");
+        assert!(reply(&db, "XPENDING s g").starts_with("!NOGROUP"));
+    }
+
+    #[test]
+    fn group_reads_replay_from_log() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("my_redis-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path: PathBuf = dir.join("stream.aof");
+        let db = state(4);
+        db.aof
+            .set(Arc::new(
+                Aof::open(path.clone(), FsyncPolicy::Always).unwrap(),
+            ))
+            .unwrap();
+        reply(&db, "XADD s * n 1");
+        reply(&db, "XGROUP CREATE s g $");
+        let mut blocked = block(&db, "XREADGROUP GROUP g alice BLOCK 0 STREAMS s >");
+        let id: String = reply(&db, "XADD s * n 2");
+        assert_eq!(
+            render(&blocked.rx.try_recv().unwrap()),
+            format!("[[s [[{id} [n 2]]]]]")
+        );
+        reply(&db, "XADD s * n 3");
+        reply(&db, "XREADGROUP GROUP g bob STREAMS s >");
+        reply(&db, &format!("XCLAIM s g carol 0 {id}"));
+
+        let restored = state(2);
+        aof::load(&restored, &path).unwrap();
+        // 重写日志时流整体用 RESTORE 重建
+        let rewritten = state(2);
+        for args in aof::entry_commands("s", db.db.lock_all().get("s").unwrap()) {
+            execute(&rewritten, &args);
+        }
+        for copy in [&restored, &rewritten] {
+            for cmd in ["XRANGE s - +", "XPENDING s g"] {
+                assert_eq!(reply(copy, cmd), reply(&db, cmd));
+            }
+            // 分发时间和次数也一样
+            assert_eq!(pending(copy), pending(&db));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! 字符串相关命令
+
+use super::{error, key, ok, parse_int, syntax_error, wrong_arity, wrong_type, Ctx};
+use crate::db::{now_ms, Entry, Value};
+use crate::frame::Frame;
+use bytes::Bytes;
+
+/// PING [message]
+pub fn ping(_ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match args {
+        [_] => Frame::Simple("PONG".to_string()),
+        [_, msg] => Frame::Bulk(msg.clone()),
+        _ => wrong_arity("ping"),
+    }
+}
+
+/// GET key
+pub fn get(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    match ctx.db.get(key(&args[1])).map(|entry| &entry.value) {
+        Some(Value::String(data)) => Frame::Bulk(data.clone()),
+        Some(_) => wrong_type(),
+        None => Frame::Null,
+    }
+}
+
+/// SET key value [NX | XX] [EX seconds | PX milliseconds | KEEPTTL]
+pub fn set(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let key: &str = key(&args[1]);
+    let mut entry: Entry = Entry::new(Value::String(args[2].clone()));
+    let (mut nx, mut xx, mut keep_ttl) = (false, false, false);
+
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"KEEPTTL" => keep_ttl = true,
+            unit @ (b"EX" | b"PX") => {
+                let amount: u64 = match options.next().map(parse_int) {
+                    Some(Ok(amount)) if amount > 0 => amount,
+                    Some(Err(err)) => return err,
+                    _ => return error("ERR invalid expire time in 'set' command"),
+                };
+                let ms: Option<u64> = if unit == b"EX" {
+                    amount.checked_mul(1000)
+                } else {
+                    Some(amount)
+                };
+                match ms.and_then(|ms| now_ms().checked_add(ms)) {
+                    Some(at) => entry.expires_at = Some(at),
+                    None => return error("ERR invalid expire time in 'set' command"),
+                }
+            }
+            _ => return syntax_error(),
+        }
+    }
+    if (nx && xx) || (keep_ttl && entry.expires_at.is_some()) {
+        return syntax_error();
+    }
+
+    let existing: Option<&Entry> = ctx.db.get(key);
+    if (nx && existing.is_some()) || (xx && existing.is_none()) {
+        return Frame::Null;
+    }
+    if keep_ttl {
+        entry.expires_at = existing.and_then(|e| e.expires_at);
+    }
+    ctx.db.insert(key.to_string(), entry);
+    ok()
+}
+
+/// MGET key [key ...]
+pub fn mget(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    let values: Vec<Frame> = args[1..]
+        .iter()
+        .map(|arg| match ctx.db.get(key(arg)).map(|entry| &entry.value) {
+            Some(Value::String(data)) => Frame::Bulk(data.clone()),
+            // 其它类型的值当作不存在
+            _ => Frame::Null,
+        })
+        .collect();
+    Frame::Array(values)
+}
+
+/// MSET key value [key value ...]
+pub fn mset(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if args.len().is_multiple_of(2) {
+        return wrong_arity("mset");
+    }
+    for pair in args[1..].chunks(2) {
+        ctx.db.insert(
+            key(&pair[0]).to_string(),
+            Entry::new(Value::String(pair[1].clone())),
+        );
+    }
+    ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{reply, state};
+
+    #[test]
+    fn set_options() {
+        let db = state(4);
+        assert_eq!(reply(&db, "SET k v XX"), "nil");
+        assert_eq!(reply(&db, "SET k v NX"), "OK");
+        assert_eq!(reply(&db, "SET k w NX"), "nil");
+        assert_eq!(reply(&db, "SET k w XX PX 100000"), "OK");
+        assert_eq!(reply(&db, "GET k"), "w");
+        assert_eq!(reply(&db, "SET k v NX XX"), "!ERR syntax error");
+        assert!(reply(&db, "SET k v EX 0").starts_with("!ERR invalid expire"));
+        // 算出的过期时间溢出时报错，不会让服务端崩溃
+        for option in ["EX 18446744073709551615", "PX 18446744073709551615"] {
+            assert_eq!(
+                reply(&db, &format!("SET k v {option}")),
+                "!ERR invalid expire time in 'set' command"
+            );
+        }
+        assert_eq!(reply(&db, "GET k"), "w");
+
+        assert_eq!(reply(&db, "SET gone v PX 1"), "OK");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(reply(&db, "GET gone"), "nil");
+    }
+}
@@ -0,0 +1,226 @@
+//! 事务：MULTI/EXEC/DISCARD 与 WATCH 乐观锁
+//!
+//! MULTI 之后的命令只排队不执行，EXEC 锁住全部分片后依次执行，中间不会插入其他客户端的命令。
+//! WATCH 记录 key 的版本号，EXEC 时发现被监视的 key 改过就放弃事务并回复空值。
+
+use super::{error, invoke, key, lookup, ok, select_command, Ctx};
+use crate::frame::Frame;
+use crate::session::Watched;
+use bytes::Bytes;
+
+/// MULTI
+pub fn multi(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    if ctx.session.in_multi() {
+        return error("ERR MULTI calls can not be nested");
+    }
+    ctx.session.multi = Some(Vec::new());
+    ctx.session.multi_failed = false;
+    ok()
+}
+
+/// EXEC，调用时已经锁住全部分片
+pub fn exec(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    let queued: Vec<Vec<Bytes>> = match ctx.session.multi.take() {
+        Some(queued) => queued,
+        None => return error("ERR EXEC without MULTI"),
+    };
+    let failed: bool = std::mem::take(&mut ctx.session.multi_failed);
+    let changed: bool = ctx.session.watched.iter().any(|w| w.changed(&mut ctx.db));
+    ctx.session.unwatch(&mut ctx.db);
+    if failed {
+        return error("EXECABORT Transaction discarded because of previous errors.");
+    }
+    if changed {
+        return Frame::Null;
+    }
+
+    // 事务中的阻塞命令不等待
+    let may_block: bool = std::mem::replace(&mut ctx.may_block, false);
+    let start: usize = ctx.propagate.len();
+    let replies: Vec<Frame> = queued
+        .iter()
+        .map(|args| {
+            let spec = lookup(&args[0]).expect("command was checked when queued");
+            let db: usize = ctx.db.selected();
+            let reply: Frame = invoke(ctx, spec, args);
+            // 事务中切换了数据库，在传播的命令里留下标记，之后的命令换到新的数据库
+            if ctx.db.selected() != db {
+                ctx.propagate.push(select_command(ctx.db.selected()));
+            }
+            reply
+        })
+        .collect();
+    ctx.may_block = may_block;
+    // 事务中的脚本已经把自己的写入包了一层，去掉它们，避免嵌套
+    let mut effects: Vec<Vec<Bytes>> = ctx.propagate.split_off(start);
+    effects.retain(|args| !matches!(&args[..], [name] if name == "MULTI" || name == "EXEC"));
+    ctx.propagate.extend(effects);
+    // 用 MULTI/EXEC 包起来，重放日志时整个事务同样原子地执行。只切换了数据库的事务不需要传播
+    if ctx.propagate[start..]
+        .iter()
+        .any(|args| !args[0].eq_ignore_ascii_case(b"SELECT"))
+    {
+        ctx.propagate
+            .insert(start, vec![Bytes::from_static(b"MULTI")]);
+        ctx.propagate.push(vec![Bytes::from_static(b"EXEC")]);
+    }
+    Frame::Array(replies)
+}
+
+/// DISCARD
+pub fn discard(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    if ctx.session.multi.take().is_none() {
+        return error("ERR DISCARD without MULTI");
+    }
+    ctx.session.multi_failed = false;
+    ctx.session.unwatch(&mut ctx.db);
+    ok()
+}
+
+/// WATCH key [key ...]
+pub fn watch(ctx: &mut Ctx<'_>, args: &[Bytes]) -> Frame {
+    if ctx.session.in_multi() {
+        return error("ERR WATCH inside MULTI is not allowed");
+    }
+    for arg in &args[1..] {
+        let key: &str = key(arg);
+        let db: usize = ctx.session.db;
+        if ctx
+            .session
+            .watched
+            .iter()
+            .any(|w| w.db == db && w.key == key)
+        {
+            continue;
+        }
+        let version: u64 = ctx.db.watch(key);
+        let existed: bool = ctx.db.get(key).is_some();
+        ctx.session.watched.push(Watched {
+            db,
+            key: key.to_string(),
+            version,
+            existed,
+        });
+    }
+    ok()
+}
+
+/// UNWATCH，调用时已经锁住全部分片
+pub fn unwatch(ctx: &mut Ctx<'_>, _args: &[Bytes]) -> Frame {
+    ctx.session.unwatch(&mut ctx.db);
+    ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{render, reply, run_in, state};
+    use crate::session::Session;
+
+    #[test]
+    fn exec_runs_queued_commands() {
+        let db = state(4);
+        let mut session: Session = Session::new();
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        assert_eq!(send("MULTI"), "OK");
+        assert_eq!(send("SET a 1"), "QUEUED");
+        assert_eq!(send("RPUSH l x y"), "QUEUED");
+        assert_eq!(send("GET a"), "QUEUED");
+        assert_eq!(reply(&db, "EXISTS a"), "0");
+        assert_eq!(send("EXEC"), "[OK 2 1]");
+        assert_eq!(send("EXEC"), "!ERR EXEC without MULTI");
+
+        assert_eq!(send("MULTI"), "OK");
+        assert_eq!(send("SET a 2"), "QUEUED");
+        assert_eq!(send("DISCARD"), "OK");
+        assert_eq!(reply(&db, "GET a"), "1");
+    }
+
+    #[test]
+    fn queue_errors_abort_exec() {
+        let db = state(2);
+        let mut session: Session = Session::new();
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        send("MULTI");
+        send("SET a 1");
+        assert!(send("GET").starts_with("!ERR wrong number"));
+        assert!(send("EXEC").starts_with("!EXECABORT"));
+        assert_eq!(reply(&db, "EXISTS a"), "0");
+        assert!(send("MULTI").starts_with("OK"));
+        assert!(send("MULTI").starts_with("!ERR MULTI calls"));
+        assert!(send("WATCH a").starts_with("!ERR WATCH inside MULTI"));
+    }
+
+    #[test]
+    fn connection_commands_are_rejected_in_multi() {
+        let db = state(2);
+        let mut session: Session = Session::new();
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        for cmd in ["MONITOR", "PSYNC ? -1"] {
+            send("MULTI");
+            send("SET a 1");
+            assert_eq!(send(cmd), "!ERR Command not allowed inside a transaction");
+            assert!(send("EXEC").starts_with("!EXECABORT"));
+        }
+        assert_eq!(reply(&db, "EXISTS a"), "0");
+    }
+
+    #[test]
+    fn watch_aborts_on_change() {
+        let db = state(4);
+        let mut session: Session = Session::new();
+        run_in(&db, &mut session, "WATCH a b");
+        reply(&db, "SET b other");
+        run_in(&db, &mut session, "MULTI");
+        run_in(&db, &mut session, "SET a 1");
+        assert_eq!(render(&run_in(&db, &mut session, "EXEC")), "nil");
+        assert_eq!(reply(&db, "EXISTS a"), "0");
+
+        // EXEC 之后监视自动解除，读操作也不会让事务失效
+        reply(&db, "SET b again");
+        run_in(&db, &mut session, "WATCH a");
+        reply(&db, "GET a");
+        run_in(&db, &mut session, "MULTI");
+        run_in(&db, &mut session, "SET a 1");
+        assert_eq!(render(&run_in(&db, &mut session, "EXEC")), "[OK]");
+        session.close(&db.db);
+    }
+
+    #[test]
+    fn watched_key_expiring_aborts() {
+        let db = state(2);
+        reply(&db, "SET a 1 PX 20");
+        let mut session: Session = Session::new();
+        run_in(&db, &mut session, "WATCH a");
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        run_in(&db, &mut session, "MULTI");
+        run_in(&db, &mut session, "GET a");
+        assert_eq!(render(&run_in(&db, &mut session, "EXEC")), "nil");
+    }
+
+    #[test]
+    fn watch_and_exec_across_databases() {
+        let db = state(4);
+        let mut session: Session = Session::new();
+        let mut send = |cmd: &str| render(&run_in(&db, &mut session, cmd));
+        send("SELECT 1");
+        send("WATCH a");
+        send("SELECT 0");
+        // 其他数据库中的同名 key 与被监视的 key 无关
+        reply(&db, "SET a 0");
+        send("MULTI");
+        send("SET b 0");
+        send("SELECT 2");
+        send("SET b 2");
+        assert_eq!(send("EXEC"), "[OK OK OK]");
+        assert_eq!(send("GET b"), "2");
+        assert_eq!(reply(&db, "GET b"), "0");
+
+        send("WATCH a");
+        let mut other: Session = Session::new();
+        run_in(&db, &mut other, "SELECT 2");
+        run_in(&db, &mut other, "SET a 2");
+        send("MULTI");
+        send("GET a");
+        assert_eq!(send("EXEC"), "nil");
+    }
+}
@@ -0,0 +1,747 @@
+//! 服务端配置
+//!
+//! 配置项的名字与 Redis 相同，可以写在 redis.conf 风格的配置文件里，每行一个
+//! `名字 值`，`#` 开头的行是注释；也可以在命令行上用 `--名字 值` 覆盖，
+//! 部分配置项还能用 `CONFIG SET` 在运行时修改。
+
+use crate::acl::Acl;
+use crate::aof::FsyncPolicy;
+use crate::db::{DEFAULT_DATABASES, DEFAULT_SHARDS};
+use crate::evict::EvictionPolicy;
+use crate::log::Level;
+use crate::DEFAULT_PORT;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// 服务端配置
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// 监听地址
+    pub bind: String,
+    /// 监听端口，0 表示不监听 TCP
+    pub port: u16,
+    /// TLS 端口，0 表示不开启，需要编译时打开 `tls` feature
+    pub tls_port: u16,
+    /// 服务端证书（PEM），可以带上中间证书
+    pub tls_cert_file: Option<PathBuf>,
+    /// 服务端私钥（PEM）
+    pub tls_key_file: Option<PathBuf>,
+    /// 校验客户端证书用的 CA 证书（PEM）
+    pub tls_ca_cert_file: Option<PathBuf>,
+    /// 是否要求客户端出示证书
+    pub tls_auth_clients: ClientAuth,
+    /// Unix 套接字的路径，`None` 表示不监听
+    pub unixsocket: Option<PathBuf>,
+    /// Unix 套接字文件的权限，0 表示按 umask 创建
+    pub unixsocketperm: u32,
+    /// 分片数
+    pub shards: usize,
+    /// 逻辑数据库个数，`SELECT` 的编号从 0 到它减一
+    pub databases: usize,
+    /// 快照文件所在目录
+    pub dir: PathBuf,
+    /// 快照文件名
+    pub dbfilename: String,
+    /// 自动保存规则，为空表示关闭自动保存
+    pub save: Vec<SaveRule>,
+    /// 是否开启追加写日志
+    pub appendonly: bool,
+    /// 日志文件名，与快照放在同一目录
+    pub appendfilename: String,
+    /// 日志刷盘策略
+    pub appendfsync: FsyncPolicy,
+    /// 同时在线的客户端数上限
+    pub max_clients: usize,
+    /// 内存上限（字节），0 表示不限制
+    pub maxmemory: u64,
+    /// 超出内存上限后的淘汰策略
+    pub maxmemory_policy: EvictionPolicy,
+    /// 淘汰时每轮取样的 key 数，越大越精确也越慢
+    pub maxmemory_samples: usize,
+    /// 启动后跟随的主节点
+    pub replicaof: Option<(String, u16)>,
+    /// 副本连接主节点时认证用的用户名，`None` 表示 `default` 用户
+    pub masteruser: Option<String>,
+    /// 副本连接主节点时认证用的密码
+    pub masterauth: Option<String>,
+    /// 复制积压缓冲区的大小（字节）
+    pub repl_backlog_size: usize,
+    /// 脚本执行时间上限（毫秒），0 表示不限制
+    pub script_time_limit: u64,
+    /// 执行时间达到多少微秒的命令记入慢查询日志，负数表示关闭
+    pub slowlog_log_slower_than: i64,
+    /// 慢查询日志最多保留的条数
+    pub slowlog_max_len: usize,
+    /// Prometheus 指标的 HTTP 端口，0 表示不开启
+    pub metrics_port: u16,
+    /// 是否以集群模式运行
+    pub cluster_enabled: bool,
+    /// 超过这么久（毫秒）没有联系上的节点显示为断开
+    pub cluster_node_timeout: u64,
+    /// 告诉其它节点和客户端的地址，`None` 表示使用 `bind`
+    pub cluster_announce_ip: Option<String>,
+    /// `default` 用户的密码，空字符串表示不需要密码
+    pub requirepass: String,
+    /// 配置文件中的 `user` 行，每行是用户名和若干条 ACL 规则
+    pub users: Vec<String>,
+    /// 日志级别
+    pub loglevel: Level,
+}
+
+/// TLS 连接是否校验客户端证书
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// 必须出示 CA 签发的证书
+    Yes,
+    /// 不要求证书
+    No,
+    /// 可以不出示，出示了就必须有效
+    Optional,
+}
+
+impl ClientAuth {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClientAuth::Yes => "yes",
+            ClientAuth::No => "no",
+            ClientAuth::Optional => "optional",
+        }
+    }
+}
+
+impl FromStr for ClientAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ClientAuth, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "yes" => Ok(ClientAuth::Yes),
+            "no" => Ok(ClientAuth::No),
+            "optional" => Ok(ClientAuth::Optional),
+            _ => Err(format!("invalid tls-auth-clients: {s}")),
+        }
+    }
+}
+
+/// 自动保存规则：`seconds` 秒内至少有 `changes` 次修改就触发一次 BGSAVE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// 所有配置项，`CONFIG GET *` 按这个顺序返回
+pub const PARAMETERS: &[&str] = &[
+    "bind",
+    "port",
+    "tls-port",
+    "tls-cert-file",
+    "tls-key-file",
+    "tls-ca-cert-file",
+    "tls-auth-clients",
+    "unixsocket",
+    "unixsocketperm",
+    "shards",
+    "databases",
+    "dir",
+    "dbfilename",
+    "save",
+    "appendonly",
+    "appendfilename",
+    "appendfsync",
+    "maxclients",
+    "maxmemory",
+    "maxmemory-policy",
+    "maxmemory-samples",
+    "replicaof",
+    "masteruser",
+    "masterauth",
+    "repl-backlog-size",
+    "script-time-limit",
+    "slowlog-log-slower-than",
+    "slowlog-max-len",
+    "metrics-port",
+    "cluster-enabled",
+    "cluster-node-timeout",
+    "cluster-announce-ip",
+    "requirepass",
+    "loglevel",
+];
+
+/// 只能在启动时设置的配置项
+const IMMUTABLE: &[&str] = &[
+    "bind",
+    "port",
+    "tls-port",
+    "tls-cert-file",
+    "tls-key-file",
+    "tls-ca-cert-file",
+    "tls-auth-clients",
+    "unixsocket",
+    "unixsocketperm",
+    "shards",
+    "databases",
+    "appendonly",
+    "appendfilename",
+    "maxclients",
+    "replicaof",
+    "metrics-port",
+    "cluster-enabled",
+    "cluster-announce-ip",
+    "user",
+];
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: "127.0.0.1".to_string(),
+            port: DEFAULT_PORT,
+            tls_port: 0,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            tls_auth_clients: ClientAuth::Yes,
+            unixsocket: None,
+            unixsocketperm: 0,
+            shards: DEFAULT_SHARDS,
+            databases: DEFAULT_DATABASES,
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1,
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 100,
+                },
+                SaveRule {
+                    seconds: 60,
+                    changes: 10000,
+                },
+            ],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            max_clients: 10000,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            maxmemory_samples: 5,
+            replicaof: None,
+            masteruser: None,
+            masterauth: None,
+            repl_backlog_size: 1024 * 1024,
+            script_time_limit: 5000,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            metrics_port: 0,
+            cluster_enabled: false,
+            cluster_node_timeout: 15000,
+            cluster_announce_ip: None,
+            requirepass: String::new(),
+            users: Vec::new(),
+            loglevel: Level::Notice,
+        }
+    }
+}
+
+impl Config {
+    /// 从命令行参数构建配置，参数形如 `[配置文件] --port 6380 --save "60 1000"`，
+    /// 命令行上的配置项覆盖配置文件中的同名项
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Config, String> {
+        let mut config: Config = Config::default();
+        let mut args = args.peekable();
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            let content: String = fs::read_to_string(&path)
+                .map_err(|err| format!("can't open config file '{path}': {err}"))?;
+            config.load_str(&content)?;
+        }
+        while let Some(arg) = args.next() {
+            let name: &str = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument: {arg}"))?;
+            let value: String = args
+                .next()
+                .ok_or_else(|| format!("missing value for {arg}"))?;
+            config
+                .apply(name, &value)
+                .map_err(|err| format!("{arg}: {err}"))?;
+        }
+        Ok(config)
+    }
+
+    /// 读取配置文件的内容。文件中可以有多行 `save`，它们合在一起替换默认的保存规则
+    pub fn load_str(&mut self, content: &str) -> Result<(), String> {
+        let mut saw_save: bool = false;
+        for (number, line) in content.lines().enumerate() {
+            let context = |err: String| format!("config file line {}: {err}", number + 1);
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<String> = split_args(line)
+                .map_err(context)?
+                .into_iter()
+                .map(|word| String::from_utf8(word).map_err(|_| context("invalid UTF-8".into())))
+                .collect::<Result<_, _>>()?;
+            let (name, values) = words.split_first().expect("line is not empty");
+            if values.is_empty() {
+                return Err(context(format!("missing value for '{name}'")));
+            }
+            let value: String = values.join(" ");
+            if name.eq_ignore_ascii_case("save") {
+                if !saw_save {
+                    self.save.clear();
+                    saw_save = true;
+                }
+                self.save.extend(parse_save(&value).map_err(context)?);
+            } else {
+                self.apply(name, &value).map_err(context)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 修改一个配置项，`max-clients` 是 `maxclients` 的别名
+    fn apply(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name.to_ascii_lowercase().as_str() {
+            "bind" => self.bind = value.to_string(),
+            "port" => self.port = value.parse().map_err(|_| "invalid port")?,
+            "tls-port" => self.tls_port = value.parse().map_err(|_| "invalid port")?,
+            "tls-cert-file" => self.tls_cert_file = parse_path(value),
+            "tls-key-file" => self.tls_key_file = parse_path(value),
+            "tls-ca-cert-file" => self.tls_ca_cert_file = parse_path(value),
+            "unixsocket" => self.unixsocket = parse_path(value),
+            "unixsocketperm" => {
+                self.unixsocketperm = u32::from_str_radix(value, 8)
+                    .ok()
+                    .filter(|perm| *perm <= 0o777)
+                    .ok_or_else(|| format!("invalid permissions: {value}"))?
+            }
+            "tls-auth-clients" => self.tls_auth_clients = value.parse()?,
+            "shards" => self.shards = parse_positive(value)?,
+            "databases" => self.databases = parse_positive(value)?,
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => self.dbfilename = value.to_string(),
+            "save" => self.save = parse_save(value)?,
+            "appendonly" => self.appendonly = parse_bool(value)?,
+            "appendfilename" => self.appendfilename = value.to_string(),
+            "appendfsync" => self.appendfsync = value.parse()?,
+            "maxclients" | "max-clients" => self.max_clients = parse_positive(value)?,
+            "maxmemory" => self.maxmemory = parse_memory(value)?,
+            "maxmemory-policy" => self.maxmemory_policy = value.parse()?,
+            "maxmemory-samples" => self.maxmemory_samples = parse_positive(value)?,
+            "replicaof" | "slaveof" => self.replicaof = parse_replicaof(value)?,
+            "masteruser" => self.masteruser = Some(value.to_string()).filter(|v| !v.is_empty()),
+            "masterauth" => self.masterauth = Some(value.to_string()).filter(|v| !v.is_empty()),
+            "repl-backlog-size" => {
+                self.repl_backlog_size = usize::try_from(parse_memory(value)?)
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or_else(|| format!("invalid backlog size: {value}"))?
+            }
+            "script-time-limit" => {
+                self.script_time_limit = value
+                    .parse()
+                    .map_err(|_| format!("invalid time limit: {value}"))?
+            }
+            "slowlog-log-slower-than" => {
+                self.slowlog_log_slower_than = value
+                    .parse()
+                    .map_err(|_| format!("invalid threshold: {value}"))?
+            }
+            "slowlog-max-len" => {
+                self.slowlog_max_len = value
+                    .parse()
+                    .map_err(|_| format!("invalid length: {value}"))?
+            }
+            "metrics-port" => self.metrics_port = value.parse().map_err(|_| "invalid port")?,
+            "cluster-enabled" => self.cluster_enabled = parse_bool(value)?,
+            "cluster-node-timeout" => {
+                self.cluster_node_timeout = value
+                    .parse()
+                    .ok()
+                    .filter(|ms| *ms > 0)
+                    .ok_or_else(|| format!("invalid node timeout: {value}"))?
+            }
+            "cluster-announce-ip" => {
+                self.cluster_announce_ip = Some(value.to_string()).filter(|v| !v.is_empty())
+            }
+            "requirepass" => self.requirepass = value.to_string(),
+            // 先按规则建一次用户，把错误留在启动时报告
+            "user" => {
+                Acl::new("", &[value.to_string()])?;
+                self.users.push(value.to_string());
+            }
+            "loglevel" => self.loglevel = value.parse()?,
+            other => return Err(format!("unknown config option '{other}'")),
+        }
+        Ok(())
+    }
+
+    /// 运行时修改配置项，只能在启动时设置的配置项返回错误
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let name: String = name.to_ascii_lowercase();
+        if IMMUTABLE.contains(&name.as_str()) {
+            return Err("can't set immutable config".to_string());
+        }
+        self.apply(&name, value)
+    }
+
+    /// 读取配置项，格式与配置文件中的写法一致
+    pub fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "bind" => self.bind.clone(),
+            "port" => self.port.to_string(),
+            "tls-port" => self.tls_port.to_string(),
+            "tls-cert-file" => display_path(&self.tls_cert_file),
+            "tls-key-file" => display_path(&self.tls_key_file),
+            "tls-ca-cert-file" => display_path(&self.tls_ca_cert_file),
+            "tls-auth-clients" => self.tls_auth_clients.as_str().to_string(),
+            "unixsocket" => display_path(&self.unixsocket),
+            "unixsocketperm" => format!("{:o}", self.unixsocketperm),
+            "shards" => self.shards.to_string(),
+            "databases" => self.databases.to_string(),
+            "dir" => self.dir.display().to_string(),
+            "dbfilename" => self.dbfilename.clone(),
+            "save" => self
+                .save
+                .iter()
+                .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+                .collect::<Vec<String>>()
+                .join(" "),
+            "appendonly" => if self.appendonly { "yes" } else { "no" }.to_string(),
+            "appendfilename" => self.appendfilename.clone(),
+            "appendfsync" => self.appendfsync.as_str().to_string(),
+            "maxclients" => self.max_clients.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.as_str().to_string(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "replicaof" => match &self.replicaof {
+                Some((host, port)) => format!("{host} {port}"),
+                None => String::new(),
+            },
+            "masteruser" => self.masteruser.clone().unwrap_or_default(),
+            "masterauth" => self.masterauth.clone().unwrap_or_default(),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "script-time-limit" => self.script_time_limit.to_string(),
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than.to_string(),
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            "metrics-port" => self.metrics_port.to_string(),
+            "cluster-enabled" => if self.cluster_enabled { "yes" } else { "no" }.to_string(),
+            "cluster-node-timeout" => self.cluster_node_timeout.to_string(),
+            "cluster-announce-ip" => self.cluster_announce_ip.clone().unwrap_or_default(),
+            "requirepass" => self.requirepass.clone(),
+            "loglevel" => self.loglevel.as_str().to_string(),
+            _ => return None,
+        })
+    }
+
+    /// 监听地址和端口
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    /// TCP 监听地址，`port` 为 0 时不监听 TCP，只接受 TLS 或 Unix 套接字上的连接
+    pub fn tcp_addr(&self) -> Option<String> {
+        (self.port != 0).then(|| self.addr())
+    }
+
+    /// TLS 监听地址，与服务端绑定同一个地址
+    pub fn tls_addr(&self) -> Option<String> {
+        (self.tls_port != 0).then(|| format!("{}:{}", self.bind, self.tls_port))
+    }
+
+    /// Prometheus 指标的监听地址，与服务端绑定同一个地址
+    pub fn metrics_addr(&self) -> Option<String> {
+        (self.metrics_port != 0).then(|| format!("{}:{}", self.bind, self.metrics_port))
+    }
+
+    /// 集群中其它节点和客户端连接本节点用的主机名或 IP
+    pub fn announce_ip(&self) -> String {
+        match &self.cluster_announce_ip {
+            Some(ip) => ip.clone(),
+            // 监听所有地址时没有一个确定的地址可以告诉别人，只能假定都在本机
+            None if self.bind == "0.0.0.0" || self.bind == "::" => "127.0.0.1".to_string(),
+            None => self.bind.clone(),
+        }
+    }
+
+    /// 快照文件的完整路径
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// 日志文件的完整路径
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+}
+
+/// 文件路径，空字符串表示不设置
+fn parse_path(value: &str) -> Option<PathBuf> {
+    Some(PathBuf::from(value)).filter(|_| !value.is_empty())
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+/// 解析 `yes`/`no`
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("expected yes or no, got {value}")),
+    }
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("expected a positive integer, got {value}"))
+}
+
+/// 解析 `"host port"`，`"no one"` 表示不跟随任何主节点
+fn parse_replicaof(value: &str) -> Result<Option<(String, u16)>, String> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    match words.as_slice() {
+        [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => Ok(None),
+        [host, port] => {
+            let port: u16 = port.parse().map_err(|_| format!("invalid port: {port}"))?;
+            Ok(Some((host.to_string(), port)))
+        }
+        _ => Err("expected 'host port' or 'no one'".to_string()),
+    }
+}
+
+/// 解析 `"900 1 300 10"` 形式的保存规则，空字符串表示关闭自动保存
+pub fn parse_save(value: &str) -> Result<Vec<SaveRule>, String> {
+    let numbers: Vec<u64> = value
+        .split_whitespace()
+        .map(|n| n.parse().map_err(|_| format!("invalid save rule: {value}")))
+        .collect::<Result<_, _>>()?;
+    if !numbers.len().is_multiple_of(2) {
+        return Err(format!("invalid save rule: {value}"));
+    }
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| SaveRule {
+            seconds: pair[0],
+            changes: pair[1],
+        })
+        .collect())
+}
+
+/// 解析带单位的内存大小，单位的含义与 redis.conf 相同：
+/// `k`/`m`/`g` 是 1000 的幂，`kb`/`mb`/`gb` 是 1024 的幂
+pub fn parse_memory(value: &str) -> Result<u64, String> {
+    let lower: String = value.to_ascii_lowercase();
+    let split: usize = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid memory size: {value}")),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid memory size: {value}"))
+}
+
+/// 把一行拆成若干参数，支持双引号（可用 `\n`、`\"`、`\xHH` 等转义）和单引号。
+/// `\xHH` 得到的是原始字节，所以参数不一定是合法的 UTF-8
+pub fn split_args(line: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut args: Vec<Vec<u8>> = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quote: char = match chars.peek() {
+            None => return Ok(args),
+            Some(&c) if c == '"' || c == '\'' => {
+                chars.next();
+                c
+            }
+            Some(_) => ' ',
+        };
+        let mut arg: Vec<u8> = Vec::new();
+        let push = |arg: &mut Vec<u8>, c: char| {
+            arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+        };
+        loop {
+            match (quote, chars.next()) {
+                (' ', None) => break,
+                (_, None) => return Err("unbalanced quotes".to_string()),
+                (' ', Some(c)) if c.is_whitespace() => break,
+                (' ', Some(c)) => push(&mut arg, c),
+                ('"', Some('\\')) => match chars.next() {
+                    Some('n') => arg.push(b'\n'),
+                    Some('r') => arg.push(b'\r'),
+                    Some('t') => arg.push(b'\t'),
+                    Some('x') => {
+                        let hex: String = chars.by_ref().take(2).collect();
+                        let byte: u8 = u8::from_str_radix(&hex, 16)
+                            .map_err(|_| format!("invalid escape \\x{hex}"))?;
+                        arg.push(byte);
+                    }
+                    Some(c) => push(&mut arg, c),
+                    None => return Err("unbalanced quotes".to_string()),
+                },
+                ('\'', Some('\\')) if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    arg.push(b'\'');
+                }
+                (q, Some(c)) if c == q => {
+                    // 结束引号后面必须是空白或行尾
+                    if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                        return Err("closing quote must be followed by a space".to_string());
+                    }
+                    break;
+                }
+                (_, Some(c)) => push(&mut arg, c),
+            }
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_memory, parse_save, split_args, Config, SaveRule};
+    use crate::aof::FsyncPolicy;
+    use crate::log::Level;
+
+    #[test]
+    fn build_from_args() {
+        let args = [
+            "--shards",
+            "4",
+            "--databases",
+            "2",
+            "--save",
+            "10 2",
+            "--dbfilename",
+            "a.rdb",
+            "--appendonly",
+            "yes",
+            "--appendfsync",
+            "always",
+            "--max-clients",
+            "2",
+        ];
+        let config: Config = Config::build(args.iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(config.shards, 4);
+        assert_eq!(config.databases, 2);
+        assert!(config.appendonly);
+        assert_eq!(config.appendfsync, FsyncPolicy::Always);
+        assert_eq!(config.max_clients, 2);
+        assert_eq!(
+            config.save,
+            vec![SaveRule {
+                seconds: 10,
+                changes: 2
+            }]
+        );
+        assert!(config.rdb_path().ends_with("a.rdb"));
+
+        assert!(Config::build(["--shards", "0"].iter().map(|s| s.to_string())).is_err());
+        assert!(parse_save("10").is_err());
+        assert!(parse_save("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn config_file_and_overrides() {
+        let path = std::env::temp_dir().join(format!("my_redis-{}.conf", std::process::id()));
+        std::fs::write(
+            &path,
+            "# comment\n\
+             bind 0.0.0.0\n\
+             port 7000\n\
+             save 900 1\n\
+             save 60 100\n\
+             maxmemory 100mb\n\
+             loglevel warning\n\
+             dbfilename \"my dump.rdb\"\n",
+        )
+        .unwrap();
+        let args = [path.display().to_string(), "--port".into(), "7001".into()];
+        let config: Config = Config::build(args.into_iter()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.addr(), "0.0.0.0:7001");
+        assert_eq!(config.save.len(), 2);
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.loglevel, Level::Warning);
+        assert_eq!(config.dbfilename, "my dump.rdb");
+
+        let mut config: Config = Config::default();
+        assert!(config.load_str("save \"\"\n").is_ok());
+        assert!(config.save.is_empty());
+        assert!(config.load_str("nope 1\n").is_err());
+        assert!(config.load_str("port\n").is_err());
+    }
+
+    #[test]
+    fn runtime_set_and_get() {
+        let mut config: Config = Config::default();
+        config.set("MAXMEMORY", "1gb").unwrap();
+        assert_eq!(config.get("maxmemory").unwrap(), "1073741824");
+        config.set("save", "").unwrap();
+        assert_eq!(config.get("save").unwrap(), "");
+        assert!(config.set("port", "1").is_err());
+        assert!(config.set("loglevel", "loud").is_err());
+        assert_eq!(config.get("appendfsync").unwrap(), "everysec");
+        assert!(config.set("unixsocket", "/tmp/my_redis.sock").is_err());
+    }
+
+    #[test]
+    fn unix_socket() {
+        let mut config: Config = Config::default();
+        assert_eq!(config.get("unixsocket").unwrap(), "");
+        config
+            .load_str("port 0\nunixsocket /tmp/my_redis.sock\nunixsocketperm 770\n")
+            .unwrap();
+        assert_eq!(config.tcp_addr(), None);
+        assert_eq!(config.unixsocketperm, 0o770);
+        assert_eq!(config.get("unixsocket").unwrap(), "/tmp/my_redis.sock");
+        assert_eq!(config.get("unixsocketperm").unwrap(), "770");
+        assert!(config.load_str("unixsocketperm 800\n").is_err());
+        assert!(config.load_str("unixsocketperm 1777\n").is_err());
+    }
+
+    #[test]
+    fn memory_units_and_quoting() {
+        assert_eq!(parse_memory("1k").unwrap(), 1000);
+        assert_eq!(parse_memory("2KB").unwrap(), 2048);
+        assert_eq!(parse_memory("3").unwrap(), 3);
+        assert!(parse_memory("1tb").is_err());
+        assert!(parse_memory("mb").is_err());
+
+        assert_eq!(
+            split_args(r#"set "a b" 'c d' e\x"#).unwrap(),
+            [&b"set"[..], b"a b", b"c d", b"e\\x"]
+        );
+        assert_eq!(split_args(r#""\x41\n""#).unwrap(), [b"A\n"]);
+        // 转义得到的字节原样保留，不当作字符编码
+        assert_eq!(
+            split_args(r#""\xff\x00é" x"#).unwrap(),
+            [&b"\xff\x00\xc3\xa9"[..], b"x"]
+        );
+        assert_eq!(split_args(r#""""#).unwrap(), [b""]);
+        assert!(split_args(r#""open"#).is_err());
+        assert!(split_args(r#""a"b"#).is_err());
+    }
+}
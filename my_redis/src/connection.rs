@@ -0,0 +1,79 @@
+//! 客户端连接
+//!
+//! 在 `Stream` 上收发 `Frame`，读缓冲区里可能攒着多个帧或半个帧，
+//! 写入时按连接当前的协议版本编码。
+
+use crate::error::Result;
+use crate::frame::{Decoder, Frame, Protocol};
+use crate::net::Stream;
+use bytes::BytesMut;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+
+/// 在 TCP 或 TLS 连接上收发帧
+#[derive(Debug)]
+pub struct Connection {
+    stream: BufWriter<Stream>,
+    /// 读缓冲区
+    buffer: BytesMut,
+    /// 读缓冲区的解析器
+    decoder: Decoder,
+    /// 写出时使用的协议版本
+    protocol: Protocol,
+}
+
+impl Connection {
+    pub fn new(socket: impl Into<Stream>) -> Connection {
+        Connection {
+            stream: BufWriter::new(socket.into()),
+            buffer: BytesMut::with_capacity(4 * 1024),
+            decoder: Decoder::default(),
+            protocol: Protocol::Resp2,
+        }
+    }
+
+    /// 对端地址
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.get_ref().peer_addr().ok()
+    }
+
+    /// 切换写出时的协议版本
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// 读取一个完整的帧，对端在两个帧之间关闭连接时返回 `None`
+    pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            if let Some(frame) = self.decoder.decode(&mut self.buffer)? {
+                return Ok(Some(frame));
+            }
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return if self.buffer.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed in the middle of a frame",
+                    )
+                    .into())
+                };
+            }
+        }
+    }
+
+    /// 写入已经编码好的数据并刷新
+    pub async fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data).await?;
+        self.stream.flush().await
+    }
+
+    /// 写入一个帧并刷新
+    pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let mut buf: BytesMut = BytesMut::new();
+        frame.encode(self.protocol, &mut buf);
+        self.stream.write_all(&buf).await?;
+        self.stream.flush().await
+    }
+}
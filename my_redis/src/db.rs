@@ -0,0 +1,906 @@
+use crate::dict::Dict;
+use crate::evict::{self, Access};
+use crate::stream::Stream;
+use crate::zset::ZSet;
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 默认分片数
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// 默认的逻辑数据库个数
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// 每个 key 的固定开销：哈希表的槽位、`String` 和 `Entry` 本身
+const ENTRY_OVERHEAD: usize = 64 + size_of::<String>() + size_of::<Entry>();
+
+/// 估算集合类型的内存时最多取样的元素个数
+const SIZE_SAMPLES: usize = 5;
+
+/// RANDOMKEY 随机取到已过期 key 时最多重试的次数
+const RANDOM_TRIES: usize = 100;
+
+/// 分片数据库
+///
+/// 按 key 的哈希值把数据分散到多个由 `Mutex` 保护的哈希表上，
+/// 不同分片上的请求不再互相阻塞。`Db` 可以廉价地克隆，所有克隆共享同一份数据。
+///
+/// 逻辑数据库（`SELECT` 的编号）不单独分片：每个分片里为每个数据库各放一张表，
+/// 同一个 key 在所有数据库中都落在同一个分片上，`MOVE` 只需要锁一个分片。
+#[derive(Debug, Clone)]
+pub struct Db {
+    shards: Arc<Vec<Mutex<Shard>>>,
+    /// 逻辑数据库个数
+    databases: usize,
+    /// 所有 key 估算占用的内存（字节），见 `Entry::memory_usage`
+    used: Arc<AtomicUsize>,
+    /// 因为过期被删除的 key 数
+    expired: Arc<AtomicU64>,
+}
+
+/// 单个分片
+#[derive(Debug)]
+pub struct Shard {
+    /// 每个逻辑数据库落在这个分片上的部分，下标是数据库编号
+    dbs: Vec<Keyspace>,
+}
+
+/// 一个逻辑数据库落在单个分片上的部分
+#[derive(Debug, Default)]
+struct Keyspace {
+    /// 键值对，用 `Dict` 是为了支持 SCAN 的游标
+    entries: Dict<String, Entry>,
+    /// 带过期时间的 key 数，包括尚未清理的过期 key。`get_mut` 取出的 key 不计在内，
+    /// 释放锁时按修改后的过期时间重新计入
+    expires: usize,
+    /// 这些 key 的过期时间之和（毫秒），用于算平均剩余时间
+    expires_at_sum: u128,
+    /// 被 WATCH 的 key 的版本号，只记录至少有一个客户端在监视的 key
+    watched: HashMap<String, Watch>,
+}
+
+/// 一个被监视的 key
+#[derive(Debug, Default)]
+struct Watch {
+    /// 每次修改加一
+    version: u64,
+    /// 监视它的客户端数，降到 0 时移除
+    watchers: usize,
+}
+
+/// 存储的一条数据
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// 值
+    pub value: Value,
+    /// 过期时间（Unix 毫秒时间戳），`None` 表示永不过期
+    pub expires_at: Option<u64>,
+    /// 最近访问时间和访问频率，供内存淘汰使用
+    pub access: Access,
+}
+
+/// 值的类型
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(HashMap<Bytes, Bytes>),
+    Set(HashSet<Bytes>),
+    ZSet(ZSet),
+    Stream(Stream),
+}
+
+/// 一次加锁拿到的若干分片
+///
+/// 多 key 命令需要同时操作多个分片，`Guard` 按分片下标从小到大依次加锁，
+/// 避免两个命令以相反顺序加锁造成死锁。读写都作用在 `select` 选中的逻辑数据库上，默认是 0 号。
+pub struct Guard<'a> {
+    db: &'a Db,
+    /// 已加锁的分片，按分片号升序排列
+    shards: Vec<(usize, MutexGuard<'a, Shard>)>,
+    /// 选中的逻辑数据库
+    index: usize,
+    /// 通过 `get_mut` 取出、可能被修改的 key 和它所在的数据库。取出时先从内存统计中减掉，
+    /// 释放锁时再按修改后的大小加回去
+    checked_out: Vec<(usize, String)>,
+}
+
+impl Db {
+    /// 创建一个有 `num_shards` 个分片、`databases` 个逻辑数据库的数据库
+    pub fn new(num_shards: usize, databases: usize) -> Db {
+        assert!(num_shards > 0, "shard count must be positive");
+        assert!(databases > 0, "database count must be positive");
+        let mut shards: Vec<Mutex<Shard>> = Vec::with_capacity(num_shards);
+        for _ in 0..num_shards {
+            let dbs: Vec<Keyspace> = (0..databases).map(|_| Keyspace::default()).collect();
+            shards.push(Mutex::new(Shard { dbs }));
+        }
+        Db {
+            shards: Arc::new(shards),
+            databases,
+            used: Arc::new(AtomicUsize::new(0)),
+            expired: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// 分片数
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// 逻辑数据库个数
+    pub fn databases(&self) -> usize {
+        self.databases
+    }
+
+    /// 计算 key 所在的分片号
+    pub fn shard_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// 锁住给定 key 所在的全部分片
+    pub fn lock_keys<'a, I>(&'a self, keys: I) -> Guard<'a>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut indexes: Vec<usize> = keys
+            .into_iter()
+            .map(|key| self.shard_index(key.as_ref()))
+            .collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        self.lock_indexes(indexes)
+    }
+
+    /// 锁住单个分片
+    pub fn lock_shard(&self, idx: usize) -> Guard<'_> {
+        self.lock_indexes([idx])
+    }
+
+    /// 锁住所有分片，用于需要遍历或整体替换键空间的命令
+    pub fn lock_all(&self) -> Guard<'_> {
+        self.lock_indexes(0..self.shards.len())
+    }
+
+    /// `indexes` 必须升序且不重复，保证所有调用方的加锁顺序一致。
+    /// 持锁的命令 panic 后锁会中毒。那条命令的修改可能只做了一半，但分片本身仍然可用，
+    /// 照常加锁，不让一条命令拖垮整个服务端
+    fn lock_indexes(&self, indexes: impl IntoIterator<Item = usize>) -> Guard<'_> {
+        let shards = indexes
+            .into_iter()
+            .map(|idx| {
+                let shard = self.shards[idx]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                (idx, shard)
+            })
+            .collect();
+        Guard {
+            db: self,
+            shards,
+            index: 0,
+            checked_out: Vec::new(),
+        }
+    }
+
+    /// 所有 key 估算占用的内存（字节）
+    pub fn used_memory(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// 因为过期被删除的 key 数
+    pub fn expired_keys(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// 删除所有已过期的 key，每次只锁一个分片，返回删除的个数
+    pub fn purge_expired(&self) -> usize {
+        let now: u64 = now_ms();
+        let mut purged: usize = 0;
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut freed: usize = 0;
+            for keyspace in shard.dbs.iter_mut() {
+                let (mut expires, mut expires_at_sum): (usize, u128) = (0, 0);
+                keyspace.entries.retain(|key, entry| {
+                    if entry.is_expired(now) {
+                        freed += entry.memory_usage(key);
+                        purged += 1;
+                        // 过期的 key 一定带着过期时间
+                        expires += 1;
+                        expires_at_sum += u128::from(entry.expires_at.unwrap_or(0));
+                        false
+                    } else {
+                        true
+                    }
+                });
+                keyspace.expires -= expires;
+                keyspace.expires_at_sum -= expires_at_sum;
+            }
+            self.used.fetch_sub(freed, Ordering::Relaxed);
+        }
+        self.expired.fetch_add(purged as u64, Ordering::Relaxed);
+        purged
+    }
+}
+
+impl Default for Db {
+    fn default() -> Db {
+        Db::new(DEFAULT_SHARDS, DEFAULT_DATABASES)
+    }
+}
+
+impl Keyspace {
+    /// key 被修改，让监视它的事务失效
+    fn touch(&mut self, key: &str) {
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.version += 1;
+        }
+    }
+
+    /// 把一个 key 的过期时间计入统计
+    fn count_expiry(&mut self, expires_at: Option<u64>) {
+        if let Some(at) = expires_at {
+            self.expires += 1;
+            self.expires_at_sum += u128::from(at);
+        }
+    }
+
+    /// 从统计中去掉一个 key 的过期时间
+    fn forget_expiry(&mut self, expires_at: Option<u64>) {
+        if let Some(at) = expires_at {
+            self.expires -= 1;
+            self.expires_at_sum -= u128::from(at);
+        }
+    }
+}
+
+impl<'a> Guard<'a> {
+    fn position(&self, key: &str) -> usize {
+        let idx: usize = self.db.shard_index(key);
+        self.shards
+            .binary_search_by_key(&idx, |(i, _)| *i)
+            .expect("key was not declared when locking")
+    }
+
+    /// 选中的数据库在 key 所在分片上的部分
+    fn shard(&self, key: &str) -> &Keyspace {
+        &self.shards[self.position(key)].1.dbs[self.index]
+    }
+
+    fn shard_mut(&mut self, key: &str) -> &mut Keyspace {
+        let pos: usize = self.position(key);
+        &mut self.shards[pos].1.dbs[self.index]
+    }
+
+    /// 选中的数据库在各个已加锁分片上的部分
+    fn keyspaces(&self) -> impl Iterator<Item = &Keyspace> {
+        let index: usize = self.index;
+        self.shards.iter().map(move |(_, shard)| &shard.dbs[index])
+    }
+
+    /// 切换到编号为 `index` 的逻辑数据库，返回之前选中的编号
+    pub fn select(&mut self, index: usize) -> usize {
+        assert!(index < self.db.databases, "database index out of range");
+        std::mem::replace(&mut self.index, index)
+    }
+
+    /// 选中的逻辑数据库
+    pub fn selected(&self) -> usize {
+        self.index
+    }
+
+    /// 逻辑数据库个数
+    pub fn databases(&self) -> usize {
+        self.db.databases
+    }
+
+    /// 开始监视 key，返回它当前的版本号
+    pub fn watch(&mut self, key: &str) -> u64 {
+        let watch: &mut Watch = self
+            .shard_mut(key)
+            .watched
+            .entry(key.to_string())
+            .or_default();
+        watch.watchers += 1;
+        watch.version
+    }
+
+    /// 取消一次 `watch`
+    pub fn unwatch(&mut self, key: &str) {
+        let watched: &mut HashMap<String, Watch> = &mut self.shard_mut(key).watched;
+        if let Some(watch) = watched.get_mut(key) {
+            watch.watchers -= 1;
+            if watch.watchers == 0 {
+                watched.remove(key);
+            }
+        }
+    }
+
+    /// 被监视的 key 当前的版本号，未被监视时为 0
+    pub fn version(&self, key: &str) -> u64 {
+        self.shard(key)
+            .watched
+            .get(key)
+            .map_or(0, |watch| watch.version)
+    }
+
+    /// 读取 key，已过期的 key 视为不存在
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        let now: u64 = now_ms();
+        let entry: &Entry = self
+            .shard(key)
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))?;
+        entry.access.hit(now);
+        Some(entry)
+    }
+
+    /// 以可变方式读取 key，顺便删除已过期的 key
+    ///
+    /// 调用方拿到可变引用后通常会修改数据，所以被监视的 key 的版本号会加一
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        let now: u64 = now_ms();
+        if self.shard(key).entries.get(key)?.is_expired(now) {
+            self.remove(key);
+            return None;
+        }
+        let index: usize = self.index;
+        if !self
+            .checked_out
+            .iter()
+            .any(|(db, k)| *db == index && k == key)
+        {
+            let entry: &Entry = self.shard(key).entries.get(key).unwrap();
+            let (size, expires_at): (usize, Option<u64>) =
+                (entry.memory_usage(key), entry.expires_at);
+            self.db.used.fetch_sub(size, Ordering::Relaxed);
+            self.shard_mut(key).forget_expiry(expires_at);
+            self.checked_out.push((index, key.to_string()));
+        }
+        let shard: &mut Keyspace = self.shard_mut(key);
+        shard.touch(key);
+        let entry: &mut Entry = shard.entries.get_mut(key)?;
+        entry.access.hit(now);
+        Some(entry)
+    }
+
+    /// 写入 key，返回旧值
+    pub fn insert(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        self.db
+            .used
+            .fetch_add(entry.memory_usage(&key), Ordering::Relaxed);
+        let checked_out: bool = self.check_in(&key);
+        let shard: &mut Keyspace = self.shard_mut(&key);
+        shard.touch(&key);
+        shard.count_expiry(entry.expires_at);
+        let old: Option<Entry> = shard.entries.insert(key.clone(), entry);
+        if let (Some(old), false) = (&old, checked_out) {
+            shard.forget_expiry(old.expires_at);
+            self.db
+                .used
+                .fetch_sub(old.memory_usage(&key), Ordering::Relaxed);
+        }
+        old
+    }
+
+    /// 删除 key
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        let now: u64 = now_ms();
+        let checked_out: bool = self.check_in(key);
+        let shard: &mut Keyspace = self.shard_mut(key);
+        let entry: Entry = shard.entries.remove(key)?;
+        if !checked_out {
+            shard.forget_expiry(entry.expires_at);
+            self.db
+                .used
+                .fetch_sub(entry.memory_usage(key), Ordering::Relaxed);
+        }
+        if entry.is_expired(now) {
+            self.db.expired.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.shard_mut(key).touch(key);
+        Some(entry)
+    }
+
+    /// 删除选中的数据库在已加锁分片中的全部 key，返回删除的个数
+    pub fn clear(&mut self) -> usize {
+        let keys: Vec<String> = self
+            .keyspaces()
+            .flat_map(|keyspace| keyspace.entries.keys().cloned())
+            .collect();
+        keys.iter().filter(|key| self.remove(key).is_some()).count()
+    }
+
+    /// 把选中的数据库在已加锁分片中的数据整个取走，留下空表。
+    /// 与 `clear` 的结果相同，但不逐个删除，取走的数据可以交给别的线程释放
+    pub fn detach(&mut self) -> Vec<Dict<String, Entry>> {
+        let index: usize = self.index;
+        let mut checked_out: Vec<String> = Vec::new();
+        self.checked_out.retain(|(db, key)| {
+            if *db == index {
+                checked_out.push(key.clone());
+            }
+            *db != index
+        });
+        let mut detached: Vec<Dict<String, Entry>> = Vec::with_capacity(self.shards.len());
+        let mut freed: usize = 0;
+        for (_, shard) in self.shards.iter_mut() {
+            let keyspace: &mut Keyspace = &mut shard.dbs[index];
+            let entries: Dict<String, Entry> = std::mem::take(&mut keyspace.entries);
+            keyspace.expires = 0;
+            keyspace.expires_at_sum = 0;
+            for (key, entry) in entries.iter() {
+                if !checked_out.contains(key) {
+                    freed += entry.memory_usage(key);
+                }
+                keyspace.touch(key);
+            }
+            detached.push(entries);
+        }
+        self.db.used.fetch_sub(freed, Ordering::Relaxed);
+        detached
+    }
+
+    /// 交换两个逻辑数据库在已加锁分片中的数据，调用方需要锁住全部分片。
+    /// 监视这两个数据库中任何 key 的事务都会失效
+    pub fn swap(&mut self, a: usize, b: usize) {
+        let databases: usize = self.db.databases;
+        assert!(
+            a < databases && b < databases,
+            "database index out of range"
+        );
+        for (_, shard) in self.shards.iter_mut() {
+            let (first, second) = (a.min(b), a.max(b));
+            if first != second {
+                let (head, tail) = shard.dbs.split_at_mut(second);
+                let (first, second) = (&mut head[first], &mut tail[0]);
+                std::mem::swap(&mut first.entries, &mut second.entries);
+                std::mem::swap(&mut first.expires, &mut second.expires);
+                std::mem::swap(&mut first.expires_at_sum, &mut second.expires_at_sum);
+            }
+            for index in [a, b] {
+                for watch in shard.dbs[index].watched.values_mut() {
+                    watch.version += 1;
+                }
+            }
+        }
+        for (db, _) in self.checked_out.iter_mut() {
+            if *db == a {
+                *db = b;
+            } else if *db == b {
+                *db = a;
+            }
+        }
+    }
+
+    /// 把 key 从 `checked_out` 中移除，返回它之前是否在里面
+    fn check_in(&mut self, key: &str) -> bool {
+        let index: usize = self.index;
+        match self
+            .checked_out
+            .iter()
+            .position(|(db, k)| *db == index && k == key)
+        {
+            Some(pos) => {
+                self.checked_out.swap_remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 遍历选中的数据库在已加锁分片中所有未过期的数据
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        let now: u64 = now_ms();
+        self.keyspaces()
+            .flat_map(|keyspace| keyspace.entries.iter())
+            .filter(move |(_, entry)| !entry.is_expired(now))
+    }
+
+    /// 按数据库编号依次遍历所有数据库在已加锁分片中未过期的数据，同时给出数据库编号
+    pub fn iter_all(&self) -> impl Iterator<Item = (usize, &String, &Entry)> {
+        let now: u64 = now_ms();
+        (0..self.db.databases).flat_map(move |index| {
+            self.shards
+                .iter()
+                .flat_map(move |(_, shard)| shard.dbs[index].entries.iter())
+                .filter(move |(_, entry)| !entry.is_expired(now))
+                .map(move |(key, entry)| (index, key, entry))
+        })
+    }
+
+    /// 从游标处继续遍历键空间，回调至少收到 `count` 个未过期的 key 或者访问过 `10 * count`
+    /// 个桶后返回下一个游标，0 表示遍历结束。调用方需要锁住全部分片
+    ///
+    /// 游标除以分片数的余数是分片号，商是分片内 `Dict` 的游标，一个分片遍历完再遍历下一个
+    pub fn scan(&self, cursor: u64, count: usize, mut f: impl FnMut(&String, &Entry)) -> u64 {
+        let num_shards: u64 = self.db.num_shards() as u64;
+        assert_eq!(
+            self.shards.len() as u64,
+            num_shards,
+            "scan requires all shards"
+        );
+        let now: u64 = now_ms();
+        let mut shard: usize = (cursor % num_shards) as usize;
+        let mut inner: u64 = cursor / num_shards;
+        let (mut found, mut steps): (usize, usize) = (0, 0);
+        loop {
+            inner = self.shards[shard].1.dbs[self.index]
+                .entries
+                .scan(inner, |key, entry| {
+                    if !entry.is_expired(now) {
+                        f(key, entry);
+                        found += 1;
+                    }
+                });
+            steps += 1;
+            if inner == 0 {
+                shard += 1;
+                if shard as u64 == num_shards {
+                    return 0;
+                }
+            }
+            if found >= count || steps >= count.saturating_mul(10) {
+                return inner * num_shards + shard as u64;
+            }
+        }
+    }
+
+    /// 随机取一个未过期的 key，调用方需要锁住全部分片
+    pub fn random_key(&self) -> Option<&String> {
+        let total: usize = self.len();
+        if total == 0 {
+            return None;
+        }
+        let now: u64 = now_ms();
+        for _ in 0..RANDOM_TRIES {
+            // 按分片大小加权选分片，每个 key 被选中的机会相同
+            let mut pick: usize = evict::random() as usize % total;
+            let keyspace: &Keyspace = self.keyspaces().find(|keyspace| {
+                if pick < keyspace.entries.len() {
+                    return true;
+                }
+                pick -= keyspace.entries.len();
+                false
+            })?;
+            match keyspace.entries.random(evict::random) {
+                Some((key, entry)) if !entry.is_expired(now) => return Some(key),
+                _ => continue,
+            }
+        }
+        // 几乎全是过期 key，退而取第一个未过期的
+        self.iter().next().map(|(key, _)| key)
+    }
+
+    /// 选中的数据库在已加锁分片中的 key 总数，包括尚未清理的过期 key
+    pub fn len(&self) -> usize {
+        self.keyspaces()
+            .map(|keyspace| keyspace.entries.len())
+            .sum()
+    }
+
+    /// 选中的数据库在已加锁分片中是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 选中的数据库在已加锁分片中带过期时间的 key 数和它们的平均剩余时间（毫秒），
+    /// 不遍历数据。包括尚未清理的过期 key，它们的剩余时间算作负数
+    pub fn expiry_stats(&self) -> (usize, u64) {
+        let (expires, sum) =
+            self.keyspaces()
+                .fold((0, 0), |(expires, sum): (usize, u128), keyspace| {
+                    (expires + keyspace.expires, sum + keyspace.expires_at_sum)
+                });
+        let avg_ttl: u64 = match sum.checked_div(expires as u128) {
+            Some(avg) => (avg as u64).saturating_sub(now_ms()),
+            None => 0,
+        };
+        (expires, avg_ttl)
+    }
+
+    /// 从选中的数据库在已加锁分片中的第 `skip` 个 key 开始取样，到末尾后回到开头，跳过不满足 `filter` 的 key，
+    /// 包括已过期的 key
+    pub fn sample(
+        &self,
+        skip: usize,
+        count: usize,
+        filter: impl Fn(&Entry) -> bool,
+    ) -> Vec<(&String, &Entry)> {
+        let entries = || {
+            self.keyspaces()
+                .flat_map(|keyspace| keyspace.entries.iter())
+        };
+        entries()
+            .skip(skip)
+            .chain(entries().take(skip))
+            .filter(|(_, entry)| filter(entry))
+            .take(count)
+            .collect()
+    }
+}
+
+impl Drop for Guard<'_> {
+    /// 把 `get_mut` 取出的 key 按修改后的大小和过期时间加回统计
+    fn drop(&mut self) {
+        for (index, key) in std::mem::take(&mut self.checked_out) {
+            self.index = index;
+            let used: &AtomicUsize = &self.db.used;
+            let shard: &mut Keyspace = self.shard_mut(&key);
+            if let Some(entry) = shard.entries.get(&key) {
+                used.fetch_add(entry.memory_usage(&key), Ordering::Relaxed);
+                let expires_at: Option<u64> = entry.expires_at;
+                shard.count_expiry(expires_at);
+            }
+        }
+    }
+}
+
+impl Entry {
+    /// 用给定的值创建一条永不过期的数据
+    pub fn new(value: Value) -> Entry {
+        Entry {
+            value,
+            expires_at: None,
+            access: Access::new(),
+        }
+    }
+
+    /// 在时刻 `now`（Unix 毫秒）是否已经过期
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(at) if at <= now)
+    }
+
+    /// 估算这条数据占用的内存（字节），包括 key 本身
+    ///
+    /// 集合类型与 Redis 的 `MEMORY USAGE` 一样只取样前几个元素，按平均大小乘以元素个数，
+    /// 这样修改大集合后重新估算的代价是常数。内容不变时结果不变，
+    /// 所以加入和移出统计时算出的大小总能对上。
+    pub fn memory_usage(&self, key: &str) -> usize {
+        ENTRY_OVERHEAD + key.len() + self.value.memory_usage()
+    }
+}
+
+impl Value {
+    fn memory_usage(&self) -> usize {
+        fn estimate(len: usize, overhead: usize, items: impl Iterator<Item = usize>) -> usize {
+            let sampled: Vec<usize> = items.take(SIZE_SAMPLES).collect();
+            if sampled.is_empty() {
+                return 0;
+            }
+            len * (overhead + sampled.iter().sum::<usize>() / sampled.len())
+        }
+        const BYTES: usize = size_of::<Bytes>();
+        match self {
+            Value::String(data) => data.len(),
+            Value::List(list) => estimate(list.len(), BYTES, list.iter().map(|v| v.len())),
+            Value::Hash(hash) => estimate(
+                hash.len(),
+                2 * BYTES + 8,
+                hash.iter().map(|(k, v)| k.len() + v.len()),
+            ),
+            Value::Set(set) => estimate(set.len(), BYTES + 8, set.iter().map(|m| m.len())),
+            Value::ZSet(zset) => {
+                // 成员同时存在于哈希表和有序索引里
+                estimate(
+                    zset.len(),
+                    2 * BYTES + 24,
+                    zset.iter().map(|(m, _)| 2 * m.len()),
+                )
+            }
+            // 消费组的待确认条目不计入，它们随确认很快释放
+            Value::Stream(stream) => estimate(
+                stream.len(),
+                16 + size_of::<Vec<Bytes>>(),
+                stream
+                    .iter()
+                    .map(|(_, fields)| fields.iter().map(|f| BYTES + f.len()).sum()),
+            ),
+        }
+    }
+
+    /// `TYPE` 命令返回的类型名
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+impl From<Bytes> for Value {
+    fn from(data: Bytes) -> Value {
+        Value::String(data)
+    }
+}
+
+/// 当前的 Unix 毫秒时间戳
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::{now_ms, Db, Entry, Value};
+    use bytes::Bytes;
+
+    #[test]
+    fn keys_spread_over_shards() {
+        let db: Db = Db::new(8, 2);
+        let mut used: Vec<bool> = vec![false; db.num_shards()];
+        for i in 0..1000 {
+            used[db.shard_index(&format!("key:{i}"))] = true;
+        }
+        assert!(used.iter().all(|u| *u));
+    }
+
+    #[test]
+    fn lock_keys_across_shards() {
+        let db: Db = Db::new(4, 2);
+        let keys: Vec<String> = (0..32).map(|i| format!("k{i}")).collect();
+        {
+            let mut guard = db.lock_keys(&keys);
+            for key in &keys {
+                guard.insert(key.clone(), Entry::new(Bytes::from(key.clone()).into()));
+            }
+        }
+        let guard = db.lock_all();
+        assert_eq!(guard.len(), keys.len());
+        assert!(matches!(&guard.get("k7").unwrap().value, Value::String(v) if v == "k7"));
+    }
+
+    #[test]
+    fn expired_keys_are_invisible() {
+        let db: Db = Db::new(2, 2);
+        {
+            let mut guard = db.lock_all();
+            let mut entry: Entry = Entry::new(Bytes::from("v").into());
+            entry.expires_at = Some(now_ms() - 1);
+            guard.insert("old".to_string(), entry);
+            guard.insert("new".to_string(), Entry::new(Bytes::from("v").into()));
+            assert!(guard.get("old").is_none());
+            assert_eq!(guard.iter().count(), 1);
+        }
+        assert_eq!(db.lock_all().expiry_stats().0, 1);
+        assert_eq!(db.purge_expired(), 1);
+        assert_eq!(db.lock_all().len(), 1);
+        assert_eq!(db.lock_all().expiry_stats(), (0, 0));
+    }
+
+    #[test]
+    fn expiry_stats_follow_changes() {
+        let db: Db = Db::new(2, 2);
+        let at: u64 = now_ms() + 10_000;
+        let mut guard = db.lock_all();
+        for key in ["a", "b", "c"] {
+            let mut entry: Entry = Entry::new(Bytes::from("v").into());
+            entry.expires_at = Some(at);
+            guard.insert(key.to_string(), entry);
+        }
+        guard.insert("d".to_string(), Entry::new(Bytes::from("v").into()));
+        let (expires, avg_ttl) = guard.expiry_stats();
+        assert_eq!(expires, 3);
+        assert!(avg_ttl > 9_000 && avg_ttl <= 10_000, "{avg_ttl}");
+        // 取出修改过期时间的 key 在释放锁时重新计入
+        guard.get_mut("a").unwrap().expires_at = None;
+        guard.get_mut("d").unwrap().expires_at = Some(at);
+        guard.remove("b");
+        guard.insert("c".to_string(), Entry::new(Bytes::from("v").into()));
+        drop(guard);
+        let mut guard = db.lock_all();
+        assert_eq!(guard.expiry_stats().0, 1);
+        guard.swap(0, 1);
+        assert_eq!(guard.expiry_stats().0, 0);
+        guard.select(1);
+        assert_eq!(guard.expiry_stats().0, 1);
+        guard.detach();
+        assert_eq!(guard.expiry_stats(), (0, 0));
+    }
+
+    #[test]
+    fn writes_bump_watched_versions() {
+        let db: Db = Db::new(2, 2);
+        let mut guard = db.lock_all();
+        assert_eq!(guard.watch("k"), 0);
+        guard.get_mut("k");
+        assert_eq!(guard.version("k"), 0);
+        guard.insert("k".to_string(), Entry::new(Bytes::from("v").into()));
+        guard.get_mut("k");
+        assert!(guard.remove("k").is_some());
+        assert_eq!(guard.version("k"), 3);
+        // 没人监视之后不再记录版本
+        guard.unwatch("k");
+        guard.insert("k".to_string(), Entry::new(Bytes::from("v").into()));
+        assert_eq!(guard.version("k"), 0);
+    }
+
+    #[test]
+    fn memory_accounting_follows_changes() {
+        let db: Db = Db::new(2, 2);
+        {
+            let mut guard = db.lock_all();
+            guard.insert("s".to_string(), Entry::new(Bytes::from("v").into()));
+            guard.insert("l".to_string(), Entry::new(Value::List(Default::default())));
+        }
+        let base: usize = db.used_memory();
+        assert!(base > 0);
+        {
+            let mut guard = db.lock_all();
+            for _ in 0..100 {
+                if let Value::List(list) = &mut guard.get_mut("l").unwrap().value {
+                    list.push_back(Bytes::from(vec![0; 100]));
+                }
+            }
+        }
+        let grown: usize = db.used_memory();
+        assert!(grown >= base + 100 * 100, "{base} -> {grown}");
+        {
+            let mut guard = db.lock_all();
+            guard.get_mut("l");
+            guard.insert("l".to_string(), Entry::new(Bytes::from("v").into()));
+            guard.get_mut("s");
+            guard.remove("s");
+        }
+        {
+            let mut guard = db.lock_all();
+            guard.remove("l");
+            let mut entry: Entry = Entry::new(Bytes::from("v").into());
+            entry.expires_at = Some(now_ms() - 1);
+            guard.insert("old".to_string(), entry);
+        }
+        assert_eq!(db.purge_expired(), 1);
+        assert_eq!(db.used_memory(), 0);
+    }
+
+    #[test]
+    fn databases_are_separate() {
+        let db: Db = Db::new(2, 4);
+        let mut guard = db.lock_all();
+        guard.insert("k".to_string(), Entry::new(Bytes::from("zero").into()));
+        assert_eq!(guard.select(2), 0);
+        assert!(guard.get("k").is_none());
+        guard.insert("k".to_string(), Entry::new(Bytes::from("two").into()));
+        guard.insert("j".to_string(), Entry::new(Bytes::from("two").into()));
+        assert_eq!(guard.len(), 2);
+        let all: Vec<(usize, &String)> = guard.iter_all().map(|(db, k, _)| (db, k)).collect();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].0, 0);
+
+        // 交换让两边监视的 key 都失效
+        assert_eq!(guard.watch("k"), 0);
+        guard.swap(0, 2);
+        assert_eq!(guard.version("k"), 1);
+        assert_eq!(guard.len(), 1);
+        assert!(matches!(&guard.get("k").unwrap().value, Value::String(v) if v == "zero"));
+        guard.select(0);
+        assert_eq!(guard.len(), 2);
+
+        let used: usize = db.used_memory();
+        guard.get_mut("j");
+        let detached = guard.detach();
+        assert_eq!(detached.iter().map(|d| d.len()).sum::<usize>(), 2);
+        assert!(guard.is_empty());
+        drop(guard);
+        assert!(db.used_memory() < used);
+        assert!(db.used_memory() > 0);
+    }
+}
@@ -0,0 +1,234 @@
+//! 支持游标遍历的哈希表
+//!
+//! 键空间用它代替 `HashMap`：标准库的哈希表扩容后遍历顺序整个打乱，没法用一个游标分多次遍历。
+//! 这里用链地址法，桶数总是 2 的幂，`scan` 沿用 Redis 的反向二进制游标：游标从高位开始递增，
+//! 桶数翻倍或减半后，已经访问过的桶拆出或合并成的桶仍然排在游标之前。所以遍历开始时就存在、
+//! 期间没有被删除的元素至少返回一次，代价是表缩小时可能重复返回。
+
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash, RandomState};
+
+/// 桶数的下限
+const MIN_BUCKETS: usize = 4;
+
+/// 链地址法的哈希表，元素数超过桶数时扩容，不到桶数的 1/8 时缩小
+#[derive(Debug, Clone)]
+pub struct Dict<K, V> {
+    buckets: Vec<Vec<(K, V)>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Dict<K, V> {
+        Dict {
+            buckets: Vec::new(),
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    /// 元素个数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn bucket<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+        self.hasher.hash_one(key) as usize & (self.buckets.len() - 1)
+    }
+
+    /// 查找 key
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        self.buckets[self.bucket(key)]
+            .iter()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| v)
+    }
+
+    /// 以可变方式查找 key
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let idx: usize = self.bucket(key);
+        self.buckets[idx]
+            .iter_mut()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| v)
+    }
+
+    /// 插入或覆盖，返回旧值
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if self.buckets.is_empty() {
+            self.resize(MIN_BUCKETS);
+        }
+        let idx: usize = self.bucket(&key);
+        if let Some((_, old)) = self.buckets[idx].iter_mut().find(|(k, _)| *k == key) {
+            return Some(std::mem::replace(old, value));
+        }
+        self.buckets[idx].push((key, value));
+        self.len += 1;
+        if self.len > self.buckets.len() {
+            self.resize(self.buckets.len() * 2);
+        }
+        None
+    }
+
+    /// 删除 key，返回它的值
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let idx: usize = self.bucket(key);
+        let bucket: &mut Vec<(K, V)> = &mut self.buckets[idx];
+        let pos: usize = bucket.iter().position(|(k, _)| k.borrow() == key)?;
+        let (_, value) = bucket.swap_remove(pos);
+        self.len -= 1;
+        self.shrink();
+        Some(value)
+    }
+
+    /// 只保留 `f` 返回 `true` 的元素
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for bucket in &mut self.buckets {
+            bucket.retain_mut(|(k, v)| f(k, v));
+        }
+        self.len = self.buckets.iter().map(Vec::len).sum();
+        self.shrink();
+    }
+
+    /// 遍历全部元素，顺序不固定
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.buckets.iter().flatten().map(|(k, v)| (k, v))
+    }
+
+    /// 遍历全部 key
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// 访问游标指向的桶中的全部元素，返回下一个游标，返回 0 表示遍历结束
+    pub fn scan(&self, cursor: u64, mut f: impl FnMut(&K, &V)) -> u64 {
+        if self.buckets.is_empty() {
+            return 0;
+        }
+        let mask: u64 = self.buckets.len() as u64 - 1;
+        for (k, v) in &self.buckets[(cursor & mask) as usize] {
+            f(k, v);
+        }
+        // 把桶号以外的位都置 1 后在反转的二进制上加一，相当于从高位开始递增桶号
+        (cursor | !mask)
+            .reverse_bits()
+            .wrapping_add(1)
+            .reverse_bits()
+    }
+
+    /// 随机取一个元素，`random` 提供随机数
+    pub fn random(&self, mut random: impl FnMut() -> u64) -> Option<(&K, &V)> {
+        if self.len == 0 {
+            return None;
+        }
+        // 装载因子不低于 1/8，期望几次就能碰到非空的桶
+        loop {
+            let bucket: &Vec<(K, V)> = &self.buckets[random() as usize & (self.buckets.len() - 1)];
+            if !bucket.is_empty() {
+                let (k, v) = &bucket[random() as usize % bucket.len()];
+                return Some((k, v));
+            }
+        }
+    }
+
+    /// 大量删除后缩小，避免遍历和 `scan` 在空桶上空转
+    fn shrink(&mut self) {
+        if self.buckets.len() > MIN_BUCKETS && self.len * 8 < self.buckets.len() {
+            self.resize(self.len.next_power_of_two().max(MIN_BUCKETS));
+        }
+    }
+
+    /// 一次性重新分配所有元素。Redis 为了不阻塞会渐进式地迁移，这里在分片锁下直接完成
+    fn resize(&mut self, size: usize) {
+        let buckets: Vec<Vec<(K, V)>> = (0..size).map(|_| Vec::new()).collect();
+        let old: Vec<Vec<(K, V)>> = std::mem::replace(&mut self.buckets, buckets);
+        for (key, value) in old.into_iter().flatten() {
+            let idx: usize = self.bucket(&key);
+            self.buckets[idx].push((key, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Dict;
+    use std::collections::HashSet;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut dict: Dict<String, i32> = Dict::default();
+        for i in 0..100 {
+            assert_eq!(dict.insert(i.to_string(), i), None);
+        }
+        assert_eq!(dict.insert("7".to_string(), 70), Some(7));
+        assert_eq!(dict.len(), 100);
+        assert_eq!(dict.get("7"), Some(&70));
+        *dict.get_mut("8").unwrap() += 1;
+        assert_eq!(dict.get("8"), Some(&9));
+        assert_eq!(dict.remove("8"), Some(9));
+        assert_eq!(dict.remove("8"), None);
+        dict.retain(|_, v| *v < 10);
+        assert_eq!(dict.len(), 8);
+        assert_eq!(dict.buckets.len(), 8);
+        assert_eq!(dict.iter().count(), 8);
+    }
+
+    #[test]
+    fn scan_survives_resizing() {
+        let mut dict: Dict<u32, ()> = Dict::default();
+        for i in 0..1000 {
+            dict.insert(i, ());
+        }
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut cursor: u64 = 0;
+        let mut calls: usize = 0;
+        loop {
+            cursor = dict.scan(cursor, |k, _| {
+                seen.insert(*k);
+            });
+            calls += 1;
+            // 遍历途中先扩容再缩小
+            if calls == 100 {
+                for i in 1000..5000 {
+                    dict.insert(i, ());
+                }
+            }
+            if calls == 300 {
+                dict.retain(|k, _| *k < 1000 || *k % 97 == 0);
+            }
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert!((0..1000).all(|i| seen.contains(&i)));
+    }
+}
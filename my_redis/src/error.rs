@@ -0,0 +1,45 @@
+//! 服务端处理连接时的错误
+
+use crate::frame::ProtocolError;
+use std::{fmt, io};
+
+/// 处理连接时的错误，出现后连接会被关闭
+#[derive(Debug)]
+pub enum Error {
+    /// 读写连接失败，或者对端在帧的中间断开
+    Io(io::Error),
+    /// 收到的数据不符合协议，之后的字节已经无法对齐到帧的边界
+    Protocol(ProtocolError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => err.fmt(f),
+            Error::Protocol(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Protocol(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(err: ProtocolError) -> Error {
+        Error::Protocol(err)
+    }
+}
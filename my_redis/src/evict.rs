@@ -0,0 +1,362 @@
+//! 内存淘汰
+//!
+//! 设置了 `maxmemory` 后，客户端发来可能增加内存的写命令前先检查内存用量，超出时按
+//! `maxmemory-policy` 删除 key。与 Redis 一样用的是近似算法：每轮取样
+//! `maxmemory-samples` 个候选 key，淘汰其中最合适的一个，直到内存回到上限以内。
+//! `noeviction` 策略下不删除任何 key，写命令直接返回 OOM 错误。
+
+use crate::db::{now_ms, Entry, Guard};
+use crate::log;
+use crate::log::Level;
+use crate::state::State;
+use bytes::Bytes;
+use std::cell::Cell;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 超出内存上限后的淘汰策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// 不淘汰，写命令返回错误
+    NoEviction,
+    /// 在所有 key 中淘汰最久未访问的
+    AllKeysLru,
+    /// 在设置了过期时间的 key 中淘汰最久未访问的
+    VolatileLru,
+    /// 在所有 key 中淘汰访问频率最低的
+    AllKeysLfu,
+    /// 在设置了过期时间的 key 中淘汰访问频率最低的
+    VolatileLfu,
+    /// 在所有 key 中随机淘汰
+    AllKeysRandom,
+    /// 在设置了过期时间的 key 中随机淘汰
+    VolatileRandom,
+    /// 淘汰最快过期的 key
+    VolatileTtl,
+}
+
+/// 新 key 的访问频率计数，给它们一点时间积累访问次数，不至于一写入就被淘汰
+const LFU_INIT_VAL: u8 = 5;
+
+/// 访问频率计数的对数因子，越大计数增长越慢
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// 多少毫秒没有访问，访问频率计数减一
+const LFU_DECAY_MS: u64 = 60 * 1000;
+
+/// 取样时起点在前多少个 key 中随机选择，限制跳过 key 的开销
+const SAMPLE_WINDOW: usize = 1024;
+
+/// 一个 key 的最近访问时间和访问频率
+///
+/// 读命令只拿到 `&Entry`，所以用 `Cell` 记录访问。`Entry` 只会在分片锁内被访问，
+/// 不需要原子操作。
+#[derive(Debug, Clone)]
+pub struct Access {
+    /// 最近一次访问的 Unix 毫秒时间戳
+    last: Cell<u64>,
+    /// 对数增长的访问频率计数，含义与 Redis 的 LFU 计数相同
+    counter: Cell<u8>,
+}
+
+impl Access {
+    pub fn new() -> Access {
+        Access {
+            last: Cell::new(now_ms()),
+            counter: Cell::new(LFU_INIT_VAL),
+        }
+    }
+
+    /// 记录一次访问：先按闲置时间衰减频率计数，再以递减的概率加一
+    pub fn hit(&self, now: u64) {
+        let mut counter: u8 = self.frequency(now);
+        if counter < u8::MAX {
+            let base: f64 = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            let p: f64 = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+            if (random() as f64 / u64::MAX as f64) < p {
+                counter += 1;
+            }
+        }
+        self.counter.set(counter);
+        self.last.set(now);
+    }
+
+    /// 闲置了多少毫秒
+    pub fn idle(&self, now: u64) -> u64 {
+        now.saturating_sub(self.last.get())
+    }
+
+    /// 衰减之后的访问频率计数
+    pub fn frequency(&self, now: u64) -> u8 {
+        let periods: u64 = self.idle(now) / LFU_DECAY_MS;
+        self.counter
+            .get()
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+impl Default for Access {
+    fn default() -> Access {
+        Access::new()
+    }
+}
+
+impl EvictionPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileLfu => "volatile-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileRandom => "volatile-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// 是否只淘汰设置了过期时间的 key
+    fn volatile(self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::VolatileLru
+                | EvictionPolicy::VolatileLfu
+                | EvictionPolicy::VolatileRandom
+                | EvictionPolicy::VolatileTtl
+        )
+    }
+
+    /// 候选 key 的淘汰优先级，越大越先淘汰
+    fn score(self, entry: &Entry, now: u64) -> u64 {
+        match self {
+            EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => entry.access.idle(now),
+            EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu => {
+                (u8::MAX - entry.access.frequency(now)) as u64
+            }
+            EvictionPolicy::VolatileTtl => u64::MAX - entry.expires_at.unwrap_or(u64::MAX),
+            EvictionPolicy::NoEviction
+            | EvictionPolicy::AllKeysRandom
+            | EvictionPolicy::VolatileRandom => 0,
+        }
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EvictionPolicy, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "volatile-lru" => Ok(EvictionPolicy::VolatileLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "volatile-lfu" => Ok(EvictionPolicy::VolatileLfu),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            "volatile-random" => Ok(EvictionPolicy::VolatileRandom),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            _ => Err(format!("invalid maxmemory policy: {s}")),
+        }
+    }
+}
+
+/// 把内存用量降到 `maxmemory` 以内，做不到时返回 `false`，调用方应拒绝写命令。
+/// 不能在持有分片锁时调用
+pub fn free_memory(state: &State) -> bool {
+    let (maxmemory, policy, samples) = {
+        let config = state.config();
+        (
+            config.maxmemory,
+            config.maxmemory_policy,
+            config.maxmemory_samples,
+        )
+    };
+    if maxmemory == 0 {
+        return true;
+    }
+    while state.db.used_memory() as u64 > maxmemory {
+        if policy == EvictionPolicy::NoEviction || !evict_one(state, policy, samples) {
+            return false;
+        }
+    }
+    true
+}
+
+/// 取样并淘汰一个 key，找不到可以淘汰的 key 时返回 `false`
+fn evict_one(state: &State, policy: EvictionPolicy, samples: usize) -> bool {
+    let now: u64 = now_ms();
+    let shards: usize = state.db.num_shards();
+    let start: usize = random() as usize % shards;
+    let mut best: Option<(u64, usize, usize, String)> = None;
+    let mut sampled: usize = 0;
+    // 从随机的分片开始逐个取样，每个分片上所有数据库的 key 一起比较，凑够个数为止
+    'shards: for idx in (0..shards).map(|i| (start + i) % shards) {
+        let mut guard: Guard<'_> = state.db.lock_shard(idx);
+        for db in 0..guard.databases() {
+            guard.select(db);
+            let len: usize = guard.len();
+            if len == 0 {
+                continue;
+            }
+            let skip: usize = random() as usize % len.min(SAMPLE_WINDOW);
+            let candidates = guard.sample(skip, samples - sampled, |entry| {
+                !entry.is_expired(now) && (!policy.volatile() || entry.expires_at.is_some())
+            });
+            sampled += candidates.len();
+            for (key, entry) in candidates {
+                let score: u64 = policy.score(entry, now);
+                if best.as_ref().is_none_or(|(best, _, _, _)| score > *best) {
+                    best = Some((score, idx, db, key.clone()));
+                }
+            }
+            if sampled >= samples {
+                break 'shards;
+            }
+        }
+    }
+    let (_, idx, db, key) = match best {
+        Some(best) => best,
+        None => return false,
+    };
+    let mut guard: Guard<'_> = state.db.lock_shard(idx);
+    guard.select(db);
+    if guard.remove(&key).is_some() {
+        // 持有分片锁时传播，和普通的 DEL 一样
+        state.propagate(
+            db,
+            &[vec![Bytes::from_static(b"DEL"), Bytes::from(key.clone())]],
+        );
+        state.evicted_keys.fetch_add(1, Ordering::Relaxed);
+        log!(Level::Debug, "Evicted key {key} ({})", policy.as_str());
+    }
+    true
+}
+
+/// 取样、LFU 计数和 RANDOMKEY 用的伪随机数（xorshift），不需要密码学强度
+pub fn random() -> u64 {
+    static SEED: AtomicU64 = AtomicU64::new(0);
+    let mut x: u64 = SEED.load(Ordering::Relaxed);
+    if x == 0 {
+        x = now_ms() | 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    SEED.store(x, Ordering::Relaxed);
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Access, LFU_DECAY_MS, LFU_INIT_VAL};
+    use crate::cmd::test::{send, state};
+    use crate::state::State;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn limited(policy: &str, maxmemory: usize) -> Arc<State> {
+        let state: Arc<State> = state(4);
+        state.set_config("maxmemory-policy", policy).unwrap();
+        state
+            .set_config("maxmemory", &maxmemory.to_string())
+            .unwrap();
+        state
+    }
+
+    #[test]
+    fn noeviction_rejects_writes_but_allows_deletes() {
+        let state: Arc<State> = state(4);
+        send(&state, "SET a 1");
+        let used: usize = state.db.used_memory();
+        state.set_config("maxmemory", &used.to_string()).unwrap();
+        assert_eq!(send(&state, "SET b 2"), "OK");
+        assert_eq!(
+            send(&state, "SET c 3"),
+            "!OOM command not allowed when used memory > 'maxmemory'."
+        );
+        assert_eq!(send(&state, "GET a"), "1");
+        assert_eq!(send(&state, "DEL a"), "1");
+        assert_eq!(send(&state, "SET c 3"), "OK");
+    }
+
+    #[test]
+    fn volatile_ttl_keeps_persistent_keys() {
+        let state: Arc<State> = limited("volatile-ttl", 4000);
+        for i in 0..10 {
+            send(&state, &format!("SET keep:{i} v"));
+        }
+        for i in 0..100 {
+            assert_eq!(
+                send(&state, &format!("SET temp:{i} v EX {}", 100 + i)),
+                "OK"
+            );
+            assert!(state.db.used_memory() <= 4000 + 256);
+        }
+        for i in 0..10 {
+            assert_eq!(send(&state, &format!("EXISTS keep:{i}")), "1");
+        }
+        // 最晚过期的 key 留了下来
+        assert_eq!(send(&state, "EXISTS temp:99"), "1");
+        assert!(
+            state
+                .evicted_keys
+                .load(std::sync::atomic::Ordering::Relaxed)
+                > 0
+        );
+
+        // 没有设置过期时间的 key 可以淘汰时，volatile 策略也只能拒绝
+        let state: Arc<State> = limited("volatile-lru", 1000);
+        for i in 0..20 {
+            send(&state, &format!("SET keep:{i} v"));
+        }
+        assert!(send(&state, "SET more v").starts_with("!OOM"));
+    }
+
+    #[test]
+    fn allkeys_lru_prefers_idle_keys() {
+        let state: Arc<State> = limited("allkeys-lru", 0);
+        state.set_config("maxmemory-samples", "10").unwrap();
+        for i in 0..50 {
+            send(&state, &format!("SET cold:{i} v"));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        for i in 0..50 {
+            send(&state, &format!("SET hot:{i} v"));
+        }
+        let used: usize = state.db.used_memory();
+        state.set_config("maxmemory", &used.to_string()).unwrap();
+        for i in 0..40 {
+            send(&state, &format!("SET new:{i} v"));
+        }
+        let count = |prefix: &str| {
+            (0..50)
+                .filter(|i| send(&state, &format!("EXISTS {prefix}:{i}")) == "1")
+                .count()
+        };
+        let (cold, hot): (usize, usize) = (count("cold"), count("hot"));
+        assert!(cold < hot, "cold {cold} hot {hot}");
+        // 写命令执行前才检查内存，所以最多超出一个 key 的大小
+        assert!(state.db.used_memory() <= used + 256);
+    }
+
+    #[test]
+    fn allkeys_random_frees_memory() {
+        let state: Arc<State> = limited("allkeys-random", 3000);
+        for i in 0..100 {
+            assert_eq!(send(&state, &format!("RPUSH list:{i} a b c")), "3");
+        }
+        assert!(state.db.used_memory() <= 3000 + 512);
+    }
+
+    #[test]
+    fn lfu_counter_grows_slowly_and_decays() {
+        let access: Access = Access::new();
+        let now: u64 = crate::db::now_ms();
+        for _ in 0..1000 {
+            access.hit(now);
+        }
+        let counter: u8 = access.frequency(now);
+        assert!(counter > LFU_INIT_VAL + 5 && counter < 40, "{counter}");
+        assert_eq!(access.frequency(now + 3 * LFU_DECAY_MS), counter - 3);
+        assert_eq!(access.idle(now + 10), 10);
+    }
+}
@@ -0,0 +1,554 @@
+//! RESP2/RESP3 协议的帧
+//!
+//! 解析是增量的：缓冲区里还不够一个完整的帧时返回 `None` 并保留已收到的字节，
+//! 读到更多数据后再试。`Decoder` 记住至少还要多少字节，数据到齐之前不重新解析，
+//! 大帧分成很多次收到时不会每次都从头扫一遍。编码按连接协商的协议版本进行，RESP3 独有的类型在 RESP2
+//! 连接上退化成数组、字符串和整数，命令实现只需要构造一种回复。
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt;
+
+/// 单个字符串参数的长度上限，与 Redis 的 `proto-max-bulk-len` 默认值相同
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// 聚合类型的元素个数上限
+const MAX_ELEMENTS: usize = 1024 * 1024;
+/// 聚合类型的嵌套层数上限，防止恶意输入耗尽栈空间
+const MAX_DEPTH: usize = 64;
+/// 一行（类型标记到 `\r\n`）的长度上限
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// 协议版本，连接建立时是 RESP2，客户端可以用 `HELLO 3` 切换
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+/// 一个 RESP 帧
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<Frame>),
+    /// RESP3 映射，RESP2 下展开成键值交替的数组
+    Map(Vec<(Frame, Frame)>),
+    /// RESP3 集合，RESP2 下是数组
+    Set(Vec<Frame>),
+    /// RESP3 浮点数，RESP2 下是字符串
+    Double(f64),
+    /// RESP3 布尔值，RESP2 下是整数 1 和 0
+    Boolean(bool),
+    /// RESP3 推送消息，RESP2 下是数组
+    Push(Vec<Frame>),
+}
+
+/// 收到的数据不符合协议
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolError(pub String);
+
+/// 解析过程中的错误
+enum Error {
+    /// 数据还没收完
+    Incomplete,
+    Invalid(String),
+}
+
+impl Frame {
+    /// 从缓冲区开头解析一个帧，成功时消费掉对应的字节
+    pub fn parse(buf: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
+        let parsed: Option<(Frame, usize)> = Frame::peek(buf)?;
+        Ok(parsed.map(|(frame, len)| {
+            buf.advance(len);
+            frame
+        }))
+    }
+
+    /// 从缓冲区开头解析一个帧但不消费，成功时同时返回它占的字节数
+    pub fn peek(buf: &[u8]) -> Result<Option<(Frame, usize)>, ProtocolError> {
+        Decoder::default().peek(buf)
+    }
+
+    /// 按协议版本编码
+    pub fn encode(&self, protocol: Protocol, buf: &mut BytesMut) {
+        let resp3: bool = protocol == Protocol::Resp3;
+        match self {
+            Frame::Simple(s) => line(buf, b'+', &sanitize(s)),
+            Frame::Error(e) => line(buf, b'-', &sanitize(e)),
+            Frame::Integer(n) => line(buf, b':', &n.to_string()),
+            Frame::Bulk(data) => bulk(buf, data),
+            Frame::Null if resp3 => buf.put_slice(b"_\r\n"),
+            Frame::Null => buf.put_slice(b"$-1\r\n"),
+            Frame::Array(items) => aggregate(buf, b'*', items, protocol),
+            Frame::Map(pairs) => {
+                if resp3 {
+                    line(buf, b'%', &pairs.len().to_string());
+                } else {
+                    line(buf, b'*', &(pairs.len() * 2).to_string());
+                }
+                for (key, value) in pairs {
+                    key.encode(protocol, buf);
+                    value.encode(protocol, buf);
+                }
+            }
+            Frame::Set(items) => aggregate(buf, if resp3 { b'~' } else { b'*' }, items, protocol),
+            Frame::Double(value) if resp3 => line(buf, b',', &format_double(*value)),
+            Frame::Double(value) => bulk(buf, format_double(*value).as_bytes()),
+            Frame::Boolean(value) if resp3 => line(buf, b'#', if *value { "t" } else { "f" }),
+            Frame::Boolean(value) => line(buf, b':', if *value { "1" } else { "0" }),
+            Frame::Push(items) => aggregate(buf, if resp3 { b'>' } else { b'*' }, items, protocol),
+        }
+    }
+
+    /// 用于错误信息的类型名
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Frame::Simple(_) => "simple string",
+            Frame::Error(_) => "error",
+            Frame::Integer(_) => "integer",
+            Frame::Bulk(_) => "bulk string",
+            Frame::Null => "null",
+            Frame::Array(_) => "array",
+            Frame::Map(_) => "map",
+            Frame::Set(_) => "set",
+            Frame::Double(_) => "double",
+            Frame::Boolean(_) => "boolean",
+            Frame::Push(_) => "push",
+        }
+    }
+}
+
+fn line(buf: &mut BytesMut, tag: u8, content: &str) {
+    buf.put_u8(tag);
+    buf.put_slice(content.as_bytes());
+    buf.put_slice(b"\r\n");
+}
+
+fn bulk(buf: &mut BytesMut, data: &[u8]) {
+    line(buf, b'$', &data.len().to_string());
+    buf.put_slice(data);
+    buf.put_slice(b"\r\n");
+}
+
+fn aggregate(buf: &mut BytesMut, tag: u8, items: &[Frame], protocol: Protocol) {
+    line(buf, tag, &items.len().to_string());
+    for item in items {
+        item.encode(protocol, buf);
+    }
+}
+
+/// 单行字符串里不能出现换行，否则会破坏帧的边界
+fn sanitize(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+/// RESP3 的浮点数格式：无穷大写作 `inf`/`-inf`
+pub fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// 在同一个缓冲区上反复解析的解析器，记着上次解析时缓冲区至少要有多长才可能解析出帧
+#[derive(Debug, Default)]
+pub struct Decoder {
+    need: usize,
+}
+
+impl Decoder {
+    /// 和 `Frame::parse` 一样，但缓冲区比上次估计的还短时直接返回 `None`
+    pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
+        let parsed: Option<(Frame, usize)> = self.peek(buf)?;
+        Ok(parsed.map(|(frame, len)| {
+            buf.advance(len);
+            frame
+        }))
+    }
+
+    /// 和 `Frame::peek` 一样，但缓冲区比上次估计的还短时直接返回 `None`
+    pub fn peek(&mut self, buf: &[u8]) -> Result<Option<(Frame, usize)>, ProtocolError> {
+        if buf.len() < self.need {
+            return Ok(None);
+        }
+        let mut parser: Parser<'_> = Parser {
+            buf,
+            pos: 0,
+            need: 0,
+        };
+        match parser.frame(0) {
+            Ok(frame) => {
+                self.need = 0;
+                Ok(Some((frame, parser.pos)))
+            }
+            Err(Error::Incomplete) => {
+                self.need = parser.need;
+                Ok(None)
+            }
+            Err(Error::Invalid(msg)) => Err(ProtocolError(msg)),
+        }
+    }
+}
+
+/// 最短的帧 `_\r\n` 的长度，用来估计还没收到的元素至少要占多少字节
+const MIN_FRAME_LEN: usize = 3;
+
+struct Parser<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    /// 返回 `Incomplete` 时缓冲区至少要有的长度
+    need: usize,
+}
+
+impl Parser<'_> {
+    /// 数据还没收完，缓冲区至少要有 `need` 字节才值得再解析
+    fn incomplete(&mut self, need: usize) -> Error {
+        self.need = need;
+        Error::Incomplete
+    }
+
+    fn frame(&mut self, depth: usize) -> Result<Frame, Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("too many nested aggregates"));
+        }
+        let tag: u8 = match self.buf.get(self.pos) {
+            Some(&tag) => tag,
+            None => return Err(self.incomplete(self.pos + 1)),
+        };
+        self.pos += 1;
+        match tag {
+            b'+' => Ok(Frame::Simple(self.string()?)),
+            b'-' => Ok(Frame::Error(self.string()?)),
+            b':' => Ok(Frame::Integer(self.integer()?)),
+            b'$' => match self.length()? {
+                None => Ok(Frame::Null),
+                Some(len) if len > MAX_BULK_LEN => Err(invalid("invalid bulk length")),
+                Some(len) => {
+                    let end: usize = self.pos + len;
+                    if self.buf.len() < end + 2 {
+                        return Err(self.incomplete(end + 2));
+                    }
+                    if &self.buf[end..end + 2] != b"\r\n" {
+                        return Err(invalid("bulk string is not terminated by CRLF"));
+                    }
+                    let data: Bytes = Bytes::copy_from_slice(&self.buf[self.pos..end]);
+                    self.pos = end + 2;
+                    Ok(Frame::Bulk(data))
+                }
+            },
+            b'*' => match self.length()? {
+                None => Ok(Frame::Null),
+                Some(len) => Ok(Frame::Array(self.items(len, depth)?)),
+            },
+            b'~' => match self.length()? {
+                None => Err(invalid("invalid set length")),
+                Some(len) => Ok(Frame::Set(self.items(len, depth)?)),
+            },
+            b'>' => match self.length()? {
+                None => Err(invalid("invalid push length")),
+                Some(len) => Ok(Frame::Push(self.items(len, depth)?)),
+            },
+            b'%' => {
+                let len: usize = match self.length()? {
+                    Some(len) if len <= MAX_ELEMENTS => len,
+                    _ => return Err(invalid("invalid map length")),
+                };
+                let mut pairs: Vec<(Frame, Frame)> = Vec::with_capacity(len.min(1024));
+                for i in 0..len {
+                    let rest: usize = 2 * (len - i - 1);
+                    let key: Frame = self.item(depth, rest + 1)?;
+                    let value: Frame = self.item(depth, rest)?;
+                    pairs.push((key, value));
+                }
+                Ok(Frame::Map(pairs))
+            }
+            b'_' => match self.line()? {
+                b"" => Ok(Frame::Null),
+                _ => Err(invalid("invalid null")),
+            },
+            b',' => {
+                let s: String = self.string()?;
+                let value: f64 = match s.as_str() {
+                    "inf" | "+inf" => f64::INFINITY,
+                    "-inf" => f64::NEG_INFINITY,
+                    s => s.parse().map_err(|_| invalid("invalid double"))?,
+                };
+                Ok(Frame::Double(value))
+            }
+            b'#' => match self.line()? {
+                b"t" => Ok(Frame::Boolean(true)),
+                b"f" => Ok(Frame::Boolean(false)),
+                _ => Err(invalid("invalid boolean")),
+            },
+            tag => Err(Error::Invalid(format!(
+                "unexpected byte '{}'",
+                (tag as char).escape_default()
+            ))),
+        }
+    }
+
+    /// 读到 `\r\n` 为止，返回不含换行的内容
+    fn line(&mut self) -> Result<&[u8], Error> {
+        let rest: &[u8] = &self.buf[self.pos..];
+        match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(end) if end > MAX_LINE_LEN => Err(invalid("line is too long")),
+            Some(end) => {
+                self.pos += end + 2;
+                Ok(&rest[..end])
+            }
+            None if rest.len() > MAX_LINE_LEN => Err(invalid("line is too long")),
+            None => Err(self.incomplete(self.buf.len() + 1)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let line: &[u8] = self.line()?;
+        String::from_utf8(line.to_vec()).map_err(|_| invalid("invalid UTF-8 in line"))
+    }
+
+    fn integer(&mut self) -> Result<i64, Error> {
+        let line: &[u8] = self.line()?;
+        std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid("invalid integer"))
+    }
+
+    /// 长度字段，`-1` 表示空值
+    fn length(&mut self) -> Result<Option<usize>, Error> {
+        match self.integer()? {
+            -1 => Ok(None),
+            len if len < 0 => Err(invalid("invalid length")),
+            len => Ok(Some(len as usize)),
+        }
+    }
+
+    fn items(&mut self, len: usize, depth: usize) -> Result<Vec<Frame>, Error> {
+        if len > MAX_ELEMENTS {
+            return Err(invalid("invalid multibulk length"));
+        }
+        // 长度来自对端，不能直接按它预分配
+        let mut items: Vec<Frame> = Vec::with_capacity(len.min(1024));
+        for i in 0..len {
+            items.push(self.item(depth, len - i - 1)?);
+        }
+        Ok(items)
+    }
+
+    /// 解析聚合类型的一个元素，后面还有 `rest` 个元素。
+    /// 数据不够时把它们至少要占的字节也算进 `need`
+    fn item(&mut self, depth: usize, rest: usize) -> Result<Frame, Error> {
+        self.frame(depth + 1).map_err(|err| match err {
+            Error::Incomplete => self.incomplete(self.need + rest * MIN_FRAME_LEN),
+            err => err,
+        })
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::Invalid(msg.to_string())
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[cfg(test)]
+mod test {
+    use super::{Decoder, Frame, Protocol};
+    use bytes::{Bytes, BytesMut};
+
+    /// 测试用的 xorshift 伪随机数，保证每次运行的输入相同
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> usize {
+            (self.next() % n) as usize
+        }
+    }
+
+    fn random_frame(rng: &mut Rng, depth: usize) -> Frame {
+        let kinds: u64 = if depth >= 3 { 7 } else { 11 };
+        match rng.below(kinds) {
+            0 => Frame::Simple(format!("s{}", rng.next())),
+            1 => Frame::Error(format!("ERR e{}", rng.next())),
+            2 => Frame::Integer(rng.next() as i64),
+            3 => {
+                let len: usize = rng.below(40);
+                Frame::Bulk(
+                    (0..len)
+                        .map(|_| rng.next() as u8)
+                        .collect::<Vec<u8>>()
+                        .into(),
+                )
+            }
+            4 => Frame::Null,
+            5 => Frame::Double(match rng.below(4) {
+                0 => f64::INFINITY,
+                1 => f64::NEG_INFINITY,
+                _ => (rng.next() as i64) as f64 / 1024.0,
+            }),
+            6 => Frame::Boolean(rng.below(2) == 0),
+            kind => {
+                let len: usize = rng.below(5);
+                let items: Vec<Frame> = (0..len).map(|_| random_frame(rng, depth + 1)).collect();
+                match kind {
+                    7 => Frame::Array(items),
+                    8 => Frame::Set(items),
+                    9 => Frame::Push(items),
+                    _ => Frame::Map(
+                        items
+                            .into_iter()
+                            .map(|item| (Frame::Bulk(Bytes::from("k")), item))
+                            .collect(),
+                    ),
+                }
+            }
+        }
+    }
+
+    fn encode(frame: &Frame, protocol: Protocol) -> BytesMut {
+        let mut buf: BytesMut = BytesMut::new();
+        frame.encode(protocol, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn resp3_round_trip_in_random_chunks() {
+        let mut rng: Rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let frames: Vec<Frame> = (0..rng.below(4) + 1)
+                .map(|_| random_frame(&mut rng, 0))
+                .collect();
+            let mut wire: Vec<u8> = Vec::new();
+            for frame in &frames {
+                wire.extend_from_slice(&encode(frame, Protocol::Resp3));
+            }
+
+            // 按随机大小分块喂给解析器，模拟从网络上陆续收到数据
+            let mut buf: BytesMut = BytesMut::new();
+            let mut parsed: Vec<Frame> = Vec::new();
+            let mut decoded: (BytesMut, Decoder, Vec<Frame>) =
+                (BytesMut::new(), Decoder::default(), Vec::new());
+            let mut offset: usize = 0;
+            while offset < wire.len() {
+                let end: usize = (offset + rng.below(16) + 1).min(wire.len());
+                buf.extend_from_slice(&wire[offset..end]);
+                decoded.0.extend_from_slice(&wire[offset..end]);
+                offset = end;
+                while let Some(frame) = Frame::parse(&mut buf).unwrap() {
+                    parsed.push(frame);
+                }
+                while let Some(frame) = decoded.1.decode(&mut decoded.0).unwrap() {
+                    decoded.2.push(frame);
+                }
+            }
+            assert_eq!(parsed, frames);
+            assert_eq!(decoded.2, frames);
+            assert!(buf.is_empty() && decoded.0.is_empty());
+        }
+    }
+
+    #[test]
+    fn decoder_waits_for_the_declared_length() {
+        let mut decoder: Decoder = Decoder::default();
+        let mut buf: BytesMut = BytesMut::from(&b"*3\r\n$5\r\nhel"[..]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        // 第一个参数到第 15 字节为止，后面两个参数至少还要 3 字节
+        assert_eq!(decoder.need, 21);
+        // 数据没到齐时不解析，坏数据也要等到够长才发现
+        buf.extend_from_slice(b"lo\r\n:x");
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"\r\n:1\r\n");
+        assert!(decoder.decode(&mut buf).is_err());
+
+        let mut decoder: Decoder = Decoder::default();
+        let mut buf: BytesMut = BytesMut::from(&b"$100000\r\n"[..]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        assert_eq!(decoder.need, 100_011);
+        buf.extend_from_slice(&[b'a'; 100_000]);
+        buf.extend_from_slice(b"\r\n+OK\r\n");
+        assert!(
+            matches!(decoder.decode(&mut buf).unwrap(), Some(Frame::Bulk(data)) if data.len() == 100_000)
+        );
+        assert_eq!(decoder.need, 0);
+        assert_eq!(
+            decoder.decode(&mut buf).unwrap(),
+            Some(Frame::Simple("OK".to_string()))
+        );
+    }
+
+    #[test]
+    fn resp2_downgrades_resp3_types() {
+        let frame: Frame = Frame::Array(vec![
+            Frame::Map(vec![(Frame::Bulk("a".into()), Frame::Double(1.5))]),
+            Frame::Set(vec![Frame::Boolean(true)]),
+            Frame::Null,
+        ]);
+        assert_eq!(
+            &encode(&frame, Protocol::Resp2)[..],
+            b"*3\r\n*2\r\n$1\r\na\r\n$3\r\n1.5\r\n*1\r\n:1\r\n$-1\r\n"
+        );
+        assert_eq!(
+            &encode(&frame, Protocol::Resp3)[..],
+            b"*3\r\n%1\r\n$1\r\na\r\n,1.5\r\n~1\r\n#t\r\n_\r\n"
+        );
+        assert_eq!(
+            &encode(&Frame::Error("ERR a\r\nb".to_string()), Protocol::Resp2)[..],
+            b"-ERR a  b\r\n"
+        );
+    }
+
+    #[test]
+    fn random_garbage_never_panics() {
+        let mut rng: Rng = Rng(42);
+        let alphabet: &[u8] = b"+-:$*%~>_,#\r\n0123456789-tf";
+        for _ in 0..5000 {
+            let len: usize = rng.below(32);
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| alphabet[rng.below(alphabet.len() as u64)])
+                .collect();
+            let mut buf: BytesMut = BytesMut::from(&bytes[..]);
+            // 只要求不崩溃：要么解析出帧，要么等待更多数据，要么报协议错误
+            while let Ok(Some(_)) = Frame::parse(&mut buf) {}
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for input in [
+            &b"*-2\r\n"[..],
+            b"$3\r\nabcd\r\n",
+            b":12x\r\n",
+            b"#x\r\n",
+            b"?\r\n",
+            b"*2000000\r\n",
+        ] {
+            let mut buf: BytesMut = BytesMut::from(input);
+            assert!(Frame::parse(&mut buf).is_err(), "{input:?}");
+        }
+        let mut deep: BytesMut = BytesMut::from(&b"*1\r\n".repeat(100)[..]);
+        assert!(Frame::parse(&mut deep).is_err());
+
+        let mut partial: BytesMut = BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$1\r"[..]);
+        assert_eq!(Frame::parse(&mut partial).unwrap(), None);
+        assert_eq!(partial.len(), 16);
+    }
+}
@@ -0,0 +1,98 @@
+//! Redis 风格的通配符匹配，用于 `KEYS`、`SCAN ... MATCH` 和 `CONFIG GET`
+//!
+//! 支持 `*`、`?`、`[abc]`、`[^abc]`、`[a-z]` 以及用 `\` 转义特殊字符。
+
+/// `string` 是否匹配 `pattern`
+pub fn matches(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| a == b || (nocase && a.eq_ignore_ascii_case(&b));
+    let (mut p, mut s) = (0, 0);
+    // 最近一个 `*` 的位置以及它当前匹配到的字符串位置，失配时让它多吞一个字符再试
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if p < pattern.len() {
+            let advance: Option<usize> = match pattern[p] {
+                b'*' => {
+                    star = Some((p, s));
+                    p += 1;
+                    continue;
+                }
+                b'?' => Some(p + 1),
+                b'[' => match class(pattern, p + 1, string[s], nocase) {
+                    (true, next) => Some(next),
+                    (false, _) => None,
+                },
+                b'\\' if p + 1 < pattern.len() => eq(pattern[p + 1], string[s]).then_some(p + 2),
+                c => eq(c, string[s]).then_some(p + 1),
+            };
+            if let Some(next) = advance {
+                p = next;
+                s += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((star_p, star_s)) => {
+                p = star_p + 1;
+                s = star_s + 1;
+                star = Some((star_p, star_s + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// 匹配从 `start`（`[` 之后）开始的字符类，返回是否匹配以及 `]` 之后的位置。
+/// 没有结尾的 `]` 时字符类延续到模式末尾
+fn class(pattern: &[u8], start: usize, c: u8, nocase: bool) -> (bool, usize) {
+    let fold = |b: u8| if nocase { b.to_ascii_lowercase() } else { b };
+    let c: u8 = fold(c);
+    let mut p: usize = start;
+    let negate: bool = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+    let mut matched: bool = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            p += 1;
+            matched |= fold(pattern[p]) == c;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (mut lo, mut hi) = (fold(pattern[p]), fold(pattern[p + 2]));
+            if lo > hi {
+                std::mem::swap(&mut lo, &mut hi);
+            }
+            matched |= lo <= c && c <= hi;
+            p += 2;
+        } else {
+            matched |= fold(pattern[p]) == c;
+        }
+        p += 1;
+    }
+    (matched != negate, (p + 1).min(pattern.len()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::matches;
+
+    #[test]
+    fn wildcards_and_classes() {
+        let m = |p: &str, s: &str| matches(p.as_bytes(), s.as_bytes(), false);
+        assert!(m("*", ""));
+        assert!(m("h?llo", "hello"));
+        assert!(!m("h?llo", "hllo"));
+        assert!(m("h*llo", "heeeello"));
+        assert!(m("user:*:name", "user:42:name"));
+        assert!(!m("user:*:name", "user:42:age"));
+        assert!(m("h[ae]llo", "hallo"));
+        assert!(!m("h[^e]llo", "hello"));
+        assert!(m("h[a-b]llo", "hbllo"));
+        assert!(m("h[z-a]llo", "hcllo"));
+        assert!(m("a\\*b", "a*b"));
+        assert!(!m("a\\*b", "axb"));
+        assert!(m("*a*b*c*", "xxaxxbxxcxx"));
+        assert!(!m("*a*b*c*", "xxaxxcxxbxx"));
+        assert!(matches(b"MAX*", b"maxmemory", true));
+    }
+}
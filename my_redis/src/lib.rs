@@ -0,0 +1,43 @@
+//! 基于 tokio 的简易 Redis 服务端实现
+//!
+//! `frame` 是 RESP2/RESP3 协议编解码，`db` 是按分片存放多个逻辑数据库的存储，`dict` 是支持游标遍历的哈希表，
+//! `cmd` 是命令表与各命令的实现，`server` 负责接收连接并处理请求，`evict` 在超出内存上限时淘汰 key，
+//! `zset` 和 `stream` 是有序集合与流的数据结构，
+//! `session` 保存连接级别的事务状态，`clients` 登记所有连接，`slowlog` 是慢查询日志，
+//! `metrics` 提供 Prometheus 指标，`acl` 管理用户和权限，`rdb` 和 `aof` 负责持久化，
+//! `replication` 负责主从复制，`cluster` 维护集群的哈希槽分配，`config` 和 `log` 是配置与日志，
+//! `script` 是 EVAL 使用的脚本语言，`client` 是带连接池和自动流水线的异步客户端，
+//! `net` 是 TCP、TLS 和 Unix 套接字共用的传输层，`tls` 构建 TLS 配置（需要 `tls` feature）。
+
+pub mod acl;
+pub mod aof;
+pub mod blocking;
+pub mod client;
+pub mod clients;
+pub mod cluster;
+pub mod cmd;
+pub mod config;
+pub mod connection;
+pub mod db;
+pub mod dict;
+pub mod error;
+pub mod evict;
+pub mod frame;
+pub mod glob;
+pub mod log;
+pub mod metrics;
+pub mod net;
+pub mod rdb;
+pub mod replication;
+pub mod script;
+pub mod server;
+pub mod session;
+pub mod slowlog;
+pub mod state;
+pub mod stream;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod zset;
+
+/// 默认监听端口
+pub const DEFAULT_PORT: u16 = 6379;
@@ -0,0 +1,100 @@
+//! 日志
+//!
+//! 级别与 Redis 相同，从低到高依次是 debug、verbose、notice、warning，
+//! 低于当前级别的日志不输出。级别可以用 `CONFIG SET loglevel` 在运行时调整。
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// 日志级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Notice as u8);
+
+/// 输出一条日志，格式与 `format!` 相同
+///
+/// ```ignore
+/// log!(Level::Notice, "DB loaded from disk: {keys} keys");
+/// ```
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)+));
+        }
+    };
+}
+
+/// 设置当前的日志级别
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// 当前的日志级别
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Debug,
+        1 => Level::Verbose,
+        2 => Level::Notice,
+        _ => Level::Warning,
+    }
+}
+
+/// 该级别的日志是否输出
+pub fn enabled(level: Level) -> bool {
+    level >= self::level()
+}
+
+/// 写出一条日志，警告写到标准错误，其余写到标准输出。
+/// 每行开头的符号与 Redis 相同：`.` debug，`-` verbose，`*` notice，`#` warning
+pub fn write(level: Level, args: fmt::Arguments<'_>) {
+    let mark: char = match level {
+        Level::Debug => '.',
+        Level::Verbose => '-',
+        Level::Notice => '*',
+        Level::Warning => '#',
+    };
+    if level == Level::Warning {
+        eprintln!("{mark} {args}");
+    } else {
+        println!("{mark} {args}");
+    }
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Verbose => "verbose",
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "verbose" => Ok(Level::Verbose),
+            "notice" => Ok(Level::Notice),
+            "warning" => Ok(Level::Warning),
+            _ => Err(format!("invalid log level: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,372 @@
+//! Prometheus 指标
+//!
+//! 配置了 `metrics-port` 时服务端在这个端口上另外监听 HTTP，`GET /metrics` 按 Prometheus 的文本
+//! 格式返回每个命令的执行次数和耗时分布，以及连接、键空间、淘汰和持久化的状态。HTTP 只实现到
+//! 够抓取用的程度：每个连接处理一个请求后关闭。
+
+use crate::db::Guard;
+use crate::log;
+use crate::log::Level;
+use crate::state::State;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// 耗时直方图各个桶的上界（微秒），最后还有一个 `+Inf` 桶
+const BUCKETS: [u64; 13] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000,
+];
+
+/// 请求头的长度上限
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// 读取请求的超时
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 按命令统计的执行次数和耗时
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands: Mutex<BTreeMap<&'static str, CommandStats>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct CommandStats {
+    calls: u64,
+    /// 总耗时（微秒）
+    total: u64,
+    /// 落在每个桶里的次数，不累加，`BUCKETS.len()` 处是 `+Inf` 桶
+    buckets: [u64; BUCKETS.len() + 1],
+}
+
+impl Metrics {
+    /// 记录一次命令执行
+    pub fn record(&self, name: &'static str, elapsed: Duration) {
+        let micros: u64 = elapsed.as_micros() as u64;
+        let bucket: usize = BUCKETS.partition_point(|&bound| bound < micros);
+        let mut commands = self.commands.lock().unwrap();
+        let stats: &mut CommandStats = commands.entry(name).or_default();
+        stats.calls += 1;
+        stats.total += micros;
+        stats.buckets[bucket] += 1;
+    }
+
+    /// 写出按命令统计的部分
+    fn render(&self, out: &mut String) {
+        let commands: BTreeMap<&'static str, CommandStats> = self.commands.lock().unwrap().clone();
+        header(
+            out,
+            "my_redis_commands_total",
+            "counter",
+            "Commands processed, by command.",
+        );
+        for (name, stats) in &commands {
+            sample(
+                out,
+                "my_redis_commands_total",
+                &format!("cmd=\"{name}\""),
+                stats.calls,
+            );
+        }
+        header(
+            out,
+            "my_redis_command_duration_seconds",
+            "histogram",
+            "Time spent executing commands, by command.",
+        );
+        for (name, stats) in &commands {
+            let mut cumulative: u64 = 0;
+            for (i, count) in stats.buckets.iter().enumerate() {
+                cumulative += count;
+                let le: String = match BUCKETS.get(i) {
+                    Some(&bound) => seconds(bound),
+                    None => "+Inf".to_string(),
+                };
+                sample(
+                    out,
+                    "my_redis_command_duration_seconds_bucket",
+                    &format!("cmd=\"{name}\",le=\"{le}\""),
+                    cumulative,
+                );
+            }
+            let labels: String = format!("cmd=\"{name}\"");
+            sample(
+                out,
+                "my_redis_command_duration_seconds_sum",
+                &labels,
+                seconds(stats.total),
+            );
+            sample(
+                out,
+                "my_redis_command_duration_seconds_count",
+                &labels,
+                stats.calls,
+            );
+        }
+    }
+}
+
+/// 微秒换算成秒，去掉多余的零
+fn seconds(micros: u64) -> String {
+    let text: String = format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n");
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl Display) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+    } else {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+/// 只有一个值的指标
+fn single(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    header(out, name, kind, help);
+    sample(out, name, "", value);
+}
+
+/// 生成 `/metrics` 的内容
+pub fn render(state: &State) -> String {
+    let mut out: String = String::new();
+    state.metrics.render(&mut out);
+
+    let clients = &state.clients;
+    single(
+        &mut out,
+        "my_redis_connected_clients",
+        "gauge",
+        "Number of client connections.",
+        clients.len(),
+    );
+    single(
+        &mut out,
+        "my_redis_blocked_clients",
+        "gauge",
+        "Clients waiting on a blocking command.",
+        state.blocking.blocked_clients(),
+    );
+    single(
+        &mut out,
+        "my_redis_connections_received_total",
+        "counter",
+        "Connections accepted.",
+        clients.total_connections.load(Ordering::Relaxed),
+    );
+    single(
+        &mut out,
+        "my_redis_rejected_connections_total",
+        "counter",
+        "Connections rejected because of maxclients.",
+        clients.rejected_connections.load(Ordering::Relaxed),
+    );
+
+    // 和 DBSIZE 一样包括尚未清理的过期 key，不用遍历数据
+    let mut db: Guard<'_> = state.db.lock_all();
+    let keys: usize = (0..db.databases())
+        .map(|index| {
+            db.select(index);
+            db.len()
+        })
+        .sum();
+    drop(db);
+    single(
+        &mut out,
+        "my_redis_keys",
+        "gauge",
+        "Number of keys in all databases.",
+        keys,
+    );
+    single(
+        &mut out,
+        "my_redis_used_memory_bytes",
+        "gauge",
+        "Estimated memory used by keys and values.",
+        state.db.used_memory(),
+    );
+    single(
+        &mut out,
+        "my_redis_maxmemory_bytes",
+        "gauge",
+        "Configured memory limit, 0 means unlimited.",
+        state.config().maxmemory,
+    );
+    single(
+        &mut out,
+        "my_redis_expired_keys_total",
+        "counter",
+        "Keys removed because they expired.",
+        state.db.expired_keys(),
+    );
+    single(
+        &mut out,
+        "my_redis_evicted_keys_total",
+        "counter",
+        "Keys evicted because of maxmemory.",
+        state.evicted_keys.load(Ordering::Relaxed),
+    );
+
+    let rdb = &state.rdb;
+    let flag = |b: bool| u8::from(b);
+    single(
+        &mut out,
+        "my_redis_rdb_changes_since_last_save",
+        "gauge",
+        "Writes since the last successful save.",
+        rdb.dirty.load(Ordering::Relaxed),
+    );
+    single(
+        &mut out,
+        "my_redis_rdb_last_save_timestamp_seconds",
+        "gauge",
+        "Unix time of the last successful save.",
+        rdb.last_save.load(Ordering::Relaxed),
+    );
+    single(
+        &mut out,
+        "my_redis_rdb_bgsave_in_progress",
+        "gauge",
+        "Whether a BGSAVE is running.",
+        flag(rdb.bgsave_in_progress.load(Ordering::Relaxed)),
+    );
+    single(
+        &mut out,
+        "my_redis_rdb_last_bgsave_ok",
+        "gauge",
+        "Whether the last BGSAVE succeeded.",
+        flag(rdb.last_bgsave_ok.load(Ordering::Relaxed)),
+    );
+    let aof = state.aof.get();
+    single(
+        &mut out,
+        "my_redis_aof_enabled",
+        "gauge",
+        "Whether the append only file is enabled.",
+        flag(aof.is_some()),
+    );
+    single(
+        &mut out,
+        "my_redis_aof_rewrite_in_progress",
+        "gauge",
+        "Whether an AOF rewrite is running.",
+        flag(aof.is_some_and(|aof| aof.rewrite_in_progress.load(Ordering::Relaxed))),
+    );
+    single(
+        &mut out,
+        "my_redis_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+        state.started.elapsed().as_secs(),
+    );
+    out
+}
+
+/// 接受抓取请求，直到任务被取消
+pub async fn serve(listener: TcpListener, state: Arc<State>) {
+    loop {
+        let socket: TcpStream = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                log!(Level::Warning, "Accepting metrics connection: {err}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let state: Arc<State> = state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle(socket, &state).await {
+                log!(Level::Verbose, "Metrics request failed: {err}");
+            }
+        });
+    }
+}
+
+/// 读取一个请求并回复，然后关闭连接
+async fn handle(mut socket: TcpStream, state: &State) -> std::io::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    let read = async {
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < MAX_REQUEST {
+            let mut chunk: [u8; 1024] = [0; 1024];
+            let n: usize = socket.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        std::io::Result::Ok(())
+    };
+    if tokio::time::timeout(READ_TIMEOUT, read).await.is_err() {
+        return Ok(());
+    }
+    let request: String = String::from_utf8_lossy(&buf).into_owned();
+    let mut words = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, target): (&str, &str) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    let path: &str = target.split('?').next().unwrap_or("");
+    let (status, body): (&str, String) = match (method, path) {
+        ("GET" | "HEAD", "/metrics") => ("200 OK", render(state)),
+        ("GET" | "HEAD", _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    let mut response: String = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{seconds, serve};
+    use crate::cmd::test::{send, state};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn get(addr: SocketAddr, request: &str) -> String {
+        let mut socket: TcpStream = TcpStream::connect(addr).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response: String = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn scrape_metrics() {
+        let state = state(2);
+        send(&state, "SET a 1");
+        send(&state, "GET a");
+        send(&state, "GET a");
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, state.clone()));
+
+        let response: String = get(addr, "GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("# TYPE my_redis_command_duration_seconds histogram\n"));
+        assert!(response.contains("\nmy_redis_commands_total{cmd=\"get\"} 2\n"));
+        assert!(response
+            .contains("my_redis_command_duration_seconds_bucket{cmd=\"get\",le=\"+Inf\"} 2\n"));
+        assert!(response.contains("\nmy_redis_command_duration_seconds_count{cmd=\"set\"} 1\n"));
+        assert!(response.contains("\nmy_redis_keys 1\n"));
+
+        let response: String = get(addr, "GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response: String = get(addr, "POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 "));
+        assert_eq!(seconds(2_500), "0.0025");
+        assert_eq!(seconds(1_000_000), "1");
+    }
+}
@@ -0,0 +1,227 @@
+//! 传输层
+//!
+//! 普通连接直接走 TCP；开启 `tls` feature 后还可以在 TCP 之上加一层 TLS；同一台机器上的服务
+//! 还可以走 Unix 套接字，省掉 TCP 协议栈的开销。服务端和客户端都通过 `Stream` 读写，不关心下面
+//! 是哪一种。服务端接受连接后先得到 `Accepted`，TLS 握手放到连接任务里
+//! 做，慢的握手不会拖住接收循环。
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsStream;
+
+/// 一条已经建立好的连接
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream<TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// 服务端监听的一个端口
+#[derive(Debug)]
+pub enum Incoming {
+    Tcp(TcpListener),
+    /// 在这个端口上接受的连接都要先完成 TLS 握手
+    #[cfg(feature = "tls")]
+    Tls(TcpListener, Arc<rustls::ServerConfig>),
+    /// Unix 套接字及其路径
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// 刚接受、还没有完成握手的连接
+#[derive(Debug)]
+pub enum Accepted {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(TcpStream, Arc<rustls::ServerConfig>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// 对端地址，Unix 套接字没有网络地址，返回错误
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.get_ref().0.peer_addr(),
+            #[cfg(unix)]
+            Stream::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix socket peers have no network address",
+            )),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Stream {
+        Stream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Stream {
+        Stream::Unix(stream)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+impl Incoming {
+    /// 在 `path` 上监听 Unix 套接字。上次没有清理掉的套接字文件先删除；`perm` 不为 0 时把
+    /// 文件权限设置成它，例如 `0o770` 只允许同组的用户连接
+    #[cfg(unix)]
+    pub fn unix(path: &Path, perm: u32) -> io::Result<Incoming> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        // 只删除套接字，路径写错时不会误删普通文件
+        if let Ok(meta) = std::fs::symlink_metadata(path) {
+            if meta.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        let listener: UnixListener = UnixListener::bind(path)?;
+        if perm != 0 {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(perm))?;
+        }
+        Ok(Incoming::Unix(listener, path.to_path_buf()))
+    }
+
+    /// 接受一个连接
+    pub async fn accept(&self) -> io::Result<Accepted> {
+        match self {
+            Incoming::Tcp(listener) => Ok(Accepted::Tcp(accept_tcp(listener).await?)),
+            #[cfg(feature = "tls")]
+            Incoming::Tls(listener, config) => {
+                Ok(Accepted::Tls(accept_tcp(listener).await?, config.clone()))
+            }
+            #[cfg(unix)]
+            Incoming::Unix(listener, _) => Ok(Accepted::Unix(listener.accept().await?.0)),
+        }
+    }
+
+    /// 实际监听的地址，Unix 套接字是文件路径
+    pub fn local_addr(&self) -> io::Result<String> {
+        match self {
+            Incoming::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            #[cfg(feature = "tls")]
+            Incoming::Tls(listener, _) => Ok(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Incoming::Unix(_, path) => Ok(path.display().to_string()),
+        }
+    }
+
+    /// Unix 套接字文件的路径，其它端口返回 `None`
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        match self {
+            #[cfg(unix)]
+            Incoming::Unix(_, path) => Some(path.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// 接受一个 TCP 连接并关掉 Nagle 算法。每条回复单独写出，流水线上后面的回复否则要等对端
+/// 延迟发送的 ACK，每批多出几十毫秒
+async fn accept_tcp(listener: &TcpListener) -> io::Result<TcpStream> {
+    let (stream, _) = listener.accept().await?;
+    let _ = stream.set_nodelay(true);
+    Ok(stream)
+}
+
+impl Accepted {
+    /// 对端地址
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Accepted::Tcp(stream) => stream.peer_addr().ok(),
+            #[cfg(feature = "tls")]
+            Accepted::Tls(stream, _) => stream.peer_addr().ok(),
+            #[cfg(unix)]
+            Accepted::Unix(_) => None,
+        }
+    }
+
+    /// 完成握手，得到可以读写的连接
+    pub async fn establish(self) -> io::Result<Stream> {
+        match self {
+            Accepted::Tcp(stream) => Ok(Stream::Tcp(stream)),
+            #[cfg(feature = "tls")]
+            Accepted::Tls(stream, config) => {
+                let stream = tokio_rustls::TlsAcceptor::from(config)
+                    .accept(stream)
+                    .await?;
+                Ok(Stream::Tls(Box::new(stream.into())))
+            }
+            #[cfg(unix)]
+            Accepted::Unix(stream) => Ok(Stream::Unix(stream)),
+        }
+    }
+}
@@ -0,0 +1,636 @@
+//! RDB 风格的快照持久化
+//!
+//! 文件格式：
+//!
+//! ```text
+//! "MYRDB0001"
+//! { [0xFE <数据库编号>] [0xFC <过期时间: u64 LE 毫秒>] <类型: u8> <key> <value> }*
+//! 0xFF <CRC64: u64 LE>
+//! ```
+//!
+//! 0xFE 切换之后的 key 所在的逻辑数据库，编号是变长整数，文件开头默认是 0 号数据库。
+//! 字符串以 LEB128 变长整数作为长度前缀；列表、哈希、集合、有序集合先写元素个数，
+//! 再依次写出每个元素，有序集合的分数是 8 字节的 f64。流依次写出条目、最大 ID 和消费组，
+//! ID 和时间戳都是 8 字节的 u64。
+//! CRC64 覆盖校验和之前的全部字节，加载时校验失败即认为文件损坏。
+
+use crate::db::{now_ms, Db, Entry, Guard, Value};
+use crate::log;
+use crate::log::Level;
+use crate::state::State;
+use crate::stream::{Group, Pending, Stream, StreamId};
+use crate::zset::ZSet;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAGIC: &[u8] = b"MYRDB0001";
+const OPCODE_EXPIRE_MS: u8 = 0xFC;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_HASH: u8 = 2;
+const TYPE_SET: u8 = 3;
+const TYPE_ZSET: u8 = 4;
+const TYPE_STREAM: u8 = 5;
+
+/// BGSAVE 失败后，自动保存至少等待这么久才重试
+const RETRY_DELAY_SECS: u64 = 5;
+
+/// 持久化状态
+#[derive(Debug)]
+pub struct Status {
+    /// 上次保存以来的修改次数
+    pub dirty: AtomicU64,
+    /// 上次成功保存的时间（Unix 秒）
+    pub last_save: AtomicU64,
+    /// 上次尝试 BGSAVE 的时间（Unix 秒）
+    pub last_bgsave_try: AtomicU64,
+    /// 是否有 BGSAVE 正在进行
+    pub bgsave_in_progress: AtomicBool,
+    /// 上次 BGSAVE 是否成功
+    pub last_bgsave_ok: AtomicBool,
+}
+
+impl Default for Status {
+    fn default() -> Status {
+        Status {
+            dirty: AtomicU64::new(0),
+            last_save: AtomicU64::new(now_secs()),
+            last_bgsave_try: AtomicU64::new(0),
+            bgsave_in_progress: AtomicBool::new(false),
+            last_bgsave_ok: AtomicBool::new(true),
+        }
+    }
+}
+
+/// 把数据编码为快照，每条数据带着它所在的数据库编号
+pub fn encode<'a>(entries: impl Iterator<Item = (usize, &'a String, &'a Entry)>) -> Vec<u8> {
+    let mut buf: Vec<u8> = MAGIC.to_vec();
+    let mut selected: usize = 0;
+    for (db, key, entry) in entries {
+        if db != selected {
+            buf.push(OPCODE_SELECTDB);
+            write_len(&mut buf, db);
+            selected = db;
+        }
+        if let Some(at) = entry.expires_at {
+            buf.push(OPCODE_EXPIRE_MS);
+            buf.extend_from_slice(&at.to_le_bytes());
+        }
+        write_value(&mut buf, key, &entry.value);
+    }
+    buf.push(OPCODE_EOF);
+    let checksum: u64 = crc64(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// 解码快照，得到数据库编号、key 和数据。校验和不符或结构不完整时返回 `InvalidData`
+pub fn decode(buf: &[u8]) -> io::Result<Vec<(usize, String, Entry)>> {
+    if buf.len() < MAGIC.len() + 1 + 8 || &buf[..MAGIC.len()] != MAGIC {
+        return Err(corrupt("bad header"));
+    }
+    let (body, checksum) = buf.split_at(buf.len() - 8);
+    if crc64(body) != u64::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(corrupt("checksum mismatch"));
+    }
+
+    let mut reader: Reader<'_> = Reader {
+        buf: body,
+        pos: MAGIC.len(),
+    };
+    let mut entries: Vec<(usize, String, Entry)> = Vec::new();
+    let mut db: usize = 0;
+    loop {
+        let mut expires_at: Option<u64> = None;
+        let mut opcode: u8 = reader.u8()?;
+        if opcode == OPCODE_SELECTDB {
+            db = reader.len()?;
+            opcode = reader.u8()?;
+        }
+        if opcode == OPCODE_EXPIRE_MS {
+            expires_at = Some(reader.u64()?);
+            opcode = reader.u8()?;
+        }
+        if opcode == OPCODE_EOF {
+            break;
+        }
+        let key: String = String::from_utf8(reader.bytes()?.to_vec())
+            .map_err(|_| corrupt("key is not valid UTF-8"))?;
+        let value: Value = read_value(&mut reader, opcode)?;
+        let mut entry: Entry = Entry::new(value);
+        entry.expires_at = expires_at;
+        entries.push((db, key, entry));
+    }
+    if reader.pos != body.len() {
+        return Err(corrupt("trailing bytes after EOF"));
+    }
+    Ok(entries)
+}
+
+/// 同步保存：调用方持有全部分片的锁，保存期间其它命令都会等待
+pub fn save(guard: &Guard<'_>, path: &Path, status: &Status) -> io::Result<()> {
+    write_file(path, &encode(guard.iter_all()))?;
+    status.dirty.store(0, Ordering::SeqCst);
+    status.last_save.store(now_secs(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// 后台保存：在锁内复制一份数据，编码和写盘交给单独的线程。
+/// 已有 BGSAVE 在进行时返回 `false`
+pub fn bgsave(guard: &Guard<'_>, path: PathBuf, status: Arc<Status>) -> bool {
+    if status.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    // 值是 `Bytes`，复制只增加引用计数
+    let snapshot: Vec<(usize, String, Entry)> = guard
+        .iter_all()
+        .map(|(db, key, entry)| (db, key.clone(), entry.clone()))
+        .collect();
+    let dirty: u64 = status.dirty.load(Ordering::SeqCst);
+    status.last_bgsave_try.store(now_secs(), Ordering::SeqCst);
+
+    thread::spawn(move || {
+        let result: io::Result<()> = write_file(
+            &path,
+            &encode(snapshot.iter().map(|(db, k, e)| (*db, k, e))),
+        );
+        match result {
+            Ok(()) => {
+                // 只扣除快照开始前的修改，保存期间的新修改留给下一次
+                status.dirty.fetch_sub(dirty, Ordering::SeqCst);
+                status.last_save.store(now_secs(), Ordering::SeqCst);
+                status.last_bgsave_ok.store(true, Ordering::SeqCst);
+                log!(Level::Notice, "Background saving terminated with success");
+            }
+            Err(err) => {
+                status.last_bgsave_ok.store(false, Ordering::SeqCst);
+                log!(Level::Warning, "Background saving error: {err}");
+            }
+        }
+        status.bgsave_in_progress.store(false, Ordering::SeqCst);
+    });
+    true
+}
+
+/// 启动时加载快照，文件不存在时返回 0，已过期的 key 直接丢弃
+pub fn load(db: &Db, path: &Path) -> io::Result<usize> {
+    let buf: Vec<u8> = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let entries: Vec<(usize, String, Entry)> = decode(&buf)?;
+    let mut guard: Guard<'_> = db.lock_all();
+    insert_all(&mut guard, entries)
+}
+
+/// 把解码出的数据放回各自的数据库，返回放入的个数，已过期的 key 直接丢弃。
+/// 快照中的数据库编号超出配置的个数时返回 `InvalidData`，什么都不放入
+pub fn insert_all(
+    guard: &mut Guard<'_>,
+    entries: Vec<(usize, String, Entry)>,
+) -> io::Result<usize> {
+    let databases: usize = guard.databases();
+    if let Some((db, _, _)) = entries.iter().find(|(db, _, _)| *db >= databases) {
+        return Err(corrupt(&format!(
+            "snapshot uses database {db}, but only {databases} are configured"
+        )));
+    }
+    let now: u64 = now_ms();
+    let selected: usize = guard.selected();
+    let mut loaded: usize = 0;
+    for (db, key, entry) in entries {
+        if !entry.is_expired(now) {
+            guard.select(db);
+            guard.insert(key, entry);
+            loaded += 1;
+        }
+    }
+    guard.select(selected);
+    Ok(loaded)
+}
+
+/// 按配置的保存规则定期触发 BGSAVE
+pub async fn auto_save(state: Arc<State>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let status: &Status = &state.rdb;
+        let now: u64 = now_secs();
+        let dirty: u64 = status.dirty.load(Ordering::SeqCst);
+        let elapsed: u64 = now.saturating_sub(status.last_save.load(Ordering::SeqCst));
+        // 保存规则可能在运行时被修改，每次都重新读取
+        let due: bool = state
+            .config()
+            .save
+            .iter()
+            .any(|rule| dirty >= rule.changes && elapsed >= rule.seconds);
+        let may_retry: bool = status.last_bgsave_ok.load(Ordering::SeqCst)
+            || now.saturating_sub(status.last_bgsave_try.load(Ordering::SeqCst))
+                >= RETRY_DELAY_SECS;
+        if due && may_retry {
+            log!(
+                Level::Notice,
+                "{dirty} changes in {elapsed} seconds. Saving..."
+            );
+            let guard: Guard<'_> = state.db.lock_all();
+            bgsave(&guard, state.config().rdb_path(), state.rdb.clone());
+        }
+    }
+}
+
+/// 先写临时文件再改名，保证快照文件总是完整的
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let name: String = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp: PathBuf = path.with_file_name(format!("temp-{}-{name}", std::process::id()));
+    let mut file: File = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// 当前的 Unix 秒时间戳
+pub fn now_secs() -> u64 {
+    now_ms() / 1000
+}
+
+fn corrupt(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt rdb file: {msg}"),
+    )
+}
+
+fn write_value(buf: &mut Vec<u8>, key: &str, value: &Value) {
+    let kind: u8 = match value {
+        Value::String(_) => TYPE_STRING,
+        Value::List(_) => TYPE_LIST,
+        Value::Hash(_) => TYPE_HASH,
+        Value::Set(_) => TYPE_SET,
+        Value::ZSet(_) => TYPE_ZSET,
+        Value::Stream(_) => TYPE_STREAM,
+    };
+    buf.push(kind);
+    write_bytes(buf, key.as_bytes());
+    match value {
+        Value::String(data) => write_bytes(buf, data),
+        Value::List(list) => {
+            write_len(buf, list.len());
+            list.iter().for_each(|item| write_bytes(buf, item));
+        }
+        Value::Hash(hash) => {
+            write_len(buf, hash.len());
+            for (field, value) in hash {
+                write_bytes(buf, field);
+                write_bytes(buf, value);
+            }
+        }
+        Value::Set(set) => {
+            write_len(buf, set.len());
+            set.iter().for_each(|member| write_bytes(buf, member));
+        }
+        Value::ZSet(zset) => {
+            write_len(buf, zset.len());
+            for (member, score) in zset.iter() {
+                write_bytes(buf, member);
+                buf.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Stream(stream) => write_stream(buf, stream),
+    }
+}
+
+/// 条目数，每个条目的 ID 和字段；最大 ID；消费组数，每个组的名字、分发位置、
+/// 待确认条目（ID、消费者、分发时间、分发次数）和消费者（名字、活动时间）
+fn write_stream(buf: &mut Vec<u8>, stream: &Stream) {
+    let write_id = |buf: &mut Vec<u8>, id: &StreamId| {
+        buf.extend_from_slice(&id.ms.to_le_bytes());
+        buf.extend_from_slice(&id.seq.to_le_bytes());
+    };
+    write_len(buf, stream.len());
+    for (id, fields) in stream.iter() {
+        write_id(buf, id);
+        write_len(buf, fields.len());
+        fields.iter().for_each(|field| write_bytes(buf, field));
+    }
+    write_id(buf, &stream.last_id());
+    write_len(buf, stream.groups().len());
+    for (name, group) in stream.groups() {
+        write_bytes(buf, name);
+        write_id(buf, &group.last_delivered);
+        write_len(buf, group.pending.len());
+        for (id, pending) in &group.pending {
+            write_id(buf, id);
+            write_bytes(buf, &pending.consumer);
+            buf.extend_from_slice(&pending.delivered_at.to_le_bytes());
+            buf.extend_from_slice(&pending.deliveries.to_le_bytes());
+        }
+        write_len(buf, group.consumers.len());
+        for (consumer, seen_at) in &group.consumers {
+            write_bytes(buf, consumer);
+            buf.extend_from_slice(&seen_at.to_le_bytes());
+        }
+    }
+}
+
+fn read_stream(reader: &mut Reader<'_>) -> io::Result<Stream> {
+    let read_id = |reader: &mut Reader<'_>| -> io::Result<StreamId> {
+        Ok(StreamId::new(reader.u64()?, reader.u64()?))
+    };
+    let mut stream: Stream = Stream::new();
+    for _ in 0..reader.len()? {
+        let id: StreamId = read_id(reader)?;
+        let len: usize = reader.len()?;
+        let mut fields: Vec<Bytes> = Vec::new();
+        for _ in 0..len {
+            fields.push(reader.blob()?);
+        }
+        if !stream.add(id, fields) {
+            return Err(corrupt("stream ids are not increasing"));
+        }
+    }
+    stream.set_last_id(read_id(reader)?);
+    for _ in 0..reader.len()? {
+        let name: Bytes = reader.blob()?;
+        let mut group: Group = Group {
+            last_delivered: read_id(reader)?,
+            ..Group::default()
+        };
+        for _ in 0..reader.len()? {
+            let id: StreamId = read_id(reader)?;
+            let pending: Pending = Pending {
+                consumer: reader.blob()?,
+                delivered_at: reader.u64()?,
+                deliveries: reader.u64()?,
+            };
+            group.pending.insert(id, pending);
+        }
+        for _ in 0..reader.len()? {
+            let consumer: Bytes = reader.blob()?;
+            group.consumers.insert(consumer, reader.u64()?);
+        }
+        stream.groups_mut().insert(name, group);
+    }
+    Ok(stream)
+}
+
+fn read_value(reader: &mut Reader<'_>, kind: u8) -> io::Result<Value> {
+    let value: Value = match kind {
+        TYPE_STRING => Value::String(reader.blob()?),
+        TYPE_LIST => {
+            let len: usize = reader.len()?;
+            let mut list: VecDeque<Bytes> = VecDeque::new();
+            for _ in 0..len {
+                list.push_back(reader.blob()?);
+            }
+            Value::List(list)
+        }
+        TYPE_HASH => {
+            let len: usize = reader.len()?;
+            let mut hash: HashMap<Bytes, Bytes> = HashMap::new();
+            for _ in 0..len {
+                hash.insert(reader.blob()?, reader.blob()?);
+            }
+            Value::Hash(hash)
+        }
+        TYPE_SET => {
+            let len: usize = reader.len()?;
+            let mut set: HashSet<Bytes> = HashSet::new();
+            for _ in 0..len {
+                set.insert(reader.blob()?);
+            }
+            Value::Set(set)
+        }
+        TYPE_ZSET => {
+            let len: usize = reader.len()?;
+            let mut zset: ZSet = ZSet::new();
+            for _ in 0..len {
+                let member: Bytes = reader.blob()?;
+                let score: f64 = f64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+                if score.is_nan() {
+                    return Err(corrupt("zset score is NaN"));
+                }
+                zset.insert(member, score);
+            }
+            Value::ZSet(zset)
+        }
+        TYPE_STREAM => Value::Stream(read_stream(reader)?),
+        other => return Err(corrupt(&format!("unknown type {other:#x}"))),
+    };
+    Ok(value)
+}
+
+fn write_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    write_len(buf, data.len());
+    buf.extend_from_slice(data);
+}
+
+fn write_len(buf: &mut Vec<u8>, len: usize) {
+    let mut len: u64 = len as u64;
+    // LEB128：每字节 7 位，最高位表示后面还有字节
+    loop {
+        let byte: u8 = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// 快照读取游标
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let end: usize = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| corrupt("unexpected end of file"))?;
+        let data: &[u8] = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let mut len: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte: u8 = self.u8()?;
+            len |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(len as usize);
+            }
+        }
+        Err(corrupt("length overflow"))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len: usize = self.len()?;
+        self.take(len)
+    }
+
+    fn blob(&mut self) -> io::Result<Bytes> {
+        Ok(Bytes::copy_from_slice(self.bytes()?))
+    }
+}
+
+/// CRC-64/Jones，与 Redis 的 RDB 校验算法相同
+fn crc64(data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+    const TABLE: [u64; 256] = {
+        let mut table: [u64; 256] = [0; 256];
+        let mut i: usize = 0;
+        while i < 256 {
+            let mut crc: u64 = i as u64;
+            let mut bit: u32 = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ POLY
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    data.iter().fold(0, |crc, byte| {
+        TABLE[((crc ^ u64::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc64, decode, encode, insert_all, load, save, Status};
+    use crate::cmd::test::{reply, run, state};
+    use crate::db::{now_ms, Db, Entry, Value};
+    use bytes::Bytes;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir: PathBuf = std::env::temp_dir().join(format!("my_redis-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn crc64_check_value() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn round_trip_with_expiration() {
+        let mut entry: Entry = Entry::new(Bytes::from(vec![0u8; 300]).into());
+        entry.expires_at = Some(1_700_000_000_000);
+        let entries: Vec<(usize, String, Entry)> = vec![
+            (0, "a".to_string(), Entry::new(Bytes::from("1").into())),
+            (3, "big".to_string(), entry),
+        ];
+        let buf: Vec<u8> = encode(entries.iter().map(|(db, k, e)| (*db, k, e)));
+        let decoded: Vec<(usize, String, Entry)> = decode(&buf).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!((decoded[0].0, decoded[0].1.as_str()), (0, "a"));
+        assert_eq!(decoded[1].0, 3);
+        assert!(matches!(&decoded[1].2.value, Value::String(v) if v.len() == 300));
+        assert_eq!(decoded[1].2.expires_at, Some(1_700_000_000_000));
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let entries: Vec<(usize, String, Entry)> = vec![(
+            0,
+            "key".to_string(),
+            Entry::new(Bytes::from("value").into()),
+        )];
+        let buf: Vec<u8> = encode(entries.iter().map(|(db, k, e)| (*db, k, e)));
+
+        let mut flipped: Vec<u8> = buf.clone();
+        flipped[12] ^= 0x01;
+        assert!(decode(&flipped).is_err());
+        assert!(decode(&buf[..buf.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn save_then_load() {
+        let path: PathBuf = temp_path("save_then_load.rdb");
+        let db: Db = Db::new(4, 4);
+        {
+            let mut guard = db.lock_all();
+            guard.insert("k1".to_string(), Entry::new(Bytes::from("v1").into()));
+            let mut gone: Entry = Entry::new(Bytes::from("v2").into());
+            gone.expires_at = Some(now_ms() + 50);
+            guard.insert("k2".to_string(), gone);
+            guard.select(2);
+            guard.insert("k1".to_string(), Entry::new(Bytes::from("other").into()));
+            save(&guard, &path, &Status::default()).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(60));
+
+        let restored: Db = Db::new(8, 4);
+        assert_eq!(load(&restored, &path).unwrap(), 2);
+        let mut guard = restored.lock_all();
+        assert!(matches!(&guard.get("k1").unwrap().value, Value::String(v) if v == "v1"));
+        guard.select(2);
+        assert!(matches!(&guard.get("k1").unwrap().value, Value::String(v) if v == "other"));
+        drop(guard);
+        // 数据库个数不够时拒绝加载
+        assert!(load(&Db::new(2, 2), &path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn round_trip_all_types() {
+        let db = state(4);
+        run(&db, "RPUSH l a b c");
+        run(&db, "HSET h f1 v1 f2 v2");
+        run(&db, "SADD s x y");
+        run(&db, "ZADD z 1.5 m1 -2 m2");
+        run(&db, "XADD x 1-1 f v");
+        run(&db, "XADD x 2-1 f w");
+        run(&db, "XGROUP CREATE x g 0");
+        run(&db, "XREADGROUP GROUP g c COUNT 1 STREAMS x >");
+        run(&db, "XDEL x 2-1");
+        let buf: Vec<u8> = encode(db.db.lock_all().iter_all());
+
+        let restored = state(2);
+        insert_all(&mut restored.db.lock_all(), decode(&buf).unwrap()).unwrap();
+        assert_eq!(reply(&restored, "LRANGE l 0 -1"), "[a b c]");
+        assert_eq!(reply(&restored, "HGET h f2"), "v2");
+        assert_eq!(reply(&restored, "SCARD s"), "2");
+        assert_eq!(
+            reply(&restored, "ZRANGE z 0 -1 WITHSCORES"),
+            "[m2 -2 m1 1.5]"
+        );
+        assert_eq!(reply(&restored, "XRANGE x - +"), "[[1-1 [f v]]]");
+        assert_eq!(reply(&restored, "XPENDING x g"), "[1 1-1 1-1 [[c 1]]]");
+        // 删除的条目不会让最大 ID 回退
+        assert!(reply(&restored, "XADD x 2-1 f w").starts_with("!ERR"));
+    }
+}
@@ -0,0 +1,842 @@
+//! 主从复制
+//!
+//! 副本用 `REPLICAOF host port` 连上主节点后发送 `PSYNC replid offset`。主节点能从积压缓冲区
+//! （backlog）里补上副本缺失的那段命令流时回复 `+CONTINUE`，否则回复
+//! `+FULLRESYNC replid offset` 并发送一份快照。之后主节点把每条写命令按 RESP 编码后
+//! 原样发给副本，副本每秒用 `REPLCONF ACK offset` 报告处理到的位置。
+//!
+//! 偏移量是主节点发出的命令流的总字节数，副本处理完同样多的字节后偏移量与主节点相同，
+//! 断线重连时据此判断能否只补发缺失的部分。副本不自己生成命令流，而是把主节点的命令流
+//! 原样转给自己的副本，所以整条复制链上的 replid 和偏移量都是一致的。
+
+use crate::aof;
+use crate::cmd;
+use crate::db::{now_ms, Guard};
+use crate::frame::{Decoder, Frame};
+use crate::log;
+use crate::log::Level;
+use crate::rdb;
+use crate::session::Session;
+use crate::state::State;
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+
+/// 发往单个副本、还没写出去的数据块数上限，超出时断开这个副本，让它稍后重新同步
+const REPLICA_BUFFER: usize = 64 * 1024;
+
+/// 副本向主节点报告偏移量的间隔
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 副本断线后第一次重连前的等待时间，之后每次翻倍
+const RECONNECT_MIN: Duration = Duration::from_millis(100);
+
+/// 重连等待时间的上限
+const RECONNECT_MAX: Duration = Duration::from_secs(5);
+
+/// 复制状态
+#[derive(Debug)]
+pub struct Replication {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// 当前命令流的 id
+    replid: String,
+    /// 成为主节点之前跟随的命令流，和切换时的偏移量。
+    /// 之前的兄弟副本可以凭它在新主节点上继续部分同步
+    replid2: Option<(String, u64)>,
+    /// 命令流的总字节数
+    offset: u64,
+    /// 最近的命令流，第一个副本连上之后才创建
+    backlog: Option<VecDeque<u8>>,
+    /// 命令流中最后一条 `SELECT` 选中的数据库，`None` 表示下一条命令前要先写 `SELECT`
+    selected: Option<usize>,
+    /// 积压缓冲区的容量
+    backlog_size: usize,
+    role: Role,
+    /// 连接到本节点的副本
+    replicas: Vec<Replica>,
+    /// 下一个副本的编号
+    next_id: u64,
+    /// 完整同步的次数
+    sync_full: u64,
+    /// 成功的部分同步次数
+    sync_partial_ok: u64,
+    /// 被拒绝的部分同步次数
+    sync_partial_err: u64,
+}
+
+/// 节点的角色
+#[derive(Debug)]
+enum Role {
+    Master,
+    Replica {
+        host: String,
+        port: u16,
+        /// 与主节点之间的连接状态
+        link: LinkState,
+        /// 数据是否与主节点的命令流一致，一致时重连可以尝试部分同步
+        synced: bool,
+        /// 维护连接的后台任务
+        task: Option<AbortHandle>,
+    },
+}
+
+/// 副本与主节点之间的连接状态，名字与 `ROLE` 的输出一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Connect,
+    Connecting,
+    Sync,
+    Connected,
+}
+
+impl LinkState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        }
+    }
+}
+
+/// 连接到本节点的一个副本
+#[derive(Debug)]
+struct Replica {
+    id: u64,
+    /// 副本的 IP 和它的监听端口
+    addr: String,
+    port: u16,
+    tx: mpsc::Sender<Bytes>,
+    /// 副本最近报告的偏移量
+    ack: u64,
+}
+
+/// `PSYNC` 成功后交给连接的命令流
+#[derive(Debug)]
+pub struct Feed {
+    pub id: u64,
+    /// 先写给副本的内容：`+FULLRESYNC` 和快照，或者 `+CONTINUE` 和积压缓冲区里缺失的部分
+    pub preamble: Vec<u8>,
+    /// 之后的命令流
+    pub rx: mpsc::Receiver<Bytes>,
+}
+
+/// `ROLE` 命令的内容
+#[derive(Debug)]
+pub enum RoleInfo {
+    Master {
+        offset: u64,
+        /// 副本的 IP、端口和确认的偏移量
+        replicas: Vec<(String, u16, u64)>,
+    },
+    Replica {
+        host: String,
+        port: u16,
+        link: LinkState,
+        offset: u64,
+    },
+}
+
+impl Replication {
+    pub fn new(backlog_size: usize) -> Replication {
+        Replication {
+            inner: Mutex::new(Inner {
+                replid: random_id(),
+                replid2: None,
+                offset: 0,
+                backlog: None,
+                selected: None,
+                backlog_size,
+                role: Role::Master,
+                replicas: Vec::new(),
+                next_id: 1,
+                sync_full: 0,
+                sync_partial_ok: 0,
+                sync_partial_err: 0,
+            }),
+        }
+    }
+
+    /// 是否是副本
+    pub fn is_replica(&self) -> bool {
+        matches!(self.inner.lock().unwrap().role, Role::Replica { .. })
+    }
+
+    /// 当前命令流的 id 和偏移量
+    pub fn position(&self) -> (String, u64) {
+        let inner = self.inner.lock().unwrap();
+        (inner.replid.clone(), inner.offset)
+    }
+
+    /// 完整同步、成功和失败的部分同步次数
+    pub fn stats(&self) -> (u64, u64, u64) {
+        let inner = self.inner.lock().unwrap();
+        (
+            inner.sync_full,
+            inner.sync_partial_ok,
+            inner.sync_partial_err,
+        )
+    }
+
+    pub fn role(&self) -> RoleInfo {
+        let inner = self.inner.lock().unwrap();
+        match &inner.role {
+            Role::Master => RoleInfo::Master {
+                offset: inner.offset,
+                replicas: inner
+                    .replicas
+                    .iter()
+                    .map(|r| (r.addr.clone(), r.port, r.ack))
+                    .collect(),
+            },
+            Role::Replica {
+                host, port, link, ..
+            } => RoleInfo::Replica {
+                host: host.clone(),
+                port: *port,
+                link: *link,
+                offset: inner.offset,
+            },
+        }
+    }
+
+    /// `CONFIG SET repl-backlog-size`
+    pub fn set_backlog_size(&self, size: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.backlog_size = size;
+        inner.trim_backlog();
+    }
+
+    /// 把主节点上在数据库 `db` 执行的写命令加入命令流，调用方持有命令涉及分片的锁。
+    /// 副本的命令流来自它的主节点，自己执行的命令不再重复加入
+    pub fn propagate(&self, db: usize, commands: &[Vec<Bytes>]) {
+        let mut inner = self.inner.lock().unwrap();
+        if matches!(inner.role, Role::Replica { .. }) || inner.backlog.is_none() {
+            return;
+        }
+        let mut buf: Vec<u8> = Vec::new();
+        aof::encode_in(&mut inner.selected, db, commands, &mut buf);
+        inner.feed(&buf);
+    }
+
+    /// 注册一个需要完整同步的副本。调用方持有全部分片的锁，
+    /// 快照和偏移量对应命令流中的同一个位置
+    pub fn attach_full(&self, guard: &Guard<'_>, addr: String, port: u16) -> Feed {
+        let snapshot: Vec<u8> = rdb::encode(guard.iter_all());
+        let mut inner = self.inner.lock().unwrap();
+        inner.sync_full += 1;
+        if inner.backlog.is_none() {
+            inner.backlog = Some(VecDeque::new());
+        }
+        // 新副本加载完快照后从 0 号数据库开始执行命令流
+        inner.selected = None;
+        let mut preamble: Vec<u8> =
+            format!("+FULLRESYNC {} {}\r\n", inner.replid, inner.offset).into_bytes();
+        preamble.extend_from_slice(format!("${}\r\n", snapshot.len()).as_bytes());
+        preamble.extend_from_slice(&snapshot);
+        inner.attach(addr, port, preamble)
+    }
+
+    /// 尝试部分同步，`offset` 是副本需要的下一个字节的偏移量，与 Redis 一样从 1 开始。
+    /// 积压缓冲区里没有所需的数据时返回 `None`，需要完整同步
+    pub fn attach_partial(
+        &self,
+        replid: &str,
+        offset: u64,
+        addr: String,
+        port: u16,
+    ) -> Option<Feed> {
+        let mut inner = self.inner.lock().unwrap();
+        let known: bool = replid == inner.replid
+            || matches!(&inner.replid2, Some((id, until)) if id == replid && offset <= until + 1);
+        let backlog: Option<&VecDeque<u8>> = inner.backlog.as_ref();
+        let start: u64 = inner.offset + 1 - backlog.map_or(0, |b| b.len() as u64);
+        if !known || backlog.is_none() || offset < start || offset > inner.offset + 1 {
+            if replid != "?" {
+                inner.sync_partial_err += 1;
+            }
+            return None;
+        }
+        inner.sync_partial_ok += 1;
+        let mut preamble: Vec<u8> = format!("+CONTINUE {}\r\n", inner.replid).into_bytes();
+        let backlog: &VecDeque<u8> = inner.backlog.as_ref().unwrap();
+        preamble.extend(backlog.range((offset - start) as usize..));
+        Some(inner.attach(addr, port, preamble))
+    }
+
+    /// 记录副本报告的偏移量
+    pub fn ack(&self, id: u64, offset: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(replica) = inner.replicas.iter_mut().find(|r| r.id == id) {
+            replica.ack = offset;
+        }
+    }
+
+    /// 副本的连接关闭
+    pub fn detach(&self, id: u64) {
+        self.inner.lock().unwrap().replicas.retain(|r| r.id != id);
+    }
+
+    /// 断开所有副本，它们会重连并尝试部分同步
+    pub fn disconnect_replicas(&self) {
+        self.inner.lock().unwrap().replicas.clear();
+    }
+
+    /// `REPLICAOF host port`：丢弃现有数据，改为跟随新的主节点。已经在跟随它时返回 `false`
+    pub fn replicaof(&self, state: &Arc<State>, host: String, port: u16) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if let Role::Replica {
+            host: h, port: p, ..
+        } = &inner.role
+        {
+            if *h == host && *p == port {
+                return false;
+            }
+        }
+        inner.stop_link();
+        let task: AbortHandle =
+            tokio::spawn(link(state.clone(), host.clone(), port)).abort_handle();
+        inner.role = Role::Replica {
+            host,
+            port,
+            link: LinkState::Connect,
+            synced: false,
+            task: Some(task),
+        };
+        // 自己的副本需要从新的命令流重新同步
+        inner.replicas.clear();
+        true
+    }
+
+    /// `REPLICAOF NO ONE`：停止复制，保留现有数据，开始一段新的命令流
+    pub fn promote(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Role::Master = inner.role {
+            return;
+        }
+        inner.stop_link();
+        inner.role = Role::Master;
+        inner.selected = None;
+        let old: String = std::mem::replace(&mut inner.replid, random_id());
+        inner.replid2 = Some((old, inner.offset));
+        log!(
+            Level::Notice,
+            "MASTER MODE enabled, new replication id {}",
+            inner.replid
+        );
+    }
+
+    /// 副本更新与主节点的连接状态
+    fn set_link(&self, state: LinkState) {
+        if let Role::Replica { link, .. } = &mut self.inner.lock().unwrap().role {
+            *link = state;
+        }
+    }
+
+    /// 重连时发给主节点的 `PSYNC` 参数
+    fn psync_args(&self) -> (String, u64) {
+        let inner = self.inner.lock().unwrap();
+        match inner.role {
+            Role::Replica { synced: true, .. } => (inner.replid.clone(), inner.offset + 1),
+            _ => ("?".to_string(), 0),
+        }
+    }
+
+    /// 完整同步后接上主节点的命令流
+    fn reset(&self, replid: String, offset: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replid = replid;
+        inner.replid2 = None;
+        inner.offset = offset;
+        inner.backlog = Some(VecDeque::new());
+        if let Role::Replica { synced, .. } = &mut inner.role {
+            *synced = true;
+        }
+    }
+
+    /// 部分同步成功，命令流接着之前的位置继续
+    fn resume(&self, replid: String) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.replid != replid {
+            // 主节点换过命令流，之前的那段仍然可以用来给自己的副本做部分同步
+            let old: String = std::mem::replace(&mut inner.replid, replid);
+            inner.replid2 = Some((old, inner.offset));
+        }
+    }
+
+    /// 副本转发从主节点收到的命令流
+    fn proxy(&self, data: &[u8]) {
+        self.inner.lock().unwrap().feed(data);
+    }
+}
+
+impl Inner {
+    fn feed(&mut self, data: &[u8]) {
+        self.offset += data.len() as u64;
+        if let Some(backlog) = self.backlog.as_mut() {
+            backlog.extend(data);
+        }
+        self.trim_backlog();
+        if self.replicas.is_empty() {
+            return;
+        }
+        let chunk: Bytes = Bytes::copy_from_slice(data);
+        self.replicas.retain(|replica| {
+            if replica.tx.try_send(chunk.clone()).is_ok() {
+                return true;
+            }
+            log!(
+                Level::Warning,
+                "Disconnecting replica {}:{}, output buffer limit reached or connection closed",
+                replica.addr,
+                replica.port
+            );
+            false
+        });
+    }
+
+    fn trim_backlog(&mut self) {
+        let size: usize = self.backlog_size;
+        if let Some(backlog) = self.backlog.as_mut() {
+            if backlog.len() > size {
+                backlog.drain(..backlog.len() - size);
+            }
+        }
+    }
+
+    fn attach(&mut self, addr: String, port: u16, preamble: Vec<u8>) -> Feed {
+        let (tx, rx) = mpsc::channel(REPLICA_BUFFER);
+        let id: u64 = self.next_id;
+        self.next_id += 1;
+        log!(Level::Notice, "Replica {addr}:{port} attached");
+        self.replicas.push(Replica {
+            id,
+            addr,
+            port,
+            tx,
+            ack: 0,
+        });
+        Feed { id, preamble, rx }
+    }
+
+    fn stop_link(&mut self) {
+        if let Role::Replica {
+            task: Some(task), ..
+        } = &self.role
+        {
+            task.abort();
+        }
+    }
+}
+
+/// 随机生成 40 个十六进制字符的 id，replid 和集群的节点 id 都用它
+pub fn random_id() -> String {
+    // 每个 `RandomState` 都有随机的种子，足够区分不同的命令流
+    (0..3)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(now_ms());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect::<String>()[..40]
+        .to_string()
+}
+
+/// 副本维护与主节点之间连接的后台任务，断线后按指数退避重连
+async fn link(state: Arc<State>, host: String, port: u16) {
+    let mut delay: Duration = RECONNECT_MIN;
+    // 命令流选中的数据库，部分同步后命令流接着断线前的位置，数据库也要接着用
+    let mut db: usize = 0;
+    loop {
+        state.repl.set_link(LinkState::Connecting);
+        match sync_with(&state, &host, port, &mut delay, &mut db).await {
+            Ok(()) => log!(Level::Notice, "Connection with master lost"),
+            Err(err) => log!(
+                Level::Warning,
+                "Error condition on socket for SYNC with {host}:{port}: {err}"
+            ),
+        }
+        state.repl.set_link(LinkState::Connect);
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX);
+    }
+}
+
+/// 与主节点握手、同步，然后一直执行主节点发来的命令流，直到连接断开
+async fn sync_with(
+    state: &Arc<State>,
+    host: &str,
+    port: u16,
+    delay: &mut Duration,
+    db: &mut usize,
+) -> io::Result<()> {
+    let mut link: Link = Link {
+        stream: TcpStream::connect((host, port)).await?,
+        buffer: BytesMut::with_capacity(16 * 1024),
+        decoder: Decoder::default(),
+    };
+    // 主节点要求认证时先 AUTH，否则连 PING 都会被拒绝
+    let (masteruser, masterauth) = {
+        let config = state.config();
+        (config.masteruser.clone(), config.masterauth.clone())
+    };
+    if let Some(password) = &masterauth {
+        match &masteruser {
+            Some(user) => link.request(&["AUTH", user, password]).await?,
+            None => link.request(&["AUTH", password]).await?,
+        };
+    }
+    link.request(&["PING"]).await?;
+    let listening_port: String = state.config().port.to_string();
+    link.request(&["REPLCONF", "listening-port", &listening_port])
+        .await?;
+    link.request(&["REPLCONF", "capa", "psync2"]).await?;
+
+    state.repl.set_link(LinkState::Sync);
+    let (replid, offset) = state.repl.psync_args();
+    let reply: String = link
+        .request(&["PSYNC", &replid, &offset.to_string()])
+        .await?;
+    let words: Vec<&str> = reply.split(' ').collect();
+    match words.as_slice() {
+        ["FULLRESYNC", replid, offset] => {
+            let offset: u64 = offset
+                .parse()
+                .map_err(|_| invalid("bad FULLRESYNC offset"))?;
+            log!(Level::Notice, "Full resync from master: {replid}:{offset}");
+            let snapshot: Bytes = link.bulk().await?;
+            load(state, &snapshot)?;
+            state.repl.reset(replid.to_string(), offset);
+            *db = 0;
+        }
+        ["CONTINUE", replid] => {
+            log!(
+                Level::Notice,
+                "Successful partial resynchronization with master"
+            );
+            state.repl.resume(replid.to_string());
+        }
+        _ => return Err(invalid(&format!("unexpected reply to PSYNC: {reply}"))),
+    }
+    state.repl.set_link(LinkState::Connected);
+    *delay = RECONNECT_MIN;
+
+    let mut session: Session = Session::new();
+    session.db = *db;
+    let mut ack = tokio::time::interval(ACK_INTERVAL);
+    loop {
+        // 先执行缓冲区里已经完整的命令
+        while let Some((args, len)) = link.command()? {
+            if args[0].eq_ignore_ascii_case(b"REPLCONF") {
+                // REPLCONF GETACK：马上报告，报告的偏移量不包括这条命令本身
+                let (_, offset) = state.repl.position();
+                link.send(&["REPLCONF", "ACK", &offset.to_string()]).await?;
+            } else if !args[0].eq_ignore_ascii_case(b"PING") {
+                if let Frame::Error(err) = cmd::execute_in(state, &mut session, &args) {
+                    log!(
+                        Level::Warning,
+                        "Error applying a command from master: {err}"
+                    );
+                }
+                *db = session.db;
+            }
+            let raw: Bytes = link.buffer.split_to(len).freeze();
+            state.repl.proxy(&raw);
+        }
+        tokio::select! {
+            read = link.stream.read_buf(&mut link.buffer) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+            }
+            _ = ack.tick() => {
+                let (_, offset) = state.repl.position();
+                link.send(&["REPLCONF", "ACK", &offset.to_string()]).await?;
+            }
+        }
+    }
+}
+
+/// 用主节点发来的快照替换全部数据
+fn load(state: &State, snapshot: &[u8]) -> io::Result<()> {
+    let entries = rdb::decode(snapshot)?;
+    let mut guard: Guard<'_> = state.db.lock_all();
+    for db in 0..guard.databases() {
+        guard.select(db);
+        guard.clear();
+    }
+    guard.select(0);
+    let loaded: usize = rdb::insert_all(&mut guard, entries)?;
+    log!(
+        Level::Notice,
+        "MASTER <-> REPLICA sync: loaded {loaded} keys"
+    );
+    // 旧的日志与新数据无关了，在锁内开始重写
+    if let Some(aof) = state.aof.get() {
+        if !aof.rewrite(&guard) {
+            log!(
+                Level::Warning,
+                "Can't rewrite the append only file after sync, a rewrite is already in progress"
+            );
+        }
+    }
+    Ok(())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// 副本到主节点的连接。快照后面没有 CRLF，不能当作普通的帧来读，所以直接操作缓冲区
+struct Link {
+    stream: TcpStream,
+    buffer: BytesMut,
+    /// 解析快照之后的命令流，只在 `command` 解析出完整的命令后才从缓冲区取走数据
+    decoder: Decoder,
+}
+
+impl Link {
+    /// 发送一条命令
+    async fn send(&mut self, args: &[&str]) -> io::Result<()> {
+        let args: Vec<Bytes> = args
+            .iter()
+            .map(|a| Bytes::copy_from_slice(a.as_bytes()))
+            .collect();
+        let mut buf: Vec<u8> = Vec::new();
+        aof::encode(&args, &mut buf);
+        self.stream.write_all(&buf).await
+    }
+
+    /// 发送一条命令并读取单行的回复，错误回复转成 `io::Error`
+    async fn request(&mut self, args: &[&str]) -> io::Result<String> {
+        self.send(args).await?;
+        let line: String = self.line().await?;
+        match line.as_bytes().first() {
+            Some(b'+') => Ok(line[1..].to_string()),
+            Some(b'-') => Err(io::Error::other(format!(
+                "{} rejected by master: {}",
+                args[0],
+                &line[1..]
+            ))),
+            _ => Err(invalid(&format!("unexpected reply: {line}"))),
+        }
+    }
+
+    /// 读取一行，不含行尾的 CRLF
+    async fn line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(pos) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                let line: BytesMut = self.buffer.split_to(pos);
+                self.buffer.advance(2);
+                return String::from_utf8(line.to_vec()).map_err(|_| invalid("reply is not UTF-8"));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// 读取 `$len\r\n` 开头、后面没有 CRLF 的快照
+    async fn bulk(&mut self) -> io::Result<Bytes> {
+        let line: String = self.line().await?;
+        let len: usize = line
+            .strip_prefix('$')
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| invalid(&format!("expected snapshot, got {line}")))?;
+        while self.buffer.len() < len {
+            self.fill().await?;
+        }
+        Ok(self.buffer.split_to(len).freeze())
+    }
+
+    /// 从缓冲区解析一条完整的命令，返回参数和它在命令流中占的字节数。
+    /// 命令本身仍留在缓冲区开头，由调用方执行后取走并转发
+    fn command(&mut self) -> io::Result<Option<(Vec<Bytes>, usize)>> {
+        let (frame, len) = match self
+            .decoder
+            .peek(&self.buffer)
+            .map_err(|err| invalid(&err.to_string()))?
+        {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        let args: Vec<Bytes> = cmd::parse_args(frame)
+            .ok()
+            .filter(|args| !args.is_empty())
+            .ok_or_else(|| invalid("master sent something that is not a command"))?;
+        Ok(Some((args, len)))
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by master",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::test::{render, reply, run_in, send, state};
+    use crate::server;
+    use crate::session::Session;
+    use crate::state::State;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    async fn start(state: &Arc<State>) -> SocketAddr {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(server::run(
+            listener,
+            state.clone(),
+            std::future::pending::<()>(),
+        ));
+        addr
+    }
+
+    async fn eventually(what: &str, check: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if check() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out waiting for {what}");
+    }
+
+    /// 启动主节点，让一个新的副本跟随它并等到完成第一次同步
+    async fn pair() -> (Arc<State>, Arc<State>) {
+        let primary: Arc<State> = state(4);
+        let addr: SocketAddr = start(&primary).await;
+        reply(&primary, "SET synced yes");
+        let replica: Arc<State> = state(2);
+        assert_eq!(
+            send(&replica, &format!("REPLICAOF 127.0.0.1 {}", addr.port())),
+            "OK"
+        );
+        eventually("first sync", || reply(&replica, "GET synced") == "yes").await;
+        (primary, replica)
+    }
+
+    #[tokio::test]
+    async fn replica_syncs_and_follows_the_stream() {
+        let primary: Arc<State> = state(4);
+        let addr: SocketAddr = start(&primary).await;
+        reply(&primary, "SET a 1");
+        reply(&primary, "RPUSH l x y");
+        reply(&primary, "SET t v PX 100000");
+
+        let replica: Arc<State> = state(2);
+        let cmd: String = format!("REPLICAOF 127.0.0.1 {}", addr.port());
+        assert_eq!(send(&replica, &cmd), "OK");
+        eventually("full sync", || reply(&replica, "GET a") == "1").await;
+        assert_eq!(reply(&replica, "LRANGE l 0 -1"), "[x y]");
+        let expires_at: Option<u64> = replica.db.lock_keys(["t"]).get("t").unwrap().expires_at;
+        assert!(expires_at.unwrap() > crate::db::now_ms() + 90_000);
+
+        reply(&primary, "SET b 2");
+        reply(&primary, "DEL a");
+        reply(&primary, "LPOP l");
+        eventually("stream", || reply(&replica, "LRANGE l 0 -1") == "[y]").await;
+        assert_eq!(reply(&replica, "GET b"), "2");
+        assert_eq!(reply(&replica, "EXISTS a"), "0");
+
+        assert_eq!(
+            send(&replica, "SET c 3"),
+            "!READONLY You can't write against a read only replica."
+        );
+        assert_eq!(
+            send(&replica, &cmd),
+            "OK Already connected to specified master"
+        );
+        assert!(send(&replica, "ROLE").starts_with("[slave 127.0.0.1"));
+        assert!(send(&replica, "HELLO").contains(" role replica "));
+
+        // 副本每秒报告一次偏移量
+        let offset: u64 = primary.repl.position().1;
+        eventually("ack", || {
+            send(&primary, "ROLE") == format!("[master {offset} [[127.0.0.1 6379 {offset}]]]")
+        })
+        .await;
+        assert_eq!(replica.repl.position(), primary.repl.position());
+
+        assert_eq!(send(&replica, "REPLICAOF NO ONE"), "OK");
+        assert_eq!(send(&replica, "SET c 3"), "OK");
+        assert!(send(&replica, "ROLE").starts_with("[master"));
+        assert!(send(&replica, "HELLO").contains(" role master "));
+    }
+
+    #[tokio::test]
+    async fn reconnect_continues_from_backlog() {
+        let (primary, replica) = pair().await;
+        primary.repl.disconnect_replicas();
+        for i in 0..10 {
+            reply(&primary, &format!("SET k{i} {i}"));
+        }
+        eventually("partial resync", || reply(&replica, "GET k9") == "9").await;
+        assert_eq!(primary.repl.stats(), (1, 1, 0));
+        assert_eq!(reply(&replica, "GET synced"), "yes");
+    }
+
+    /// 在数据库 `db` 中读取 key
+    fn get_in(state: &Arc<State>, db: usize, key: &str) -> String {
+        let mut session: Session = Session::new();
+        run_in(state, &mut session, &format!("SELECT {db}"));
+        render(&run_in(state, &mut session, &format!("GET {key}")))
+    }
+
+    #[tokio::test]
+    async fn databases_follow_snapshot_and_stream() {
+        let primary: Arc<State> = state(4);
+        let addr: SocketAddr = start(&primary).await;
+        let mut session: Session = Session::new();
+        run_in(&primary, &mut session, "SELECT 2");
+        run_in(&primary, &mut session, "SET a snapshot");
+        let replica: Arc<State> = state(2);
+        let cmd: String = format!("REPLICAOF 127.0.0.1 {}", addr.port());
+        assert_eq!(send(&replica, &cmd), "OK");
+        eventually("full sync", || get_in(&replica, 2, "a") == "snapshot").await;
+
+        reply(&primary, "SET c zero");
+        run_in(&primary, &mut session, "SET b stream");
+        eventually("stream", || get_in(&replica, 2, "b") == "stream").await;
+        assert_eq!(get_in(&replica, 0, "c"), "zero");
+        assert_eq!(get_in(&replica, 0, "b"), "nil");
+
+        // 命令流已经选中了 2 号数据库，部分同步后的命令不再带 SELECT
+        primary.repl.disconnect_replicas();
+        run_in(&primary, &mut session, "SET d partial");
+        eventually("partial resync", || get_in(&replica, 2, "d") == "partial").await;
+        assert_eq!(primary.repl.stats(), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn backlog_overflow_forces_full_resync() {
+        let (primary, replica) = pair().await;
+        primary.set_config("repl-backlog-size", "100").unwrap();
+        primary.repl.disconnect_replicas();
+        for i in 0..20 {
+            reply(&primary, &format!("SET key{i} value{i}"));
+        }
+        eventually("full resync", || reply(&replica, "GET key19") == "value19").await;
+        assert_eq!(primary.repl.stats(), (2, 0, 1));
+        assert_eq!(reply(&replica, "GET key0"), "value0");
+    }
+}
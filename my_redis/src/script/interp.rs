@@ -0,0 +1,908 @@
+//! 解释执行语法树
+//!
+//! 值的类型和 Lua 脚本里看到的一致：命令回复按 Redis 的规则转换成脚本里的值，
+//! 脚本的返回值再按同样的规则转换回回复。列表是引用语义，赋值给另一个变量后修改会互相可见。
+
+use super::parse::{BinOp, Block, Builtin, Expr, Stmt, StmtKind, UnOp, MAX_DEPTH};
+use crate::frame::{format_double, Frame};
+use bytes::Bytes;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// 每执行这么多步检查一次是否超时
+const CHECK_INTERVAL: u64 = 1000;
+
+/// 脚本通过它执行命令
+pub trait Host {
+    fn call(&mut self, args: Vec<Bytes>) -> Frame;
+}
+
+/// 脚本中的值
+#[derive(Debug, Clone)]
+enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Bytes),
+    List(List),
+    /// 状态回复，例如 `call('SET', ...)` 返回的 OK
+    Status(String),
+    /// 错误回复，`pcall` 出错时返回
+    Error(String),
+}
+
+/// 共享的列表，可以直接或间接地包含自己
+#[derive(Debug, Clone)]
+struct List(Rc<RefCell<Vec<Value>>>);
+
+impl List {
+    fn new(items: Vec<Value>) -> List {
+        List(Rc::new(RefCell::new(items)))
+    }
+}
+
+impl Deref for List {
+    type Target = RefCell<Vec<Value>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for List {
+    /// 逐层展开释放，嵌套很深的列表也不会在析构时递归耗尽栈
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.0) != 1 {
+            return;
+        }
+        let mut pending: Vec<Value> = std::mem::take(&mut *self.0.borrow_mut());
+        while let Some(value) = pending.pop() {
+            if let Value::List(list) = value {
+                if Rc::strong_count(&list.0) == 1 {
+                    pending.append(&mut list.0.borrow_mut());
+                }
+            }
+        }
+    }
+}
+
+/// 中止脚本的原因
+enum Error {
+    /// 脚本自身的错误，例如类型不对
+    Runtime { line: usize, msg: String },
+    /// `call` 执行的命令回复了错误
+    Reply(String),
+    /// 超出时间限制
+    Killed(Duration),
+}
+
+/// 语句执行后的控制流
+enum Flow {
+    Normal,
+    Break,
+    Return(Value),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+struct Interp<'h> {
+    host: &'h mut dyn Host,
+    /// 由外到内的各层作用域
+    scopes: Vec<HashMap<String, Value>>,
+    /// 正在执行的语句所在行
+    line: usize,
+    steps: u64,
+    deadline: Option<(Instant, Duration)>,
+    /// 当前递归的层数，解析时已经限制了语法树的深度，这里再兜底一次
+    depth: usize,
+}
+
+/// 执行脚本，`KEYS` 和 `ARGV` 是预先定义的列表。出错时返回错误回复
+pub fn run(
+    program: &Block,
+    keys: &[Bytes],
+    argv: &[Bytes],
+    host: &mut dyn Host,
+    limit: Option<Duration>,
+) -> Frame {
+    let list =
+        |items: &[Bytes]| Value::List(List::new(items.iter().cloned().map(Value::Str).collect()));
+    let globals: HashMap<String, Value> = HashMap::from([
+        ("KEYS".to_string(), list(keys)),
+        ("ARGV".to_string(), list(argv)),
+    ]);
+    let mut interp: Interp<'_> = Interp {
+        host,
+        scopes: vec![globals],
+        line: 0,
+        steps: 0,
+        // 限制大到算不出截止时间时等同于不限制
+        deadline: limit.and_then(|limit| Some((Instant::now().checked_add(limit)?, limit))),
+        depth: 0,
+    };
+    match interp.block(program) {
+        // 列表可以包含自己，转换时同样限制层数
+        Ok(Flow::Return(value)) => to_frame(value, 0).unwrap_or_else(|| {
+            Frame::Error(format!(
+                "ERR Error running script: line {}: reply is nested too deeply",
+                interp.line
+            ))
+        }),
+        Ok(_) => Frame::Null,
+        Err(Error::Runtime { line, msg }) => {
+            Frame::Error(format!("ERR Error running script: line {line}: {msg}"))
+        }
+        Err(Error::Reply(err)) => Frame::Error(err),
+        Err(Error::Killed(limit)) => Frame::Error(format!(
+            "ERR Script killed after exceeding the time limit of {} ms",
+            limit.as_millis()
+        )),
+    }
+}
+
+impl Interp<'_> {
+    fn error<T>(&self, msg: impl Into<String>) -> Result<T> {
+        Err(Error::Runtime {
+            line: self.line,
+            msg: msg.into(),
+        })
+    }
+
+    /// 每一步都计数，定期检查是否超时
+    fn tick(&mut self) -> Result<()> {
+        self.steps += 1;
+        if self.steps.is_multiple_of(CHECK_INTERVAL) {
+            if let Some((deadline, limit)) = self.deadline {
+                if Instant::now() >= deadline {
+                    return Err(Error::Killed(limit));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 在新的作用域中执行
+    fn block(&mut self, block: &Block) -> Result<Flow> {
+        self.scoped(HashMap::new(), block)
+    }
+
+    fn scoped(&mut self, scope: HashMap<String, Value>, block: &Block) -> Result<Flow> {
+        self.nested(|interp| {
+            interp.scopes.push(scope);
+            let result: Result<Flow> = interp.stmts(block);
+            interp.scopes.pop();
+            result
+        })
+    }
+
+    /// 递归进入下一层，超过 `MAX_DEPTH` 时报错
+    fn nested<T>(&mut self, run: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_DEPTH {
+            return self.error("stack overflow");
+        }
+        self.depth += 1;
+        let result: Result<T> = run(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn stmts(&mut self, block: &Block) -> Result<Flow> {
+        for stmt in block {
+            self.line = stmt.line;
+            self.tick()?;
+            match self.stmt(stmt)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<Flow> {
+        match &stmt.kind {
+            StmtKind::Local(name, value) => {
+                let value: Value = match value {
+                    Some(expr) => self.eval(expr)?,
+                    None => Value::Nil,
+                };
+                let scope = self.scopes.last_mut().expect("at least the global scope");
+                scope.insert(name.clone(), value);
+            }
+            StmtKind::Assign(name, expr) => {
+                let value: Value = self.eval(expr)?;
+                match self.scopes.iter_mut().rev().find_map(|s| s.get_mut(name)) {
+                    Some(slot) => *slot = value,
+                    None => {
+                        return self.error(format!(
+                            "assignment to undeclared variable '{name}', use 'local'"
+                        ))
+                    }
+                }
+            }
+            StmtKind::SetIndex(list, index, expr) => {
+                let list: Value = self.eval(list)?;
+                let index: Value = self.eval(index)?;
+                let value: Value = self.eval(expr)?;
+                let Value::List(items) = list else {
+                    return self.error(format!("attempt to index a {} value", type_name(&list)));
+                };
+                let mut items = items.borrow_mut();
+                match self.position(&index)? {
+                    Some(i) if i < items.len() => items[i] = value,
+                    // 紧接着末尾赋值等于追加
+                    Some(i) if i == items.len() => items.push(value),
+                    _ => return self.error("list index out of range"),
+                }
+            }
+            StmtKind::Call(builtin, args) => {
+                self.call(*builtin, args)?;
+            }
+            StmtKind::If(branches, otherwise) => {
+                for (cond, body) in branches {
+                    if truthy(&self.eval(cond)?) {
+                        return self.block(body);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.block(body);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                while truthy(&self.eval(cond)?) {
+                    self.tick()?;
+                    match self.block(body)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            StmtKind::For {
+                var,
+                start,
+                stop,
+                step,
+                body,
+            } => {
+                let start: Value = self.eval(start)?;
+                let stop: Value = self.eval(stop)?;
+                let step: Value = match step {
+                    Some(step) => self.eval(step)?,
+                    None => Value::Int(1),
+                };
+                let (mut counter, stop, step) = match (number(&start), number(&stop), number(&step))
+                {
+                    (Some(start), Some(stop), Some(step)) => (start, stop, step),
+                    _ => return self.error("'for' initial value, limit and step must be numbers"),
+                };
+                if as_float(&step) == 0.0 {
+                    return self.error("'for' step is zero");
+                }
+                // 三者都是整数时按整数计数，否则按小数
+                if !matches!(
+                    (&counter, &stop, &step),
+                    (Value::Int(_), Value::Int(_), Value::Int(_))
+                ) {
+                    counter = Value::Float(as_float(&counter));
+                }
+                let up: bool = as_float(&step) > 0.0;
+                loop {
+                    let done: bool = match (&counter, &stop) {
+                        (Value::Int(i), Value::Int(s)) => {
+                            if up {
+                                i > s
+                            } else {
+                                i < s
+                            }
+                        }
+                        _ => {
+                            let (i, s) = (as_float(&counter), as_float(&stop));
+                            if up {
+                                i > s
+                            } else {
+                                i < s
+                            }
+                        }
+                    };
+                    if done {
+                        break;
+                    }
+                    self.tick()?;
+                    let scope: HashMap<String, Value> =
+                        HashMap::from([(var.clone(), counter.clone())]);
+                    match self.scoped(scope, body)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                    counter = match (&counter, &step) {
+                        (Value::Int(i), Value::Int(s)) => match i.checked_add(*s) {
+                            Some(next) => Value::Int(next),
+                            None => break,
+                        },
+                        _ => Value::Float(as_float(&counter) + as_float(&step)),
+                    };
+                }
+            }
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Return(expr) => {
+                let value: Value = match expr {
+                    Some(expr) => self.eval(expr)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value> {
+        if self.depth >= MAX_DEPTH {
+            return self.error("stack overflow");
+        }
+        self.depth += 1;
+        let value: Result<Value> = self.value(expr);
+        self.depth -= 1;
+        value
+    }
+
+    fn value(&mut self, expr: &Expr) -> Result<Value> {
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Int(n) => Value::Int(*n),
+            Expr::Float(f) => Value::Float(*f),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Name(name) => match self.scopes.iter().rev().find_map(|s| s.get(name)) {
+                Some(value) => value.clone(),
+                None => return self.error(format!("undefined variable '{name}'")),
+            },
+            Expr::List(items) => {
+                let items: Vec<Value> = items
+                    .iter()
+                    .map(|item| self.eval(item))
+                    .collect::<Result<_>>()?;
+                Value::List(List::new(items))
+            }
+            Expr::Index(list, index) => {
+                let list: Value = self.eval(list)?;
+                let index: Value = self.eval(index)?;
+                let Value::List(items) = list else {
+                    return self.error(format!("attempt to index a {} value", type_name(&list)));
+                };
+                let items = items.borrow();
+                match self.position(&index)? {
+                    Some(i) => items.get(i).cloned().unwrap_or(Value::Nil),
+                    None => Value::Nil,
+                }
+            }
+            Expr::Call(builtin, args) => self.call(*builtin, args)?,
+            Expr::Unary(op, operand) => {
+                let value: Value = self.eval(operand)?;
+                match op {
+                    UnOp::Not => Value::Bool(!truthy(&value)),
+                    UnOp::Neg => match number(&value) {
+                        Some(Value::Int(n)) => match n.checked_neg() {
+                            Some(n) => Value::Int(n),
+                            None => Value::Float(-(n as f64)),
+                        },
+                        Some(v) => Value::Float(-as_float(&v)),
+                        None => return self.arith_error(&value),
+                    },
+                    UnOp::Len => match &value {
+                        Value::Str(s) => Value::Int(s.len() as i64),
+                        Value::List(items) => Value::Int(items.borrow().len() as i64),
+                        _ => {
+                            return self.error(format!(
+                                "attempt to get length of a {} value",
+                                type_name(&value)
+                            ))
+                        }
+                    },
+                }
+            }
+            Expr::And(lhs, rhs) => {
+                let lhs: Value = self.eval(lhs)?;
+                if truthy(&lhs) {
+                    self.eval(rhs)?
+                } else {
+                    lhs
+                }
+            }
+            Expr::Or(lhs, rhs) => {
+                let lhs: Value = self.eval(lhs)?;
+                if truthy(&lhs) {
+                    lhs
+                } else {
+                    self.eval(rhs)?
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs: Value = self.eval(lhs)?;
+                let rhs: Value = self.eval(rhs)?;
+                self.binary(*op, lhs, rhs)?
+            }
+        })
+    }
+
+    fn binary(&self, op: BinOp, lhs: Value, rhs: Value) -> Result<Value> {
+        match op {
+            BinOp::Eq => return Ok(Value::Bool(equal(&lhs, &rhs))),
+            BinOp::Ne => return Ok(Value::Bool(!equal(&lhs, &rhs))),
+            BinOp::Concat => {
+                return match (to_bytes(&lhs), to_bytes(&rhs)) {
+                    (Some(a), Some(b)) => Ok(Value::Str(Bytes::from([&a[..], &b[..]].concat()))),
+                    (None, _) => self.error(format!(
+                        "attempt to concatenate a {} value",
+                        type_name(&lhs)
+                    )),
+                    (_, None) => self.error(format!(
+                        "attempt to concatenate a {} value",
+                        type_name(&rhs)
+                    )),
+                };
+            }
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                // 比较不做字符串和数字之间的转换
+                let ordering = match (&lhs, &rhs) {
+                    (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                    (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+                    (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                        as_float(&lhs).partial_cmp(&as_float(&rhs))
+                    }
+                    _ => {
+                        return self.error(format!(
+                            "attempt to compare {} with {}",
+                            type_name(&lhs),
+                            type_name(&rhs)
+                        ))
+                    }
+                };
+                let result: bool = match (op, ordering) {
+                    (_, None) => false,
+                    (BinOp::Lt, Some(o)) => o.is_lt(),
+                    (BinOp::Le, Some(o)) => o.is_le(),
+                    (BinOp::Gt, Some(o)) => o.is_gt(),
+                    (_, Some(o)) => o.is_ge(),
+                };
+                return Ok(Value::Bool(result));
+            }
+            _ => {}
+        }
+        let (a, b) = match (number(&lhs), number(&rhs)) {
+            (Some(a), Some(b)) => (a, b),
+            (None, _) => return self.arith_error(&lhs),
+            (_, None) => return self.arith_error(&rhs),
+        };
+        if let (Value::Int(x), Value::Int(y)) = (&a, &b) {
+            let (x, y) = (*x, *y);
+            let result: Option<i64> = match op {
+                BinOp::Add => x.checked_add(y),
+                BinOp::Sub => x.checked_sub(y),
+                BinOp::Mul => x.checked_mul(y),
+                BinOp::Mod if y == 0 => return self.error("attempt to perform 'n%0'"),
+                // 结果的符号与除数相同
+                BinOp::Mod => x.checked_rem(y).map(|r| {
+                    if r != 0 && (r < 0) != (y < 0) {
+                        r + y
+                    } else {
+                        r
+                    }
+                }),
+                _ => None,
+            };
+            if let Some(n) = result {
+                return Ok(Value::Int(n));
+            }
+        }
+        let (x, y) = (as_float(&a), as_float(&b));
+        Ok(Value::Float(match op {
+            BinOp::Add => x + y,
+            BinOp::Sub => x - y,
+            BinOp::Mul => x * y,
+            BinOp::Div => x / y,
+            _ => x - (x / y).floor() * y,
+        }))
+    }
+
+    fn arith_error<T>(&self, value: &Value) -> Result<T> {
+        self.error(format!(
+            "attempt to perform arithmetic on a {} value",
+            type_name(value)
+        ))
+    }
+
+    /// 把从 1 开始的下标转成从 0 开始的位置，不是正整数时返回 `None`
+    fn position(&self, index: &Value) -> Result<Option<usize>> {
+        match number(index) {
+            Some(Value::Int(i)) if i >= 1 => Ok(Some(i as usize - 1)),
+            Some(Value::Float(f)) if f >= 1.0 && f.fract() == 0.0 => Ok(Some(f as usize - 1)),
+            Some(_) => Ok(None),
+            None => self.error(format!("invalid list index of type {}", type_name(index))),
+        }
+    }
+
+    fn call(&mut self, builtin: Builtin, args: &[Expr]) -> Result<Value> {
+        let args: Vec<Value> = args
+            .iter()
+            .map(|arg| self.eval(arg))
+            .collect::<Result<_>>()?;
+        let expect = |n: usize, name: &str| -> Result<()> {
+            if args.len() == n {
+                Ok(())
+            } else {
+                self.error(format!("wrong number of arguments to '{name}'"))
+            }
+        };
+        match builtin {
+            Builtin::Call | Builtin::PCall => {
+                if args.is_empty() {
+                    return self.error("please specify at least one argument for call");
+                }
+                let argv: Vec<Bytes> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Value::Str(_) | Value::Int(_) | Value::Float(_) => to_bytes(arg),
+                        _ => None,
+                    })
+                    .collect::<Option<_>>()
+                    .map_or_else(
+                        || self.error("command arguments must be strings or numbers"),
+                        Ok,
+                    )?;
+                match self.host.call(argv) {
+                    Frame::Error(err) if builtin == Builtin::Call => Err(Error::Reply(err)),
+                    frame => Ok(from_frame(frame)),
+                }
+            }
+            Builtin::ErrorReply | Builtin::StatusReply => {
+                expect(1, "error_reply")?;
+                let text: String = match to_bytes(&args[0]) {
+                    Some(b) => String::from_utf8_lossy(&b).into_owned(),
+                    None => return self.error("reply text must be a string"),
+                };
+                Ok(if builtin == Builtin::ErrorReply {
+                    Value::Error(text)
+                } else {
+                    Value::Status(text)
+                })
+            }
+            Builtin::ToNumber => {
+                expect(1, "tonumber")?;
+                Ok(number(&args[0]).unwrap_or(Value::Nil))
+            }
+            Builtin::ToString => {
+                expect(1, "tostring")?;
+                Ok(Value::Str(Bytes::from(display(&args[0]))))
+            }
+            Builtin::Type => {
+                expect(1, "type")?;
+                Ok(Value::Str(Bytes::from_static(
+                    type_name(&args[0]).as_bytes(),
+                )))
+            }
+            Builtin::Push => {
+                expect(2, "push")?;
+                match &args[0] {
+                    Value::List(items) => items.borrow_mut().push(args[1].clone()),
+                    other => {
+                        return self.error(format!(
+                            "bad argument to 'push' (list expected, got {})",
+                            type_name(other)
+                        ))
+                    }
+                }
+                Ok(Value::Nil)
+            }
+        }
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Nil | Value::Bool(false))
+}
+
+/// 数字原样返回，字符串尝试按数字解析，和 Lua 一样在算术运算中自动转换
+fn number(value: &Value) -> Option<Value> {
+    match value {
+        Value::Int(_) | Value::Float(_) => Some(value.clone()),
+        Value::Str(s) => {
+            let s: &str = std::str::from_utf8(s).ok()?.trim();
+            if let Ok(n) = s.parse::<i64>() {
+                Some(Value::Int(n))
+            } else {
+                s.parse::<f64>()
+                    .ok()
+                    .filter(|f| !f.is_nan())
+                    .map(Value::Float)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => *n as f64,
+        Value::Float(f) => *f,
+        _ => unreachable!("checked to be a number"),
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::Int(x), Value::Int(y)) => x == y,
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            as_float(a) == as_float(b)
+        }
+        (Value::Str(x), Value::Str(y)) => x == y,
+        (Value::List(x), Value::List(y)) => Rc::ptr_eq(&x.0, &y.0),
+        (Value::Status(x), Value::Status(y)) | (Value::Error(x), Value::Error(y)) => x == y,
+        _ => false,
+    }
+}
+
+/// 字符串和数字可以转成字节串，用于拼接和命令参数
+fn to_bytes(value: &Value) -> Option<Bytes> {
+    match value {
+        Value::Str(s) => Some(s.clone()),
+        Value::Int(n) => Some(Bytes::from(n.to_string())),
+        Value::Float(f) => Some(Bytes::from(format_double(*f))),
+        _ => None,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Str(s) => String::from_utf8_lossy(s).into_owned(),
+        Value::Int(_) | Value::Float(_) => {
+            String::from_utf8_lossy(&to_bytes(value).expect("number")).into_owned()
+        }
+        Value::List(items) => format!("list: {:p}", Rc::as_ptr(&items.0)),
+        Value::Status(s) | Value::Error(s) => s.clone(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Bool(_) => "boolean",
+        Value::Int(_) | Value::Float(_) => "number",
+        Value::Str(_) => "string",
+        Value::List(_) => "list",
+        Value::Status(_) => "status",
+        Value::Error(_) => "error",
+    }
+}
+
+/// 命令回复转换成脚本中的值
+fn from_frame(frame: Frame) -> Value {
+    let list = |items: Vec<Value>| Value::List(List::new(items));
+    match frame {
+        Frame::Simple(s) => Value::Status(s),
+        Frame::Error(e) => Value::Error(e),
+        Frame::Integer(n) => Value::Int(n),
+        Frame::Bulk(b) => Value::Str(b),
+        Frame::Null => Value::Nil,
+        Frame::Double(d) => Value::Float(d),
+        Frame::Boolean(b) => Value::Bool(b),
+        Frame::Array(items) | Frame::Set(items) | Frame::Push(items) => {
+            list(items.into_iter().map(from_frame).collect())
+        }
+        Frame::Map(pairs) => list(
+            pairs
+                .into_iter()
+                .flat_map(|(k, v)| [from_frame(k), from_frame(v)])
+                .collect(),
+        ),
+    }
+}
+
+/// 脚本的返回值转换成回复：true 是 1，false 和 nil 是空值，小数截断成整数。
+/// 列表嵌套超过 `MAX_DEPTH` 层时返回 `None`
+fn to_frame(value: Value, depth: usize) -> Option<Frame> {
+    let frame: Frame = match value {
+        Value::Nil | Value::Bool(false) => Frame::Null,
+        Value::Bool(true) => Frame::Integer(1),
+        Value::Int(n) => Frame::Integer(n),
+        Value::Float(f) => Frame::Integer(f as i64),
+        Value::Str(s) => Frame::Bulk(s),
+        Value::List(_) if depth >= MAX_DEPTH => return None,
+        Value::List(items) => Frame::Array(
+            items
+                .borrow()
+                .iter()
+                .map(|item| to_frame(item.clone(), depth + 1))
+                .collect::<Option<Vec<Frame>>>()?,
+        ),
+        Value::Status(s) => Frame::Simple(s),
+        Value::Error(e) => Frame::Error(e),
+    };
+    Some(frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, Host};
+    use crate::frame::Frame;
+    use crate::script::parse::parse;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    /// 记录收到的命令，GET 返回 "41"，FAIL 返回错误
+    struct Recorder(Vec<Vec<Bytes>>);
+
+    impl Host for Recorder {
+        fn call(&mut self, args: Vec<Bytes>) -> Frame {
+            let reply: Frame = match &args[0][..] {
+                b"GET" => Frame::Bulk(Bytes::from("41")),
+                b"FAIL" => Frame::Error("ERR failed".to_string()),
+                _ => Frame::Simple("OK".to_string()),
+            };
+            self.0.push(args);
+            reply
+        }
+    }
+
+    fn eval(source: &str, limit: Option<Duration>) -> (Frame, Vec<Vec<Bytes>>) {
+        let program = parse(source.as_bytes()).unwrap();
+        let mut host: Recorder = Recorder(Vec::new());
+        let keys: Vec<Bytes> = vec![Bytes::from("k")];
+        let argv: Vec<Bytes> = vec![Bytes::from("2"), Bytes::from("x")];
+        let reply: Frame = run(&program, &keys, &argv, &mut host, limit);
+        (reply, host.0)
+    }
+
+    #[test]
+    fn evaluates_scripts() {
+        let (reply, calls) = eval(
+            "local n = call('GET', KEYS[1]) + ARGV[1]\ncall('SET', KEYS[1], n)\nreturn n",
+            None,
+        );
+        assert_eq!(reply, Frame::Integer(43));
+        assert_eq!(
+            calls[1],
+            vec![Bytes::from("SET"), Bytes::from("k"), Bytes::from("43")]
+        );
+
+        let (reply, _) = eval(
+            "local t = {} local s = 0\n\
+             for i = 10, 1, -3 do push(t, i) s = s + i end\n\
+             local j = 0 while true do j = j + 1 if j >= 5 then break end end\n\
+             return {t, s, j, -7 % 3, 7 / 2, 'a' .. 1, #ARGV, t[9], 1 < 2 and 'y' or 'n'}",
+            None,
+        );
+        let expected: Frame = Frame::Array(vec![
+            Frame::Array(vec![
+                Frame::Integer(10),
+                Frame::Integer(7),
+                Frame::Integer(4),
+                Frame::Integer(1),
+            ]),
+            Frame::Integer(22),
+            Frame::Integer(5),
+            Frame::Integer(2),
+            Frame::Integer(3),
+            Frame::Bulk(Bytes::from("a1")),
+            Frame::Integer(2),
+            Frame::Null,
+            Frame::Bulk(Bytes::from("y")),
+        ]);
+        assert_eq!(reply, expected);
+    }
+
+    #[test]
+    fn errors_carry_line_numbers_and_reply_errors_propagate() {
+        let (reply, _) = eval("local x = 1\nreturn x + {}", None);
+        assert_eq!(
+            reply,
+            Frame::Error(
+                "ERR Error running script: line 2: attempt to perform arithmetic on a list value"
+                    .to_string()
+            )
+        );
+        let (reply, _) = eval("return 7 % 0", None);
+        assert_eq!(
+            reply,
+            Frame::Error("ERR Error running script: line 1: attempt to perform 'n%0'".to_string())
+        );
+        let (reply, _) = eval("y = 1", None);
+        assert!(matches!(reply, Frame::Error(e) if e.contains("undeclared variable 'y'")));
+        // call 出错中止脚本，pcall 把错误作为值返回
+        let (reply, calls) = eval("call('FAIL') call('SET', 'a', 1)", None);
+        assert_eq!(
+            (reply, calls.len()),
+            (Frame::Error("ERR failed".to_string()), 1)
+        );
+        let (reply, _) = eval("local e = pcall('FAIL') return {type(e), e}", None);
+        assert_eq!(
+            reply,
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("error")),
+                Frame::Error("ERR failed".to_string())
+            ])
+        );
+        let (reply, _) = eval("return status_reply('PONG')", None);
+        assert_eq!(reply, Frame::Simple("PONG".to_string()));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        // 正好在解析的深度限制以内的脚本可以执行
+        let source: String = format!("return {}1{}", "(".repeat(198), ")".repeat(198));
+        assert_eq!(eval(&source, None).0, Frame::Integer(1));
+        let source: String = format!("return 1{}", " + 1".repeat(198));
+        assert_eq!(eval(&source, None).0, Frame::Integer(199));
+
+        for source in [
+            format!("return {}1{}", "(".repeat(200_000), ")".repeat(200_000)),
+            format!("return 1{}", " + 1".repeat(200_000)),
+            format!("return {}1", "- ".repeat(200_000)),
+            format!("return 'a'{}", " .. 'a'".repeat(200_000)),
+            format!("return {}1{}", "{".repeat(200_000), "}".repeat(200_000)),
+            format!(
+                "{}{}",
+                "if true then ".repeat(200_000),
+                "end ".repeat(200_000)
+            ),
+        ] {
+            let err = parse(source.as_bytes()).unwrap_err();
+            assert_eq!(err.msg, "chunk has too many syntax levels");
+        }
+    }
+
+    #[test]
+    fn self_referencing_lists_are_not_converted_forever() {
+        let too_deep = |line: u32| {
+            Frame::Error(format!(
+                "ERR Error running script: line {line}: reply is nested too deeply"
+            ))
+        };
+        assert_eq!(
+            eval("local t = {} push(t, t) return t", None).0,
+            too_deep(1)
+        );
+        assert_eq!(
+            eval("local t = {1}\nt[1] = t\nreturn t", None).0,
+            too_deep(3)
+        );
+        assert_eq!(
+            eval(
+                "local a = {} local b = {} push(a, b) push(b, a) return a",
+                None
+            )
+            .0,
+            too_deep(1)
+        );
+        let source: &str = "local t = {} for i = 1, 1000000 do t = {t} end return t";
+        assert_eq!(eval(source, None).0, too_deep(1));
+        // 深度限制以内的嵌套照常返回
+        let (reply, _) = eval("local t = {} for i = 1, 198 do t = {t} end return t", None);
+        let mut depth: usize = 0;
+        let mut frame: &Frame = &reply;
+        while let Frame::Array(items) = frame {
+            depth += 1;
+            frame = items.first().unwrap_or(&Frame::Null);
+        }
+        assert_eq!(depth, 199);
+    }
+
+    #[test]
+    fn endless_loop_hits_time_limit() {
+        let (reply, _) = eval("while true do end", Some(Duration::from_millis(20)));
+        assert_eq!(
+            reply,
+            Frame::Error("ERR Script killed after exceeding the time limit of 20 ms".to_string())
+        );
+        let (reply, _) = eval("return 1", Some(Duration::from_millis(u64::MAX)));
+        assert_eq!(reply, Frame::Integer(1));
+    }
+}
@@ -0,0 +1,126 @@
+//! 服务端脚本
+//!
+//! 脚本语言是 Lua 的一个小子集（见 `parse`），用 `call(...)` 执行命令。脚本执行期间锁住全部分片，
+//! 中间不会插入其他客户端的命令；传播给日志和副本的是脚本执行过的写命令，用 MULTI/EXEC 包起来，
+//! 所以重放时不需要脚本本身。编译好的脚本按源码的 SHA-1 缓存，EVALSHA 直接用摘要执行。
+
+mod interp;
+mod parse;
+
+pub use interp::{run, Host};
+pub use parse::{parse, Block, SyntaxError};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// 已编译脚本的缓存
+#[derive(Debug, Default)]
+pub struct Scripts {
+    cache: Mutex<HashMap<String, Arc<Block>>>,
+}
+
+impl Scripts {
+    /// 编译脚本并放入缓存，返回它的摘要和编译结果
+    pub fn load(&self, source: &[u8]) -> Result<(String, Arc<Block>), SyntaxError> {
+        let sha: String = sha1_hex(source);
+        if let Some(program) = self.get(&sha) {
+            return Ok((sha, program));
+        }
+        let program: Arc<Block> = Arc::new(parse(source)?);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(sha.clone(), program.clone());
+        Ok((sha, program))
+    }
+
+    /// 按摘要查找，摘要不区分大小写
+    pub fn get(&self, sha: &str) -> Option<Arc<Block>> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&sha.to_ascii_lowercase())
+            .cloned()
+    }
+
+    /// 清空缓存
+    pub fn flush(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+/// SHA-1 摘要的十六进制形式
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1(data).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    // 补一个 1 位和若干 0，使长度模 64 余 56，最后 8 字节是以位计的原始长度
+    let mut message: Vec<u8> = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w: [u32; 80] = [0; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k): (u32, u32) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp: u32 = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest: [u8; 20] = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::sha1_hex;
+
+    #[test]
+    fn sha1_matches_known_digests() {
+        assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // 跨越两个分组的输入
+        assert_eq!(
+            sha1_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        // 与 Redis 中 `SCRIPT LOAD "return 1"` 的结果相同
+        assert_eq!(
+            sha1_hex(b"return 1"),
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db"
+        );
+    }
+}
@@ -0,0 +1,667 @@
+//! 词法和语法分析，把脚本解析成语法树
+//!
+//! 语法是 Lua 的一个子集：`local` 变量、赋值、`if/elseif/else`、`while`、数值 `for`、
+//! `break`、`return`，表达式支持数字、字符串、`{...}` 列表、下标、`#` 长度、算术、`..` 拼接、
+//! 比较和 `and/or/not`。函数只能调用内置函数，名字在解析时就确定。
+
+use bytes::Bytes;
+
+/// 语句序列
+pub type Block = Vec<Stmt>;
+
+/// 一条语句及其所在行号
+#[derive(Debug)]
+pub struct Stmt {
+    pub line: usize,
+    pub kind: StmtKind,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
+    /// `local name = expr`，没有初值时为 nil
+    Local(String, Option<Expr>),
+    /// `name = expr`
+    Assign(String, Expr),
+    /// `list[index] = expr`
+    SetIndex(Expr, Expr, Expr),
+    /// 只为副作用调用的函数
+    Call(Builtin, Vec<Expr>),
+    /// 各个条件分支和可选的 else
+    If(Vec<(Expr, Block)>, Option<Block>),
+    While(Expr, Block),
+    /// `for var = start, stop [, step] do ... end`
+    For {
+        var: String,
+        start: Expr,
+        stop: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    Break,
+    Return(Option<Expr>),
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Bytes),
+    Name(String),
+    List(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Builtin, Vec<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// 解析时的二元运算符，`and`、`or` 短路求值，在语法树里是单独的节点
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Or,
+    And,
+    Binary(BinOp),
+}
+
+impl Operator {
+    /// 优先级，越大结合得越紧
+    fn priority(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Binary(op) => match op {
+                BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 3,
+                BinOp::Concat => 4,
+                BinOp::Add | BinOp::Sub => 5,
+                BinOp::Mul | BinOp::Div | BinOp::Mod => 6,
+            },
+        }
+    }
+}
+
+/// 内置函数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    /// 执行命令，出错时中止脚本
+    Call,
+    /// 执行命令，出错时返回错误值
+    PCall,
+    /// 构造错误回复
+    ErrorReply,
+    /// 构造状态回复
+    StatusReply,
+    ToNumber,
+    ToString,
+    Type,
+    /// 在列表末尾追加
+    Push,
+}
+
+impl Builtin {
+    fn lookup(name: &str) -> Option<Builtin> {
+        Some(match name {
+            "call" | "redis.call" => Builtin::Call,
+            "pcall" | "redis.pcall" => Builtin::PCall,
+            "error_reply" | "redis.error_reply" => Builtin::ErrorReply,
+            "status_reply" | "redis.status_reply" => Builtin::StatusReply,
+            "tonumber" => Builtin::ToNumber,
+            "tostring" => Builtin::ToString,
+            "type" => Builtin::Type,
+            "push" | "table.insert" => Builtin::Push,
+            _ => return None,
+        })
+    }
+}
+
+/// 语法错误，带行号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub msg: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(Vec<u8>),
+    /// 运算符和标点
+    Sym(&'static str),
+    Eof,
+}
+
+/// 按长度从长到短排列，保证优先匹配较长的符号
+const SYMBOLS: &[&str] = &[
+    "..", "==", "~=", "!=", "<=", ">=", "+", "-", "*", "/", "%", "#", "<", ">", "=", "(", ")", "{",
+    "}", "[", "]", ",", ";", ".",
+];
+
+/// 语法树最多嵌套的层数，与 Lua 的限制相同。解析和执行都是递归的，层数不加限制时
+/// 一个嵌套很深的脚本就能让服务端栈溢出
+pub const MAX_DEPTH: usize = 200;
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "if", "local", "nil", "not",
+    "or", "return", "then", "true", "while",
+];
+
+/// 把脚本解析成语法树
+pub fn parse(source: &[u8]) -> Result<Block, SyntaxError> {
+    let tokens: Vec<(Token, usize)> = lex(source)?;
+    let mut parser: Parser = Parser {
+        tokens,
+        pos: 0,
+        depth: 0,
+    };
+    let block: Block = parser.block()?;
+    match parser.peek() {
+        Token::Eof => Ok(block),
+        _ => Err(parser.unexpected()),
+    }
+}
+
+fn lex(src: &[u8]) -> Result<Vec<(Token, usize)>, SyntaxError> {
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut line: usize = 1;
+    let mut i: usize = 0;
+    let err = |line: usize, msg: String| SyntaxError { line, msg };
+    while i < src.len() {
+        let c: u8 = src[i];
+        if c == b'\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+        } else if src[i..].starts_with(b"--") {
+            while i < src.len() && src[i] != b'\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_digit() {
+            let start: usize = i;
+            while i < src.len() && (src[i].is_ascii_alphanumeric() || src[i] == b'.') {
+                // `1..2` 中的 `..` 是拼接运算符
+                if src[i] == b'.' && src.get(i + 1) == Some(&b'.') {
+                    break;
+                }
+                i += 1;
+            }
+            let text: &str = std::str::from_utf8(&src[start..i]).expect("ascii");
+            let token: Token = if let Ok(n) = text.parse::<i64>() {
+                Token::Int(n)
+            } else if let Ok(f) = text.parse::<f64>() {
+                Token::Float(f)
+            } else {
+                return Err(err(line, format!("malformed number near '{text}'")));
+            };
+            tokens.push((token, line));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start: usize = i;
+            while i < src.len() && (src[i].is_ascii_alphanumeric() || src[i] == b'_') {
+                i += 1;
+            }
+            let name: &str = std::str::from_utf8(&src[start..i]).expect("ascii");
+            tokens.push((Token::Name(name.to_string()), line));
+        } else if c == b'"' || c == b'\'' {
+            let start_line: usize = line;
+            let mut s: Vec<u8> = Vec::new();
+            i += 1;
+            loop {
+                match src.get(i) {
+                    None | Some(b'\n') => return Err(err(start_line, "unfinished string".into())),
+                    Some(&q) if q == c => break,
+                    Some(b'\\') => {
+                        i += 1;
+                        s.push(match src.get(i) {
+                            Some(b'n') => b'\n',
+                            Some(b'r') => b'\r',
+                            Some(b't') => b'\t',
+                            Some(b'0') => 0,
+                            Some(&e @ (b'\\' | b'"' | b'\'')) => e,
+                            _ => return Err(err(line, "invalid escape sequence".into())),
+                        });
+                    }
+                    Some(&b) => s.push(b),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push((Token::Str(s), start_line));
+        } else {
+            let sym: &'static str = SYMBOLS
+                .iter()
+                .find(|sym| src[i..].starts_with(sym.as_bytes()))
+                .ok_or_else(|| err(line, format!("unexpected symbol '{}'", c as char)))?;
+            i += sym.len();
+            tokens.push((Token::Sym(sym), line));
+        }
+    }
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// 正在解析的节点在语法树中的深度
+    depth: usize,
+}
+
+impl Parser {
+    /// 进入下一层，超过 `MAX_DEPTH` 时报错。出错时整个解析都会放弃，所以只在成功时退回
+    fn enter(&mut self) -> Result<(), SyntaxError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("chunk has too many syntax levels".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 在下一层解析，返回后退回当前层
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Parser) -> Result<T, SyntaxError>,
+    ) -> Result<T, SyntaxError> {
+        self.enter()?;
+        let parsed: T = parse(self)?;
+        self.depth -= 1;
+        Ok(parsed)
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token: Token = self.tokens[self.pos].0.clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_sym(&self, sym: &str) -> bool {
+        matches!(self.peek(), Token::Sym(s) if *s == sym)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Name(n) if n == keyword)
+    }
+
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        let matched: bool = self.is_sym(sym);
+        if matched {
+            self.advance();
+        }
+        matched
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let matched: bool = self.is_keyword(keyword);
+        if matched {
+            self.advance();
+        }
+        matched
+    }
+
+    fn expect_sym(&mut self, sym: &str) -> Result<(), SyntaxError> {
+        if self.eat_sym(sym) {
+            Ok(())
+        } else {
+            Err(self.error(format!("'{sym}' expected near {}", self.describe())))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SyntaxError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(format!("'{keyword}' expected near {}", self.describe())))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, SyntaxError> {
+        match self.peek() {
+            Token::Name(n) if !KEYWORDS.contains(&n.as_str()) => {
+                let n: String = n.clone();
+                self.advance();
+                Ok(n)
+            }
+            _ => Err(self.error(format!("name expected near {}", self.describe()))),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            Token::Name(n) => format!("'{n}'"),
+            Token::Int(n) => format!("'{n}'"),
+            Token::Float(f) => format!("'{f}'"),
+            Token::Str(_) => "string".to_string(),
+            Token::Sym(s) => format!("'{s}'"),
+            Token::Eof => "<eof>".to_string(),
+        }
+    }
+
+    fn error(&self, msg: String) -> SyntaxError {
+        SyntaxError {
+            line: self.line(),
+            msg,
+        }
+    }
+
+    fn unexpected(&self) -> SyntaxError {
+        self.error(format!("unexpected {}", self.describe()))
+    }
+
+    /// 读到块结束的关键字或文件末尾为止
+    fn block(&mut self) -> Result<Block, SyntaxError> {
+        let mut block: Block = Vec::new();
+        loop {
+            while self.eat_sym(";") {}
+            let ends: bool = matches!(self.peek(), Token::Eof)
+                || ["end", "else", "elseif"].iter().any(|k| self.is_keyword(k));
+            if ends {
+                return Ok(block);
+            }
+            let stmt: Stmt = self.nested(Parser::statement)?;
+            let last: bool = matches!(stmt.kind, StmtKind::Return(_) | StmtKind::Break);
+            block.push(stmt);
+            // return 和 break 必须是块中的最后一条语句
+            if last {
+                while self.eat_sym(";") {}
+                return Ok(block);
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, SyntaxError> {
+        let line: usize = self.line();
+        let kind: StmtKind = if self.eat_keyword("local") {
+            let name: String = self.name()?;
+            let value: Option<Expr> = if self.eat_sym("=") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            StmtKind::Local(name, value)
+        } else if self.eat_keyword("if") {
+            let mut branches: Vec<(Expr, Block)> = Vec::new();
+            let mut otherwise: Option<Block> = None;
+            loop {
+                let cond: Expr = self.expr()?;
+                self.expect_keyword("then")?;
+                branches.push((cond, self.block()?));
+                if self.eat_keyword("elseif") {
+                    continue;
+                }
+                if self.eat_keyword("else") {
+                    otherwise = Some(self.block()?);
+                }
+                self.expect_keyword("end")?;
+                break;
+            }
+            StmtKind::If(branches, otherwise)
+        } else if self.eat_keyword("while") {
+            let cond: Expr = self.expr()?;
+            self.expect_keyword("do")?;
+            let body: Block = self.block()?;
+            self.expect_keyword("end")?;
+            StmtKind::While(cond, body)
+        } else if self.eat_keyword("for") {
+            let var: String = self.name()?;
+            self.expect_sym("=")?;
+            let start: Expr = self.expr()?;
+            self.expect_sym(",")?;
+            let stop: Expr = self.expr()?;
+            let step: Option<Expr> = if self.eat_sym(",") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect_keyword("do")?;
+            let body: Block = self.block()?;
+            self.expect_keyword("end")?;
+            StmtKind::For {
+                var,
+                start,
+                stop,
+                step,
+                body,
+            }
+        } else if self.eat_keyword("break") {
+            StmtKind::Break
+        } else if self.eat_keyword("return") {
+            let ends: bool = matches!(self.peek(), Token::Eof)
+                || self.is_sym(";")
+                || ["end", "else", "elseif"].iter().any(|k| self.is_keyword(k));
+            StmtKind::Return(if ends { None } else { Some(self.expr()?) })
+        } else {
+            // 赋值或函数调用，先按表达式解析，再看是否跟着 `=`
+            match self.suffixed()? {
+                Expr::Call(builtin, args) => StmtKind::Call(builtin, args),
+                target if self.eat_sym("=") => {
+                    let value: Expr = self.expr()?;
+                    match target {
+                        Expr::Name(name) => StmtKind::Assign(name, value),
+                        Expr::Index(list, index) => StmtKind::SetIndex(*list, *index, value),
+                        _ => {
+                            return Err(SyntaxError {
+                                line,
+                                msg: "cannot assign to this expression".into(),
+                            })
+                        }
+                    }
+                }
+                _ => {
+                    return Err(SyntaxError {
+                        line,
+                        msg: "syntax error, expected a statement".into(),
+                    })
+                }
+            }
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn expr(&mut self) -> Result<Expr, SyntaxError> {
+        self.enter()?;
+        let expr: Expr = self.binary(0)?;
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    /// 按优先级爬升解析二元运算，只接受优先级高于 `limit` 的运算符。比起每个优先级一个函数，
+    /// 每层括号占用的栈少得多。左结合的运算在循环里解析，`a + b + c ...` 每多一项语法树就
+    /// 深一层，同样计入层数
+    fn binary(&mut self, limit: u8) -> Result<Expr, SyntaxError> {
+        let start: usize = self.depth;
+        let mut lhs: Expr = self.unary()?;
+        while let Some(op) = self.operator().filter(|op| op.priority() > limit) {
+            self.advance();
+            self.enter()?;
+            // `..` 是右结合的，右边接着吃同一优先级的运算符
+            let rhs: Expr = match op {
+                Operator::Binary(BinOp::Concat) => self.binary(op.priority() - 1)?,
+                _ => self.binary(op.priority())?,
+            };
+            lhs = match op {
+                Operator::Or => Expr::Or(Box::new(lhs), Box::new(rhs)),
+                Operator::And => Expr::And(Box::new(lhs), Box::new(rhs)),
+                Operator::Binary(op) => Expr::Binary(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+        self.depth = start;
+        Ok(lhs)
+    }
+
+    /// 当前位置的二元运算符
+    fn operator(&self) -> Option<Operator> {
+        let op: BinOp = match self.peek() {
+            Token::Name(n) if n == "or" => return Some(Operator::Or),
+            Token::Name(n) if n == "and" => return Some(Operator::And),
+            Token::Sym("==") => BinOp::Eq,
+            Token::Sym("~=") | Token::Sym("!=") => BinOp::Ne,
+            Token::Sym("<") => BinOp::Lt,
+            Token::Sym("<=") => BinOp::Le,
+            Token::Sym(">") => BinOp::Gt,
+            Token::Sym(">=") => BinOp::Ge,
+            Token::Sym("..") => BinOp::Concat,
+            Token::Sym("+") => BinOp::Add,
+            Token::Sym("-") => BinOp::Sub,
+            Token::Sym("*") => BinOp::Mul,
+            Token::Sym("/") => BinOp::Div,
+            Token::Sym("%") => BinOp::Mod,
+            _ => return None,
+        };
+        Some(Operator::Binary(op))
+    }
+
+    fn unary(&mut self) -> Result<Expr, SyntaxError> {
+        let op: UnOp = if self.eat_keyword("not") {
+            UnOp::Not
+        } else if self.eat_sym("-") {
+            UnOp::Neg
+        } else if self.eat_sym("#") {
+            UnOp::Len
+        } else {
+            return self.suffixed();
+        };
+        Ok(Expr::Unary(op, Box::new(self.nested(Parser::unary)?)))
+    }
+
+    /// 基本表达式后面跟着若干下标
+    fn suffixed(&mut self) -> Result<Expr, SyntaxError> {
+        let start: usize = self.depth;
+        let mut expr: Expr = self.primary()?;
+        while self.eat_sym("[") {
+            self.enter()?;
+            let index: Expr = self.expr()?;
+            self.expect_sym("]")?;
+            expr = Expr::Index(Box::new(expr), Box::new(index));
+        }
+        self.depth = start;
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, SyntaxError> {
+        let expr: Expr = match self.peek().clone() {
+            Token::Int(n) => Expr::Int(n),
+            Token::Float(f) => Expr::Float(f),
+            Token::Str(s) => Expr::Str(Bytes::from(s)),
+            Token::Name(n) if n == "nil" => Expr::Nil,
+            Token::Name(n) if n == "true" => Expr::Bool(true),
+            Token::Name(n) if n == "false" => Expr::Bool(false),
+            Token::Sym("(") => {
+                self.advance();
+                let expr: Expr = self.expr()?;
+                self.expect_sym(")")?;
+                return Ok(expr);
+            }
+            Token::Sym("{") => {
+                self.advance();
+                let mut items: Vec<Expr> = Vec::new();
+                while !self.is_sym("}") {
+                    items.push(self.expr()?);
+                    if !self.eat_sym(",") && !self.eat_sym(";") {
+                        break;
+                    }
+                }
+                self.expect_sym("}")?;
+                return Ok(Expr::List(items));
+            }
+            Token::Name(_) => {
+                let mut name: String = self.name()?;
+                // `redis.call` 这样的限定名只用于内置函数
+                while self.eat_sym(".") {
+                    name.push('.');
+                    name.push_str(&self.name()?);
+                }
+                if !self.is_sym("(") {
+                    if name.contains('.') {
+                        return Err(self.error(format!("unknown name '{name}'")));
+                    }
+                    return Ok(Expr::Name(name));
+                }
+                let builtin: Builtin = Builtin::lookup(&name)
+                    .ok_or_else(|| self.error(format!("unknown function '{name}'")))?;
+                self.advance();
+                let mut args: Vec<Expr> = Vec::new();
+                if !self.is_sym(")") {
+                    args.push(self.expr()?);
+                    while self.eat_sym(",") {
+                        args.push(self.expr()?);
+                    }
+                }
+                self.expect_sym(")")?;
+                return Ok(Expr::Call(builtin, args));
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.advance();
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Expr, StmtKind};
+
+    #[test]
+    fn parses_statements_and_reports_lines() {
+        let block = parse(
+            b"local n = tonumber(redis.call('GET', KEYS[1])) or 0 -- comment\n\
+              if n < 10 then n = n + 1 elseif n == 10 then return 'max' end\n\
+              for i = 1, #ARGV do push(t, ARGV[i] .. 'x') end\n\
+              return {n, 1.5, \"a\\nb\"}",
+        )
+        .unwrap();
+        assert_eq!(block.len(), 4);
+        assert!(matches!(&block[0].kind, StmtKind::Local(name, Some(Expr::Or(..))) if name == "n"));
+        assert_eq!(block[2].line, 3);
+        assert!(
+            matches!(&block[3].kind, StmtKind::Return(Some(Expr::List(items))) if items.len() == 3)
+        );
+
+        let err = parse(b"local x = 1\nif x then\nx = 2\n").unwrap_err();
+        assert_eq!(
+            (err.line, err.msg.as_str()),
+            (4, "'end' expected near <eof>")
+        );
+        assert_eq!(
+            parse(b"print(1)").unwrap_err().msg,
+            "unknown function 'print'"
+        );
+        assert!(parse(b"return 1 2").is_err());
+        assert!(parse(b"x = 'open").is_err());
+    }
+}
@@ -0,0 +1,743 @@
+//! 接收连接并处理请求
+//!
+//! 每个监听的端口由一个任务接受连接，连接交给独立的任务处理，TLS 握手也在连接任务里完成。关闭时先停止接收新连接，再通知所有连接任务在处理完
+//! 当前命令后退出，最多等待 `SHUTDOWN_TIMEOUT`，最后把数据落盘。
+
+use crate::aof;
+use crate::blocking::Blocked;
+use crate::clients::{ClientInfo, Mode};
+use crate::cluster;
+use crate::cmd::{self, Outcome};
+use crate::connection::Connection;
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::log;
+use crate::log::Level;
+use crate::net::{Accepted, Incoming, Stream};
+use crate::rdb;
+use crate::replication::Feed;
+use crate::session::Session;
+use crate::state::State;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+
+/// 关闭时等待连接任务退出的最长时间
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 建立连接（TLS 握手）的最长时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 接收连接的循环及其共享的资源
+struct Listener {
+    /// 各个端口接受的连接
+    incoming: mpsc::Receiver<Accepted>,
+    state: Arc<State>,
+    /// 限制同时在线的连接数，每个连接任务持有一个许可
+    limit_connections: Arc<Semaphore>,
+    /// 丢弃发送端即通知所有连接任务关闭
+    notify_shutdown: broadcast::Sender<()>,
+    /// 每个连接任务持有一个克隆，全部丢弃后接收端返回 `None`，说明所有任务都已退出
+    shutdown_complete_tx: mpsc::Sender<()>,
+}
+
+/// 连接任务用来得知服务端正在关闭，或者连接被 `CLIENT KILL` 关闭
+struct Shutdown {
+    shutdown: bool,
+    notify: broadcast::Receiver<()>,
+    client: Arc<ClientInfo>,
+}
+
+/// 在一个 TCP 端口上接收连接直到 `shutdown` 完成，然后优雅地关闭
+pub async fn run(listener: TcpListener, state: Arc<State>, shutdown: impl Future) {
+    run_all(vec![Incoming::Tcp(listener)], state, shutdown).await
+}
+
+/// 同时在多个端口（包括 Unix 套接字）上接收连接，其余同 `run`。退出时删除 Unix 套接字文件
+pub async fn run_all(listeners: Vec<Incoming>, state: Arc<State>, shutdown: impl Future) {
+    let (accepted_tx, incoming) = mpsc::channel(listeners.len().max(1));
+    let sockets: Vec<PathBuf> = listeners.iter().filter_map(Incoming::socket_path).collect();
+    // 随这个函数返回一起被丢弃，接受连接的任务随之取消
+    let mut accepting: JoinSet<()> = JoinSet::new();
+    for listener in listeners {
+        accepting.spawn(accept(listener, accepted_tx.clone()));
+    }
+    drop(accepted_tx);
+
+    let auto_save = tokio::spawn(rdb::auto_save(state.clone()));
+    let everysec = tokio::spawn(aof::everysec(state.clone()));
+    let gossip = tokio::spawn(cluster::gossip(state.clone()));
+
+    let max_clients: usize = state.config().max_clients;
+    let (notify_shutdown, _) = broadcast::channel(1);
+    let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
+    let mut server: Listener = Listener {
+        incoming,
+        limit_connections: Arc::new(Semaphore::new(max_clients)),
+        state,
+        notify_shutdown,
+        shutdown_complete_tx,
+    };
+
+    tokio::select! {
+        _ = server.run() => {}
+        _ = shutdown => log!(Level::Notice, "Received shutdown signal, closing connections"),
+    }
+
+    let Listener {
+        state,
+        notify_shutdown,
+        shutdown_complete_tx,
+        ..
+    } = server;
+    drop(notify_shutdown);
+    drop(shutdown_complete_tx);
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown_complete_rx.recv())
+        .await
+        .is_err()
+    {
+        log!(
+            Level::Warning,
+            "Connections did not finish within {SHUTDOWN_TIMEOUT:?}, shutting down anyway"
+        );
+    }
+    auto_save.abort();
+    everysec.abort();
+    gossip.abort();
+    persist(&state).await;
+    for path in sockets {
+        log!(Level::Notice, "Removing the unix socket file.");
+        if let Err(err) = std::fs::remove_file(&path) {
+            log!(Level::Warning, "Error removing the unix socket file: {err}");
+        }
+    }
+    log!(Level::Notice, "Ready to exit, bye bye...");
+}
+
+/// 退出前把数据落盘：刷新日志，配置了保存规则时再写一次快照
+async fn persist(state: &Arc<State>) {
+    let save: bool = !state.config().save.is_empty();
+    // 进行中的 BGSAVE 会写同一个临时文件，等它结束
+    while save && state.rdb.bgsave_in_progress.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    // 刷新日志和写快照都是阻塞的文件操作，不占用运行时的工作线程
+    let state: Arc<State> = state.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        if let Some(aof) = state.aof.get() {
+            if let Err(err) = aof.sync() {
+                log!(Level::Warning, "Error syncing the AOF file: {err}");
+            }
+        }
+        if !save {
+            return;
+        }
+        log!(
+            Level::Notice,
+            "Saving the final RDB snapshot before exiting."
+        );
+        let guard = state.db.lock_all();
+        if let Err(err) = rdb::save(&guard, &state.config().rdb_path(), &state.rdb) {
+            log!(Level::Warning, "Error saving DB on disk: {err}");
+        }
+    })
+    .await;
+    if let Err(err) = result {
+        log!(Level::Warning, "Error saving DB on disk: {err}");
+    }
+}
+
+impl Listener {
+    /// 循环接收连接，每个连接交给一个独立的任务处理
+    async fn run(&mut self) {
+        while let Some(socket) = self.incoming.recv().await {
+            let permit: OwnedSemaphorePermit =
+                match self.limit_connections.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        self.state
+                            .clients
+                            .rejected_connections
+                            .fetch_add(1, Ordering::Relaxed);
+                        tokio::spawn(reject(socket));
+                        continue;
+                    }
+                };
+            let state: Arc<State> = self.state.clone();
+            let shutdown: Shutdown = Shutdown {
+                shutdown: false,
+                notify: self.notify_shutdown.subscribe(),
+                client: state.clients.register(socket.peer_addr()),
+            };
+            let shutdown_complete: mpsc::Sender<()> = self.shutdown_complete_tx.clone();
+
+            if let Some(peer) = socket.peer_addr() {
+                log!(Level::Verbose, "Accepted {peer}");
+            }
+            tokio::spawn(async move {
+                process(socket, state, shutdown).await;
+                // 连接结束后才归还许可和关闭通知
+                drop(permit);
+                drop(shutdown_complete);
+            });
+        }
+    }
+}
+
+/// 在一个端口上接受连接，交给 `Listener` 处理。出错（例如文件描述符耗尽）时退避重试，
+/// 而不是让服务端退出
+async fn accept(listener: Incoming, accepted: mpsc::Sender<Accepted>) {
+    let mut backoff: u64 = 1;
+    loop {
+        match listener.accept().await {
+            Ok(socket) => {
+                backoff = 1;
+                if accepted.send(socket).await.is_err() {
+                    return;
+                }
+            }
+            Err(err) => {
+                log!(Level::Warning, "Accepting client connection: {err}");
+                tokio::time::sleep(Duration::from_secs(backoff)).await;
+                backoff = (backoff * 2).min(64);
+            }
+        }
+    }
+}
+
+/// 完成握手，超时或失败时返回错误
+async fn establish(socket: Accepted) -> io::Result<Stream> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, socket.establish())
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "handshake timed out",
+            ))
+        })
+}
+
+/// 连接数已满，告知客户端后关闭连接
+async fn reject(socket: Accepted) {
+    let Ok(socket) = establish(socket).await else {
+        return;
+    };
+    let mut connection: Connection = Connection::new(socket);
+    let frame: Frame = Frame::Error("ERR max number of clients reached".to_string());
+    let _ = connection.write_frame(&frame).await;
+}
+
+impl Shutdown {
+    /// 是否应当关闭连接
+    fn is_shutdown(&self) -> bool {
+        self.shutdown || self.client.is_killed()
+    }
+
+    /// 等待关闭通知
+    async fn recv(&mut self) {
+        if self.is_shutdown() {
+            return;
+        }
+        tokio::select! {
+            // 发送端被丢弃时返回错误，这正是关闭的信号
+            _ = self.notify.recv() => {}
+            _ = self.client.killed() => {}
+        }
+        self.shutdown = true;
+    }
+}
+
+async fn process(socket: Accepted, state: Arc<State>, mut shutdown: Shutdown) {
+    let socket: Stream = match establish(socket).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            log!(
+                Level::Verbose,
+                "Closing connection after failed handshake: {err}"
+            );
+            state.clients.unregister(shutdown.client.id);
+            return;
+        }
+    };
+    let mut connection: Connection = Connection::new(socket);
+    let mut session: Session = Session::new();
+    session.addr = connection.peer_addr();
+    session.id = shutdown.client.id;
+    session.user = state.acl.auto_login();
+    match serve(&mut connection, &state, &mut session, &mut shutdown).await {
+        Ok(()) => {}
+        Err(Error::Protocol(err)) => {
+            // 之后的数据无法再对齐到帧的边界，回复错误后关闭连接
+            log!(
+                Level::Verbose,
+                "Closing connection after protocol error: {err}"
+            );
+            let _ = connection
+                .write_frame(&Frame::Error(format!("ERR {err}")))
+                .await;
+        }
+        Err(Error::Io(err)) => log!(Level::Verbose, "Closing connection after I/O error: {err}"),
+    }
+    session.close(&state.db);
+    state.clients.unregister(session.id);
+}
+
+async fn serve(
+    connection: &mut Connection,
+    state: &Arc<State>,
+    session: &mut Session,
+    shutdown: &mut Shutdown,
+) -> Result<()> {
+    // 阻塞期间客户端继续发来的命令，解除阻塞后按顺序处理
+    let mut pending: VecDeque<Frame> = VecDeque::new();
+    let client: Arc<ClientInfo> = shutdown.client.clone();
+
+    // 关闭只在两条命令之间生效，正在执行的命令总能写完回复
+    while !shutdown.is_shutdown() {
+        let frame: Frame = match pending.pop_front() {
+            Some(frame) => frame,
+            None => {
+                let frame: Option<Frame> = tokio::select! {
+                    frame = connection.read_frame() => frame?,
+                    _ = shutdown.recv() => return Ok(()),
+                };
+                match frame {
+                    Some(frame) => frame,
+                    None => return Ok(()),
+                }
+            }
+        };
+        let response: Frame = match cmd::parse_args(frame) {
+            Ok(args) => {
+                client.begin(&args);
+                let outcome: Outcome = cmd::dispatch(state, session, &args);
+                client.end(session);
+                match outcome {
+                    Outcome::Reply(frame) => frame,
+                    Outcome::Block(blocked) => {
+                        client.set_mode(Mode::Blocked);
+                        let result = wait(state, connection, &mut pending, shutdown, blocked).await;
+                        client.set_mode(Mode::Normal);
+                        match result? {
+                            Some(frame) => frame,
+                            // 等待期间连接已经关闭或服务端正在关闭
+                            None => return Ok(()),
+                        }
+                    }
+                    Outcome::Sync(feed) => {
+                        client.set_mode(Mode::Replica);
+                        return replicate(connection, state, shutdown, feed).await;
+                    }
+                    Outcome::Monitor(rx) => {
+                        client.set_mode(Mode::Monitor);
+                        connection
+                            .write_frame(&Frame::Simple("OK".to_string()))
+                            .await?;
+                        return monitor(connection, shutdown, rx).await;
+                    }
+                }
+            }
+            Err(err) => err,
+        };
+
+        connection.set_protocol(session.protocol);
+        connection.write_frame(&response).await?;
+    }
+    Ok(())
+}
+
+/// 连接来自副本：先写出同步的内容，再一直转发命令流，同时读取副本报告的偏移量
+async fn replicate(
+    connection: &mut Connection,
+    state: &State,
+    shutdown: &mut Shutdown,
+    mut feed: Feed,
+) -> Result<()> {
+    let result: Result<()> = async {
+        connection.write_raw(&feed.preamble).await?;
+        loop {
+            tokio::select! {
+                data = feed.rx.recv() => match data {
+                    Some(data) => connection.write_raw(&data).await?,
+                    // 副本跟不上命令流，已经被断开
+                    None => return Ok(()),
+                },
+                frame = connection.read_frame() => match frame? {
+                    Some(frame) => {
+                        // 副本只会发来 REPLCONF ACK offset
+                        let args: Vec<Bytes> = cmd::parse_args(frame).unwrap_or_default();
+                        if let [name, option, offset] = args.as_slice() {
+                            if name.eq_ignore_ascii_case(b"replconf")
+                                && option.eq_ignore_ascii_case(b"ack")
+                            {
+                                if let Some(offset) = std::str::from_utf8(offset)
+                                    .ok()
+                                    .and_then(|offset| offset.parse().ok())
+                                {
+                                    state.repl.ack(feed.id, offset);
+                                }
+                            }
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+    }
+    .await;
+    state.repl.detach(feed.id);
+    result
+}
+
+/// 连接执行了 MONITOR：把其他客户端执行的命令逐行发出，直到客户端断开。之后发来的命令被忽略
+async fn monitor(
+    connection: &mut Connection,
+    shutdown: &mut Shutdown,
+    mut rx: mpsc::Receiver<String>,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            line = rx.recv() => match line {
+                Some(line) => connection.write_frame(&Frame::Simple(line)).await?,
+                // 跟不上输出，已经被移除
+                None => return Ok(()),
+            },
+            frame = connection.read_frame() => if frame?.is_none() {
+                return Ok(());
+            },
+            _ = shutdown.recv() => return Ok(()),
+        }
+    }
+}
+
+/// 等待阻塞命令的结果，同时继续读取连接以便及时发现客户端断开
+async fn wait(
+    state: &State,
+    connection: &mut Connection,
+    pending: &mut VecDeque<Frame>,
+    shutdown: &mut Shutdown,
+    mut blocked: Blocked,
+) -> Result<Option<Frame>> {
+    let sleep = async {
+        match blocked.timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(sleep);
+
+    let result: Result<Option<Frame>> = loop {
+        tokio::select! {
+            // 优先取已经送达的数据，避免数据已交付却因为连接事件被丢弃
+            biased;
+            reply = &mut blocked.rx => {
+                break Ok(Some(reply.unwrap_or(Frame::Null)));
+            }
+            _ = &mut sleep => break Ok(Some(Frame::Null)),
+            _ = shutdown.recv() => break Ok(None),
+            frame = connection.read_frame() => match frame {
+                Ok(Some(frame)) => pending.push_back(frame),
+                Ok(None) => break Ok(None),
+                Err(err) => break Err(err),
+            },
+        }
+    };
+    // 关闭接收端后，之后写入的数据会留在原处
+    blocked.rx.close();
+    state.blocking.unblock(&blocked);
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::run;
+    use crate::client::Client;
+    use crate::cmd::test::{render, state};
+    use crate::config::Config;
+    use crate::connection::Connection;
+    use crate::frame::{Frame, Protocol};
+    use crate::state::State;
+    use bytes::Bytes;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket() {
+        use super::run_all;
+        use crate::net::Incoming;
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::{Path, PathBuf};
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("my_redis-{}.sock", std::process::id()));
+        // 上次没有清理掉的套接字文件不妨碍重新监听
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let incoming: Incoming = Incoming::unix(&path, 0o700).unwrap();
+        let mode: u32 = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(run_all(vec![incoming], state(2), stopped));
+
+        let url: String = format!("unix://{}", path.display());
+        let client: Client = Client::connect(&url).await.unwrap();
+        client.set("k", "v").await.unwrap();
+        assert_eq!(client.get("k").await.unwrap(), Some(Bytes::from("v")));
+        let list: String = client.client_list().await.unwrap();
+        assert!(list.contains("addr= "), "{list}");
+
+        // 退出时删除套接字文件
+        drop(client);
+        stop.send(()).unwrap();
+        server.await.unwrap();
+        assert!(!path.exists());
+        assert!(Incoming::unix(Path::new("/nonexistent/my_redis.sock"), 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn concurrent_clients() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, state(4), std::future::pending::<()>()));
+
+        let mut tasks = Vec::new();
+        for i in 0..8 {
+            tasks.push(tokio::spawn(async move {
+                let client: Client = Client::connect(&addr.to_string()).await.unwrap();
+                for j in 0..50 {
+                    let key: String = format!("{i}:{j}");
+                    client.set(&key, &key).await.unwrap();
+                    let value = client.get(&key).await.unwrap().unwrap();
+                    assert_eq!(&value[..], key.as_bytes());
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+    }
+
+    fn command(args: &[&str]) -> Frame {
+        Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn blpop_waits_for_push() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, state(4), std::future::pending::<()>()));
+
+        let mut waiter = Connection::new(TcpStream::connect(addr).await.unwrap());
+        waiter
+            .write_frame(&command(&["BLPOP", "jobs", "0"]))
+            .await
+            .unwrap();
+        // 阻塞期间发来的命令在解除阻塞后处理
+        waiter.write_frame(&command(&["PING"])).await.unwrap();
+
+        let mut pusher = Connection::new(TcpStream::connect(addr).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        pusher
+            .write_frame(&command(&["RPUSH", "jobs", "j1"]))
+            .await
+            .unwrap();
+        pusher.read_frame().await.unwrap();
+
+        let popped = waiter.read_frame().await.unwrap().unwrap();
+        assert_eq!(render(&popped), "[jobs j1]");
+        let pong = waiter.read_frame().await.unwrap().unwrap();
+        assert_eq!(render(&pong), "PONG");
+
+        waiter
+            .write_frame(&command(&["BRPOP", "jobs", "0.05"]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            waiter.read_frame().await.unwrap(),
+            Some(Frame::Null)
+        ));
+    }
+
+    #[tokio::test]
+    async fn transaction_with_watch() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, state(4), std::future::pending::<()>()));
+
+        let mut conn = Connection::new(TcpStream::connect(addr).await.unwrap());
+        let mut other = Connection::new(TcpStream::connect(addr).await.unwrap());
+        for (args, expected) in [
+            (&["WATCH", "balance"][..], "OK"),
+            (&["MULTI"][..], "OK"),
+            (&["SET", "balance", "10"][..], "QUEUED"),
+            (&["RPUSH", "log", "a", "b"][..], "QUEUED"),
+            (&["LRANGE", "log", "0", "-1"][..], "QUEUED"),
+            (&["EXEC"][..], "[OK 2 [a b]]"),
+            (&["WATCH", "balance"][..], "OK"),
+        ] {
+            conn.write_frame(&command(args)).await.unwrap();
+            let frame = conn.read_frame().await.unwrap().unwrap();
+            assert_eq!(render(&frame), expected);
+        }
+
+        other
+            .write_frame(&command(&["SET", "balance", "0"]))
+            .await
+            .unwrap();
+        other.read_frame().await.unwrap();
+        for args in [&["MULTI"][..], &["SET", "balance", "20"][..]] {
+            conn.write_frame(&command(args)).await.unwrap();
+            conn.read_frame().await.unwrap();
+        }
+        conn.write_frame(&command(&["EXEC"])).await.unwrap();
+        assert!(matches!(
+            conn.read_frame().await.unwrap(),
+            Some(Frame::Null)
+        ));
+    }
+
+    #[tokio::test]
+    async fn hello_negotiates_resp3() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, state(4), std::future::pending::<()>()));
+
+        let mut conn = Connection::new(TcpStream::connect(addr).await.unwrap());
+        for args in [&["HSET", "h", "f", "v"][..], &["ZADD", "z", "1.5", "m"][..]] {
+            conn.write_frame(&command(args)).await.unwrap();
+            conn.read_frame().await.unwrap();
+        }
+        // RESP2 下映射展开成数组，浮点数是字符串
+        conn.write_frame(&command(&["HGETALL", "h"])).await.unwrap();
+        assert_eq!(render(&conn.read_frame().await.unwrap().unwrap()), "[f v]");
+
+        conn.write_frame(&command(&["HELLO", "3"])).await.unwrap();
+        assert!(matches!(
+            conn.read_frame().await.unwrap(),
+            Some(Frame::Map(_))
+        ));
+        conn.set_protocol(Protocol::Resp3);
+        conn.write_frame(&command(&["HGETALL", "h"])).await.unwrap();
+        assert_eq!(render(&conn.read_frame().await.unwrap().unwrap()), "{f v}");
+        conn.write_frame(&command(&["ZSCORE", "z", "m"]))
+            .await
+            .unwrap();
+        assert_eq!(conn.read_frame().await.unwrap(), Some(Frame::Double(1.5)));
+        conn.write_frame(&command(&["GET", "missing"]))
+            .await
+            .unwrap();
+        assert_eq!(conn.read_frame().await.unwrap(), Some(Frame::Null));
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_connections() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(run(listener, state(2), stopped));
+
+        let mut idle = Connection::new(TcpStream::connect(addr).await.unwrap());
+        let mut blocked = Connection::new(TcpStream::connect(addr).await.unwrap());
+        idle.write_frame(&command(&["SET", "k", "v"]))
+            .await
+            .unwrap();
+        idle.read_frame().await.unwrap();
+        blocked
+            .write_frame(&command(&["BLPOP", "q", "0"]))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap();
+        // 两个连接都被服务端关闭
+        assert!(matches!(idle.read_frame().await, Ok(None) | Err(_)));
+        assert!(matches!(blocked.read_frame().await, Ok(None) | Err(_)));
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn max_clients_rejects_excess_connections() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let state = State::new(Config {
+            save: Vec::new(),
+            max_clients: 1,
+            ..Config::default()
+        });
+        tokio::spawn(run(listener, state, std::future::pending::<()>()));
+
+        let mut first = Connection::new(TcpStream::connect(addr).await.unwrap());
+        first.write_frame(&command(&["PING"])).await.unwrap();
+        assert_eq!(render(&first.read_frame().await.unwrap().unwrap()), "PONG");
+
+        let mut second = Connection::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(
+            render(&second.read_frame().await.unwrap().unwrap()),
+            "!ERR max number of clients reached"
+        );
+        assert!(matches!(second.read_frame().await, Ok(None) | Err(_)));
+
+        // 第一个连接断开后名额空出来
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut third = Connection::new(TcpStream::connect(addr).await.unwrap());
+        third.write_frame(&command(&["PING"])).await.unwrap();
+        assert_eq!(render(&third.read_frame().await.unwrap().unwrap()), "PONG");
+    }
+
+    #[tokio::test]
+    async fn monitor_and_client_kill() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, state(2), std::future::pending::<()>()));
+
+        let mut monitor = Connection::new(TcpStream::connect(addr).await.unwrap());
+        monitor.write_frame(&command(&["MONITOR"])).await.unwrap();
+        assert_eq!(render(&monitor.read_frame().await.unwrap().unwrap()), "OK");
+
+        let mut conn = Connection::new(TcpStream::connect(addr).await.unwrap());
+        conn.write_frame(&command(&["SET", "k", "a b"]))
+            .await
+            .unwrap();
+        conn.read_frame().await.unwrap();
+        let line: String = render(&monitor.read_frame().await.unwrap().unwrap());
+        assert!(line.ends_with(r#"] "SET" "k" "a b""#), "{line}");
+
+        conn.write_frame(&command(&["CLIENT", "LIST"]))
+            .await
+            .unwrap();
+        let list: String = render(&conn.read_frame().await.unwrap().unwrap());
+        assert!(list.contains("flags=O "), "{list}");
+        assert!(list.contains("cmd=client user=default\n"), "{list}");
+
+        // 关闭 MONITOR 连接，自己的连接不受影响
+        conn.write_frame(&command(&["CLIENT", "KILL", "ID", "1"]))
+            .await
+            .unwrap();
+        assert_eq!(render(&conn.read_frame().await.unwrap().unwrap()), "1");
+        // 先收到 CLIENT 命令本身的输出，然后连接被关闭
+        while let Ok(Some(frame)) = monitor.read_frame().await {
+            assert!(render(&frame).contains(r#""CLIENT""#));
+        }
+        conn.write_frame(&command(&["PING"])).await.unwrap();
+        assert_eq!(render(&conn.read_frame().await.unwrap().unwrap()), "PONG");
+    }
+}
@@ -0,0 +1,89 @@
+//! 连接级别的状态
+//!
+//! 协议版本、选中的数据库、事务队列和 WATCH 的 key 属于单个连接，不放在共享的 `State` 里。
+
+use crate::db::{Db, Guard};
+use crate::frame::Protocol;
+use bytes::Bytes;
+use std::net::SocketAddr;
+
+/// 一个客户端连接的会话状态
+#[derive(Debug, Default)]
+pub struct Session {
+    /// 连接在登记表中的编号，日志重放等内部会话为 0
+    pub id: u64,
+    /// `HELLO` 协商的协议版本
+    pub protocol: Protocol,
+    /// `SELECT` 选中的逻辑数据库
+    pub db: usize,
+    /// `HELLO ... SETNAME` 设置的连接名
+    pub name: Option<String>,
+    /// 已经认证的 ACL 用户，`None` 表示还没有认证。日志重放等内部会话不经过权限检查
+    pub user: Option<String>,
+    /// 客户端的地址，日志重放等内部会话没有地址
+    pub addr: Option<SocketAddr>,
+    /// 副本用 `REPLCONF listening-port` 告知的监听端口
+    pub listening_port: Option<u16>,
+    /// MULTI 之后排队等待 EXEC 的命令，`None` 表示不在事务中
+    pub multi: Option<Vec<Vec<Bytes>>>,
+    /// 排队时有命令出错，EXEC 会放弃整个事务
+    pub multi_failed: bool,
+    /// WATCH 的 key
+    pub watched: Vec<Watched>,
+    /// 执行过 `ASKING`，下一条命令可以访问本节点正在迁入的槽
+    pub asking: bool,
+}
+
+/// 一个被 WATCH 的 key 以及监视开始时的状态
+#[derive(Debug)]
+pub struct Watched {
+    /// key 所在的数据库
+    pub db: usize,
+    pub key: String,
+    /// 开始监视时的版本号
+    pub version: u64,
+    /// 开始监视时 key 是否存在，用于发现期间过期的 key
+    pub existed: bool,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// 是否在 MULTI 和 EXEC 之间
+    pub fn in_multi(&self) -> bool {
+        self.multi.is_some()
+    }
+
+    /// 取消所有 WATCH，调用方持有这些 key 所在分片的锁
+    pub fn unwatch(&mut self, db: &mut Guard<'_>) {
+        let selected: usize = db.selected();
+        for watched in self.watched.drain(..) {
+            db.select(watched.db);
+            db.unwatch(&watched.key);
+        }
+        db.select(selected);
+    }
+
+    /// 连接关闭时释放会话占用的共享资源
+    pub fn close(&mut self, db: &Db) {
+        if self.watched.is_empty() {
+            return;
+        }
+        let keys: Vec<String> = self.watched.iter().map(|w| w.key.clone()).collect();
+        let mut guard: Guard<'_> = db.lock_keys(&keys);
+        self.unwatch(&mut guard);
+    }
+}
+
+impl Watched {
+    /// 开始监视之后 key 是否被修改过或已经过期
+    pub fn changed(&self, db: &mut Guard<'_>) -> bool {
+        let selected: usize = db.select(self.db);
+        let changed: bool =
+            db.version(&self.key) != self.version || (self.existed && db.get(&self.key).is_none());
+        db.select(selected);
+        changed
+    }
+}
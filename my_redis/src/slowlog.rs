@@ -0,0 +1,155 @@
+//! 慢查询日志
+//!
+//! 执行时间达到 `slowlog-log-slower-than` 微秒的命令记入一个环形缓冲区，最多保留
+//! `slowlog-max-len` 条。和 Redis 一样只统计命令本身的执行时间，不包括网络读写和阻塞等待。
+
+use crate::session::Session;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 每条记录最多保留的参数个数
+const MAX_ARGS: usize = 32;
+/// 每个参数最多保留的字节数
+const MAX_ARG_LEN: usize = 128;
+
+/// 慢查询日志
+#[derive(Debug, Default)]
+pub struct Slowlog {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// 新的记录在前
+    entries: VecDeque<Entry>,
+    next_id: u64,
+}
+
+/// 一条慢查询记录
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: u64,
+    /// 记录时的 Unix 时间戳（秒）
+    pub time: u64,
+    /// 执行时间（微秒）
+    pub duration: u64,
+    /// 命令和参数，过长的部分被截断
+    pub args: Vec<Bytes>,
+    pub addr: String,
+    pub name: String,
+}
+
+impl Slowlog {
+    /// 执行时间达到 `threshold` 微秒时记录命令，`threshold` 为负数时不记录
+    pub fn record(
+        &self,
+        session: &Session,
+        args: &[Bytes],
+        elapsed: Duration,
+        threshold: i64,
+        max_len: usize,
+    ) {
+        if threshold < 0 || elapsed.as_micros() < threshold as u128 {
+            return;
+        }
+        let mut kept: Vec<Bytes> = args
+            .iter()
+            .take(if args.len() > MAX_ARGS {
+                MAX_ARGS - 1
+            } else {
+                MAX_ARGS
+            })
+            .map(|arg| {
+                if arg.len() <= MAX_ARG_LEN {
+                    return arg.clone();
+                }
+                let mut short: Vec<u8> = arg[..MAX_ARG_LEN].to_vec();
+                short.extend_from_slice(
+                    format!("... ({} more bytes)", arg.len() - MAX_ARG_LEN).as_bytes(),
+                );
+                Bytes::from(short)
+            })
+            .collect();
+        if args.len() > MAX_ARGS {
+            kept.push(Bytes::from(format!(
+                "... ({} more arguments)",
+                args.len() - MAX_ARGS + 1
+            )));
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let entry: Entry = Entry {
+            id: inner.next_id,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration: elapsed.as_micros() as u64,
+            args: kept,
+            addr: session
+                .addr
+                .map_or_else(String::new, |addr| addr.to_string()),
+            name: session.name.clone().unwrap_or_default(),
+        };
+        inner.next_id += 1;
+        inner.entries.push_front(entry);
+        inner.entries.truncate(max_len);
+    }
+
+    /// 最新的 `count` 条记录
+    pub fn get(&self, count: usize) -> Vec<Entry> {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.iter().take(count).cloned().collect()
+    }
+
+    /// 当前的记录数
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// 是否没有记录
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 清空记录，编号继续递增
+    pub fn reset(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Slowlog;
+    use crate::session::Session;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    #[test]
+    fn keeps_newest_entries_and_truncates_arguments() {
+        let slowlog: Slowlog = Slowlog::default();
+        let session: Session = Session::new();
+        let fast: Duration = Duration::from_micros(5);
+        slowlog.record(&session, &[Bytes::from("GET")], fast, 10, 2);
+        slowlog.record(&session, &[Bytes::from("GET")], fast, -1, 2);
+        assert!(slowlog.is_empty());
+
+        let args: Vec<Bytes> = (0..40).map(|i| Bytes::from(i.to_string())).collect();
+        slowlog.record(&session, &args, fast, 0, 2);
+        slowlog.record(&session, &[Bytes::from(vec![b'x'; 200])], fast, 0, 2);
+        slowlog.record(&session, &[Bytes::from("PING")], fast, 0, 2);
+        let entries = slowlog.get(10);
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].id, entries[1].id), (2, 1));
+        assert_eq!(entries[0].args, vec!["PING"]);
+        assert!(entries[1].args[0].ends_with(b"... (72 more bytes)"));
+
+        slowlog.reset();
+        slowlog.record(&session, &args, fast, 0, 2);
+        let entry = &slowlog.get(1)[0];
+        assert_eq!(entry.id, 3);
+        assert_eq!(entry.args.len(), 32);
+        assert_eq!(entry.args[31], "... (9 more arguments)");
+    }
+}
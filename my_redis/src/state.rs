@@ -0,0 +1,115 @@
+use crate::acl::Acl;
+use crate::aof::Aof;
+use crate::blocking::Blocking;
+use crate::clients::Clients;
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::db::Db;
+use crate::log;
+use crate::metrics::Metrics;
+use crate::rdb;
+use crate::replication::Replication;
+use crate::script::Scripts;
+use crate::slowlog::Slowlog;
+use bytes::Bytes;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, OnceLock, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+/// 服务端共享状态，所有连接通过 `Arc<State>` 访问
+#[derive(Debug)]
+pub struct State {
+    /// 数据库
+    pub db: Db,
+    /// 配置，部分配置项可以在运行时修改
+    config: RwLock<Config>,
+    /// 快照持久化状态
+    pub rdb: Arc<rdb::Status>,
+    /// 追加写日志，加载完数据后才打开，避免重放的命令再次写入
+    pub aof: OnceLock<Arc<Aof>>,
+    /// 阻塞在列表上的客户端
+    pub blocking: Blocking,
+    /// 因超出内存上限被淘汰的 key 数
+    pub evicted_keys: AtomicU64,
+    /// 主从复制
+    pub repl: Replication,
+    /// 已编译脚本的缓存
+    pub scripts: Scripts,
+    /// 已连接的客户端
+    pub clients: Clients,
+    /// 慢查询日志
+    pub slowlog: Slowlog,
+    /// 按命令统计的执行次数和耗时
+    pub metrics: Metrics,
+    /// ACL 用户
+    pub acl: Acl,
+    /// 集群状态，没有开启集群模式时为 `None`
+    pub cluster: Option<Cluster>,
+    /// 启动时间
+    pub started: Instant,
+}
+
+impl State {
+    /// 按配置创建一个空的服务端状态
+    pub fn new(config: Config) -> Arc<State> {
+        let acl: Acl = Acl::new(&config.requirepass, &config.users)
+            .expect("user rules are checked when the config is loaded");
+        let cluster: Option<Cluster> = config.cluster_enabled.then(|| {
+            Cluster::new(
+                &config.announce_ip(),
+                config.port,
+                Duration::from_millis(config.cluster_node_timeout),
+            )
+        });
+        Arc::new(State {
+            db: Db::new(config.shards, config.databases),
+            repl: Replication::new(config.repl_backlog_size),
+            config: RwLock::new(config),
+            rdb: Arc::new(rdb::Status::default()),
+            aof: OnceLock::new(),
+            blocking: Blocking::default(),
+            evicted_keys: AtomicU64::new(0),
+            scripts: Scripts::default(),
+            clients: Clients::default(),
+            slowlog: Slowlog::default(),
+            metrics: Metrics::default(),
+            acl,
+            cluster,
+            started: Instant::now(),
+        })
+    }
+
+    /// 当前配置
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().unwrap()
+    }
+
+    /// `CONFIG SET`：修改配置并让它立即生效
+    pub fn set_config(&self, name: &str, value: &str) -> Result<(), String> {
+        let mut config = self.config.write().unwrap();
+        config.set(name, value)?;
+        log::set_level(config.loglevel);
+        if let Some(aof) = self.aof.get() {
+            aof.set_policy(config.appendfsync);
+        }
+        self.repl.set_backlog_size(config.repl_backlog_size);
+        if let Some(cluster) = &self.cluster {
+            cluster.set_node_timeout(Duration::from_millis(config.cluster_node_timeout));
+        }
+        if name.eq_ignore_ascii_case("requirepass") {
+            self.acl.set_requirepass(&config.requirepass);
+        }
+        Ok(())
+    }
+
+    /// 把在数据库 `db` 上执行的写命令传播出去，调用方持有命令涉及分片的锁
+    pub fn propagate(&self, db: usize, commands: &[Vec<Bytes>]) {
+        if commands.is_empty() {
+            return;
+        }
+        if let Some(aof) = self.aof.get() {
+            aof.append(db, commands);
+        }
+        self.repl.propagate(db, commands);
+    }
+}
@@ -0,0 +1,479 @@
+//! 流
+//!
+//! 条目按 ID 保存在 `BTreeMap` 里，ID 是 `毫秒时间戳-序号`，严格递增。
+//! 消费组记录已经分发到的位置和待确认条目（PEL），每个待确认条目属于组里的一个消费者，
+//! 带着最近一次分发的时间和分发次数，XACK 后移出，XCLAIM 可以把它转给别的消费者。
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+
+/// 条目 ID
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+/// 流
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    /// 条目，值是依次排列的字段和值
+    entries: BTreeMap<StreamId, Vec<Bytes>>,
+    /// 添加过的最大 ID，条目被删除后也不会回退
+    last_id: StreamId,
+    /// 消费组，按名字排列
+    groups: BTreeMap<Bytes, Group>,
+}
+
+/// 消费组
+#[derive(Debug, Clone, Default)]
+pub struct Group {
+    /// 已经分发过的最大 ID
+    pub last_delivered: StreamId,
+    /// 分发出去还没有确认的条目
+    pub pending: BTreeMap<StreamId, Pending>,
+    /// 消费者及其最近一次活动的时间（Unix 毫秒）
+    pub consumers: BTreeMap<Bytes, u64>,
+}
+
+/// 一个待确认条目
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    pub consumer: Bytes,
+    /// 最近一次分发的时间（Unix 毫秒）
+    pub delivered_at: u64,
+    /// 分发次数
+    pub deliveries: u64,
+}
+
+/// XCLAIM 的选项
+#[derive(Debug, Clone, Default)]
+pub struct Claim {
+    /// 只转移空闲至少这么久（毫秒）的条目
+    pub min_idle: u64,
+    /// 转移后的分发时间，`None` 表示现在
+    pub delivered_at: Option<u64>,
+    /// 直接设置分发次数
+    pub retry_count: Option<u64>,
+    /// 条目不在 PEL 里但还在流中时也加入 PEL
+    pub force: bool,
+    /// 只返回 ID，不增加分发次数
+    pub justid: bool,
+}
+
+/// 阻塞的 XREAD/XREADGROUP 在一个流上等待读取的内容
+#[derive(Debug, Clone)]
+pub enum Read {
+    /// XREAD：ID 大于它的条目
+    After(StreamId),
+    /// XREADGROUP ... >：组里还没有分发过的条目
+    Group {
+        group: Bytes,
+        consumer: Bytes,
+        noack: bool,
+    },
+}
+
+/// 一个条目：ID 和字段，字段为 `None` 表示条目已经被删除（只会出现在 PEL 的历史里）
+pub type Item = (StreamId, Option<Vec<Bytes>>);
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    /// 解析 `ms-seq`，省略序号时取 `default_seq`
+    pub fn parse(arg: &[u8], default_seq: u64) -> Option<StreamId> {
+        let s: &str = std::str::from_utf8(arg).ok()?;
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (s.parse().ok()?, default_seq),
+        };
+        Some(StreamId { ms, seq })
+    }
+
+    /// 紧接着的下一个 ID，已经是最大值时返回 `None`
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// 设置添加过的最大 ID，只用于加载快照
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = self.last_id.max(id);
+    }
+
+    /// 在时刻 `now` 自动生成的 ID：时间戳没有前进时沿用上一个 ID 的时间戳并递增序号
+    pub fn next_id(&self, now: u64) -> Option<StreamId> {
+        if now > self.last_id.ms {
+            Some(StreamId::new(now, 0))
+        } else {
+            self.last_id.next()
+        }
+    }
+
+    /// 添加条目，ID 必须大于添加过的最大 ID（空流是 `0-0`）
+    pub fn add(&mut self, id: StreamId, fields: Vec<Bytes>) -> bool {
+        if id <= self.last_id {
+            return false;
+        }
+        self.last_id = id;
+        self.entries.insert(id, fields);
+        true
+    }
+
+    /// 删除条目，返回它是否存在
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        self.entries.remove(&id).is_some()
+    }
+
+    /// 从最早的条目开始删除，直到只剩 `maxlen` 个，返回删除的个数
+    pub fn trim(&mut self, maxlen: usize) -> usize {
+        let mut removed: usize = 0;
+        while self.entries.len() > maxlen {
+            self.entries.pop_first();
+            removed += 1;
+        }
+        removed
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<&Vec<Bytes>> {
+        self.entries.get(&id)
+    }
+
+    /// 按 ID 升序遍历所有条目
+    pub fn iter(&self) -> impl Iterator<Item = (&StreamId, &Vec<Bytes>)> {
+        self.entries.iter()
+    }
+
+    /// 取 ID 在区间内的条目，`rev` 时从大到小，最多 `count` 个
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        rev: bool,
+        count: Option<usize>,
+    ) -> Vec<(StreamId, Vec<Bytes>)> {
+        if is_empty(start, end) {
+            return Vec::new();
+        }
+        let range = self.entries.range((start, end));
+        let count: usize = count.unwrap_or(usize::MAX);
+        let clone = |(id, fields): (&StreamId, &Vec<Bytes>)| (*id, fields.clone());
+        if rev {
+            range.rev().take(count).map(clone).collect()
+        } else {
+            range.take(count).map(clone).collect()
+        }
+    }
+
+    pub fn groups(&self) -> &BTreeMap<Bytes, Group> {
+        &self.groups
+    }
+
+    pub fn groups_mut(&mut self) -> &mut BTreeMap<Bytes, Group> {
+        &mut self.groups
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut Group> {
+        self.groups.get_mut(name)
+    }
+
+    /// 创建消费组，从 `last_delivered` 之后开始分发，组已存在时返回 `false`
+    pub fn create_group(&mut self, name: Bytes, last_delivered: StreamId) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        self.groups.insert(
+            name,
+            Group {
+                last_delivered,
+                ..Group::default()
+            },
+        );
+        true
+    }
+
+    /// XREADGROUP ... >：把组里还没分发过的条目交给消费者，`noack` 时不进入 PEL。
+    /// 组不存在时返回 `None`
+    pub fn read_new(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        count: Option<usize>,
+        noack: bool,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Vec<Bytes>)>> {
+        let after: StreamId = self.groups.get(group)?.last_delivered;
+        let items: Vec<(StreamId, Vec<Bytes>)> =
+            self.range(Bound::Excluded(after), Bound::Unbounded, false, count);
+        let group: &mut Group = self.groups.get_mut(group)?;
+        group.touch(consumer, now);
+        if let Some((last, _)) = items.last() {
+            group.last_delivered = *last;
+        }
+        if !noack {
+            for (id, _) in &items {
+                group.pending.insert(
+                    *id,
+                    Pending {
+                        consumer: consumer.clone(),
+                        delivered_at: now,
+                        deliveries: 1,
+                    },
+                );
+            }
+        }
+        Some(items)
+    }
+
+    /// XREADGROUP 指定 ID：消费者自己 PEL 里 ID 大于 `after` 的条目，不改变分发记录
+    pub fn read_pending(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        after: StreamId,
+        count: Option<usize>,
+        now: u64,
+    ) -> Option<Vec<Item>> {
+        let group: &mut Group = self.groups.get_mut(group)?;
+        group.touch(consumer, now);
+        let items: Vec<Item> = group
+            .pending
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .filter(|(_, pending)| pending.consumer == consumer)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, _)| (*id, self.entries.get(id).cloned()))
+            .collect();
+        Some(items)
+    }
+
+    /// XCLAIM：把空闲足够久的待确认条目转给 `consumer`，返回转移成功的 ID。
+    /// 已经从流中删除的条目同时从 PEL 中移除。组不存在时返回 `None`
+    pub fn claim(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        ids: &[StreamId],
+        claim: &Claim,
+        now: u64,
+    ) -> Option<Vec<StreamId>> {
+        let group: &mut Group = self.groups.get_mut(group)?;
+        let mut claimed: Vec<StreamId> = Vec::new();
+        for id in ids {
+            if !self.entries.contains_key(id) {
+                group.pending.remove(id);
+                continue;
+            }
+            let pending: &mut Pending = match group.pending.get_mut(id) {
+                Some(pending) => pending,
+                None if claim.force => group.pending.entry(*id).or_insert(Pending {
+                    consumer: consumer.clone(),
+                    delivered_at: now,
+                    deliveries: 0,
+                }),
+                None => continue,
+            };
+            if claim.min_idle > 0 && now.saturating_sub(pending.delivered_at) < claim.min_idle {
+                continue;
+            }
+            pending.consumer = consumer.clone();
+            pending.delivered_at = claim.delivered_at.unwrap_or(now);
+            match claim.retry_count {
+                Some(count) => pending.deliveries = count,
+                None if !claim.justid => pending.deliveries += 1,
+                None => {}
+            }
+            claimed.push(*id);
+        }
+        if !claimed.is_empty() {
+            group.touch(consumer, now);
+        }
+        Some(claimed)
+    }
+}
+
+impl Group {
+    /// 记录消费者的活动，消费者不存在时创建，返回是否新建
+    pub fn touch(&mut self, consumer: &Bytes, now: u64) -> bool {
+        self.consumers.insert(consumer.clone(), now).is_none()
+    }
+
+    /// ID 在区间内的待确认条目，按 ID 升序
+    pub fn pending_range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+    ) -> impl Iterator<Item = (&StreamId, &Pending)> {
+        let range = (!is_empty(start, end)).then(|| self.pending.range((start, end)));
+        range.into_iter().flatten()
+    }
+
+    /// 确认条目，返回它是否在 PEL 里
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        self.pending.remove(&id).is_some()
+    }
+
+    /// 删除消费者和它的待确认条目，返回删除的待确认条目数
+    pub fn remove_consumer(&mut self, consumer: &[u8]) -> usize {
+        if self.consumers.remove(consumer).is_none() {
+            return 0;
+        }
+        let before: usize = self.pending.len();
+        self.pending
+            .retain(|_, pending| pending.consumer != consumer);
+        before - self.pending.len()
+    }
+}
+
+/// 区间是否为空。`BTreeMap::range` 在起点大于终点时会 panic，调用前先检查
+fn is_empty(start: Bound<StreamId>, end: Bound<StreamId>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e)) => s >= e,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Claim, Stream, StreamId};
+    use bytes::Bytes;
+    use std::ops::Bound;
+
+    fn ids(items: &[(StreamId, Vec<Bytes>)]) -> Vec<String> {
+        items.iter().map(|(id, _)| id.to_string()).collect()
+    }
+
+    #[test]
+    fn ids_increase_and_trim() {
+        let mut stream: Stream = Stream::new();
+        let first: StreamId = stream.next_id(100).unwrap();
+        assert_eq!(first.to_string(), "100-0");
+        assert!(stream.add(first, vec![Bytes::from("f"), Bytes::from("v")]));
+        // 时钟回拨时沿用上一个时间戳
+        let second: StreamId = stream.next_id(50).unwrap();
+        assert_eq!(second, StreamId::new(100, 1));
+        assert!(stream.add(second, Vec::new()));
+        assert!(!stream.add(StreamId::new(100, 1), Vec::new()));
+        assert!(stream.add(StreamId::new(200, 0), Vec::new()));
+
+        let all = stream.range(Bound::Unbounded, Bound::Unbounded, false, None);
+        assert_eq!(ids(&all), ["100-0", "100-1", "200-0"]);
+        let rev = stream.range(
+            Bound::Unbounded,
+            Bound::Excluded(StreamId::new(200, 0)),
+            true,
+            Some(1),
+        );
+        assert_eq!(ids(&rev), ["100-1"]);
+        assert_eq!(stream.trim(1), 2);
+        assert_eq!(stream.len(), 1);
+        assert_eq!(stream.last_id(), StreamId::new(200, 0));
+        assert_eq!(
+            StreamId::parse(b"5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse(b"5-x", 0), None);
+    }
+
+    #[test]
+    fn groups_track_pending_entries() {
+        let mut stream: Stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(
+                StreamId::new(ms, 0),
+                vec![Bytes::from("n"), Bytes::from(ms.to_string())],
+            );
+        }
+        assert!(stream.create_group(Bytes::from("g"), StreamId::MIN));
+        assert!(!stream.create_group(Bytes::from("g"), StreamId::MIN));
+        let (alice, bob) = (Bytes::from("alice"), Bytes::from("bob"));
+
+        let read = stream.read_new(b"g", &alice, Some(2), false, 1000).unwrap();
+        assert_eq!(ids(&read), ["1-0", "2-0"]);
+        let read = stream.read_new(b"g", &bob, None, false, 1000).unwrap();
+        assert_eq!(ids(&read), ["3-0"]);
+        assert!(stream
+            .read_new(b"g", &bob, None, false, 1000)
+            .unwrap()
+            .is_empty());
+        assert!(stream
+            .read_new(b"missing", &bob, None, false, 1000)
+            .is_none());
+
+        // 已删除的条目在历史里显示为空
+        stream.remove(StreamId::new(2, 0));
+        let history = stream
+            .read_pending(b"g", &alice, StreamId::MIN, None, 1000)
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history[1].1.is_none());
+
+        let group = stream.group_mut(b"g").unwrap();
+        assert!(group.ack(StreamId::new(1, 0)));
+        assert!(!group.ack(StreamId::new(1, 0)));
+
+        // 空闲不够久的条目不转移，已删除的条目从 PEL 移除
+        let claim: Claim = Claim {
+            min_idle: 500,
+            ..Claim::default()
+        };
+        let ids3 = [StreamId::new(2, 0), StreamId::new(3, 0)];
+        assert!(stream
+            .claim(b"g", &alice, &ids3, &claim, 1200)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            stream.claim(b"g", &alice, &ids3, &claim, 1600).unwrap(),
+            [StreamId::new(3, 0)]
+        );
+        let group = stream.group_mut(b"g").unwrap();
+        assert_eq!(group.pending.len(), 1);
+        let pending = &group.pending[&StreamId::new(3, 0)];
+        assert_eq!(
+            (pending.consumer.as_ref(), pending.deliveries),
+            (&b"alice"[..], 2)
+        );
+        assert_eq!(group.remove_consumer(b"alice"), 1);
+        assert!(group.pending.is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+//! TLS 配置
+//!
+//! 需要打开 `tls` feature。服务端在 `tls-port` 上另外监听，证书和私钥从 PEM 文件读取；配置了
+//! `tls-ca-cert-file` 时按 `tls-auth-clients` 校验客户端证书。客户端用 `client_config` 构建的
+//! 配置连接 TLS 端口，服务端证书按 CA 文件校验，需要时也出示自己的证书。
+
+use crate::config::{ClientAuth, Config};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::path::Path;
+use std::sync::Arc;
+
+/// 统一使用 ring，不依赖进程级别的默认实现
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// 按服务端配置构建 TLS 配置
+pub fn server_config(config: &Config) -> Result<Arc<ServerConfig>, String> {
+    let cert_file: &Path = config
+        .tls_cert_file
+        .as_deref()
+        .ok_or("tls-port requires tls-cert-file")?;
+    let key_file: &Path = config
+        .tls_key_file
+        .as_deref()
+        .ok_or("tls-port requires tls-key-file")?;
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?;
+    let builder = match (&config.tls_ca_cert_file, config.tls_auth_clients) {
+        (_, ClientAuth::No) => builder.with_no_client_auth(),
+        (None, _) => {
+            return Err("tls-auth-clients requires tls-ca-cert-file, or set it to no".to_string())
+        }
+        (Some(ca_file), auth) => {
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots(ca_file)?), provider());
+            let verifier = if auth == ClientAuth::Optional {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|err| err.to_string())?)
+        }
+    };
+    let config: ServerConfig = builder
+        .with_single_cert(certs(cert_file)?, key(key_file)?)
+        .map_err(|err| format!("invalid TLS certificate or key: {err}"))?;
+    Ok(Arc::new(config))
+}
+
+/// 客户端的 TLS 配置：用 `ca_file` 校验服务端证书，`identity` 是要出示的证书和私钥
+pub fn client_config(
+    ca_file: &Path,
+    identity: Option<(&Path, &Path)>,
+) -> Result<Arc<ClientConfig>, String> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_root_certificates(roots(ca_file)?);
+    let config: ClientConfig = match identity {
+        Some((cert_file, key_file)) => builder
+            .with_client_auth_cert(certs(cert_file)?, key(key_file)?)
+            .map_err(|err| format!("invalid TLS certificate or key: {err}"))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let context = |err: String| format!("can't load certificates from {}: {err}", path.display());
+    let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(path)
+        .map_err(|err| context(err.to_string()))?
+        .collect::<Result<_, _>>()
+        .map_err(|err| context(err.to_string()))?;
+    if certs.is_empty() {
+        return Err(context("no certificate found".to_string()));
+    }
+    Ok(certs)
+}
+
+fn key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|err| format!("can't load private key from {}: {err}", path.display()))
+}
+
+fn roots(path: &Path) -> Result<RootCertStore, String> {
+    let mut roots: RootCertStore = RootCertStore::empty();
+    for cert in certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|err| format!("invalid CA certificate in {}: {err}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{client_config, server_config};
+    use crate::client::{Client, ClientConfig};
+    use crate::cmd::test::state;
+    use crate::config::{ClientAuth, Config};
+    use crate::net::Incoming;
+    use crate::server;
+    use bytes::Bytes;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    /// 生成 CA、服务端证书和客户端证书，写到 `dir` 下
+    fn certificates(dir: &Path) {
+        let mut params: CertificateParams = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+        std::fs::write(dir.join("ca.crt"), ca.pem()).unwrap();
+        for (name, sans) in [
+            ("server", vec!["localhost", "127.0.0.1"]),
+            ("client", vec!["client"]),
+        ] {
+            let key: KeyPair = KeyPair::generate().unwrap();
+            let sans: Vec<String> = sans.into_iter().map(String::from).collect();
+            let cert = CertificateParams::new(sans)
+                .unwrap()
+                .signed_by(&key, &ca)
+                .unwrap();
+            std::fs::write(dir.join(format!("{name}.crt")), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{name}.key")), key.serialize_pem()).unwrap();
+        }
+    }
+
+    fn config(addr: SocketAddr, dir: &Path, identity: bool) -> ClientConfig {
+        let (cert, key): (PathBuf, PathBuf) = (dir.join("client.crt"), dir.join("client.key"));
+        let identity = identity.then_some((cert.as_path(), key.as_path()));
+        ClientConfig {
+            addr: addr.to_string(),
+            pool_size: 1,
+            timeout: Some(Duration::from_secs(5)),
+            tls: Some(client_config(&dir.join("ca.crt"), identity).unwrap()),
+            ..ClientConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn tls_with_client_certificates() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("my_redis-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        certificates(&dir);
+        let mut settings: Config = Config {
+            tls_cert_file: Some(dir.join("server.crt")),
+            tls_key_file: Some(dir.join("server.key")),
+            ..Config::default()
+        };
+        assert_eq!(
+            server_config(&settings).unwrap_err(),
+            "tls-auth-clients requires tls-ca-cert-file, or set it to no"
+        );
+        settings.tls_ca_cert_file = Some(dir.join("ca.crt"));
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let incoming: Incoming = Incoming::Tls(listener, server_config(&settings).unwrap());
+        tokio::spawn(server::run_all(
+            vec![incoming],
+            state(2),
+            std::future::pending::<()>(),
+        ));
+
+        let client: Client = Client::with_config(config(addr, &dir, true)).await.unwrap();
+        client.set("k", "v").await.unwrap();
+        assert_eq!(client.get("k").await.unwrap(), Some(Bytes::from("v")));
+        // 没有客户端证书时握手被拒绝
+        assert!(Client::with_config(config(addr, &dir, false))
+            .await
+            .is_err());
+
+        // 客户端证书可选时两种客户端都能连上
+        settings.tls_auth_clients = ClientAuth::Optional;
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let incoming: Incoming = Incoming::Tls(listener, server_config(&settings).unwrap());
+        tokio::spawn(server::run_all(
+            vec![incoming],
+            state(2),
+            std::future::pending::<()>(),
+        ));
+        let client: Client = Client::with_config(config(addr, &dir, false))
+            .await
+            .unwrap();
+        client.set("k", "v").await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
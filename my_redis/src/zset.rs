@@ -0,0 +1,230 @@
+//! 有序集合
+//!
+//! 用 `HashMap` 保存成员到分数的映射，用 `BTreeSet<(分数, 成员)>` 维护顺序，
+//! 按成员查分数是 O(1)，按分数区间查询是 O(log n + m)。
+
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+/// 可以全序比较的分数，NaN 在写入前已经被拒绝
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Score) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Score) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// 有序集合
+#[derive(Debug, Clone, Default)]
+pub struct ZSet {
+    scores: HashMap<Bytes, f64>,
+    sorted: BTreeSet<(Score, Bytes)>,
+}
+
+/// 分数区间的一端
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ZSet {
+    pub fn new() -> ZSet {
+        ZSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// 插入或更新成员，返回是否为新成员
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                self.sorted.remove(&(Score(old), member.clone()));
+                self.sorted.insert((Score(score), member));
+                false
+            }
+            None => {
+                self.sorted.insert((Score(score), member));
+                true
+            }
+        }
+    }
+
+    /// 删除成员，返回是否存在
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove_entry(member) {
+            Some((member, score)) => {
+                self.sorted.remove(&(Score(score), member));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// 按分数从小到大遍历
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.sorted.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// 按排名取 `[start, stop]` 区间，负数下标从末尾倒数
+    pub fn range_by_rank(&self, start: i64, stop: i64) -> Vec<(&Bytes, f64)> {
+        let len: i64 = self.len() as i64;
+        let start: i64 = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let stop: i64 = if stop < 0 {
+            len + stop
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop || start >= len {
+            return Vec::new();
+        }
+        self.iter()
+            .skip(start as usize)
+            .take((stop - start + 1) as usize)
+            .collect()
+    }
+
+    /// 取分数在 `[min, max]` 区间内的成员
+    pub fn range_by_score(&self, min: ScoreBound, max: ScoreBound) -> Vec<(&Bytes, f64)> {
+        let (min_score, max_score) = (min.value(), max.value());
+        if min_score > max_score {
+            return Vec::new();
+        }
+        // 空成员是同分数下最小的元素，作为区间起点可以覆盖该分数的所有成员
+        let lower = Bound::Included((Score(min_score), Bytes::new()));
+        self.sorted
+            .range((lower, Bound::Unbounded))
+            .take_while(|(score, _)| max.admits_from_above(score.0))
+            .filter(|(score, _)| min.admits_from_below(score.0))
+            .map(|(score, member)| (member, score.0))
+            .collect()
+    }
+}
+
+impl ScoreBound {
+    /// 解析 `1.5`、`(1.5`、`-inf`、`+inf` 形式的区间端点
+    pub fn parse(arg: &[u8]) -> Option<ScoreBound> {
+        let s: &str = std::str::from_utf8(arg).ok()?;
+        let (exclusive, s) = match s.strip_prefix('(') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let value: f64 = parse_score(s.as_bytes())?;
+        Some(if exclusive {
+            ScoreBound::Exclusive(value)
+        } else {
+            ScoreBound::Inclusive(value)
+        })
+    }
+
+    fn value(self) -> f64 {
+        match self {
+            ScoreBound::Inclusive(v) | ScoreBound::Exclusive(v) => v,
+        }
+    }
+
+    fn admits_from_below(self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(min) => score >= min,
+            ScoreBound::Exclusive(min) => score > min,
+        }
+    }
+
+    fn admits_from_above(self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        }
+    }
+}
+
+/// 解析分数，支持 `inf`、`+inf`、`-inf`，拒绝 NaN
+pub fn parse_score(arg: &[u8]) -> Option<f64> {
+    let s: &str = std::str::from_utf8(arg).ok()?;
+    let value: f64 = match s.to_ascii_lowercase().as_str() {
+        "inf" | "+inf" => f64::INFINITY,
+        "-inf" => f64::NEG_INFINITY,
+        other => other.parse().ok()?,
+    };
+    (!value.is_nan()).then_some(value)
+}
+
+/// 按 Redis 的习惯格式化分数：整数不带小数点，无穷大写作 `inf`/`-inf`
+pub fn format_score(score: f64) -> String {
+    score.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ScoreBound, ZSet};
+    use bytes::Bytes;
+
+    fn members(items: Vec<(&Bytes, f64)>) -> Vec<String> {
+        items
+            .into_iter()
+            .map(|(m, _)| String::from_utf8_lossy(m).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn update_keeps_order() {
+        let mut zset: ZSet = ZSet::new();
+        assert!(zset.insert("a".into(), 3.0));
+        assert!(zset.insert("b".into(), 1.0));
+        assert!(zset.insert("c".into(), 2.0));
+        assert!(!zset.insert("a".into(), 0.5));
+        assert_eq!(members(zset.range_by_rank(0, -1)), ["a", "b", "c"]);
+        assert_eq!(members(zset.range_by_rank(-2, 10)), ["b", "c"]);
+        assert!(zset.remove(b"b"));
+        assert_eq!(zset.len(), 2);
+    }
+
+    #[test]
+    fn score_ranges() {
+        let mut zset: ZSet = ZSet::new();
+        for (i, m) in ["a", "b", "c", "d"].iter().enumerate() {
+            zset.insert(Bytes::from(*m), i as f64);
+        }
+        let bound = |s: &str| ScoreBound::parse(s.as_bytes()).unwrap();
+        assert_eq!(
+            members(zset.range_by_score(bound("1"), bound("2"))),
+            ["b", "c"]
+        );
+        assert_eq!(
+            members(zset.range_by_score(bound("(1"), bound("+inf"))),
+            ["c", "d"]
+        );
+        assert_eq!(
+            members(zset.range_by_score(bound("-inf"), bound("(1"))),
+            ["a"]
+        );
+        assert!(zset.range_by_score(bound("3"), bound("1")).is_empty());
+        assert!(ScoreBound::parse(b"nan").is_none());
+    }
+}
@@ -0,0 +1,305 @@
+//! 在本机的几个端口上启动集群节点，检查槽的分配、重定向和槽迁移
+
+use bytes::Bytes;
+use my_redis::client::{cmd, Client, ClusterClient, Error};
+use my_redis::cluster::key_slot;
+use my_redis::config::Config;
+use my_redis::frame::Frame;
+use my_redis::server;
+use my_redis::state::State;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// 启动一个开启集群模式的节点，返回它的地址
+async fn start() -> String {
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port: u16 = listener.local_addr().unwrap().port();
+    let state = State::new(Config {
+        port,
+        save: Vec::new(),
+        cluster_enabled: true,
+        ..Config::default()
+    });
+    tokio::spawn(server::run(listener, state, std::future::pending::<()>()));
+    format!("127.0.0.1:{port}")
+}
+
+async fn request(client: &Client, args: &[&str]) -> Result<Frame, Error> {
+    let (name, rest) = args.split_first().unwrap();
+    client.request(cmd(name).args(rest)).await
+}
+
+async fn text(client: &Client, args: &[&str]) -> String {
+    match request(client, args).await.unwrap() {
+        Frame::Bulk(data) => String::from_utf8_lossy(&data).into_owned(),
+        Frame::Simple(s) => s,
+        frame => panic!("unexpected {frame:?}"),
+    }
+}
+
+/// 等待条件成立，最多等五秒
+async fn eventually<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    for _ in 0..100 {
+        if condition().await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("condition did not hold in time");
+}
+
+/// 三个节点平分全部的槽，等到每个节点都知道完整的分配
+async fn three_nodes() -> (Vec<String>, Vec<Client>) {
+    let mut addrs: Vec<String> = Vec::new();
+    let mut clients: Vec<Client> = Vec::new();
+    for _ in 0..3 {
+        let addr: String = start().await;
+        clients.push(Client::connect(&addr).await.unwrap());
+        addrs.push(addr);
+    }
+    for (client, range) in
+        clients
+            .iter()
+            .zip([("0", "5460"), ("5461", "10922"), ("10923", "16383")])
+    {
+        assert_eq!(
+            text(client, &["CLUSTER", "ADDSLOTSRANGE", range.0, range.1]).await,
+            "OK"
+        );
+    }
+    let port: String = addrs[0].rsplit_once(':').unwrap().1.to_string();
+    for client in &clients[1..] {
+        assert_eq!(
+            text(client, &["CLUSTER", "MEET", "127.0.0.1", &port]).await,
+            "OK"
+        );
+    }
+    for client in &clients {
+        eventually(|| async {
+            let info: String = text(client, &["CLUSTER", "INFO"]).await;
+            info.contains("cluster_state:ok") && info.contains("cluster_known_nodes:3")
+        })
+        .await;
+    }
+    (addrs, clients)
+}
+
+#[tokio::test]
+async fn redirects_to_the_owner() {
+    let (addrs, clients) = three_nodes().await;
+    assert!(text(&clients[0], &["INFO", "server"])
+        .await
+        .contains("redis_mode:cluster\r\n"));
+    // RESP2 下 HELLO 的回复是键值交替的数组
+    let Frame::Array(hello) = request(&clients[0], &["HELLO"]).await.unwrap() else {
+        panic!("HELLO returns an array");
+    };
+    assert!(hello.chunks(2).any(|pair| pair
+        == [
+            Frame::Bulk(Bytes::from("mode")),
+            Frame::Bulk(Bytes::from("cluster"))
+        ]));
+    // "foo" 在槽 12182，属于第三个节点
+    assert_eq!(key_slot(b"foo"), 12182);
+    match request(&clients[0], &["SET", "foo", "bar"]).await {
+        Err(Error::Server(err)) => assert_eq!(err, format!("MOVED 12182 {}", addrs[2])),
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(text(&clients[2], &["SET", "foo", "bar"]).await, "OK");
+    match request(&clients[2], &["MSET", "a", "1", "b", "2"]).await {
+        Err(Error::Server(err)) => assert!(err.starts_with("CROSSSLOT")),
+        other => panic!("unexpected {other:?}"),
+    }
+    // 脚本按声明的 key 路由，只能访问本节点的 key
+    let get: &str = "return call('GET', KEYS[1])";
+    match request(&clients[0], &["EVAL", get, "1", "foo"]).await {
+        Err(Error::Server(err)) => assert_eq!(err, format!("MOVED 12182 {}", addrs[2])),
+        other => panic!("unexpected {other:?}"),
+    }
+    match request(&clients[2], &["EVAL", get, "2", "foo", "b"]).await {
+        Err(Error::Server(err)) => assert!(err.starts_with("CROSSSLOT")),
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(text(&clients[2], &["EVAL", get, "1", "foo"]).await, "bar");
+    match request(
+        &clients[2],
+        &["EVAL", "return call('GET', 'b')", "1", "foo"],
+    )
+    .await
+    {
+        Err(Error::Server(err)) => assert_eq!(
+            err,
+            "ERR Script attempted to access a non local key in a cluster node"
+        ),
+        other => panic!("unexpected {other:?}"),
+    }
+
+    let cluster: ClusterClient = ClusterClient::connect(&addrs[0]).await.unwrap();
+    for i in 0..100 {
+        let key: String = format!("key:{i}");
+        cluster.request(cmd("SET").arg(&key).arg(i)).await.unwrap();
+    }
+    for i in 0..100 {
+        let key: String = format!("key:{i}");
+        assert_eq!(
+            cluster.request(cmd("GET").arg(&key)).await.unwrap(),
+            Frame::Bulk(Bytes::from(i.to_string()))
+        );
+    }
+    // 哈希标签让相关的 key 落在同一个节点
+    cluster
+        .request(
+            cmd("MSET")
+                .arg("{user}:a")
+                .arg("1")
+                .arg("{user}:b")
+                .arg("2"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        cluster
+            .request(cmd("EVAL").arg(get).arg(1).arg("{user}:a"))
+            .await
+            .unwrap(),
+        Frame::Bulk(Bytes::from("1"))
+    );
+    let mut total: i64 = 0;
+    for client in &clients {
+        let Frame::Integer(n) = request(client, &["DBSIZE"]).await.unwrap() else {
+            panic!("DBSIZE is an integer");
+        };
+        assert!(n > 0, "keys are spread over all nodes");
+        total += n;
+    }
+    assert_eq!(total, 103);
+}
+
+#[tokio::test]
+async fn migrate_a_slot_with_ask() {
+    let (addrs, clients) = three_nodes().await;
+    let cluster: ClusterClient = ClusterClient::connect(&addrs[1]).await.unwrap();
+    let slot: u16 = key_slot(b"foo");
+    let (source, target) = (&clients[2], &clients[0]);
+    cluster
+        .request(cmd("SET").arg("{foo}:1").arg("one"))
+        .await
+        .unwrap();
+    cluster
+        .request(cmd("SET").arg("{foo}:2").arg("two"))
+        .await
+        .unwrap();
+
+    let source_id: String = text(source, &["CLUSTER", "MYID"]).await;
+    let target_id: String = text(target, &["CLUSTER", "MYID"]).await;
+    let slot_arg: String = slot.to_string();
+    assert_eq!(
+        text(
+            target,
+            &["CLUSTER", "SETSLOT", &slot_arg, "IMPORTING", &source_id]
+        )
+        .await,
+        "OK"
+    );
+    assert_eq!(
+        text(
+            source,
+            &["CLUSTER", "SETSLOT", &slot_arg, "MIGRATING", &target_id]
+        )
+        .await,
+        "OK"
+    );
+
+    // 搬走一个 key：源节点上找不到的 key 回复 ASK，目标节点只在 ASKING 之后接受
+    let Frame::Bulk(payload) = request(source, &["DUMP", "{foo}:1"]).await.unwrap() else {
+        panic!("DUMP returns the serialized value");
+    };
+    let replies: Vec<Frame> = target
+        .pipeline(vec![
+            cmd("ASKING"),
+            cmd("RESTORE").arg("{foo}:1").arg(0).arg(payload),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(replies[1], Frame::Simple("OK".to_string()));
+    request(source, &["DEL", "{foo}:1"]).await.unwrap();
+    match request(source, &["GET", "{foo}:1"]).await {
+        Err(Error::Server(err)) => assert_eq!(err, format!("ASK {slot} {}", addrs[0])),
+        other => panic!("unexpected {other:?}"),
+    }
+    match request(target, &["GET", "{foo}:1"]).await {
+        Err(Error::Server(err)) => assert!(err.starts_with("MOVED")),
+        other => panic!("unexpected {other:?}"),
+    }
+    match request(source, &["MGET", "{foo}:1", "{foo}:2"]).await {
+        Err(Error::Server(err)) => assert!(err.starts_with("TRYAGAIN")),
+        other => panic!("unexpected {other:?}"),
+    }
+    // 集群客户端跟随 ASK，还没搬的 key 照常在源节点读
+    assert_eq!(
+        cluster.request(cmd("GET").arg("{foo}:1")).await.unwrap(),
+        Frame::Bulk(Bytes::from("one"))
+    );
+    assert_eq!(
+        cluster.request(cmd("GET").arg("{foo}:2")).await.unwrap(),
+        Frame::Bulk(Bytes::from("two"))
+    );
+
+    // 源节点还有这个槽的 key 时不能交出去
+    let Frame::Bulk(payload) = request(source, &["DUMP", "{foo}:2"]).await.unwrap() else {
+        panic!("DUMP returns the serialized value");
+    };
+    assert!(request(
+        source,
+        &["CLUSTER", "SETSLOT", &slot_arg, "NODE", &target_id]
+    )
+    .await
+    .is_err());
+    target
+        .pipeline(vec![
+            cmd("ASKING"),
+            cmd("RESTORE").arg("{foo}:2").arg(0).arg(payload),
+        ])
+        .await
+        .unwrap();
+    request(source, &["DEL", "{foo}:2"]).await.unwrap();
+    assert_eq!(
+        text(
+            target,
+            &["CLUSTER", "SETSLOT", &slot_arg, "NODE", &target_id]
+        )
+        .await,
+        "OK"
+    );
+    assert_eq!(
+        text(
+            source,
+            &["CLUSTER", "SETSLOT", &slot_arg, "NODE", &target_id]
+        )
+        .await,
+        "OK"
+    );
+
+    // 新的归属传到所有节点，集群客户端收到 MOVED 后更新缓存
+    for client in &clients {
+        eventually(|| async {
+            text(client, &["CLUSTER", "NODES"])
+                .await
+                .lines()
+                .any(|line| {
+                    line.starts_with(&target_id)
+                        && line.split_whitespace().any(|word| word == slot_arg)
+                })
+        })
+        .await;
+    }
+    assert_eq!(
+        cluster.request(cmd("GET").arg("{foo}:2")).await.unwrap(),
+        Frame::Bulk(Bytes::from("two"))
+    );
+    assert_eq!(cluster.slot_owner(slot).as_deref(), Some(addrs[0].as_str()));
+}
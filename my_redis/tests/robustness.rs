@@ -0,0 +1,141 @@
+//! 向服务端发送非法数据和不完整的帧，服务端应回复错误或关闭该连接，但不能崩溃
+
+use my_redis::config::Config;
+use my_redis::server;
+use my_redis::state::State;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn start() -> SocketAddr {
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let state = State::new(Config {
+        save: Vec::new(),
+        ..Config::default()
+    });
+    tokio::spawn(server::run(listener, state, std::future::pending::<()>()));
+    addr
+}
+
+/// 读到对端关闭为止，最多等一秒
+async fn read_to_close(stream: &mut TcpStream) -> Vec<u8> {
+    let mut buf: Vec<u8> = Vec::new();
+    tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(&mut buf))
+        .await
+        .expect("server kept the connection open")
+        .unwrap();
+    buf
+}
+
+/// 发送一条请求并读取一次回复
+async fn request(stream: &mut TcpStream, bytes: &[u8]) -> Vec<u8> {
+    stream.write_all(bytes).await.unwrap();
+    let mut buf: Vec<u8> = vec![0; 512];
+    let n: usize = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf))
+        .await
+        .expect("no reply")
+        .unwrap();
+    buf.truncate(n);
+    buf
+}
+
+async fn assert_alive(addr: SocketAddr) {
+    let mut stream: TcpStream = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(
+        request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await,
+        b"+PONG\r\n"
+    );
+}
+
+#[tokio::test]
+async fn garbage_gets_protocol_error_and_close() {
+    let addr: SocketAddr = start().await;
+    for garbage in [
+        &b"\x00\xff\xfehello\r\n"[..],
+        b"*2\r\n$3\r\nGET\r\n$1\r\nkey\r\n",
+        b"*-5\r\n",
+        b"$99999999999\r\n",
+        b"*1\r\n:abc\r\n",
+    ] {
+        let mut stream: TcpStream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(garbage).await.unwrap();
+        let reply: Vec<u8> = read_to_close(&mut stream).await;
+        assert!(
+            reply.starts_with(b"-ERR Protocol error"),
+            "{garbage:?} -> {:?}",
+            String::from_utf8_lossy(&reply)
+        );
+    }
+    assert_alive(addr).await;
+}
+
+#[tokio::test]
+async fn well_formed_but_invalid_requests_keep_connection() {
+    let addr: SocketAddr = start().await;
+    let mut stream: TcpStream = TcpStream::connect(addr).await.unwrap();
+    let reply: Vec<u8> = request(&mut stream, b":1\r\n").await;
+    assert!(reply.starts_with(b"-ERR Protocol error: expected array"));
+    let reply: Vec<u8> = request(&mut stream, b"*1\r\n$7\r\nNOSUCHX\r\n").await;
+    assert!(reply.starts_with(b"-ERR unknown command"));
+    let reply: Vec<u8> = request(&mut stream, b"*0\r\n").await;
+    assert!(reply.starts_with(b"-ERR empty command"));
+    assert_eq!(
+        request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await,
+        b"+PONG\r\n"
+    );
+}
+
+#[tokio::test]
+async fn half_frames() {
+    let addr: SocketAddr = start().await;
+
+    // 发了半个帧就断开
+    let mut stream: TcpStream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk")
+        .await
+        .unwrap();
+    drop(stream);
+
+    // 半个帧停在那里不影响其它连接，补齐之后照常执行
+    let mut slow: TcpStream = TcpStream::connect(addr).await.unwrap();
+    slow.write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1")
+        .await
+        .unwrap();
+    assert_alive(addr).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(request(&mut slow, b"\r\nv\r\n").await, b"+OK\r\n");
+
+    // 一个字节一个字节地发
+    let mut trickle: TcpStream = TcpStream::connect(addr).await.unwrap();
+    for byte in b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n" {
+        trickle.write_all(&[*byte]).await.unwrap();
+        trickle.flush().await.unwrap();
+    }
+    let mut reply: Vec<u8> = vec![0; 16];
+    let n: usize = trickle.read(&mut reply).await.unwrap();
+    assert_eq!(&reply[..n], b"$1\r\nv\r\n");
+}
+
+#[tokio::test]
+async fn random_bytes_never_take_the_server_down() {
+    let addr: SocketAddr = start().await;
+    let alphabet: &[u8] = b"*$+-:\r\n0123456789abc";
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    for _ in 0..50 {
+        let bytes: Vec<u8> = (0..64)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                alphabet[(seed % alphabet.len() as u64) as usize]
+            })
+            .collect();
+        let mut stream: TcpStream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        drop(stream);
+    }
+    assert_alive(addr).await;
+}